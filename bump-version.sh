@@ -22,9 +22,9 @@ npm version $NEW_VERSION --no-git-tag-version --allow-same-version
 sed -i 's/"version": "[0-9]*\.[0-9]*\.[0-9]*"/"version": "'$NEW_VERSION'"/' src-tauri/tauri.conf.json
 
 # 3. Update Cargo.toml files
-# We need to update server, shared, and src-tauri Cargo.toml files
+# We need to update server, shared, sfu, and src-tauri Cargo.toml files
 # We use a loop to handle them
-CARGO_FILES=("server/Cargo.toml" "shared/Cargo.toml" "src-tauri/Cargo.toml")
+CARGO_FILES=("server/Cargo.toml" "shared/Cargo.toml" "sfu/Cargo.toml" "src-tauri/Cargo.toml")
 
 for file in "${CARGO_FILES[@]}"; do
   # Replace version = "..." with version = "NEW_VERSION"
@@ -29,7 +29,9 @@ CREATE TABLE IF NOT EXISTS servers (
     icon_url    TEXT,
     owner_id    TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
     created_at  TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
-    updated_at  TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+    updated_at  TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    link_previews INTEGER NOT NULL DEFAULT 1,   -- generate embeds for links in messages
+    edit_history_days INTEGER NOT NULL DEFAULT 0 -- days to keep earlier versions of edited messages; 0 = forever
 );
 
 CREATE INDEX IF NOT EXISTS idx_servers_owner ON servers(owner_id);
@@ -42,11 +44,55 @@ CREATE TABLE IF NOT EXISTS server_members (
     server_id TEXT NOT NULL REFERENCES servers(id)  ON DELETE CASCADE,
     role      TEXT NOT NULL DEFAULT 'member',       -- 'owner' | 'admin' | 'member'
     joined_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    server_muted    INTEGER NOT NULL DEFAULT 0,     -- set by moderators, kept across voice sessions
+    server_deafened INTEGER NOT NULL DEFAULT 0,
     PRIMARY KEY (user_id, server_id)
 );
 
 CREATE INDEX IF NOT EXISTS idx_server_members_server ON server_members(server_id);
 
+--------------------------------------------------------------------------------
+-- Roles  (per-server permission sets)
+-- `permissions` is a bitset, see server/src/permissions.rs. Every server has an
+-- @everyone role whose id equals the server id; it applies to all members.
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS roles (
+    id          TEXT PRIMARY KEY,               -- UUID (server id for @everyone)
+    server_id   TEXT    NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    name        TEXT    NOT NULL,
+    permissions INTEGER NOT NULL DEFAULT 0,     -- bitset
+    position    INTEGER NOT NULL DEFAULT 0,     -- display ordering
+    created_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_roles_server ON roles(server_id);
+
+--------------------------------------------------------------------------------
+-- Member Roles  (many-to-many: server members ↔ roles)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS member_roles (
+    user_id   TEXT NOT NULL,
+    server_id TEXT NOT NULL,
+    role_id   TEXT NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id),
+    FOREIGN KEY (user_id, server_id) REFERENCES server_members(user_id, server_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_member_roles_server ON member_roles(server_id, user_id);
+
+--------------------------------------------------------------------------------
+-- Channel Categories  (collapsible groups of channels in a server)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS channel_categories (
+    id          TEXT PRIMARY KEY,               -- UUID
+    server_id   TEXT    NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    name        TEXT    NOT NULL,
+    position    INTEGER NOT NULL DEFAULT 0,     -- display ordering
+    created_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_channel_categories_server ON channel_categories(server_id);
+
 --------------------------------------------------------------------------------
 -- Channels  (belong to a server; type = 'text' | 'voice')
 --------------------------------------------------------------------------------
@@ -55,13 +101,45 @@ CREATE TABLE IF NOT EXISTS channels (
     server_id   TEXT    NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
     name        TEXT    NOT NULL,
     type        TEXT    NOT NULL CHECK (type IN ('text', 'voice')),
-    position    INTEGER NOT NULL DEFAULT 0,     -- display ordering
+    position    INTEGER NOT NULL DEFAULT 0,     -- display ordering within the category
+    category_id TEXT             REFERENCES channel_categories(id) ON DELETE SET NULL,  -- NULL = uncategorised
+    topic       TEXT,
+    slowmode_secs INTEGER NOT NULL DEFAULT 0,   -- 0 = off
+    nsfw        INTEGER NOT NULL DEFAULT 0,
+    voice_mode  TEXT    NOT NULL DEFAULT 'mesh' CHECK (voice_mode IN ('mesh', 'sfu')),  -- voice channels only
     created_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
     updated_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
 
 CREATE INDEX IF NOT EXISTS idx_channels_server ON channels(server_id);
 
+--------------------------------------------------------------------------------
+-- Channel Permission Overrides  (per-role allow/deny bitsets on one channel)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS channel_permission_overrides (
+    channel_id TEXT    NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    role_id    TEXT    NOT NULL REFERENCES roles(id)    ON DELETE CASCADE,
+    allow      INTEGER NOT NULL DEFAULT 0,     -- bitset
+    deny       INTEGER NOT NULL DEFAULT 0,     -- bitset
+    PRIMARY KEY (channel_id, role_id)
+);
+
+--------------------------------------------------------------------------------
+-- Invites
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS invites (
+    code        TEXT PRIMARY KEY,
+    server_id   TEXT    NOT NULL REFERENCES servers(id)  ON DELETE CASCADE,
+    channel_id  TEXT             REFERENCES channels(id) ON DELETE SET NULL,  -- opened after joining
+    creator_id  TEXT    NOT NULL REFERENCES users(id)    ON DELETE CASCADE,
+    max_uses    INTEGER,                        -- NULL = unlimited
+    uses        INTEGER NOT NULL DEFAULT 0,
+    expires_at  TEXT,                           -- NULL = never
+    created_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_invites_server ON invites(server_id);
+
 --------------------------------------------------------------------------------
 -- Messages  (belong to a text channel)
 --------------------------------------------------------------------------------
@@ -72,12 +150,56 @@ CREATE TABLE IF NOT EXISTS messages (
     content    TEXT,                            -- markdown text (nullable for media-only)
     pinned     INTEGER NOT NULL DEFAULT 0,     -- boolean
     created_at TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
-    edited_at  TEXT                             -- NULL until edited
+    edited_at  TEXT,                            -- NULL until edited
+    reply_to_id TEXT,                           -- message replied to; kept after the parent is deleted
+    thread_id  TEXT REFERENCES threads(id) ON DELETE CASCADE, -- NULL for the channel timeline
+    deleted_at TEXT,                            -- set while the message is a tombstone
+    deleted_by TEXT REFERENCES users(id) ON DELETE SET NULL
 );
 
-CREATE INDEX IF NOT EXISTS idx_messages_channel   ON messages(channel_id, created_at);
+-- (created_at, id) is the pagination order; replaces idx_messages_channel
+DROP INDEX IF EXISTS idx_messages_channel;
+CREATE INDEX IF NOT EXISTS idx_messages_channel_order ON messages(channel_id, created_at, id);
 CREATE INDEX IF NOT EXISTS idx_messages_author    ON messages(author_id);
 
+--------------------------------------------------------------------------------
+-- Threads  (a sub-conversation rooted at a channel message)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS threads (
+    id              TEXT PRIMARY KEY,          -- UUID
+    channel_id      TEXT    NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    root_message_id TEXT    NOT NULL UNIQUE REFERENCES messages(id) ON DELETE CASCADE,
+    creator_id      TEXT    NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name            TEXT    NOT NULL,
+    message_count   INTEGER NOT NULL DEFAULT 0,
+    last_message_at TEXT,                      -- NULL until the first reply
+    created_at      TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_threads_channel ON threads(channel_id, created_at);
+
+--------------------------------------------------------------------------------
+-- Pending Uploads  (files not yet attached to a message)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS uploads (
+    id         TEXT PRIMARY KEY,               -- UUID, reused as the attachment id
+    owner_id   TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    file_url   TEXT NOT NULL,
+    file_name  TEXT NOT NULL,
+    mime_type  TEXT NOT NULL,
+    size_bytes INTEGER,
+    width      INTEGER,                         -- images after EXIF rotation, videos after track rotation
+    height     INTEGER,
+    blurhash   TEXT,
+    thumbnail_url TEXT,                         -- WebP renditions; NULL if none were made
+    preview_url   TEXT,
+    duration_secs REAL,                         -- videos only
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_uploads_created ON uploads(created_at);
+CREATE INDEX IF NOT EXISTS idx_uploads_file_url ON uploads(file_url);
+
 --------------------------------------------------------------------------------
 -- Message Attachments  (images / videos uploaded with a message)
 --------------------------------------------------------------------------------
@@ -88,10 +210,64 @@ CREATE TABLE IF NOT EXISTS attachments (
     file_name  TEXT NOT NULL,
     mime_type  TEXT NOT NULL,                   -- e.g. 'image/png', 'video/mp4'
     size_bytes INTEGER,
+    width      INTEGER,                         -- images after EXIF rotation, videos after track rotation
+    height     INTEGER,
+    blurhash   TEXT,
+    thumbnail_url TEXT,                         -- WebP renditions; NULL if none were made
+    preview_url   TEXT,
+    duration_secs REAL,                         -- videos only
     created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
 
 CREATE INDEX IF NOT EXISTS idx_attachments_message ON attachments(message_id);
+CREATE INDEX IF NOT EXISTS idx_attachments_file_url ON attachments(file_url);
+
+--------------------------------------------------------------------------------
+-- Message Embeds  (link previews shown under a message, in link order)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS message_embeds (
+    message_id  TEXT    NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    position    INTEGER NOT NULL,
+    url         TEXT    NOT NULL,               -- the link as written in the message
+    title       TEXT,
+    description TEXT,
+    site_name   TEXT,
+    image_url   TEXT,
+    large_image INTEGER NOT NULL DEFAULT 0,     -- boolean, Twitter summary_large_image
+    PRIMARY KEY (message_id, position)
+);
+
+--------------------------------------------------------------------------------
+-- Message Revisions  (earlier versions of edited messages)
+-- One row per edit that changed the content, holding the version it replaced.
+-- `created_at` is when that version was posted or last edited.
+-- Pruned once older than the server's edit_history_days.
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS message_revisions (
+    message_id  TEXT    NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    revision    INTEGER NOT NULL,               -- counts up from 1 with each edit
+    content     TEXT,
+    created_at  TEXT    NOT NULL,
+    replaced_at TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    replaced_by TEXT             REFERENCES users(id) ON DELETE SET NULL,
+    PRIMARY KEY (message_id, revision)
+);
+
+--------------------------------------------------------------------------------
+-- Link Previews  (cache of fetched page metadata, by URL)
+-- Rows with found = 0 remember pages that had no usable metadata or could not
+-- be fetched, so they are not retried on every message.
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS link_previews (
+    url         TEXT PRIMARY KEY,
+    found       INTEGER NOT NULL,               -- boolean
+    title       TEXT,
+    description TEXT,
+    site_name   TEXT,
+    image_url   TEXT,
+    large_image INTEGER NOT NULL DEFAULT 0,
+    fetched_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
 
 --------------------------------------------------------------------------------
 -- Reactions  (emoji reactions on messages)
@@ -120,9 +296,22 @@ CREATE TABLE IF NOT EXISTS mentions (
 CREATE INDEX IF NOT EXISTS idx_mentions_message ON mentions(message_id);
 CREATE INDEX IF NOT EXISTS idx_mentions_target  ON mentions(target_id);
 
+-- Who was notified by a mention, with their read state. @here and @everyone
+-- are expanded to the members they reached when the message was sent.
+CREATE TABLE IF NOT EXISTS user_mentions (
+    user_id    TEXT NOT NULL REFERENCES users(id)    ON DELETE CASCADE,
+    message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    read_at    TEXT,                               -- NULL while unread
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (user_id, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_mentions_inbox ON user_mentions(user_id, created_at);
+
 --------------------------------------------------------------------------------
 -- Voice State  (ephemeral: who is in which voice channel right now)
--- Rows are inserted on join and deleted on leave.
+-- Rows are inserted on join and deleted on leave, or when the WebSocket
+-- session that joined goes away. Cleared on startup.
 --------------------------------------------------------------------------------
 CREATE TABLE IF NOT EXISTS voice_states (
     user_id    TEXT NOT NULL REFERENCES users(id)    ON DELETE CASCADE,
@@ -130,11 +319,28 @@ CREATE TABLE IF NOT EXISTS voice_states (
     muted      INTEGER NOT NULL DEFAULT 0,     -- self-mute
     deafened   INTEGER NOT NULL DEFAULT 0,     -- self-deafen
     joined_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    session_id TEXT,                           -- WebSocket session that joined
     PRIMARY KEY (user_id, channel_id)
 );
 
 CREATE INDEX IF NOT EXISTS idx_voice_states_channel ON voice_states(channel_id);
 
+--------------------------------------------------------------------------------
+-- Audit Log  (moderator actions, newest read first)
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS audit_log (
+    id             TEXT PRIMARY KEY,
+    server_id      TEXT NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    actor_id       TEXT REFERENCES users(id) ON DELETE SET NULL,
+    action         TEXT NOT NULL,                -- e.g. 'server_mute', 'voice_move'
+    target_user_id TEXT REFERENCES users(id) ON DELETE SET NULL,
+    channel_id     TEXT,                         -- not a reference: outlives the channel
+    to_channel_id  TEXT,                         -- moves only
+    created_at     TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_server ON audit_log(server_id, created_at);
+
 --------------------------------------------------------------------------------
 -- Direct Message Conversations
 -- Represents a DM conversation between two users
@@ -165,7 +371,8 @@ CREATE TABLE IF NOT EXISTS dm_messages (
     edited_at       TEXT                             -- NULL until edited
 );
 
-CREATE INDEX IF NOT EXISTS idx_dm_messages_conversation ON dm_messages(conversation_id, created_at);
+DROP INDEX IF EXISTS idx_dm_messages_conversation;
+CREATE INDEX IF NOT EXISTS idx_dm_messages_conversation_order ON dm_messages(conversation_id, created_at, id);
 CREATE INDEX IF NOT EXISTS idx_dm_messages_author ON dm_messages(author_id);
 
 --------------------------------------------------------------------------------
@@ -178,10 +385,32 @@ CREATE TABLE IF NOT EXISTS dm_attachments (
     file_name  TEXT NOT NULL,
     mime_type  TEXT NOT NULL,
     size_bytes INTEGER,
+    width      INTEGER,                         -- images after EXIF rotation, videos after track rotation
+    height     INTEGER,
+    blurhash   TEXT,
+    thumbnail_url TEXT,                         -- WebP renditions; NULL if none were made
+    preview_url   TEXT,
+    duration_secs REAL,                         -- videos only
     created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
 
 CREATE INDEX IF NOT EXISTS idx_dm_attachments_message ON dm_attachments(message_id);
+CREATE INDEX IF NOT EXISTS idx_dm_attachments_file_url ON dm_attachments(file_url);
+
+--------------------------------------------------------------------------------
+-- DM Message Revisions  (earlier versions of edited DMs, kept forever)
+-- One row per edit that changed the content, holding the version it replaced.
+-- `created_at` is when that version was posted or last edited.
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS dm_message_revisions (
+    message_id  TEXT    NOT NULL REFERENCES dm_messages(id) ON DELETE CASCADE,
+    revision    INTEGER NOT NULL,               -- counts up from 1 with each edit
+    content     TEXT,
+    created_at  TEXT    NOT NULL,
+    replaced_at TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    replaced_by TEXT             REFERENCES users(id) ON DELETE SET NULL,
+    PRIMARY KEY (message_id, revision)
+);
 
 --------------------------------------------------------------------------------
 -- DM Reactions
@@ -211,3 +440,70 @@ CREATE TABLE IF NOT EXISTS user_status (
 );
 
 CREATE INDEX IF NOT EXISTS idx_user_status_status ON user_status(status);
+
+--------------------------------------------------------------------------------
+-- Read states  (last message each user has read per channel / conversation)
+-- The marker's created_at is copied so it still orders correctly after the
+-- message itself is deleted.
+--------------------------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS read_states (
+    user_id              TEXT NOT NULL REFERENCES users(id)    ON DELETE CASCADE,
+    channel_id           TEXT NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    last_read_message_id TEXT NOT NULL,
+    last_read_at         TEXT NOT NULL,              -- created_at of that message
+    updated_at           TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (user_id, channel_id)
+);
+
+CREATE TABLE IF NOT EXISTS dm_read_states (
+    user_id              TEXT NOT NULL REFERENCES users(id)            ON DELETE CASCADE,
+    conversation_id      TEXT NOT NULL REFERENCES dm_conversations(id) ON DELETE CASCADE,
+    last_read_message_id TEXT NOT NULL,
+    last_read_at         TEXT NOT NULL,
+    updated_at           TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (user_id, conversation_id)
+);
+
+--------------------------------------------------------------------------------
+-- Full-text search  (SQLite FTS5, external-content indexes)
+-- The indexes mirror messages.content / dm_messages.content by rowid and are
+-- kept in sync by the triggers below, so every insert, edit and delete
+-- (including cascades from channel or conversation deletion) is reflected.
+--------------------------------------------------------------------------------
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    content,
+    content = 'messages',
+    content_rowid = 'rowid'
+);
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+    INSERT INTO messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+
+CREATE VIRTUAL TABLE IF NOT EXISTS dm_messages_fts USING fts5(
+    content,
+    content = 'dm_messages',
+    content_rowid = 'rowid'
+);
+
+CREATE TRIGGER IF NOT EXISTS dm_messages_fts_insert AFTER INSERT ON dm_messages BEGIN
+    INSERT INTO dm_messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS dm_messages_fts_delete AFTER DELETE ON dm_messages BEGIN
+    INSERT INTO dm_messages_fts (dm_messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS dm_messages_fts_update AFTER UPDATE OF content ON dm_messages BEGIN
+    INSERT INTO dm_messages_fts (dm_messages_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+    INSERT INTO dm_messages_fts (rowid, content) VALUES (new.rowid, new.content);
+END;
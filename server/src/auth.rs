@@ -148,6 +148,16 @@ fn verify_password(password: &str, hash: &str) -> bool {
 
 // ── Auth middleware ──────────────────────────────────────────────────────
 
+/// The user id from a valid `Authorization: Bearer` header, for routes
+/// outside the auth middleware.
+pub fn bearer_user(headers: &axum::http::HeaderMap, secret: &str) -> Option<String> {
+    let token = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())?
+        .strip_prefix("Bearer ")?;
+    validate_token(token, secret).ok().map(|claims| claims.sub)
+}
+
 #[derive(Clone)]
 pub struct AuthUser {
     pub user_id: String,
@@ -2,6 +2,8 @@ use rusqlite::{params, Connection};
 use std::sync::Mutex;
 use uuid::Uuid;
 
+use crate::permissions::Permissions;
+
 pub struct Database {
     conn: Mutex<Connection>,
 }
@@ -17,8 +19,60 @@ impl Database {
 
     pub fn run_migrations(&self) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
+        let had_fts = table_exists(&conn, "messages_fts")?;
         let schema = include_str!("../../schema.sql");
         conn.execute_batch(schema)?;
+        // Databases created before full-text search existed need their
+        // indexes populated from the rows already stored.
+        if !had_fts {
+            conn.execute_batch(
+                "INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+                 INSERT INTO dm_messages_fts (dm_messages_fts) VALUES ('rebuild');",
+            )?;
+        }
+        // Columns added after the table was first released
+        add_column_if_missing(&conn, "servers", "link_previews", "INTEGER NOT NULL DEFAULT 1")?;
+        add_column_if_missing(&conn, "servers", "edit_history_days", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(
+            &conn,
+            "channels",
+            "category_id",
+            "TEXT REFERENCES channel_categories(id) ON DELETE SET NULL",
+        )?;
+        add_column_if_missing(&conn, "channels", "topic", "TEXT")?;
+        add_column_if_missing(&conn, "channels", "slowmode_secs", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "channels", "nsfw", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "channels", "voice_mode", "TEXT NOT NULL DEFAULT 'mesh'")?;
+        add_column_if_missing(&conn, "server_members", "server_muted", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "server_members", "server_deafened", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "voice_states", "session_id", "TEXT")?;
+        add_column_if_missing(&conn, "messages", "reply_to_id", "TEXT")?;
+        add_column_if_missing(
+            &conn,
+            "messages",
+            "thread_id",
+            "TEXT REFERENCES threads(id) ON DELETE CASCADE",
+        )?;
+        add_column_if_missing(&conn, "messages", "deleted_at", "TEXT")?;
+        add_column_if_missing(&conn, "messages", "deleted_by", "TEXT REFERENCES users(id) ON DELETE SET NULL")?;
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_messages_thread ON messages(thread_id, created_at, id);
+             CREATE INDEX IF NOT EXISTS idx_messages_deleted ON messages(deleted_at) WHERE deleted_at IS NOT NULL;",
+        )?;
+        for table in ["uploads", "attachments", "dm_attachments"] {
+            add_column_if_missing(&conn, table, "width", "INTEGER")?;
+            add_column_if_missing(&conn, table, "height", "INTEGER")?;
+            add_column_if_missing(&conn, table, "blurhash", "TEXT")?;
+            add_column_if_missing(&conn, table, "thumbnail_url", "TEXT")?;
+            add_column_if_missing(&conn, table, "preview_url", "TEXT")?;
+            add_column_if_missing(&conn, table, "duration_secs", "REAL")?;
+        }
+        // Servers created before roles existed get their @everyone role
+        conn.execute(
+            "INSERT OR IGNORE INTO roles (id, server_id, name, permissions)
+             SELECT id, id, '@everyone', ?1 FROM servers",
+            params![Permissions::EVERYONE_DEFAULT.bits()],
+        )?;
         Ok(())
     }
 
@@ -58,7 +112,7 @@ impl Database {
                 updated_at: row.get(8)?,
             })
         })?;
-        Ok(rows.next().transpose()?)
+        rows.next().transpose()
     }
 
     pub fn get_user_by_id(&self, id: &str) -> Result<Option<UserRow>, rusqlite::Error> {
@@ -81,7 +135,7 @@ impl Database {
                 updated_at: row.get(8)?,
             })
         })?;
-        Ok(rows.next().transpose()?)
+        rows.next().transpose()
     }
 
     pub fn update_user(
@@ -143,6 +197,11 @@ impl Database {
             "INSERT INTO server_members (user_id, server_id, role) VALUES (?1, ?2, 'owner')",
             params![owner_id, id.to_string()],
         )?;
+        // The @everyone role shares the server's id
+        conn.execute(
+            "INSERT INTO roles (id, server_id, name, permissions) VALUES (?1, ?1, '@everyone', ?2)",
+            params![id.to_string(), Permissions::EVERYONE_DEFAULT.bits()],
+        )?;
         // Create default channels
         let text_id = Uuid::new_v4();
         let voice_id = Uuid::new_v4();
@@ -160,7 +219,8 @@ impl Database {
     pub fn get_servers_for_user(&self, user_id: &str) -> Result<Vec<ServerRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT s.id, s.name, s.icon_url, s.owner_id, s.created_at, s.updated_at
+            "SELECT s.id, s.name, s.icon_url, s.owner_id, s.created_at, s.updated_at, s.link_previews,
+                    s.edit_history_days
              FROM servers s
              JOIN server_members sm ON s.id = sm.server_id
              WHERE sm.user_id = ?1
@@ -175,6 +235,8 @@ impl Database {
                     owner_id: row.get(3)?,
                     created_at: row.get(4)?,
                     updated_at: row.get(5)?,
+                    link_previews: row.get(6)?,
+                    edit_history_days: row.get(7)?,
                 })
             })?
             .collect::<Result<Vec<_>, _>>()?;
@@ -199,6 +261,20 @@ impl Database {
         Ok(())
     }
 
+    /// Distinct ids of every user that shares a server with `user_id`,
+    /// including `user_id` itself.
+    pub fn get_users_sharing_server(&self, user_id: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT other.user_id
+             FROM server_members me
+             JOIN server_members other ON other.server_id = me.server_id
+             WHERE me.user_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![user_id], |row| row.get(0))?;
+        rows.collect()
+    }
+
     pub fn get_server_members(&self, server_id: &str) -> Result<Vec<MemberRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
@@ -231,44 +307,76 @@ impl Database {
         server_id: &str,
     ) -> Result<Vec<ChannelRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, server_id, name, type, position, created_at, updated_at
-             FROM channels WHERE server_id = ?1 ORDER BY position",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "{CHANNEL_SELECT} WHERE server_id = ?1 ORDER BY position, created_at"
+        ))?;
         let rows = stmt
-            .query_map(params![server_id], |row| {
-                Ok(ChannelRow {
-                    id: row.get(0)?,
-                    server_id: row.get(1)?,
-                    name: row.get(2)?,
-                    channel_type: row.get(3)?,
-                    position: row.get(4)?,
-                    created_at: row.get(5)?,
-                    updated_at: row.get(6)?,
-                })
-            })?
+            .query_map(params![server_id], channel_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(rows)
     }
 
+    pub fn get_channel(&self, channel_id: &str) -> Result<Option<ChannelRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            &format!("{CHANNEL_SELECT} WHERE id = ?1"),
+            params![channel_id],
+            channel_from_row,
+        );
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds a channel at the end of its category, or of the uncategorised
+    /// channels when `category_id` is `None`.
     pub fn create_channel(
         &self,
         id: &Uuid,
         server_id: &str,
         name: &str,
         channel_type: &str,
+        category_id: Option<&str>,
     ) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let position: i32 = conn
             .query_row(
-                "SELECT COALESCE(MAX(position), -1) + 1 FROM channels WHERE server_id = ?1",
-                params![server_id],
+                "SELECT COALESCE(MAX(position), -1) + 1 FROM channels
+                 WHERE server_id = ?1 AND category_id IS ?2",
+                params![server_id, category_id],
                 |row| row.get(0),
             )
             .unwrap_or(0);
         conn.execute(
-            "INSERT INTO channels (id, server_id, name, type, position) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![id.to_string(), server_id, name, channel_type, position],
+            "INSERT INTO channels (id, server_id, name, type, position, category_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.to_string(), server_id, name, channel_type, position, category_id],
+        )?;
+        Ok(())
+    }
+
+    /// Updates the given settings; an empty `topic` clears it.
+    pub fn update_channel(
+        &self,
+        channel_id: &str,
+        name: Option<&str>,
+        topic: Option<&str>,
+        slowmode_secs: Option<i64>,
+        nsfw: Option<bool>,
+        voice_mode: Option<&str>,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE channels SET name = COALESCE(?2, name),
+                    topic = CASE WHEN ?3 IS NULL THEN topic ELSE NULLIF(?3, '') END,
+                    slowmode_secs = COALESCE(?4, slowmode_secs),
+                    nsfw = COALESCE(?5, nsfw),
+                    voice_mode = COALESCE(?6, voice_mode),
+                    updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+             WHERE id = ?1",
+            params![channel_id, name, topic, slowmode_secs, nsfw, voice_mode],
         )?;
         Ok(())
     }
@@ -279,94 +387,193 @@ impl Database {
         Ok(())
     }
 
+    /// Moves channels and categories of one server in a single transaction.
+    /// `channels` are `(id, category_id, position)` and `categories` are
+    /// `(id, position)`. Nothing changes and `false` is returned when any id
+    /// is not in the server.
+    pub fn reorder_channels(
+        &self,
+        server_id: &str,
+        channels: &[(String, Option<String>, i32)],
+        categories: &[(String, i32)],
+    ) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (id, position) in categories {
+            let updated = tx.execute(
+                "UPDATE channel_categories SET position = ?3 WHERE id = ?1 AND server_id = ?2",
+                params![id, server_id, position],
+            )?;
+            if updated == 0 {
+                return Ok(false);
+            }
+        }
+        for (id, category_id, position) in channels {
+            if let Some(category_id) = category_id {
+                let in_server: bool = tx.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM channel_categories WHERE id = ?1 AND server_id = ?2)",
+                    params![category_id, server_id],
+                    |row| row.get(0),
+                )?;
+                if !in_server {
+                    return Ok(false);
+                }
+            }
+            let updated = tx.execute(
+                "UPDATE channels SET category_id = ?3, position = ?4,
+                        updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                 WHERE id = ?1 AND server_id = ?2",
+                params![id, server_id, category_id, position],
+            )?;
+            if updated == 0 {
+                return Ok(false);
+            }
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Seconds since the user last posted in the channel, threads included.
+    pub fn seconds_since_last_message(
+        &self,
+        channel_id: &str,
+        author_id: &str,
+    ) -> Result<Option<f64>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT (julianday('now') - julianday(MAX(created_at))) * 86400
+             FROM messages WHERE channel_id = ?1 AND author_id = ?2",
+            params![channel_id, author_id],
+            |row| row.get(0),
+        )
+    }
+
+    // ── Channel category queries ─────────────────────────────────────────
+
+    pub fn get_categories_for_server(
+        &self,
+        server_id: &str,
+    ) -> Result<Vec<ChannelCategoryRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "{CATEGORY_SELECT} WHERE server_id = ?1 ORDER BY position, created_at"
+        ))?;
+        let rows = stmt
+            .query_map(params![server_id], category_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn get_category(&self, category_id: &str) -> Result<Option<ChannelCategoryRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            &format!("{CATEGORY_SELECT} WHERE id = ?1"),
+            params![category_id],
+            category_from_row,
+        );
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds a category below the server's existing ones.
+    pub fn create_category(&self, id: &Uuid, server_id: &str, name: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO channel_categories (id, server_id, name, position)
+             SELECT ?1, ?2, ?3, COALESCE(MAX(position), -1) + 1
+             FROM channel_categories WHERE server_id = ?2",
+            params![id.to_string(), server_id, name],
+        )?;
+        Ok(())
+    }
+
+    pub fn rename_category(&self, category_id: &str, name: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE channel_categories SET name = ?2 WHERE id = ?1",
+            params![category_id, name],
+        )?;
+        Ok(())
+    }
+
+    /// Deletes a category; its channels become uncategorised.
+    pub fn delete_category(&self, category_id: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM channel_categories WHERE id = ?1", params![category_id])?;
+        Ok(())
+    }
+
     // ── Message queries ──────────────────────────────────────────────────
 
+    /// Inserts a message and turns `upload_ids` into its attachments; a
+    /// message posted into a thread also bumps the thread's reply count and
+    /// last activity. Writes nothing if any upload is not a pending upload
+    /// of the author, or if the attachments would take the channel's server
+    /// past `server_quota_bytes`; the quota is checked in the same
+    /// transaction so concurrent posts cannot overshoot it.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_message(
         &self,
         id: &Uuid,
         channel_id: &str,
         author_id: &str,
         content: Option<&str>,
-    ) -> Result<MessageRow, rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO messages (id, channel_id, author_id, content) VALUES (?1, ?2, ?3, ?4)",
-            params![id.to_string(), channel_id, author_id, content],
+        reply_to_id: Option<&str>,
+        thread_id: Option<&str>,
+        upload_ids: &[String],
+        server_quota_bytes: u64,
+    ) -> Result<Result<MessageRow, MessageRejected>, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO messages (id, channel_id, author_id, content, reply_to_id, thread_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.to_string(), channel_id, author_id, content, reply_to_id, thread_id],
         )?;
-        let mut stmt = conn.prepare(
-            "SELECT m.id, m.channel_id, m.author_id, m.content, m.pinned, m.created_at, m.edited_at,
-                    u.username, u.avatar_url
-             FROM messages m JOIN users u ON m.author_id = u.id
-             WHERE m.id = ?1",
+        if !claim_uploads(&tx, "attachments", &id.to_string(), author_id, upload_ids)? {
+            return Ok(Err(MessageRejected::InvalidAttachment));
+        }
+        if !upload_ids.is_empty() && channel_server_storage_bytes(&tx, channel_id)? > server_quota_bytes {
+            return Ok(Err(MessageRejected::ServerQuotaExceeded));
+        }
+        let row = tx.query_row(
+            &format!("{MESSAGE_SELECT} WHERE m.id = ?1"),
+            params![id.to_string()],
+            message_from_row,
         )?;
-        stmt.query_row(params![id.to_string()], |row| {
-            Ok(MessageRow {
-                id: row.get(0)?,
-                channel_id: row.get(1)?,
-                author_id: row.get(2)?,
-                content: row.get(3)?,
-                pinned: row.get::<_, i32>(4)? != 0,
-                created_at: row.get(5)?,
-                edited_at: row.get(6)?,
-                author_username: row.get(7)?,
-                author_avatar_url: row.get(8)?,
-            })
-        })
+        if let Some(thread_id) = thread_id {
+            tx.execute(
+                "UPDATE threads SET message_count = message_count + 1, last_message_at = ?2
+                 WHERE id = ?1",
+                params![thread_id, row.created_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(Ok(row))
     }
 
+    /// One page of a channel's history, oldest first, ordered by
+    /// `(created_at, id)`. Thread replies are left out.
     pub fn get_messages(
         &self,
         channel_id: &str,
         limit: i32,
-        before: Option<&str>,
+        cursor: MessageCursor<'_>,
     ) -> Result<Vec<MessageRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let (query, p): (String, Vec<Box<dyn rusqlite::types::ToSql>>) = if let Some(before_ts) =
-            before
-        {
-            (
-                "SELECT m.id, m.channel_id, m.author_id, m.content, m.pinned, m.created_at, m.edited_at,
-                        u.username, u.avatar_url
-                 FROM messages m JOIN users u ON m.author_id = u.id
-                 WHERE m.channel_id = ?1 AND m.created_at < ?2
-                 ORDER BY m.created_at DESC LIMIT ?3".to_string(),
-                vec![
-                    Box::new(channel_id.to_string()) as Box<dyn rusqlite::types::ToSql>,
-                    Box::new(before_ts.to_string()),
-                    Box::new(limit),
-                ],
-            )
-        } else {
-            (
-                "SELECT m.id, m.channel_id, m.author_id, m.content, m.pinned, m.created_at, m.edited_at,
-                        u.username, u.avatar_url
-                 FROM messages m JOIN users u ON m.author_id = u.id
-                 WHERE m.channel_id = ?1
-                 ORDER BY m.created_at DESC LIMIT ?2".to_string(),
-                vec![
-                    Box::new(channel_id.to_string()) as Box<dyn rusqlite::types::ToSql>,
-                    Box::new(limit),
-                ],
-            )
-        };
-        let mut stmt = conn.prepare(&query)?;
-        let params_ref: Vec<&dyn rusqlite::types::ToSql> = p.iter().map(|b| b.as_ref()).collect();
-        let rows = stmt
-            .query_map(params_ref.as_slice(), |row| {
-                Ok(MessageRow {
-                    id: row.get(0)?,
-                    channel_id: row.get(1)?,
-                    author_id: row.get(2)?,
-                    content: row.get(3)?,
-                    pinned: row.get::<_, i32>(4)? != 0,
-                    created_at: row.get(5)?,
-                    edited_at: row.get(6)?,
-                    author_username: row.get(7)?,
-                    author_avatar_url: row.get(8)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(rows)
+        page_by_cursor(
+            &conn,
+            &format!("{MESSAGE_SELECT} WHERE m.channel_id = ?1 AND m.thread_id IS NULL"),
+            "m",
+            "messages",
+            channel_id,
+            limit,
+            cursor,
+            message_from_row,
+        )
     }
 
     pub fn get_pinned_messages(
@@ -374,76 +581,282 @@ impl Database {
         channel_id: &str,
     ) -> Result<Vec<MessageRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT m.id, m.channel_id, m.author_id, m.content, m.pinned, m.created_at, m.edited_at,
-                    u.username, u.avatar_url
-             FROM messages m JOIN users u ON m.author_id = u.id
-             WHERE m.channel_id = ?1 AND m.pinned = 1
-             ORDER BY m.created_at DESC",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "{MESSAGE_SELECT} WHERE m.channel_id = ?1 AND m.pinned = 1 AND m.deleted_at IS NULL
+             ORDER BY m.created_at DESC"
+        ))?;
         let rows = stmt
-            .query_map(params![channel_id], |row| {
-                Ok(MessageRow {
-                    id: row.get(0)?,
-                    channel_id: row.get(1)?,
-                    author_id: row.get(2)?,
-                    content: row.get(3)?,
-                    pinned: row.get::<_, i32>(4)? != 0,
-                    created_at: row.get(5)?,
-                    edited_at: row.get(6)?,
-                    author_username: row.get(7)?,
-                    author_avatar_url: row.get(8)?,
-                })
-            })?
+            .query_map(params![channel_id], message_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(rows)
     }
 
-    pub fn edit_message(&self, message_id: &str, content: &str) -> Result<(), rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
+    /// Replaces a message's content, keeping the previous version as a
+    /// revision if it changed.
+    pub fn edit_message(&self, message_id: &str, editor_id: &str, content: &str) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        record_revision(&tx, "messages", "message_revisions", message_id, editor_id, content)?;
+        tx.execute(
             "UPDATE messages SET content = ?1, edited_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = ?2",
             params![content, message_id],
         )?;
-        Ok(())
+        tx.commit()
     }
 
-    pub fn delete_message(&self, message_id: &str) -> Result<(), rusqlite::Error> {
+    /// Earlier versions of a message, oldest first. With `retention_days`
+    /// above zero, versions replaced longer ago than that are left out.
+    pub fn get_message_revisions(
+        &self,
+        message_id: &str,
+        retention_days: i64,
+    ) -> Result<Vec<RevisionRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM messages WHERE id = ?1", params![message_id])?;
-        Ok(())
+        get_revisions(&conn, "message_revisions", message_id, retention_days)
     }
 
-    pub fn pin_message(&self, message_id: &str, pinned: bool) -> Result<(), rusqlite::Error> {
+    /// Deletes revisions replaced longer ago than their server's
+    /// `edit_history_days`.
+    pub fn prune_message_revisions(&self) -> Result<usize, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "UPDATE messages SET pinned = ?1 WHERE id = ?2",
+            "DELETE FROM message_revisions
+             WHERE (message_id, revision) IN (
+                 SELECT r.message_id, r.revision
+                 FROM message_revisions r
+                 JOIN messages m ON m.id = r.message_id
+                 JOIN channels c ON c.id = m.channel_id
+                 JOIN servers s ON s.id = c.server_id
+                 WHERE s.edit_history_days > 0
+                   AND r.replaced_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-' || s.edit_history_days || ' days')
+             )",
+            [],
+        )
+    }
+
+    /// Turns a message into a tombstone. Its rows stay until
+    /// [`Database::purge_deleted_messages`] so moderators can still see it
+    /// and the author can restore it. Returns `false` if it was already
+    /// deleted or never existed.
+    pub fn delete_message(&self, message_id: &str, deleted_by: &str) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let changed = tx.execute(
+            "UPDATE messages SET deleted_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), deleted_by = ?2
+             WHERE id = ?1 AND deleted_at IS NULL",
+            params![message_id, deleted_by],
+        )?;
+        refresh_thread_counts(&tx, message_id)?;
+        tx.commit()?;
+        Ok(changed > 0)
+    }
+
+    /// Brings back a tombstone deleted less than `window_secs` ago.
+    /// Returns `false` if it is not a tombstone or the window has passed.
+    pub fn restore_message(&self, message_id: &str, window_secs: u64) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let changed = tx.execute(
+            "UPDATE messages SET deleted_at = NULL, deleted_by = NULL
+             WHERE id = ?1
+               AND deleted_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-' || ?2 || ' seconds')",
+            params![message_id, window_secs as i64],
+        )?;
+        refresh_thread_counts(&tx, message_id)?;
+        tx.commit()?;
+        Ok(changed > 0)
+    }
+
+    /// Removes tombstones deleted more than `max_age_secs` ago, with their
+    /// reactions, embeds, revisions and attachments. Returns how many were
+    /// removed and the URLs of the attachment files to remove from
+    /// storage. A deleted thread root is kept while its thread still has
+    /// replies.
+    pub fn purge_deleted_messages(&self, max_age_secs: u64) -> Result<(usize, Vec<String>), rusqlite::Error> {
+        const EXPIRED: &str = "SELECT m.id FROM messages m
+             WHERE m.deleted_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-' || ?1 || ' seconds')
+               AND NOT EXISTS (
+                   SELECT 1 FROM threads t JOIN messages r ON r.thread_id = t.id
+                   WHERE t.root_message_id = m.id AND r.deleted_at IS NULL
+               )";
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let max_age = max_age_secs as i64;
+        // Replies in a purged root's thread go with it
+        let files = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT a.file_url, a.thumbnail_url, a.preview_url
+                 FROM attachments a JOIN messages m ON m.id = a.message_id
+                 WHERE m.id IN ({EXPIRED})
+                    OR m.thread_id IN (SELECT t.id FROM threads t WHERE t.root_message_id IN ({EXPIRED}))"
+            ))?;
+            let rows = stmt
+                .query_map(params![max_age], |row| Ok([row.get(0)?, row.get(1)?, row.get(2)?]))?
+                .collect::<Result<Vec<[Option<String>; 3]>, _>>()?;
+            rows
+        };
+        let purged = tx.execute(&format!("DELETE FROM messages WHERE id IN ({EXPIRED})"), params![max_age])?;
+        tx.commit()?;
+        Ok((purged, files.into_iter().flatten().flatten().collect()))
+    }
+
+    /// Returns `false` if the message is missing or a tombstone.
+    pub fn pin_message(&self, message_id: &str, pinned: bool) -> Result<bool, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE messages SET pinned = ?1 WHERE id = ?2 AND deleted_at IS NULL",
             params![pinned as i32, message_id],
         )?;
-        Ok(())
+        Ok(changed > 0)
     }
 
+    /// The channel of a message. Tombstones count as missing, so checks
+    /// through `Target::Message` treat them as not found.
     pub fn get_message_channel(&self, message_id: &str) -> Result<Option<String>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT channel_id FROM messages WHERE id = ?1")?;
+        let mut stmt = conn.prepare("SELECT channel_id FROM messages WHERE id = ?1 AND deleted_at IS NULL")?;
         let mut rows = stmt.query_map(params![message_id], |row| row.get::<_, String>(0))?;
-        Ok(rows.next().transpose()?)
+        rows.next().transpose()
+    }
+
+    /// Returns `(author_id, channel_id)` for a channel message that has not
+    /// been deleted.
+    pub fn get_message_info(&self, message_id: &str) -> Result<Option<(String, String)>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT author_id, channel_id FROM messages WHERE id = ?1 AND deleted_at IS NULL",
+            params![message_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        );
+        match result {
+            Ok(info) => Ok(Some(info)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_message(&self, message_id: &str) -> Result<Option<MessageRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{MESSAGE_SELECT} WHERE m.id = ?1"))?;
+        let mut rows = stmt.query_map(params![message_id], message_from_row)?;
+        rows.next().transpose()
+    }
+
+    // ── Thread queries ───────────────────────────────────────────────────
+
+    pub fn create_thread(
+        &self,
+        id: &Uuid,
+        channel_id: &str,
+        root_message_id: &str,
+        creator_id: &str,
+        name: &str,
+    ) -> Result<ThreadRow, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO threads (id, channel_id, root_message_id, creator_id, name)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id.to_string(), channel_id, root_message_id, creator_id, name],
+        )?;
+        conn.query_row(
+            &format!("{THREAD_SELECT} WHERE id = ?1"),
+            params![id.to_string()],
+            thread_from_row,
+        )
+    }
+
+    pub fn get_thread(&self, thread_id: &str) -> Result<Option<ThreadRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{THREAD_SELECT} WHERE id = ?1"))?;
+        let mut rows = stmt.query_map(params![thread_id], thread_from_row)?;
+        rows.next().transpose()
+    }
+
+    pub fn get_thread_for_root(&self, root_message_id: &str) -> Result<Option<ThreadRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{THREAD_SELECT} WHERE root_message_id = ?1"))?;
+        let mut rows = stmt.query_map(params![root_message_id], thread_from_row)?;
+        rows.next().transpose()
+    }
+
+    /// Threads in a channel, most recently active first.
+    pub fn get_threads_for_channel(&self, channel_id: &str) -> Result<Vec<ThreadRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "{THREAD_SELECT} WHERE channel_id = ?1
+             ORDER BY COALESCE(last_message_at, created_at) DESC, id DESC"
+        ))?;
+        let rows = stmt.query_map(params![channel_id], thread_from_row)?;
+        rows.collect()
+    }
+
+    /// One page of a thread's replies, oldest first, ordered by
+    /// `(created_at, id)`.
+    pub fn get_thread_messages(
+        &self,
+        thread_id: &str,
+        limit: i32,
+        cursor: MessageCursor<'_>,
+    ) -> Result<Vec<MessageRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        page_by_cursor(
+            &conn,
+            &format!("{MESSAGE_SELECT} WHERE m.thread_id = ?1"),
+            "m",
+            "messages",
+            thread_id,
+            limit,
+            cursor,
+            message_from_row,
+        )
+    }
+
+    /// `(user_id, username, avatar_url)` for the thread's creator and
+    /// everyone who has replied, in order of first participation.
+    pub fn get_thread_participants(
+        &self,
+        thread_id: &str,
+    ) -> Result<Vec<(String, String, Option<String>)>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT u.id, u.username, u.avatar_url
+             FROM users u
+             JOIN (
+                 SELECT creator_id AS user_id, created_at AS first_at FROM threads WHERE id = ?1
+                 UNION ALL
+                 SELECT author_id, MIN(created_at) FROM messages
+                 WHERE thread_id = ?1 AND deleted_at IS NULL GROUP BY author_id
+             ) p ON p.user_id = u.id
+             GROUP BY u.id
+             ORDER BY MIN(p.first_at), u.id",
+        )?;
+        let rows = stmt.query_map(params![thread_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        rows.collect()
     }
 
     // ── Reaction queries ─────────────────────────────────────────────────
 
+    /// Returns `false`, adding nothing, if the message is missing or a
+    /// tombstone.
     pub fn add_reaction(
         &self,
         message_id: &str,
         user_id: &str,
         emoji: &str,
-    ) -> Result<(), rusqlite::Error> {
+    ) -> Result<bool, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT OR IGNORE INTO reactions (message_id, user_id, emoji) VALUES (?1, ?2, ?3)",
+            "INSERT OR IGNORE INTO reactions (message_id, user_id, emoji)
+             SELECT id, ?2, ?3 FROM messages WHERE id = ?1 AND deleted_at IS NULL",
             params![message_id, user_id, emoji],
         )?;
-        Ok(())
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE id = ?1 AND deleted_at IS NULL)",
+            params![message_id],
+            |row| row.get(0),
+        )
     }
 
     pub fn remove_reaction(
@@ -484,78 +897,326 @@ impl Database {
         Ok(rows)
     }
 
-    // ── Attachment queries ───────────────────────────────────────────────
+    // ── Upload queries ───────────────────────────────────────────────────
 
-    pub fn create_attachment(
+    /// Records a pending upload unless it would take the owner past
+    /// `quota_bytes`, checked in the same transaction so concurrent uploads
+    /// cannot overshoot. Returns `false`, writing nothing, if over quota.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_upload(
         &self,
         id: &Uuid,
-        message_id: &str,
+        owner_id: &str,
         file_url: &str,
         file_name: &str,
         mime_type: &str,
-        size_bytes: Option<i64>,
-    ) -> Result<(), rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO attachments (id, message_id, file_url, file_name, mime_type, size_bytes)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        size_bytes: i64,
+        media: &MediaInfo,
+        quota_bytes: u64,
+    ) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if user_storage_bytes(&tx, owner_id)? + size_bytes.max(0) as u64 > quota_bytes {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO uploads (id, owner_id, file_url, file_name, mime_type, size_bytes,
+                                  width, height, blurhash, thumbnail_url, preview_url, duration_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
             params![
                 id.to_string(),
-                message_id,
+                owner_id,
                 file_url,
                 file_name,
                 mime_type,
-                size_bytes
+                size_bytes,
+                media.width,
+                media.height,
+                media.blurhash,
+                media.thumbnail_url,
+                media.preview_url,
+                media.duration_secs
             ],
         )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Marks the caller's pending upload at `file_url` as used elsewhere,
+    /// e.g. as an avatar, so it is no longer collected. Other URLs are
+    /// left alone.
+    pub fn keep_upload(&self, owner_id: &str, file_url: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM uploads WHERE owner_id = ?1 AND file_url = ?2",
+            params![owner_id, file_url],
+        )?;
         Ok(())
     }
 
-    pub fn get_attachments_for_message(
-        &self,
-        message_id: &str,
-    ) -> Result<Vec<AttachmentRow>, rusqlite::Error> {
+    /// Drops pending uploads created more than `max_age_secs` ago and
+    /// returns their file URLs, including thumbnails and previews, so the
+    /// files can be removed.
+    pub fn delete_expired_uploads(&self, max_age_secs: u64) -> Result<Vec<String>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, message_id, file_url, file_name, mime_type, size_bytes, created_at
-             FROM attachments WHERE message_id = ?1",
+            "DELETE FROM uploads
+             WHERE created_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-' || ?1 || ' seconds')
+             RETURNING file_url, thumbnail_url, preview_url",
         )?;
         let rows = stmt
-            .query_map(params![message_id], |row| {
-                Ok(AttachmentRow {
-                    id: row.get(0)?,
-                    message_id: row.get(1)?,
-                    file_url: row.get(2)?,
-                    file_name: row.get(3)?,
-                    mime_type: row.get(4)?,
-                    size_bytes: row.get(5)?,
-                    created_at: row.get(6)?,
-                })
+            .query_map(params![max_age_secs as i64], |row| {
+                Ok([row.get(0)?, row.get(1)?, row.get(2)?])
             })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(rows)
+            .collect::<Result<Vec<[Option<String>; 3]>, _>>()?;
+        Ok(rows.into_iter().flatten().flatten().collect())
     }
 
-    // ── Voice state queries ──────────────────────────────────────────────
+    /// Bytes a user currently stores: pending uploads plus attachments on
+    /// their channel and DM messages.
+    pub fn get_user_storage_bytes(&self, user_id: &str) -> Result<u64, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        user_storage_bytes(&conn, user_id)
+    }
 
-    pub fn join_voice_channel(
-        &self,
-        user_id: &str,
-        channel_id: &str,
+    /// Looks up what a stored file at `file_url` belongs to. Attachments
+    /// win over avatar and icon references so a URL cannot be made public
+    /// by pointing an avatar at it.
+    pub fn find_stored_file(&self, file_url: &str) -> Result<Option<StoredFile>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        if let Some(file) = lookup_stored_file(&conn, "file_url", file_url)? {
+            return Ok(Some(file));
+        }
+
+        let public: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE avatar_url = ?1)
+                 OR EXISTS (SELECT 1 FROM servers WHERE icon_url = ?1)",
+            params![file_url],
+            |row| row.get(0),
+        )?;
+        Ok(public.then_some(StoredFile {
+            access: FileAccess::Public,
+            file_name: None,
+            mime_type: None,
+        }))
+    }
+
+    /// Looks up the upload or attachment with the given id, which owns the
+    /// thumbnail and preview named after it.
+    pub fn find_stored_file_by_id(&self, id: &str) -> Result<Option<StoredFile>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        lookup_stored_file(&conn, "id", id)
+    }
+
+    // ── Attachment queries ───────────────────────────────────────────────
+
+    pub fn create_attachment(
+        &self,
+        id: &Uuid,
+        message_id: &str,
+        file_url: &str,
+        file_name: &str,
+        mime_type: &str,
+        size_bytes: Option<i64>,
     ) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        // Remove from any existing voice channel first
         conn.execute(
-            "DELETE FROM voice_states WHERE user_id = ?1",
-            params![user_id],
+            "INSERT INTO attachments (id, message_id, file_url, file_name, mime_type, size_bytes)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id.to_string(),
+                message_id,
+                file_url,
+                file_name,
+                mime_type,
+                size_bytes
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_attachments_for_message(
+        &self,
+        message_id: &str,
+    ) -> Result<Vec<AttachmentRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, message_id, file_url, file_name, mime_type, size_bytes, created_at,
+                    width, height, blurhash, thumbnail_url, preview_url, duration_secs
+             FROM attachments WHERE message_id = ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![message_id], |row| {
+                Ok(AttachmentRow {
+                    id: row.get(0)?,
+                    message_id: row.get(1)?,
+                    file_url: row.get(2)?,
+                    file_name: row.get(3)?,
+                    mime_type: row.get(4)?,
+                    size_bytes: row.get(5)?,
+                    created_at: row.get(6)?,
+                    media: MediaInfo {
+                        width: row.get(7)?,
+                        height: row.get(8)?,
+                        blurhash: row.get(9)?,
+                        thumbnail_url: row.get(10)?,
+                        preview_url: row.get(11)?,
+                        duration_secs: row.get(12)?,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    // ── Embed queries ────────────────────────────────────────────────────
+
+    pub fn get_embeds_for_message(&self, message_id: &str) -> Result<Vec<EmbedRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT url, title, description, site_name, image_url, large_image
+             FROM message_embeds WHERE message_id = ?1 ORDER BY position",
+        )?;
+        let rows = stmt
+            .query_map(params![message_id], embed_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Replaces a message's embeds. Returns `false` if the message no
+    /// longer exists or was deleted.
+    pub fn set_message_embeds(&self, message_id: &str, embeds: &[EmbedRow]) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE id = ?1 AND deleted_at IS NULL)",
+            params![message_id],
+            |row| row.get(0),
         )?;
+        if !exists {
+            return Ok(false);
+        }
+        tx.execute("DELETE FROM message_embeds WHERE message_id = ?1", params![message_id])?;
+        for (position, e) in embeds.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO message_embeds
+                    (message_id, position, url, title, description, site_name, image_url, large_image)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    message_id,
+                    position as i64,
+                    e.url,
+                    e.title,
+                    e.description,
+                    e.site_name,
+                    e.image_url,
+                    e.large_image
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// The cached preview of `url` if it was fetched within `max_age_secs`,
+    /// or within `miss_max_age_secs` when the page had no preview. The
+    /// inner `None` is a cached miss.
+    pub fn get_link_preview(
+        &self,
+        url: &str,
+        max_age_secs: u64,
+        miss_max_age_secs: u64,
+    ) -> Result<Option<Option<EmbedRow>>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT found, url, title, description, site_name, image_url, large_image
+             FROM link_previews
+             WHERE url = ?1
+               AND fetched_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now',
+                                         '-' || CASE WHEN found THEN ?2 ELSE ?3 END || ' seconds')",
+            params![url, max_age_secs as i64, miss_max_age_secs as i64],
+            |row| {
+                let found: bool = row.get(0)?;
+                Ok(if found {
+                    Some(EmbedRow {
+                        url: row.get(1)?,
+                        title: row.get(2)?,
+                        description: row.get(3)?,
+                        site_name: row.get(4)?,
+                        image_url: row.get(5)?,
+                        large_image: row.get(6)?,
+                    })
+                } else {
+                    None
+                })
+            },
+        );
+        match result {
+            Ok(preview) => Ok(Some(preview)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Caches what was fetched for `url`; `None` records a miss.
+    pub fn store_link_preview(&self, url: &str, preview: Option<&EmbedRow>) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT INTO voice_states (user_id, channel_id) VALUES (?1, ?2)",
-            params![user_id, channel_id],
+            "INSERT OR REPLACE INTO link_previews
+                (url, found, title, description, site_name, image_url, large_image)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                url,
+                preview.is_some(),
+                preview.and_then(|p| p.title.as_deref()),
+                preview.and_then(|p| p.description.as_deref()),
+                preview.and_then(|p| p.site_name.as_deref()),
+                preview.and_then(|p| p.image_url.as_deref()),
+                preview.is_some_and(|p| p.large_image)
+            ],
         )?;
         Ok(())
     }
 
+    /// Drops cache entries older than `max_age_secs`.
+    pub fn delete_stale_link_previews(&self, max_age_secs: u64) -> Result<usize, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM link_previews
+             WHERE fetched_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-' || ?1 || ' seconds')",
+            params![max_age_secs as i64],
+        )
+    }
+
+    // ── Voice state queries ──────────────────────────────────────────────
+
+    /// Puts the user in `channel_id`, owned by the WebSocket session that
+    /// asked; only that session disconnecting takes them out again. Returns
+    /// the channel the user was in before, if any.
+    pub fn join_voice_channel(
+        &self,
+        user_id: &str,
+        channel_id: &str,
+        session_id: &str,
+    ) -> Result<Option<String>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        // Remove from any existing voice channel first
+        let previous = match conn.query_row(
+            "DELETE FROM voice_states WHERE user_id = ?1 RETURNING channel_id",
+            params![user_id],
+            |row| row.get::<_, String>(0),
+        ) {
+            Ok(channel_id) => Some(channel_id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e),
+        };
+        conn.execute(
+            "INSERT INTO voice_states (user_id, channel_id, session_id) VALUES (?1, ?2, ?3)",
+            params![user_id, channel_id, session_id],
+        )?;
+        Ok(previous)
+    }
+
     pub fn leave_voice_channel(&self, user_id: &str) -> Result<Option<String>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let channel_id: Option<String> = conn
@@ -572,6 +1233,33 @@ impl Database {
         Ok(channel_id)
     }
 
+    /// Removes the user's voice state if `session_id` joined it, returning
+    /// the channel they were in.
+    pub fn leave_voice_session(
+        &self,
+        user_id: &str,
+        session_id: &str,
+    ) -> Result<Option<String>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "DELETE FROM voice_states WHERE user_id = ?1 AND session_id = ?2 RETURNING channel_id",
+            params![user_id, session_id],
+            |row| row.get::<_, String>(0),
+        );
+        match result {
+            Ok(channel_id) => Ok(Some(channel_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Drops every voice state. Their sessions did not survive a restart,
+    /// so run once at startup. Returns how many were removed.
+    pub fn clear_voice_states(&self) -> Result<usize, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM voice_states", [])
+    }
+
     pub fn update_voice_state(
         &self,
         user_id: &str,
@@ -586,65 +1274,885 @@ impl Database {
         Ok(())
     }
 
-    pub fn get_voice_states_for_channel(
+    pub fn get_voice_states_for_channel(
+        &self,
+        channel_id: &str,
+    ) -> Result<Vec<VoiceStateRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{VOICE_STATE_SELECT} WHERE vs.channel_id = ?1"))?;
+        let rows = stmt
+            .query_map(params![channel_id], voice_state_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn get_user_voice_state(&self, user_id: &str) -> Result<Option<VoiceStateRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            &format!("{VOICE_STATE_SELECT} WHERE vs.user_id = ?1"),
+            params![user_id],
+            voice_state_from_row,
+        );
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Moves a user who is in voice to `channel_id`, keeping the session
+    /// and self flags. Returns `false` if they were not in voice.
+    pub fn move_voice_state(&self, user_id: &str, channel_id: &str) -> Result<bool, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE voice_states SET channel_id = ?2 WHERE user_id = ?1",
+            params![user_id, channel_id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Sets a member's server mute and deafen; `None` leaves a flag as it is.
+    pub fn set_member_voice_flags(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        server_muted: Option<bool>,
+        server_deafened: Option<bool>,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE server_members
+             SET server_muted = COALESCE(?3, server_muted),
+                 server_deafened = COALESCE(?4, server_deafened)
+             WHERE server_id = ?1 AND user_id = ?2",
+            params![server_id, user_id, server_muted, server_deafened],
+        )?;
+        Ok(())
+    }
+
+    /// A member's `(server_muted, server_deafened)`, or `None` if they are
+    /// not in the server.
+    pub fn get_member_voice_flags(
+        &self,
+        server_id: &str,
+        user_id: &str,
+    ) -> Result<Option<(bool, bool)>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT server_muted, server_deafened FROM server_members WHERE server_id = ?1 AND user_id = ?2",
+            params![server_id, user_id],
+            |row| Ok((row.get::<_, i32>(0)? != 0, row.get::<_, i32>(1)? != 0)),
+        );
+        match result {
+            Ok(flags) => Ok(Some(flags)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_user_voice_channel(&self, user_id: &str) -> Result<Option<String>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT channel_id FROM voice_states WHERE user_id = ?1",
+            params![user_id],
+            |row| row.get::<_, String>(0),
+        );
+        match result {
+            Ok(channel_id) => Ok(Some(channel_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // ── Audit log queries ────────────────────────────────────────────────
+
+    pub fn add_audit_log_entry(
+        &self,
+        server_id: &str,
+        actor_id: &str,
+        action: &str,
+        target_user_id: &str,
+        channel_id: Option<&str>,
+        to_channel_id: Option<&str>,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO audit_log (id, server_id, actor_id, action, target_user_id, channel_id, to_channel_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                Uuid::new_v4().to_string(),
+                server_id,
+                actor_id,
+                action,
+                target_user_id,
+                channel_id,
+                to_channel_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Newest first, up to `limit` entries older than the `before` entry.
+    pub fn get_audit_log(
+        &self,
+        server_id: &str,
+        before: Option<&str>,
+        limit: i32,
+    ) -> Result<Vec<AuditLogRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, server_id, actor_id, action, target_user_id, channel_id, to_channel_id, created_at
+             FROM audit_log
+             WHERE server_id = ?1
+               AND (?2 IS NULL OR rowid < (SELECT rowid FROM audit_log WHERE id = ?2))
+             ORDER BY rowid DESC
+             LIMIT ?3",
+        )?;
+        let rows = stmt
+            .query_map(params![server_id, before, limit], |row| {
+                Ok(AuditLogRow {
+                    id: row.get(0)?,
+                    server_id: row.get(1)?,
+                    actor_id: row.get(2)?,
+                    action: row.get(3)?,
+                    target_user_id: row.get(4)?,
+                    channel_id: row.get(5)?,
+                    to_channel_id: row.get(6)?,
+                    created_at: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    // ── Mention queries ──────────────────────────────────────────────────
+
+    pub fn create_mention(
+        &self,
+        id: &Uuid,
+        message_id: &str,
+        mention_type: &str,
+        target_id: Option<&str>,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO mentions (id, message_id, type, target_id) VALUES (?1, ?2, ?3, ?4)",
+            params![id.to_string(), message_id, mention_type, target_id],
+        )?;
+        Ok(())
+    }
+
+    /// Adds the message to each user's mentions inbox.
+    pub fn add_mention_recipients(&self, message_id: &str, user_ids: &[String]) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR IGNORE INTO user_mentions (user_id, message_id) VALUES (?1, ?2)",
+            )?;
+            for user_id in user_ids {
+                stmt.execute(params![user_id, message_id])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// A page of the user's mentions inbox, newest first. Mentions in
+    /// servers the user has since left are skipped. `before` is a message id.
+    pub fn get_mentions_for_user(
+        &self,
+        user_id: &str,
+        limit: i32,
+        before: Option<&str>,
+        unread_only: bool,
+    ) -> Result<Vec<MentionRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT m.id, m.channel_id, m.author_id, m.content, m.pinned, m.created_at, m.edited_at,
+                    u.username, u.avatar_url, m.reply_to_id, m.thread_id, m.deleted_at, m.deleted_by,
+                    c.server_id, um.read_at
+             FROM user_mentions um
+             JOIN messages m ON m.id = um.message_id
+             JOIN users u ON u.id = m.author_id
+             JOIN channels c ON c.id = m.channel_id
+             JOIN server_members sm ON sm.server_id = c.server_id AND sm.user_id = um.user_id
+             WHERE um.user_id = ?1 AND m.deleted_at IS NULL
+               AND (?3 IS NULL OR (um.created_at, um.message_id) <
+                    (SELECT created_at, message_id FROM user_mentions WHERE user_id = ?1 AND message_id = ?3))
+               AND (?4 = 0 OR um.read_at IS NULL)
+             ORDER BY um.created_at DESC, um.message_id DESC
+             LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![user_id, limit, before, unread_only], |row| {
+            Ok(MentionRow {
+                message: message_from_row(row)?,
+                server_id: row.get(13)?,
+                read: row.get::<_, Option<String>>(14)?.is_some(),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Marks the given mentions, or all of them when `message_ids` is
+    /// `None`, as read. Returns how many changed.
+    pub fn mark_mentions_read(&self, user_id: &str, message_ids: Option<&[String]>) -> Result<usize, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let changed = match message_ids {
+            None => tx.execute(
+                "UPDATE user_mentions SET read_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                 WHERE user_id = ?1 AND read_at IS NULL",
+                params![user_id],
+            )?,
+            Some(ids) => {
+                let mut stmt = tx.prepare(
+                    "UPDATE user_mentions SET read_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                     WHERE user_id = ?1 AND message_id = ?2 AND read_at IS NULL",
+                )?;
+                let mut changed = 0;
+                for id in ids {
+                    changed += stmt.execute(params![user_id, id])?;
+                }
+                changed
+            }
+        };
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    // ── Read state queries ───────────────────────────────────────────────
+
+    /// Moves the user's read marker in a channel forward to `message_id`, a
+    /// timeline message in that channel, and marks mentions up to it as read.
+    /// A marker that is already further along is kept. Returns `false` if the
+    /// message is not in the channel's timeline.
+    pub fn ack_channel(&self, user_id: &str, channel_id: &str, message_id: &str) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let created_at: Option<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT created_at FROM messages WHERE id = ?1 AND channel_id = ?2 AND thread_id IS NULL",
+            )?;
+            let mut rows = stmt.query_map(params![message_id, channel_id], |row| row.get(0))?;
+            rows.next().transpose()?
+        };
+        let Some(created_at) = created_at else {
+            return Ok(false);
+        };
+        tx.execute(
+            "INSERT INTO read_states (user_id, channel_id, last_read_message_id, last_read_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (user_id, channel_id) DO UPDATE SET
+                 last_read_message_id = excluded.last_read_message_id,
+                 last_read_at = excluded.last_read_at,
+                 updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+             WHERE (excluded.last_read_at, excluded.last_read_message_id)
+                 > (read_states.last_read_at, read_states.last_read_message_id)",
+            params![user_id, channel_id, message_id, created_at],
+        )?;
+        tx.execute(
+            "UPDATE user_mentions SET read_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+             WHERE user_id = ?1 AND read_at IS NULL AND message_id IN (
+                 SELECT m.id FROM messages m
+                 JOIN read_states rs ON rs.user_id = ?1 AND rs.channel_id = m.channel_id
+                 WHERE m.channel_id = ?2 AND m.thread_id IS NULL
+                   AND (m.created_at, m.id) <= (rs.last_read_at, rs.last_read_message_id)
+             )",
+            params![user_id, channel_id],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Unread and unread-mention counts for the user's text channels, in
+    /// every server or just `server_id`. Thread replies and the user's own
+    /// messages are not counted; a channel never acked counts everything.
+    pub fn get_channel_read_states(
+        &self,
+        user_id: &str,
+        server_id: Option<&str>,
+    ) -> Result<Vec<ChannelReadRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.server_id, rs.last_read_message_id,
+                    (SELECT COUNT(*) FROM messages m
+                     WHERE m.channel_id = c.id AND m.thread_id IS NULL AND m.author_id != ?1
+                       AND m.deleted_at IS NULL
+                       AND (rs.last_read_at IS NULL
+                            OR (m.created_at, m.id) > (rs.last_read_at, rs.last_read_message_id))),
+                    (SELECT COUNT(*) FROM user_mentions um
+                     JOIN messages m ON m.id = um.message_id
+                     WHERE um.user_id = ?1 AND um.read_at IS NULL
+                       AND m.channel_id = c.id AND m.thread_id IS NULL AND m.deleted_at IS NULL)
+             FROM channels c
+             JOIN server_members sm ON sm.server_id = c.server_id AND sm.user_id = ?1
+             LEFT JOIN read_states rs ON rs.user_id = ?1 AND rs.channel_id = c.id
+             WHERE c.type = 'text' AND (?2 IS NULL OR c.server_id = ?2)",
+        )?;
+        let rows = stmt.query_map(params![user_id, server_id], |row| {
+            Ok(ChannelReadRow {
+                channel_id: row.get(0)?,
+                server_id: row.get(1)?,
+                last_read_message_id: row.get(2)?,
+                unread_count: row.get(3)?,
+                mention_count: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Same as [`Database::ack_channel`] for a DM conversation.
+    pub fn ack_dm(&self, user_id: &str, conversation_id: &str, message_id: &str) -> Result<bool, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let created_at: Option<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT created_at FROM dm_messages WHERE id = ?1 AND conversation_id = ?2",
+            )?;
+            let mut rows = stmt.query_map(params![message_id, conversation_id], |row| row.get(0))?;
+            rows.next().transpose()?
+        };
+        let Some(created_at) = created_at else {
+            return Ok(false);
+        };
+        tx.execute(
+            "INSERT INTO dm_read_states (user_id, conversation_id, last_read_message_id, last_read_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (user_id, conversation_id) DO UPDATE SET
+                 last_read_message_id = excluded.last_read_message_id,
+                 last_read_at = excluded.last_read_at,
+                 updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+             WHERE (excluded.last_read_at, excluded.last_read_message_id)
+                 > (dm_read_states.last_read_at, dm_read_states.last_read_message_id)",
+            params![user_id, conversation_id, message_id, created_at],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Unread counts for the user's DM conversations, not counting their own
+    /// messages.
+    pub fn get_dm_read_states(&self, user_id: &str) -> Result<Vec<DmReadRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT dc.id, rs.last_read_message_id,
+                    (SELECT COUNT(*) FROM dm_messages dm
+                     WHERE dm.conversation_id = dc.id AND dm.author_id != ?1
+                       AND (rs.last_read_at IS NULL
+                            OR (dm.created_at, dm.id) > (rs.last_read_at, rs.last_read_message_id)))
+             FROM dm_conversations dc
+             LEFT JOIN dm_read_states rs ON rs.user_id = ?1 AND rs.conversation_id = dc.id
+             WHERE dc.user1_id = ?1 OR dc.user2_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![user_id], |row| {
+            Ok(DmReadRow {
+                conversation_id: row.get(0)?,
+                last_read_message_id: row.get(1)?,
+                unread_count: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    // ── Invite queries ───────────────────────────────────────────────────
+
+    pub fn create_invite(
+        &self,
+        code: &str,
+        server_id: &str,
+        channel_id: Option<&str>,
+        creator_id: &str,
+        max_uses: Option<i64>,
+        max_age_secs: Option<i64>,
+    ) -> Result<InviteRow, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO invites (code, server_id, channel_id, creator_id, max_uses, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5,
+                     CASE WHEN ?6 IS NULL THEN NULL
+                          ELSE strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '+' || ?6 || ' seconds') END)",
+            params![code, server_id, channel_id, creator_id, max_uses, max_age_secs],
+        )?;
+        conn.query_row(
+            &format!("{INVITE_SELECT} WHERE code = ?1"),
+            params![code],
+            invite_from_row,
+        )
+    }
+
+    /// Returns the invite only while it can still be used.
+    pub fn get_valid_invite(&self, code: &str) -> Result<Option<InviteRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            &format!("{INVITE_SELECT} WHERE code = ?1 AND {INVITE_VALID}"),
+            params![code],
+            invite_from_row,
+        );
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_invites_for_server(&self, server_id: &str) -> Result<Vec<InviteRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "{INVITE_SELECT} WHERE server_id = ?1 AND {INVITE_VALID} ORDER BY created_at DESC"
+        ))?;
+        let rows = stmt.query_map(params![server_id], invite_from_row)?;
+        rows.collect()
+    }
+
+    pub fn delete_invite(&self, code: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM invites WHERE code = ?1", params![code])?;
+        Ok(())
+    }
+
+    /// Public preview of a usable invite.
+    pub fn get_invite_preview(&self, code: &str) -> Result<Option<InvitePreviewRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            &format!(
+                "SELECT i.code, i.server_id, s.name, s.icon_url,
+                        (SELECT COUNT(*) FROM server_members sm WHERE sm.server_id = i.server_id),
+                        i.channel_id, c.name, i.expires_at
+                 FROM invites i
+                 JOIN servers s ON s.id = i.server_id
+                 LEFT JOIN channels c ON c.id = i.channel_id
+                 WHERE i.code = ?1 AND {INVITE_VALID}"
+            ),
+            params![code],
+            |row| {
+                Ok(InvitePreviewRow {
+                    code: row.get(0)?,
+                    server_id: row.get(1)?,
+                    server_name: row.get(2)?,
+                    server_icon_url: row.get(3)?,
+                    member_count: row.get(4)?,
+                    channel_id: row.get(5)?,
+                    channel_name: row.get(6)?,
+                    expires_at: row.get(7)?,
+                })
+            },
+        );
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Joins `user_id` to the invite's server and counts the use, atomically.
+    /// Users who are already members do not consume a use. Returns `None`
+    /// if the invite is unknown, expired or used up.
+    pub fn accept_invite(&self, code: &str, user_id: &str) -> Result<Option<AcceptedInvite>, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let invite = match tx.query_row(
+            &format!("{INVITE_SELECT} WHERE code = ?1 AND {INVITE_VALID}"),
+            params![code],
+            invite_from_row,
+        ) {
+            Ok(row) => row,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO server_members (user_id, server_id) VALUES (?1, ?2)",
+            params![user_id, invite.server_id],
+        )?;
+        if inserted > 0 {
+            tx.execute(
+                "UPDATE invites SET uses = uses + 1 WHERE code = ?1",
+                params![code],
+            )?;
+        }
+        tx.commit()?;
+        Ok(Some(AcceptedInvite {
+            server_id: invite.server_id,
+            channel_id: invite.channel_id,
+            joined: inserted > 0,
+        }))
+    }
+
+    // ── Role & permission queries ────────────────────────────────────────
+
+    /// Everything needed to compute a member's server-level permissions, or
+    /// `None` if the user is not a member of the server.
+    pub fn get_member_permission_info(
+        &self,
+        user_id: &str,
+        server_id: &str,
+    ) -> Result<Option<MemberPermissionRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT s.owner_id, sm.role
+             FROM server_members sm JOIN servers s ON s.id = sm.server_id
+             WHERE sm.user_id = ?1 AND sm.server_id = ?2",
+            params![user_id, server_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        );
+        let (owner_id, legacy_role) = match result {
+            Ok(r) => r,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut stmt = conn.prepare(
+            "SELECT r.id, r.permissions FROM roles r
+             WHERE r.server_id = ?2
+               AND (r.id = ?2 OR r.id IN (
+                    SELECT role_id FROM member_roles WHERE user_id = ?1 AND server_id = ?2))",
+        )?;
+        let roles = stmt
+            .query_map(params![user_id, server_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(MemberPermissionRow {
+            owner_id,
+            legacy_role,
+            roles,
+        }))
+    }
+
+    pub fn get_roles_for_server(&self, server_id: &str) -> Result<Vec<RoleRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, server_id, name, permissions, position, created_at
+             FROM roles WHERE server_id = ?1 ORDER BY position, created_at",
+        )?;
+        let rows = stmt
+            .query_map(params![server_id], |row| {
+                Ok(RoleRow {
+                    id: row.get(0)?,
+                    server_id: row.get(1)?,
+                    name: row.get(2)?,
+                    permissions: row.get(3)?,
+                    position: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn get_role(&self, role_id: &str) -> Result<Option<RoleRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn.query_row(
+            "SELECT id, server_id, name, permissions, position, created_at FROM roles WHERE id = ?1",
+            params![role_id],
+            |row| {
+                Ok(RoleRow {
+                    id: row.get(0)?,
+                    server_id: row.get(1)?,
+                    name: row.get(2)?,
+                    permissions: row.get(3)?,
+                    position: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            },
+        );
+        match result {
+            Ok(row) => Ok(Some(row)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn create_role(
+        &self,
+        id: &Uuid,
+        server_id: &str,
+        name: &str,
+        permissions: i64,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let position: i32 = conn
+            .query_row(
+                "SELECT COALESCE(MAX(position), 0) + 1 FROM roles WHERE server_id = ?1",
+                params![server_id],
+                |row| row.get(0),
+            )
+            .unwrap_or(1);
+        conn.execute(
+            "INSERT INTO roles (id, server_id, name, permissions, position) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id.to_string(), server_id, name, permissions, position],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_role(
+        &self,
+        role_id: &str,
+        name: Option<&str>,
+        permissions: Option<i64>,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        if let Some(v) = name {
+            conn.execute("UPDATE roles SET name = ?1 WHERE id = ?2", params![v, role_id])?;
+        }
+        if let Some(v) = permissions {
+            conn.execute(
+                "UPDATE roles SET permissions = ?1 WHERE id = ?2",
+                params![v, role_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_role(&self, role_id: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM roles WHERE id = ?1", params![role_id])?;
+        Ok(())
+    }
+
+    pub fn add_member_role(
+        &self,
+        user_id: &str,
+        server_id: &str,
+        role_id: &str,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO member_roles (user_id, server_id, role_id) VALUES (?1, ?2, ?3)",
+            params![user_id, server_id, role_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_member_role(&self, user_id: &str, role_id: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM member_roles WHERE user_id = ?1 AND role_id = ?2",
+            params![user_id, role_id],
+        )?;
+        Ok(())
+    }
+
+    /// (user_id, role_id) pairs for every explicit role assignment in a server
+    pub fn get_member_role_ids(
+        &self,
+        server_id: &str,
+    ) -> Result<Vec<(String, String)>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT user_id, role_id FROM member_roles WHERE server_id = ?1")?;
+        let rows = stmt
+            .query_map(params![server_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn get_channel_overrides(
         &self,
         channel_id: &str,
-    ) -> Result<Vec<VoiceStateRow>, rusqlite::Error> {
+    ) -> Result<Vec<ChannelOverrideRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT vs.user_id, vs.channel_id, vs.muted, vs.deafened, vs.joined_at,
-                    u.username, u.avatar_url
-             FROM voice_states vs
-             JOIN users u ON vs.user_id = u.id
-             WHERE vs.channel_id = ?1",
+            "SELECT channel_id, role_id, allow, deny
+             FROM channel_permission_overrides WHERE channel_id = ?1",
         )?;
         let rows = stmt
             .query_map(params![channel_id], |row| {
-                Ok(VoiceStateRow {
-                    user_id: row.get(0)?,
-                    channel_id: row.get(1)?,
-                    muted: row.get::<_, i32>(2)? != 0,
-                    deafened: row.get::<_, i32>(3)? != 0,
-                    joined_at: row.get(4)?,
-                    username: row.get(5)?,
-                    avatar_url: row.get(6)?,
+                Ok(ChannelOverrideRow {
+                    channel_id: row.get(0)?,
+                    role_id: row.get(1)?,
+                    allow: row.get(2)?,
+                    deny: row.get(3)?,
                 })
             })?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(rows)
     }
 
-    pub fn get_user_voice_channel(&self, user_id: &str) -> Result<Option<String>, rusqlite::Error> {
+    pub fn set_channel_override(
+        &self,
+        channel_id: &str,
+        role_id: &str,
+        allow: i64,
+        deny: i64,
+    ) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT channel_id FROM voice_states WHERE user_id = ?1",
-            params![user_id],
-            |row| row.get::<_, String>(0),
-        );
-        match result {
-            Ok(channel_id) => Ok(Some(channel_id)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e),
-        }
+        conn.execute(
+            "INSERT INTO channel_permission_overrides (channel_id, role_id, allow, deny)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(channel_id, role_id) DO UPDATE SET allow = ?3, deny = ?4",
+            params![channel_id, role_id, allow, deny],
+        )?;
+        Ok(())
     }
 
-    // ── Mention queries ──────────────────────────────────────────────────
-
-    pub fn create_mention(
+    pub fn delete_channel_override(
         &self,
-        id: &Uuid,
-        message_id: &str,
-        mention_type: &str,
-        target_id: Option<&str>,
+        channel_id: &str,
+        role_id: &str,
     ) -> Result<(), rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT INTO mentions (id, message_id, type, target_id) VALUES (?1, ?2, ?3, ?4)",
-            params![id.to_string(), message_id, mention_type, target_id],
+            "DELETE FROM channel_permission_overrides WHERE channel_id = ?1 AND role_id = ?2",
+            params![channel_id, role_id],
         )?;
         Ok(())
     }
 
+    // ── Search queries ───────────────────────────────────────────────────
+
+    /// Full-text search across every channel and DM conversation the user can
+    /// see. Hits are ordered newest first; `limit`/`offset` page through them.
+    pub fn search_messages(
+        &self,
+        user_id: &str,
+        filter: &SearchFilter,
+        limit: i32,
+        offset: i32,
+    ) -> Result<Vec<SearchHitRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut p: Vec<Box<dyn rusqlite::types::ToSql>> = vec![Box::new(user_id.to_string())];
+        let bind = |value: &str, p: &mut Vec<Box<dyn rusqlite::types::ToSql>>| {
+            p.push(Box::new(value.to_string()));
+            format!("?{}", p.len())
+        };
+
+        let mut branches = Vec::new();
+
+        if filter.include_channels {
+            let mut sql = String::from(
+                "SELECT 'channel' AS kind, m.id AS id, m.channel_id, m.author_id,
+                        m.content, m.pinned, m.created_at AS created_at, m.edited_at,
+                        u.username, u.avatar_url, m.reply_to_id, m.thread_id, ",
+            );
+            match &filter.fts_query {
+                Some(q) => {
+                    let q = bind(q, &mut p);
+                    sql.push_str(&format!(
+                        "snippet(messages_fts, 0, char(1), char(2), '…', 24)
+                         FROM messages_fts
+                         JOIN messages m ON m.rowid = messages_fts.rowid
+                         JOIN channels c ON c.id = m.channel_id
+                         JOIN server_members sm ON sm.server_id = c.server_id AND sm.user_id = ?1
+                         JOIN users u ON u.id = m.author_id
+                         WHERE messages_fts MATCH {q} AND m.deleted_at IS NULL"
+                    ));
+                }
+                None => sql.push_str(
+                    "COALESCE(m.content, '')
+                     FROM messages m
+                     JOIN channels c ON c.id = m.channel_id
+                     JOIN server_members sm ON sm.server_id = c.server_id AND sm.user_id = ?1
+                     JOIN users u ON u.id = m.author_id
+                     WHERE m.deleted_at IS NULL",
+                ),
+            }
+            if let Some(v) = &filter.server_id {
+                sql.push_str(&format!(" AND c.server_id = {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.channel_id {
+                sql.push_str(&format!(" AND m.channel_id = {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.author_id {
+                sql.push_str(&format!(" AND m.author_id = {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.since {
+                sql.push_str(&format!(" AND m.created_at >= {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.until {
+                sql.push_str(&format!(" AND m.created_at < {}", bind(v, &mut p)));
+            }
+            if filter.has_attachment {
+                sql.push_str(" AND EXISTS (SELECT 1 FROM attachments a WHERE a.message_id = m.id)");
+            }
+            branches.push(sql);
+        }
+
+        if filter.include_dms {
+            let mut sql = String::from(
+                "SELECT 'dm' AS kind, dm.id AS id, dm.conversation_id, dm.author_id,
+                        dm.content, 0, dm.created_at AS created_at, dm.edited_at,
+                        u.username, u.avatar_url, NULL, NULL, ",
+            );
+            match &filter.fts_query {
+                Some(q) => {
+                    let q = bind(q, &mut p);
+                    sql.push_str(&format!(
+                        "snippet(dm_messages_fts, 0, char(1), char(2), '…', 24)
+                         FROM dm_messages_fts
+                         JOIN dm_messages dm ON dm.rowid = dm_messages_fts.rowid
+                         JOIN dm_conversations dc ON dc.id = dm.conversation_id
+                             AND (dc.user1_id = ?1 OR dc.user2_id = ?1)
+                         JOIN users u ON u.id = dm.author_id
+                         WHERE dm_messages_fts MATCH {q}"
+                    ));
+                }
+                None => sql.push_str(
+                    "COALESCE(dm.content, '')
+                     FROM dm_messages dm
+                     JOIN dm_conversations dc ON dc.id = dm.conversation_id
+                         AND (dc.user1_id = ?1 OR dc.user2_id = ?1)
+                     JOIN users u ON u.id = dm.author_id
+                     WHERE 1 = 1",
+                ),
+            }
+            if let Some(v) = &filter.conversation_id {
+                sql.push_str(&format!(" AND dm.conversation_id = {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.author_id {
+                sql.push_str(&format!(" AND dm.author_id = {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.since {
+                sql.push_str(&format!(" AND dm.created_at >= {}", bind(v, &mut p)));
+            }
+            if let Some(v) = &filter.until {
+                sql.push_str(&format!(" AND dm.created_at < {}", bind(v, &mut p)));
+            }
+            if filter.has_attachment {
+                sql.push_str(
+                    " AND EXISTS (SELECT 1 FROM dm_attachments a WHERE a.message_id = dm.id)",
+                );
+            }
+            branches.push(sql);
+        }
+
+        if branches.is_empty() {
+            return Ok(vec![]);
+        }
+
+        p.push(Box::new(limit));
+        let limit_idx = p.len();
+        p.push(Box::new(offset));
+        let offset_idx = p.len();
+        let query = format!(
+            "{} ORDER BY created_at DESC, id DESC LIMIT ?{limit_idx} OFFSET ?{offset_idx}",
+            branches.join(" UNION ALL ")
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_ref: Vec<&dyn rusqlite::types::ToSql> = p.iter().map(|b| b.as_ref()).collect();
+        let rows = stmt
+            .query_map(params_ref.as_slice(), |row| {
+                Ok(SearchHitRow {
+                    kind: row.get(0)?,
+                    id: row.get(1)?,
+                    parent_id: row.get(2)?,
+                    author_id: row.get(3)?,
+                    content: row.get(4)?,
+                    pinned: row.get::<_, i32>(5)? != 0,
+                    created_at: row.get(6)?,
+                    edited_at: row.get(7)?,
+                    author_username: row.get(8)?,
+                    author_avatar_url: row.get(9)?,
+                    reply_to_id: row.get(10)?,
+                    thread_id: row.get(11)?,
+                    snippet: row.get(12)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
     // ── Utility ──────────────────────────────────────────────────────────
 
     pub fn get_channel_server_id(
@@ -681,7 +2189,8 @@ impl Database {
     pub fn get_server_by_id(&self, server_id: &str) -> Result<Option<ServerRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let result = conn.query_row(
-            "SELECT id, name, icon_url, owner_id, created_at, updated_at FROM servers WHERE id = ?1",
+            "SELECT id, name, icon_url, owner_id, created_at, updated_at, link_previews, edit_history_days
+             FROM servers WHERE id = ?1",
             params![server_id],
             |row| {
                 Ok(ServerRow {
@@ -691,6 +2200,8 @@ impl Database {
                     owner_id: row.get(3)?,
                     created_at: row.get(4)?,
                     updated_at: row.get(5)?,
+                    link_previews: row.get(6)?,
+                    edit_history_days: row.get(7)?,
                 })
             },
         );
@@ -701,6 +2212,26 @@ impl Database {
         }
     }
 
+    pub fn update_server(
+        &self,
+        server_id: &str,
+        name: Option<&str>,
+        icon_url: Option<&str>,
+        link_previews: Option<bool>,
+        edit_history_days: Option<i64>,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE servers SET name = COALESCE(?2, name), icon_url = COALESCE(?3, icon_url),
+                    link_previews = COALESCE(?4, link_previews),
+                    edit_history_days = COALESCE(?5, edit_history_days),
+                    updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+             WHERE id = ?1",
+            params![server_id, name, icon_url, link_previews, edit_history_days],
+        )?;
+        Ok(())
+    }
+
     // ── DM queries ───────────────────────────────────────────────────────
 
     pub fn get_dm_conversations(&self, user_id: &str) -> Result<Vec<DmConversationRow>, rusqlite::Error> {
@@ -764,19 +2295,28 @@ impl Database {
         Ok(())
     }
 
-    pub fn get_dm_messages(&self, conversation_id: &str) -> Result<Vec<DmMessageRow>, rusqlite::Error> {
+    /// One page of a conversation's history, oldest first, ordered by
+    /// `(created_at, id)`.
+    pub fn get_dm_messages(
+        &self,
+        conversation_id: &str,
+        limit: i32,
+        cursor: MessageCursor<'_>,
+    ) -> Result<Vec<DmMessageRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+        page_by_cursor(
+            &conn,
             "SELECT dm.id, dm.conversation_id, dm.author_id, dm.content, dm.created_at, dm.edited_at,
                     u.username, u.avatar_url
              FROM dm_messages dm
              JOIN users u ON dm.author_id = u.id
-             WHERE dm.conversation_id = ?1
-             ORDER BY dm.created_at ASC
-             LIMIT 100",
-        )?;
-        let rows = stmt
-            .query_map(params![conversation_id], |row| {
+             WHERE dm.conversation_id = ?1",
+            "dm",
+            "dm_messages",
+            conversation_id,
+            limit,
+            cursor,
+            |row| {
                 Ok(DmMessageRow {
                     id: row.get(0)?,
                     conversation_id: row.get(1)?,
@@ -787,9 +2327,8 @@ impl Database {
                     author_username: row.get(6)?,
                     author_avatar_url: row.get(7)?,
                 })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(rows)
+            },
+        )
     }
 
     pub fn get_last_dm_message(&self, conversation_id: &str) -> Result<Option<DmMessageRow>, rusqlite::Error> {
@@ -800,7 +2339,7 @@ impl Database {
              FROM dm_messages dm
              JOIN users u ON dm.author_id = u.id
              WHERE dm.conversation_id = ?1
-             ORDER BY dm.created_at DESC
+             ORDER BY dm.created_at DESC, dm.id DESC
              LIMIT 1",
             params![conversation_id],
             |row| {
@@ -823,43 +2362,70 @@ impl Database {
         }
     }
 
-    pub fn create_dm_message(&self, id: &Uuid, conversation_id: &str, author_id: &str, content: Option<&str>) -> Result<DmMessageRow, rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
+    /// Inserts a DM and turns `upload_ids` into its attachments. Returns
+    /// `None`, writing nothing, if any of them is not a pending upload of
+    /// the author.
+    pub fn create_dm_message(
+        &self,
+        id: &Uuid,
+        conversation_id: &str,
+        author_id: &str,
+        content: Option<&str>,
+        upload_ids: &[String],
+    ) -> Result<Option<DmMessageRow>, rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
             "INSERT INTO dm_messages (id, conversation_id, author_id, content) VALUES (?1, ?2, ?3, ?4)",
             params![id.to_string(), conversation_id, author_id, content],
         )?;
-        conn.execute(
+        if !claim_uploads(&tx, "dm_attachments", &id.to_string(), author_id, upload_ids)? {
+            return Ok(None);
+        }
+        tx.execute(
             "UPDATE dm_conversations SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = ?1",
             params![conversation_id],
         )?;
-        let mut stmt = conn.prepare(
+        let row = tx.query_row(
             "SELECT dm.id, dm.conversation_id, dm.author_id, dm.content, dm.created_at, dm.edited_at,
                     u.username, u.avatar_url
              FROM dm_messages dm JOIN users u ON dm.author_id = u.id
              WHERE dm.id = ?1",
+            params![id.to_string()],
+            |row| {
+                Ok(DmMessageRow {
+                    id: row.get(0)?,
+                    conversation_id: row.get(1)?,
+                    author_id: row.get(2)?,
+                    content: row.get(3)?,
+                    created_at: row.get(4)?,
+                    edited_at: row.get(5)?,
+                    author_username: row.get(6)?,
+                    author_avatar_url: row.get(7)?,
+                })
+            },
         )?;
-        stmt.query_row(params![id.to_string()], |row| {
-            Ok(DmMessageRow {
-                id: row.get(0)?,
-                conversation_id: row.get(1)?,
-                author_id: row.get(2)?,
-                content: row.get(3)?,
-                created_at: row.get(4)?,
-                edited_at: row.get(5)?,
-                author_username: row.get(6)?,
-                author_avatar_url: row.get(7)?,
-            })
-        })
+        tx.commit()?;
+        Ok(Some(row))
     }
 
-    pub fn edit_dm_message(&self, message_id: &str, content: &str) -> Result<(), rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
+    /// Replaces a DM's content, keeping the previous version as a revision
+    /// if it changed.
+    pub fn edit_dm_message(&self, message_id: &str, editor_id: &str, content: &str) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        record_revision(&tx, "dm_messages", "dm_message_revisions", message_id, editor_id, content)?;
+        tx.execute(
             "UPDATE dm_messages SET content = ?1, edited_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = ?2",
             params![content, message_id],
         )?;
-        Ok(())
+        tx.commit()
+    }
+
+    /// Earlier versions of a DM, oldest first.
+    pub fn get_dm_message_revisions(&self, message_id: &str) -> Result<Vec<RevisionRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        get_revisions(&conn, "dm_message_revisions", message_id, 0)
     }
 
     pub fn delete_dm_message(&self, message_id: &str) -> Result<(), rusqlite::Error> {
@@ -937,7 +2503,8 @@ impl Database {
     pub fn get_dm_attachments(&self, message_id: &str) -> Result<Vec<AttachmentRow>, rusqlite::Error> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, message_id, file_url, file_name, mime_type, size_bytes, created_at
+            "SELECT id, message_id, file_url, file_name, mime_type, size_bytes, created_at,
+                    width, height, blurhash, thumbnail_url, preview_url, duration_secs
              FROM dm_attachments WHERE message_id = ?1",
         )?;
         let rows = stmt
@@ -950,6 +2517,14 @@ impl Database {
                     mime_type: row.get(4)?,
                     size_bytes: row.get(5)?,
                     created_at: row.get(6)?,
+                    media: MediaInfo {
+                        width: row.get(7)?,
+                        height: row.get(8)?,
+                        blurhash: row.get(9)?,
+                        thumbnail_url: row.get(10)?,
+                        preview_url: row.get(11)?,
+                        duration_secs: row.get(12)?,
+                    },
                 })
             })?
             .collect::<Result<Vec<_>, _>>()?;
@@ -1064,6 +2639,404 @@ impl Database {
     }
 }
 
+/// `ALTER TABLE ... ADD COLUMN` for databases created before the column was
+/// part of `schema.sql`.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))?;
+    }
+    Ok(())
+}
+
+const CHANNEL_SELECT: &str =
+    "SELECT id, server_id, name, type, position, created_at, updated_at,
+            category_id, topic, slowmode_secs, nsfw, voice_mode
+     FROM channels";
+
+fn channel_from_row(row: &rusqlite::Row<'_>) -> Result<ChannelRow, rusqlite::Error> {
+    Ok(ChannelRow {
+        id: row.get(0)?,
+        server_id: row.get(1)?,
+        name: row.get(2)?,
+        channel_type: row.get(3)?,
+        position: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        category_id: row.get(7)?,
+        topic: row.get(8)?,
+        slowmode_secs: row.get(9)?,
+        nsfw: row.get::<_, i32>(10)? != 0,
+        voice_mode: row.get(11)?,
+    })
+}
+
+const CATEGORY_SELECT: &str =
+    "SELECT id, server_id, name, position, created_at FROM channel_categories";
+
+fn category_from_row(row: &rusqlite::Row<'_>) -> Result<ChannelCategoryRow, rusqlite::Error> {
+    Ok(ChannelCategoryRow {
+        id: row.get(0)?,
+        server_id: row.get(1)?,
+        name: row.get(2)?,
+        position: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+const MESSAGE_SELECT: &str =
+    "SELECT m.id, m.channel_id, m.author_id, m.content, m.pinned, m.created_at, m.edited_at,
+            u.username, u.avatar_url, m.reply_to_id, m.thread_id, m.deleted_at, m.deleted_by
+     FROM messages m JOIN users u ON m.author_id = u.id";
+
+fn message_from_row(row: &rusqlite::Row<'_>) -> Result<MessageRow, rusqlite::Error> {
+    Ok(MessageRow {
+        id: row.get(0)?,
+        channel_id: row.get(1)?,
+        author_id: row.get(2)?,
+        content: row.get(3)?,
+        pinned: row.get::<_, i32>(4)? != 0,
+        created_at: row.get(5)?,
+        edited_at: row.get(6)?,
+        author_username: row.get(7)?,
+        author_avatar_url: row.get(8)?,
+        reply_to_id: row.get(9)?,
+        thread_id: row.get(10)?,
+        deleted_at: row.get(11)?,
+        deleted_by: row.get(12)?,
+    })
+}
+
+fn embed_from_row(row: &rusqlite::Row<'_>) -> Result<EmbedRow, rusqlite::Error> {
+    Ok(EmbedRow {
+        url: row.get(0)?,
+        title: row.get(1)?,
+        description: row.get(2)?,
+        site_name: row.get(3)?,
+        image_url: row.get(4)?,
+        large_image: row.get(5)?,
+    })
+}
+
+const THREAD_SELECT: &str =
+    "SELECT id, channel_id, root_message_id, creator_id, name, message_count, last_message_at, created_at
+     FROM threads";
+
+fn thread_from_row(row: &rusqlite::Row<'_>) -> Result<ThreadRow, rusqlite::Error> {
+    Ok(ThreadRow {
+        id: row.get(0)?,
+        channel_id: row.get(1)?,
+        root_message_id: row.get(2)?,
+        creator_id: row.get(3)?,
+        name: row.get(4)?,
+        message_count: row.get(5)?,
+        last_message_at: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+/// Where a page of message history starts. Anchors are message ids.
+#[derive(Debug, Clone, Copy)]
+pub enum MessageCursor<'a> {
+    /// The most recent messages
+    Latest,
+    /// Messages strictly older than the anchor
+    Before(&'a str),
+    /// Messages strictly newer than the anchor
+    After(&'a str),
+    /// The anchor with older and newer messages on either side
+    Around(&'a str),
+}
+
+/// Why [`Database::create_message`] wrote nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageRejected {
+    /// An upload is not a pending upload of the author
+    InvalidAttachment,
+    /// The attachments would take the server past its storage quota
+    ServerQuotaExceeded,
+}
+
+/// Runs `base` (which must end in a `WHERE` on `?1` = `parent_id`) for one
+/// page of history. Rows come back oldest first; `(created_at, id)` breaks
+/// ties between messages sharing a timestamp.
+#[allow(clippy::too_many_arguments)]
+fn page_by_cursor<T>(
+    conn: &Connection,
+    base: &str,
+    alias: &str,
+    table: &str,
+    parent_id: &str,
+    limit: i32,
+    cursor: MessageCursor<'_>,
+    map: impl Fn(&rusqlite::Row<'_>) -> Result<T, rusqlite::Error> + Copy,
+) -> Result<Vec<T>, rusqlite::Error> {
+    let anchor = format!("(SELECT created_at, id FROM {table} WHERE id = ?3)");
+    let older = |op: &str, anchor_id: &str, limit: i32| -> Result<Vec<T>, rusqlite::Error> {
+        let mut stmt = conn.prepare(&format!(
+            "{base} AND ({alias}.created_at, {alias}.id) {op} {anchor}
+             ORDER BY {alias}.created_at DESC, {alias}.id DESC LIMIT ?2"
+        ))?;
+        let mut rows = stmt
+            .query_map(params![parent_id, limit, anchor_id], map)?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.reverse();
+        Ok(rows)
+    };
+    let newer = |op: &str, anchor_id: &str, limit: i32| -> Result<Vec<T>, rusqlite::Error> {
+        let mut stmt = conn.prepare(&format!(
+            "{base} AND ({alias}.created_at, {alias}.id) {op} {anchor}
+             ORDER BY {alias}.created_at ASC, {alias}.id ASC LIMIT ?2"
+        ))?;
+        let rows = stmt.query_map(params![parent_id, limit, anchor_id], map)?;
+        rows.collect()
+    };
+
+    match cursor {
+        MessageCursor::Latest => {
+            let mut stmt = conn.prepare(&format!(
+                "{base} ORDER BY {alias}.created_at DESC, {alias}.id DESC LIMIT ?2"
+            ))?;
+            let mut rows = stmt
+                .query_map(params![parent_id, limit], map)?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.reverse();
+            Ok(rows)
+        }
+        MessageCursor::Before(id) => older("<", id, limit),
+        MessageCursor::After(id) => newer(">", id, limit),
+        MessageCursor::Around(id) => {
+            let mut rows = older("<", id, limit / 2)?;
+            rows.extend(newer(">=", id, limit - limit / 2)?);
+            Ok(rows)
+        }
+    }
+}
+
+/// Moves pending uploads owned by `owner_id` into `table` as attachments of
+/// `message_id`. Returns `false` if any id is unknown, already attached or
+/// owned by someone else; the caller then drops the transaction.
+fn claim_uploads(
+    tx: &rusqlite::Transaction<'_>,
+    table: &str,
+    message_id: &str,
+    owner_id: &str,
+    upload_ids: &[String],
+) -> Result<bool, rusqlite::Error> {
+    let mut insert = tx.prepare(&format!(
+        "INSERT INTO {table} (id, message_id, file_url, file_name, mime_type, size_bytes,
+                              width, height, blurhash, thumbnail_url, preview_url, duration_secs)
+         SELECT id, ?2, file_url, file_name, mime_type, size_bytes,
+                width, height, blurhash, thumbnail_url, preview_url, duration_secs
+         FROM uploads WHERE id = ?1 AND owner_id = ?3"
+    ))?;
+    let mut delete = tx.prepare("DELETE FROM uploads WHERE id = ?1")?;
+    for upload_id in upload_ids {
+        if insert.execute(params![upload_id, message_id, owner_id])? == 0 {
+            return Ok(false);
+        }
+        delete.execute(params![upload_id])?;
+    }
+    Ok(true)
+}
+
+/// [`Database::get_user_storage_bytes`] on a connection the caller already
+/// holds, so a quota check can share a transaction with the write it guards.
+fn user_storage_bytes(conn: &Connection, user_id: &str) -> Result<u64, rusqlite::Error> {
+    let bytes: i64 = conn.query_row(
+        "SELECT
+            (SELECT COALESCE(SUM(size_bytes), 0) FROM uploads WHERE owner_id = ?1)
+          + (SELECT COALESCE(SUM(a.size_bytes), 0)
+             FROM attachments a JOIN messages m ON m.id = a.message_id
+             WHERE m.author_id = ?1)
+          + (SELECT COALESCE(SUM(a.size_bytes), 0)
+             FROM dm_attachments a JOIN dm_messages m ON m.id = a.message_id
+             WHERE m.author_id = ?1)",
+        params![user_id],
+        |row| row.get(0),
+    )?;
+    Ok(bytes.max(0) as u64)
+}
+
+/// Bytes attached to messages in any channel of the server `channel_id`
+/// belongs to.
+fn channel_server_storage_bytes(conn: &Connection, channel_id: &str) -> Result<u64, rusqlite::Error> {
+    let bytes: i64 = conn.query_row(
+        "SELECT COALESCE(SUM(a.size_bytes), 0)
+         FROM attachments a
+         JOIN messages m ON m.id = a.message_id
+         JOIN channels c ON c.id = m.channel_id
+         WHERE c.server_id = (SELECT server_id FROM channels WHERE id = ?1)",
+        params![channel_id],
+        |row| row.get(0),
+    )?;
+    Ok(bytes.max(0) as u64)
+}
+
+/// Recounts the thread a reply belongs to, leaving tombstones out. Does
+/// nothing for messages outside threads.
+fn refresh_thread_counts(conn: &Connection, message_id: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE threads SET
+            message_count = (SELECT COUNT(*) FROM messages r WHERE r.thread_id = threads.id AND r.deleted_at IS NULL),
+            last_message_at = (SELECT MAX(created_at) FROM messages r WHERE r.thread_id = threads.id AND r.deleted_at IS NULL)
+         WHERE id = (SELECT thread_id FROM messages WHERE id = ?1)",
+        params![message_id],
+    )?;
+    Ok(())
+}
+
+/// Copies the current content of a row in `messages` or `dm_messages` into
+/// the matching revisions table, unless `new_content` leaves it unchanged.
+fn record_revision(
+    conn: &Connection,
+    messages: &str,
+    revisions: &str,
+    message_id: &str,
+    editor_id: &str,
+    new_content: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        &format!(
+            "INSERT INTO {revisions} (message_id, revision, content, created_at, replaced_by)
+             SELECT id,
+                    (SELECT COALESCE(MAX(revision), 0) + 1 FROM {revisions} WHERE message_id = ?1),
+                    content, COALESCE(edited_at, created_at), ?2
+             FROM {messages} WHERE id = ?1 AND content IS NOT ?3"
+        ),
+        params![message_id, editor_id, new_content],
+    )?;
+    Ok(())
+}
+
+fn get_revisions(
+    conn: &Connection,
+    revisions: &str,
+    message_id: &str,
+    retention_days: i64,
+) -> Result<Vec<RevisionRow>, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT revision, content, created_at, replaced_at, replaced_by
+         FROM {revisions}
+         WHERE message_id = ?1
+           AND (?2 <= 0 OR replaced_at >= strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-' || ?2 || ' days'))
+         ORDER BY revision"
+    ))?;
+    let rows = stmt
+        .query_map(params![message_id, retention_days], |row| {
+            Ok(RevisionRow {
+                revision: row.get(0)?,
+                content: row.get(1)?,
+                created_at: row.get(2)?,
+                replaced_at: row.get(3)?,
+                replaced_by: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Finds the attachment, DM attachment or pending upload whose `column`
+/// equals `value`, in that order.
+fn lookup_stored_file(conn: &Connection, column: &str, value: &str) -> Result<Option<StoredFile>, rusqlite::Error> {
+    let lookups: [(String, AccessFromOwner); 3] = [
+        (
+            format!("SELECT message_id, file_name, mime_type FROM attachments WHERE {column} = ?1"),
+            FileAccess::Message,
+        ),
+        (
+            format!(
+                "SELECT dm.conversation_id, a.file_name, a.mime_type
+                 FROM dm_attachments a JOIN dm_messages dm ON dm.id = a.message_id
+                 WHERE a.{column} = ?1"
+            ),
+            FileAccess::DmConversation,
+        ),
+        (
+            format!("SELECT owner_id, file_name, mime_type FROM uploads WHERE {column} = ?1"),
+            FileAccess::Pending,
+        ),
+    ];
+    for (sql, access) in lookups {
+        let found = conn.query_row(&sql, params![value], |row| {
+            Ok(StoredFile {
+                access: access(row.get(0)?),
+                file_name: Some(row.get(1)?),
+                mime_type: Some(row.get(2)?),
+            })
+        });
+        match found {
+            Ok(file) => return Ok(Some(file)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+const INVITE_SELECT: &str =
+    "SELECT code, server_id, channel_id, creator_id, max_uses, uses, expires_at, created_at FROM invites";
+
+/// Filter matching invites that have neither expired nor run out of uses.
+const INVITE_VALID: &str = "(expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+     AND (max_uses IS NULL OR uses < max_uses)";
+
+fn invite_from_row(row: &rusqlite::Row<'_>) -> Result<InviteRow, rusqlite::Error> {
+    Ok(InviteRow {
+        code: row.get(0)?,
+        server_id: row.get(1)?,
+        channel_id: row.get(2)?,
+        creator_id: row.get(3)?,
+        max_uses: row.get(4)?,
+        uses: row.get(5)?,
+        expires_at: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+/// Server mute and deafen come from the membership in the channel's server.
+const VOICE_STATE_SELECT: &str =
+    "SELECT vs.user_id, vs.channel_id, vs.muted, vs.deafened,
+            COALESCE(sm.server_muted, 0), COALESCE(sm.server_deafened, 0), vs.joined_at,
+            u.username, u.avatar_url
+     FROM voice_states vs
+     JOIN users u ON vs.user_id = u.id
+     LEFT JOIN channels c ON c.id = vs.channel_id
+     LEFT JOIN server_members sm ON sm.user_id = vs.user_id AND sm.server_id = c.server_id";
+
+fn voice_state_from_row(row: &rusqlite::Row<'_>) -> Result<VoiceStateRow, rusqlite::Error> {
+    Ok(VoiceStateRow {
+        user_id: row.get(0)?,
+        channel_id: row.get(1)?,
+        muted: row.get::<_, i32>(2)? != 0,
+        deafened: row.get::<_, i32>(3)? != 0,
+        server_muted: row.get::<_, i32>(4)? != 0,
+        server_deafened: row.get::<_, i32>(5)? != 0,
+        joined_at: row.get(6)?,
+        username: row.get(7)?,
+        avatar_url: row.get(8)?,
+    })
+}
+
+fn table_exists(conn: &Connection, name: &str) -> Result<bool, rusqlite::Error> {
+    let count: i32 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
 // ── Row types ────────────────────────────────────────────────────────────────
 
 #[derive(Debug, Clone)]
@@ -1087,6 +3060,8 @@ pub struct ServerRow {
     pub owner_id: String,
     pub created_at: String,
     pub updated_at: String,
+    pub link_previews: bool,
+    pub edit_history_days: i64,
 }
 
 #[derive(Debug, Clone)]
@@ -1108,6 +3083,20 @@ pub struct ChannelRow {
     pub position: i32,
     pub created_at: String,
     pub updated_at: String,
+    pub category_id: Option<String>,
+    pub topic: Option<String>,
+    pub slowmode_secs: i64,
+    pub nsfw: bool,
+    pub voice_mode: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelCategoryRow {
+    pub id: String,
+    pub server_id: String,
+    pub name: String,
+    pub position: i32,
+    pub created_at: String,
 }
 
 #[derive(Debug, Clone)]
@@ -1121,6 +3110,70 @@ pub struct MessageRow {
     pub edited_at: Option<String>,
     pub author_username: String,
     pub author_avatar_url: Option<String>,
+    pub reply_to_id: Option<String>,
+    pub thread_id: Option<String>,
+    /// Set while the message is a tombstone
+    pub deleted_at: Option<String>,
+    pub deleted_by: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelReadRow {
+    pub channel_id: String,
+    pub server_id: String,
+    pub last_read_message_id: Option<String>,
+    pub unread_count: i64,
+    pub mention_count: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DmReadRow {
+    pub conversation_id: String,
+    pub last_read_message_id: Option<String>,
+    pub unread_count: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MentionRow {
+    pub message: MessageRow,
+    pub server_id: String,
+    pub read: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadRow {
+    pub id: String,
+    pub channel_id: String,
+    pub root_message_id: String,
+    pub creator_id: String,
+    pub name: String,
+    pub message_count: i64,
+    pub last_message_at: Option<String>,
+    pub created_at: String,
+}
+
+/// Builds a [`FileAccess`] from the id a lookup query returns.
+type AccessFromOwner = fn(String) -> FileAccess;
+
+/// Who may read a stored file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileAccess {
+    /// Attached to this channel message
+    Message(String),
+    /// Attached to a DM in this conversation
+    DmConversation(String),
+    /// Not attached yet; only this user may read it
+    Pending(String),
+    /// An avatar or server icon
+    Public,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub access: FileAccess,
+    /// Original name and declared type; unknown for avatars and icons
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -1132,6 +3185,40 @@ pub struct AttachmentRow {
     pub mime_type: String,
     pub size_bytes: Option<i64>,
     pub created_at: String,
+    pub media: MediaInfo,
+}
+
+/// An earlier version of a channel message or DM.
+#[derive(Debug, Clone)]
+pub struct RevisionRow {
+    pub revision: i64,
+    pub content: Option<String>,
+    pub created_at: String,
+    pub replaced_at: String,
+    pub replaced_by: Option<String>,
+}
+
+/// A link preview, attached to a message or cached by URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbedRow {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    pub image_url: Option<String>,
+    pub large_image: bool,
+}
+
+/// Dimensions, placeholder and renditions derived from an image upload,
+/// or dimensions and duration read from a video's headers.
+#[derive(Debug, Clone, Default)]
+pub struct MediaInfo {
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub blurhash: Option<String>,
+    pub thumbnail_url: Option<String>,
+    pub preview_url: Option<String>,
+    pub duration_secs: Option<f64>,
 }
 
 #[derive(Debug, Clone)]
@@ -1147,11 +3234,25 @@ pub struct VoiceStateRow {
     pub channel_id: String,
     pub muted: bool,
     pub deafened: bool,
+    pub server_muted: bool,
+    pub server_deafened: bool,
     pub joined_at: String,
     pub username: String,
     pub avatar_url: Option<String>,
 }
 
+#[derive(Debug, Clone)]
+pub struct AuditLogRow {
+    pub id: String,
+    pub server_id: String,
+    pub actor_id: Option<String>,
+    pub action: String,
+    pub target_user_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub to_channel_id: Option<String>,
+    pub created_at: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct DmConversationRow {
     pub id: String,
@@ -1186,3 +3287,94 @@ pub struct UserStatusRow {
     pub last_seen: String,
     pub updated_at: String,
 }
+
+#[derive(Debug, Clone)]
+pub struct InviteRow {
+    pub code: String,
+    pub server_id: String,
+    pub channel_id: Option<String>,
+    pub creator_id: String,
+    pub max_uses: Option<i64>,
+    pub uses: i64,
+    pub expires_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvitePreviewRow {
+    pub code: String,
+    pub server_id: String,
+    pub server_name: String,
+    pub server_icon_url: Option<String>,
+    pub member_count: i64,
+    pub channel_id: Option<String>,
+    pub channel_name: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AcceptedInvite {
+    pub server_id: String,
+    pub channel_id: Option<String>,
+    /// `false` if the user was already a member
+    pub joined: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemberPermissionRow {
+    pub owner_id: String,
+    pub legacy_role: String,       // server_members.role
+    pub roles: Vec<(String, i64)>, // (role_id, permissions), including @everyone
+}
+
+#[derive(Debug, Clone)]
+pub struct RoleRow {
+    pub id: String,
+    pub server_id: String,
+    pub name: String,
+    pub permissions: i64,
+    pub position: i32,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelOverrideRow {
+    pub channel_id: String,
+    pub role_id: String,
+    pub allow: i64,
+    pub deny: i64,
+}
+
+/// Filters for [`Database::search_messages`]. `fts_query` is an already
+/// sanitised FTS5 expression; `None` matches every message.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub fts_query: Option<String>,
+    pub server_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub conversation_id: Option<String>,
+    pub author_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub has_attachment: bool,
+    pub include_channels: bool,
+    pub include_dms: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHitRow {
+    pub kind: String,      // 'channel' | 'dm'
+    pub id: String,
+    pub parent_id: String, // channel_id or conversation_id
+    pub author_id: String,
+    pub content: Option<String>,
+    pub pinned: bool,
+    pub created_at: String,
+    pub edited_at: Option<String>,
+    pub author_username: String,
+    pub author_avatar_url: Option<String>,
+    pub reply_to_id: Option<String>, // channel hits only
+    pub thread_id: Option<String>,
+    /// Raw message text with matched terms between `\u{1}` and `\u{2}`
+    pub snippet: String,
+}
@@ -0,0 +1,591 @@
+//! Link previews. Links in a message are fetched in the background and the
+//! OpenGraph or Twitter card metadata of each page is stored as the
+//! message's embeds, then sent to the server with `message_updated`.
+//!
+//! Fetching URLs chosen by users is an SSRF risk: every hop, redirects
+//! included, must resolve to public addresses only, and the connection is
+//! pinned to the addresses that were checked so a second DNS answer cannot
+//! point it elsewhere. Fetches are capped in time and size, and results are
+//! cached by URL.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{header, redirect, Url};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::{db::EmbedRow, AppState};
+use shared::{
+    models::Embed,
+    ws_messages::{WsEnvelope, WsMessageUpdated},
+};
+
+/// Most links previewed per message.
+pub const MAX_EMBEDS: usize = 5;
+
+const MAX_REDIRECTS: usize = 3;
+
+/// Pages fetched at once, across all messages.
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// How long a page without a preview is remembered, at most.
+const MISS_TTL: Duration = Duration::from_secs(60 * 60);
+
+const MAX_URL_LEN: usize = 2048;
+const MAX_TITLE_CHARS: usize = 256;
+const MAX_DESCRIPTION_CHARS: usize = 350;
+
+const USER_AGENT: &str = concat!(
+    "Mozilla/5.0 (compatible; SubspaceBot/",
+    env!("CARGO_PKG_VERSION"),
+    "; link preview)"
+);
+
+/// Limits for fetching link previews.
+#[derive(Debug, Clone)]
+pub struct LinkPreviewConfig {
+    /// Off disables previews for every server
+    pub enabled: bool,
+    /// For the whole fetch, redirects included
+    pub timeout: Duration,
+    /// Bytes of a page that are read; the metadata is in its `<head>`
+    pub max_bytes: usize,
+    /// How long a fetched preview is reused for the same URL
+    pub cache_ttl: Duration,
+    /// Allow loopback, private and link-local addresses. Only for trusted
+    /// networks.
+    pub allow_private_networks: bool,
+}
+
+impl Default for LinkPreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout: Duration::from_secs(5),
+            max_bytes: 512 * 1024,
+            cache_ttl: Duration::from_secs(24 * 60 * 60),
+            allow_private_networks: false,
+        }
+    }
+}
+
+impl LinkPreviewConfig {
+    /// Reads `LINK_PREVIEWS`, `LINK_PREVIEW_TIMEOUT_SECS`,
+    /// `LINK_PREVIEW_MAX_BYTES`, `LINK_PREVIEW_CACHE_TTL_SECS` and
+    /// `LINK_PREVIEW_ALLOW_PRIVATE_NETWORKS`, keeping the default for
+    /// anything unset or unparsable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        Self {
+            enabled: var("LINK_PREVIEWS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.enabled),
+            timeout: var("LINK_PREVIEW_TIMEOUT_SECS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.timeout),
+            max_bytes: var("LINK_PREVIEW_MAX_BYTES")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_bytes),
+            cache_ttl: var("LINK_PREVIEW_CACHE_TTL_SECS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.cache_ttl),
+            allow_private_networks: var("LINK_PREVIEW_ALLOW_PRIVATE_NETWORKS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.allow_private_networks),
+        }
+    }
+}
+
+/// The link preview settings together with the limit on concurrent
+/// fetches.
+pub struct LinkPreviews {
+    pub config: LinkPreviewConfig,
+    permits: Semaphore,
+}
+
+impl LinkPreviews {
+    pub fn new(config: LinkPreviewConfig) -> Self {
+        Self {
+            config,
+            permits: Semaphore::new(MAX_CONCURRENT_FETCHES),
+        }
+    }
+}
+
+pub fn embed_from_row(r: EmbedRow) -> Embed {
+    Embed {
+        url: r.url,
+        title: r.title,
+        description: r.description,
+        site_name: r.site_name,
+        image_url: r.image_url,
+        large_image: r.large_image,
+    }
+}
+
+/// Brings a message's embeds in line with the links in `content`, in the
+/// background, and sends `message_updated` when they change. Called after
+/// a message is posted or edited. Servers that turned previews off get no
+/// new embeds, but edits still clear old ones.
+pub fn refresh_message_embeds(state: &Arc<AppState>, server_id: &str, message_id: &str, content: Option<&str>) {
+    if !state.link_previews.config.enabled {
+        return;
+    }
+    let urls = content.map(extract_urls).unwrap_or_default();
+    let (state, server_id, message_id) = (state.clone(), server_id.to_string(), message_id.to_string());
+    tokio::spawn(async move {
+        if let Err(e) = update_embeds(&state, &server_id, &message_id, urls).await {
+            tracing::error!("Failed to update embeds: message_id={}, error={}", message_id, e);
+        }
+    });
+}
+
+async fn update_embeds(
+    state: &AppState,
+    server_id: &str,
+    message_id: &str,
+    urls: Vec<String>,
+) -> Result<(), rusqlite::Error> {
+    let existing = state.db.get_embeds_for_message(message_id)?;
+    if urls.is_empty() && existing.is_empty() {
+        return Ok(());
+    }
+    let enabled = matches!(state.db.get_server_by_id(server_id)?, Some(s) if s.link_previews);
+
+    let mut embeds = Vec::new();
+    if enabled {
+        for url in &urls {
+            if let Some(embed) = preview(state, url).await {
+                embeds.push(embed);
+            }
+        }
+    }
+    if embeds == existing {
+        return Ok(());
+    }
+    // An edit made during the fetch has a refresh of its own under way
+    match state.db.get_message(message_id)? {
+        Some(m) if m.content.as_deref().map(extract_urls).unwrap_or_default() == urls => {}
+        _ => return Ok(()),
+    }
+    if !state.db.set_message_embeds(message_id, &embeds)? {
+        return Ok(());
+    }
+
+    let env = WsEnvelope {
+        msg_type: "message_updated".to_string(),
+        payload: serde_json::to_value(WsMessageUpdated {
+            message_id: Uuid::parse_str(message_id).unwrap(),
+            content: None,
+            edited_at: None,
+            pinned: None,
+            embeds: Some(embeds.into_iter().map(embed_from_row).collect()),
+        })
+        .unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_server(server_id, &serde_json::to_string(&env).unwrap())
+        .await;
+    Ok(())
+}
+
+/// The preview of `url`, from the cache or freshly fetched.
+async fn preview(state: &AppState, url: &str) -> Option<EmbedRow> {
+    let config = &state.link_previews.config;
+    match state
+        .db
+        .get_link_preview(url, config.cache_ttl.as_secs(), config.cache_ttl.min(MISS_TTL).as_secs())
+    {
+        Ok(Some(cached)) => return cached,
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to read link preview cache: {e}"),
+    }
+
+    let fetched = {
+        let _permit = state.link_previews.permits.acquire().await.ok()?;
+        fetch_preview(config, url).await
+    };
+    if let Err(e) = state.db.store_link_preview(url, fetched.as_ref()) {
+        tracing::error!("Failed to cache link preview: {e}");
+    }
+    fetched
+}
+
+/// Drops cached previews older than the cache TTL.
+pub fn prune_link_preview_cache(state: &AppState) {
+    match state.db.delete_stale_link_previews(state.link_previews.config.cache_ttl.as_secs()) {
+        Ok(0) => {}
+        Ok(n) => tracing::debug!("Dropped {n} cached link previews"),
+        Err(e) => tracing::error!("Failed to prune link preview cache: {e}"),
+    }
+}
+
+/// Runs [`prune_link_preview_cache`] forever, checking every `interval`.
+pub async fn run_cache_gc(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        prune_link_preview_cache(&state);
+    }
+}
+
+/// Links to preview in `content`, in order and without duplicates, at
+/// most [`MAX_EMBEDS`]. Links in code and links wrapped in `<...>` are
+/// skipped. Trailing punctuation and unbalanced closing parentheses are
+/// not part of a link.
+pub fn extract_urls(content: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    // Every other piece between backticks is inside a code span or block
+    let prose = content.split('`').step_by(2);
+    for word in prose.flat_map(str::split_whitespace) {
+        let Some(start) = ["https://", "http://"].iter().filter_map(|p| word.find(p)).min() else {
+            continue;
+        };
+        if word[..start].ends_with('<') {
+            continue;
+        }
+        let mut url = &word[start..];
+        loop {
+            let mut trimmed = url.trim_end_matches(['.', ',', ':', ';', '!', '?', '\'', '"', '*', '>']);
+            if trimmed.ends_with(')') && trimmed.matches('(').count() < trimmed.matches(')').count() {
+                trimmed = &trimmed[..trimmed.len() - 1];
+            }
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+        if url.len() > MAX_URL_LEN || Url::parse(url).map_or(true, |u| u.host_str().is_none()) {
+            continue;
+        }
+        if !urls.iter().any(|u| u == url) {
+            urls.push(url.to_string());
+            if urls.len() == MAX_EMBEDS {
+                break;
+            }
+        }
+    }
+    urls
+}
+
+/// Whether `ip` is a globally routable unicast address: not loopback,
+/// private, link-local, shared (CGNAT), reserved, documentation or
+/// multicast. IPv4 addresses carried in IPv6 ones are checked as IPv4.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            !(v4.is_unspecified()
+                || v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                || a == 0 // "this network"
+                || (a == 100 && (64..128).contains(&b)) // shared address space
+                || (a == 192 && b == 0 && c == 0) // protocol assignments
+                || (a == 198 && (b == 18 || b == 19)) // benchmarking
+                || a >= 240) // reserved
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_address(v4.into());
+            }
+            let s = v6.segments();
+            let embedded = |hi: u16, lo: u16| Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo));
+            // NAT64 and 6to4
+            if s[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_public_address(embedded(s[6], s[7]).into());
+            }
+            if s[0] == 0x2002 {
+                return is_public_address(embedded(s[1], s[2]).into());
+            }
+            !(s[..6] == [0; 6] // unspecified, loopback and IPv4-compatible
+                || v6.is_multicast()
+                || (s[0] & 0xfe00) == 0xfc00 // unique local
+                || (s[0] & 0xffc0) == 0xfe80 // link-local
+                || (s[0] & 0xffc0) == 0xfec0 // site-local
+                || (s[0] == 0x2001 && s[1] == 0x0db8) // documentation
+                || s[..4] == [0x0100, 0, 0, 0]) // discard-only
+        }
+    }
+}
+
+/// Fetches `url` and reads its preview. `None` when the page cannot be
+/// reached from here, is not HTML, has no title or description, or does
+/// not arrive within the limits.
+pub async fn fetch_preview(config: &LinkPreviewConfig, url: &str) -> Option<EmbedRow> {
+    let parsed = Url::parse(url).ok()?;
+    match tokio::time::timeout(config.timeout, fetch_page(config, parsed)).await {
+        Ok(Ok(Some((page_url, html)))) => parse_page(url, &page_url, &html),
+        Ok(Ok(None)) => None,
+        Ok(Err(e)) => {
+            tracing::debug!("No link preview for {url}: {e}");
+            None
+        }
+        Err(_) => {
+            tracing::debug!("Timed out fetching link preview for {url}");
+            None
+        }
+    }
+}
+
+/// Follows redirects to an HTML page and returns its final URL and the
+/// start of its body. `None` for error statuses and other content types.
+async fn fetch_page(config: &LinkPreviewConfig, mut url: Url) -> Result<Option<(Url, String)>, String> {
+    for _ in 0..=MAX_REDIRECTS {
+        let client = pinned_client(config, &url).await?;
+        let mut res = client
+            .get(url.clone())
+            .header(header::ACCEPT, "text/html,application/xhtml+xml")
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if res.status().is_redirection() {
+            let location = res
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .ok_or("redirect without a location")?;
+            url = url.join(location).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if !res.status().is_success() {
+            return Ok(None);
+        }
+        let is_html = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_ascii_lowercase)
+            .is_some_and(|v| v.starts_with("text/html") || v.starts_with("application/xhtml+xml"));
+        if !is_html {
+            return Ok(None);
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await.map_err(|e| e.to_string())? {
+            let room = config.max_bytes - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if body.len() == config.max_bytes {
+                break;
+            }
+        }
+        return Ok(Some((url, String::from_utf8_lossy(&body).into_owned())));
+    }
+    Err("too many redirects".into())
+}
+
+/// A client for one request to `url`, which may only connect to the
+/// addresses checked here.
+async fn pinned_client(config: &LinkPreviewConfig, url: &Url) -> Result<reqwest::Client, String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme {}", url.scheme()));
+    }
+    let host = url.host_str().ok_or("no host")?;
+    let port = url.port_or_known_default().ok_or("no port")?;
+    let builder = reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .no_proxy()
+        .user_agent(USER_AGENT);
+
+    let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+    let addrs: Vec<SocketAddr> = match literal {
+        Some(ip) => vec![SocketAddr::new(ip, port)],
+        None => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| format!("cannot resolve {host}: {e}"))?
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(format!("{host} has no addresses"));
+    }
+    if !config.allow_private_networks {
+        if let Some(addr) = addrs.iter().find(|a| !is_public_address(a.ip())) {
+            return Err(format!("{host} resolves to non-public address {}", addr.ip()));
+        }
+    }
+    let builder = match literal {
+        Some(_) => builder,
+        None => builder.resolve_to_addrs(host, &addrs),
+    };
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Reads the preview of a page from its `<head>`: OpenGraph tags first,
+/// then Twitter card tags, then `<title>` and the description meta tag.
+/// `url` is the link as written, `page_url` where it led after redirects.
+/// `None` if the page has neither a title nor a description.
+pub fn parse_page(url: &str, page_url: &Url, html: &str) -> Option<EmbedRow> {
+    // ASCII lowercasing keeps byte offsets, so both can be sliced alike
+    let lower = html.to_ascii_lowercase();
+    let end = lower.find("</head").unwrap_or(lower.len());
+    let (html, lower) = (&html[..end], &lower[..end]);
+
+    let mut meta: HashMap<String, &str> = HashMap::new();
+    for (start, _) in lower.match_indices("<meta") {
+        let attrs_start = start + "<meta".len();
+        if !lower[attrs_start..].starts_with(|c: char| c.is_ascii_whitespace() || c == '/') {
+            continue;
+        }
+        let Some(len) = tag_end(&html[attrs_start..]) else {
+            break;
+        };
+        let attrs = attributes(&html[attrs_start..attrs_start + len]);
+        let key = attrs
+            .iter()
+            .find(|(name, _)| name == "property")
+            .or_else(|| attrs.iter().find(|(name, _)| name == "name"))
+            .map(|(_, value)| value.trim().to_ascii_lowercase());
+        let content = attrs.iter().find(|(name, _)| name == "content").map(|(_, value)| *value);
+        if let (Some(key), Some(content)) = (key, content) {
+            meta.entry(key).or_insert(content);
+        }
+    }
+    let title_tag = lower.find("<title").and_then(|start| {
+        let open = start + tag_end(&html[start..])? + 1;
+        let close = open + lower[open..].find("</title")?;
+        Some(&html[open..close])
+    });
+
+    let get = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| meta.get(*k))
+            .map(|v| clean(v))
+            .filter(|v| !v.is_empty())
+    };
+    let title = get(&["og:title", "twitter:title"])
+        .or_else(|| title_tag.map(clean).filter(|t| !t.is_empty()))
+        .map(|t| truncate(t, MAX_TITLE_CHARS));
+    let description = get(&["og:description", "twitter:description", "description"])
+        .map(|d| truncate(d, MAX_DESCRIPTION_CHARS));
+    if title.is_none() && description.is_none() {
+        return None;
+    }
+    let image_url = get(&["og:image:secure_url", "og:image", "og:image:url", "twitter:image", "twitter:image:src"])
+        .and_then(|src| page_url.join(&src).ok())
+        .filter(|u| matches!(u.scheme(), "http" | "https") && u.as_str().len() <= MAX_URL_LEN)
+        .map(String::from);
+    let large_image = image_url.is_some()
+        && get(&["twitter:card"]).is_some_and(|card| card.eq_ignore_ascii_case("summary_large_image"));
+
+    Some(EmbedRow {
+        url: url.to_string(),
+        title,
+        description,
+        site_name: get(&["og:site_name"]).map(|s| truncate(s, MAX_TITLE_CHARS)),
+        image_url,
+        large_image,
+    })
+}
+
+/// Offset of the `>` that closes the tag `html` starts in, skipping
+/// quoted attribute values.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The attributes of a tag, given the text between its name and `>`.
+/// Names are lowercased; values are left as written.
+fn attributes(tag: &str) -> Vec<(String, &str)> {
+    let mut attrs = Vec::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            return attrs;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=').map(str::trim_start) {
+            Some(quoted) if quoted.starts_with(['"', '\'']) => {
+                let inner = &quoted[1..];
+                let end = inner.find(quoted.as_bytes()[0] as char).unwrap_or(inner.len());
+                rest = inner.get(end + 1..).unwrap_or("");
+                &inner[..end]
+            }
+            Some(bare) => {
+                let end = bare.find(|c: char| c.is_ascii_whitespace()).unwrap_or(bare.len());
+                rest = &bare[end..];
+                &bare[..end]
+            }
+            None => "",
+        };
+        attrs.push((name, value));
+    }
+}
+
+/// Decodes character references and collapses whitespace.
+fn clean(text: &str) -> String {
+    decode_entities(text).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..].find(';').filter(|&len| len <= 10).and_then(|len| {
+            let c = match &rest[1..=len] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                name => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => name.strip_prefix('#').and_then(|n| n.parse().ok()).and_then(char::from_u32),
+                },
+            };
+            c.map(|c| (c, len + 2))
+        });
+        match decoded {
+            Some((c, consumed)) => {
+                out.push(c);
+                rest = &rest[consumed..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn truncate(text: String, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some(_) => {
+            let mut cut: String = text.chars().take(max_chars - 1).collect();
+            cut.push('…');
+            cut
+        }
+        None => text,
+    }
+}
@@ -0,0 +1,47 @@
+pub mod auth;
+pub mod db;
+pub mod embeds;
+pub mod media;
+pub mod mentions;
+pub mod permissions;
+pub mod routes;
+pub mod storage;
+#[cfg(feature = "embedded-turn")]
+pub mod turn_server;
+pub mod ws;
+
+use std::sync::Arc;
+use axum::Router;
+use tower_http::cors::CorsLayer;
+
+pub struct AppState {
+    pub db: db::Database,
+    pub jwt_secret: String,
+    pub ws_state: ws::WsState,
+    pub storage: Arc<dyn storage::Storage>,
+    pub upload_limits: routes::uploads::UploadLimits,
+    pub link_previews: embeds::LinkPreviews,
+    pub message_deletion: routes::messages::MessageDeletion,
+    pub turn: routes::turn::TurnConfig,
+    /// `None` when voice channels can only be peer to peer
+    pub sfu: Option<routes::sfu::SfuConfig>,
+}
+
+/// Builds the full HTTP router. Shared by `main` and the integration tests.
+pub fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .nest("/api", routes::api_routes(state.clone()))
+        .merge(routes::turn_test::routes())
+        .route("/ws", axum::routing::get(ws::ws_handler))
+        .route("/uploads/{stored_name}", axum::routing::get(routes::uploads::serve_upload))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(tower_http::cors::Any)
+                .allow_methods(tower_http::cors::Any)
+                .allow_headers([
+                    axum::http::header::AUTHORIZATION,
+                    axum::http::header::CONTENT_TYPE,
+                ]),
+        )
+        .with_state(state)
+}
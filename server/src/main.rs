@@ -1,20 +1,7 @@
-mod auth;
-mod db;
-mod routes;
-mod ws;
-
-use std::sync::Arc;
-use axum::Router;
-use tower_http::cors::CorsLayer;
+use std::{sync::Arc, time::Duration};
+use server::{db, embeds, storage, ws, AppState};
 use tracing_subscriber::EnvFilter;
 
-pub struct AppState {
-    pub db: db::Database,
-    pub jwt_secret: String,
-    pub ws_state: ws::WsState,
-    pub upload_dir: String,
-}
-
 #[tokio::main]
 async fn main() {
     // Initialise tracing
@@ -22,37 +9,61 @@ async fn main() {
         .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
         .init();
 
+    // `server migrate-storage <from> <to> [--delete]` copies uploads
+    // between backends and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate-storage") {
+        migrate_storage(&args[1..]).await;
+        return;
+    }
+
     // Initialise database
     let db_path = std::env::var("DATABASE_URL").unwrap_or_else(|_| "subspace.db".into());
     let db = db::Database::new(&db_path).expect("Failed to initialise database");
     db.run_migrations().expect("Failed to run migrations");
+    // Nobody is connected yet, so nobody can be in voice
+    match db.clear_voice_states() {
+        Ok(0) => {}
+        Ok(stale) => tracing::info!("Cleared {stale} voice states left over from the last run"),
+        Err(e) => tracing::error!("Failed to clear voice states: {e}"),
+    }
+
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".into());
+    let storage = storage::from_env(&backend).expect("Failed to set up upload storage");
 
-    // Ensure uploads directory exists
-    let upload_dir = std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".into());
-    std::fs::create_dir_all(&upload_dir).expect("Failed to create uploads directory");
+    #[allow(unused_mut)]
+    let mut turn = server::routes::turn::TurnConfig::from_env();
+    #[cfg(feature = "embedded-turn")]
+    // Held until shutdown; dropping the server stops it
+    let _embedded_turn = server::turn_server::EmbeddedTurn::from_env(&mut turn).await;
 
     let state = Arc::new(AppState {
         db,
         jwt_secret: std::env::var("JWT_SECRET").unwrap_or_else(|_| "dev-secret-change-me".into()),
-        ws_state: ws::WsState::new(),
-        upload_dir,
+        ws_state: ws::WsState::with_heartbeat(ws::Heartbeat::from_env()),
+        storage,
+        upload_limits: server::routes::uploads::UploadLimits::from_env(),
+        link_previews: embeds::LinkPreviews::new(embeds::LinkPreviewConfig::from_env()),
+        message_deletion: server::routes::messages::MessageDeletion::from_env(),
+        turn,
+        sfu: server::routes::sfu::SfuConfig::from_env(),
     });
 
-    let app = Router::new()
-        .nest("/api", routes::api_routes(state.clone()))
-        .merge(routes::turn_test::routes())
-        .route("/ws", axum::routing::get(ws::ws_handler))
-        .nest_service("/uploads", tower_http::services::ServeDir::new(&state.upload_dir))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(tower_http::cors::Any)
-                .allow_methods(tower_http::cors::Any)
-                .allow_headers([
-                    axum::http::header::AUTHORIZATION,
-                    axum::http::header::CONTENT_TYPE,
-                ]),
-        )
-        .with_state(state);
+    // Collect uploads that were never attached to a message
+    let pending_upload_ttl = std::env::var("PENDING_UPLOAD_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(86_400);
+    tokio::spawn(server::routes::uploads::run_upload_gc(
+        state.clone(),
+        Duration::from_secs(pending_upload_ttl),
+        Duration::from_secs(15 * 60),
+    ));
+    tokio::spawn(embeds::run_cache_gc(state.clone(), Duration::from_secs(60 * 60)));
+    tokio::spawn(server::routes::messages::run_revision_gc(state.clone(), Duration::from_secs(60 * 60)));
+    tokio::spawn(server::routes::messages::run_message_purge(state.clone(), Duration::from_secs(60 * 60)));
+
+    let app = server::app(state);
 
     let addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3001".into());
     tracing::info!("Subspace server listening on {addr}");
@@ -60,3 +71,40 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
+
+async fn migrate_storage(args: &[String]) {
+    let (from, to, delete_source) = match args {
+        [from, to] => (from, to, false),
+        [from, to, flag] if flag == "--delete" => (from, to, true),
+        _ => {
+            eprintln!("Usage: server migrate-storage <local|s3> <local|s3> [--delete]");
+            std::process::exit(2);
+        }
+    };
+    let open = |name: &str| {
+        storage::from_env(name).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(2);
+        })
+    };
+    let (from, to) = (open(from), open(to));
+    match storage::migrate(from.as_ref(), to.as_ref(), delete_source).await {
+        Ok(report) => {
+            tracing::info!(
+                "Copied {} files from {} to {}, {} already present, {} failed",
+                report.copied,
+                from.name(),
+                to.name(),
+                report.skipped,
+                report.failed
+            );
+            if report.failed > 0 {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            tracing::error!("Storage migration failed: {e}");
+            std::process::exit(1);
+        }
+    }
+}
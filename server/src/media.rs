@@ -0,0 +1,365 @@
+//! Image processing for uploads: dimensions, blurhash placeholders, WebP
+//! thumbnails and previews, and metadata stripping. Videos get their
+//! dimensions and duration read from their container headers. Everything
+//! here is synchronous; callers run it on the blocking thread pool.
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use image::{metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use img_parts::{jpeg::markers, Bytes, DynImage, ImageEXIF};
+
+/// Longest side of the thumbnail shown in the message list.
+pub const THUMBNAIL_SIZE: u32 = 320;
+
+/// Longest side of the preview shown when an image is opened. Smaller
+/// images are shown from the original.
+pub const PREVIEW_SIZE: u32 = 1280;
+
+/// Images larger than this in either dimension are stored but not decoded.
+const MAX_DIMENSION: u32 = 16_384;
+
+/// Memory the decoder may allocate for one image.
+const MAX_DECODE_BYTES: u64 = 512 * 1024 * 1024;
+
+const XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// What was derived from an uploaded image.
+#[derive(Debug, Default)]
+pub struct ProcessedImage {
+    /// Display size, after applying the EXIF orientation
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: Option<String>,
+    /// WebP, at most [`THUMBNAIL_SIZE`] on the longest side
+    pub thumbnail: Option<Vec<u8>>,
+    /// WebP, at most [`PREVIEW_SIZE`] on the longest side; only produced
+    /// for larger images and when it comes out smaller than the original
+    pub preview: Option<Vec<u8>>,
+    /// The original with EXIF and XMP metadata removed, when stripping was
+    /// requested and there was something to strip
+    pub stripped: Option<Vec<u8>>,
+}
+
+/// Decodes `data` and derives its metadata and smaller renditions. Returns
+/// `None` for formats that cannot be decoded here (such as AVIF), images
+/// over the size limits and corrupt files; those are kept as they are.
+pub fn process_image(data: &[u8], strip_metadata: bool) -> Option<ProcessedImage> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format().ok()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().ok()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    image.apply_orientation(orientation);
+
+    let (width, height) = (image.width(), image.height());
+    let thumbnail = if width > THUMBNAIL_SIZE || height > THUMBNAIL_SIZE {
+        image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+    } else {
+        image.clone()
+    };
+    let preview = (width > PREVIEW_SIZE || height > PREVIEW_SIZE)
+        .then(|| encode_webp(&image.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE)))
+        .flatten()
+        .filter(|preview| preview.len() < data.len());
+
+    Some(ProcessedImage {
+        width,
+        height,
+        blurhash: blurhash(&thumbnail),
+        thumbnail: encode_webp(&thumbnail),
+        preview,
+        stripped: if strip_metadata { strip(data, orientation) } else { None },
+    })
+}
+
+fn encode_webp(image: &DynamicImage) -> Option<Vec<u8>> {
+    // The WebP encoder only takes 8-bit RGB(A)
+    let rgba = DynamicImage::ImageRgba8(image.to_rgba8());
+    let mut out = Cursor::new(Vec::new());
+    rgba.write_to(&mut out, ImageFormat::WebP).ok()?;
+    Some(out.into_inner())
+}
+
+fn blurhash(image: &DynamicImage) -> Option<String> {
+    // The hash only keeps a few components, so a tiny copy is plenty
+    let small = image.thumbnail(32, 32).to_rgba8();
+    let (x, y) = if small.width() >= small.height() { (4, 3) } else { (3, 4) };
+    blurhash::encode(x, y, small.width(), small.height(), small.as_raw()).ok()
+}
+
+/// Removes EXIF (including GPS) and XMP metadata from a JPEG, PNG or WebP.
+/// The orientation is kept so the image still displays upright. Returns
+/// `None` when there is nothing to remove.
+fn strip(data: &[u8], orientation: Orientation) -> Option<Vec<u8>> {
+    let mut image = DynImage::from_bytes(Bytes::copy_from_slice(data)).ok()??;
+    let mut changed = false;
+    if image.exif().is_some() {
+        let kept = (orientation != Orientation::NoTransforms).then(|| orientation_exif(orientation));
+        image.set_exif(kept);
+        changed = true;
+    }
+    if let DynImage::Jpeg(jpeg) = &mut image {
+        let before = jpeg.segments().len();
+        jpeg.segments_mut()
+            .retain(|s| !(s.marker() == markers::APP1 && s.contents().starts_with(XMP_PREFIX)));
+        changed |= jpeg.segments().len() != before;
+    }
+    changed.then(|| image.encoder().bytes().to_vec())
+}
+
+/// A minimal big-endian TIFF structure holding only the orientation tag.
+fn orientation_exif(orientation: Orientation) -> Bytes {
+    let mut exif = Vec::with_capacity(26);
+    exif.extend_from_slice(b"MM\0\x2a\0\0\0\x08");
+    exif.extend_from_slice(&1u16.to_be_bytes()); // one entry
+    exif.extend_from_slice(&0x0112u16.to_be_bytes()); // Orientation
+    exif.extend_from_slice(&3u16.to_be_bytes()); // SHORT
+    exif.extend_from_slice(&1u32.to_be_bytes()); // count
+    exif.extend_from_slice(&u16::from(orientation.to_exif()).to_be_bytes());
+    exif.extend_from_slice(&[0, 0]); // value padding
+    exif.extend_from_slice(&0u32.to_be_bytes()); // no next IFD
+    Bytes::from(exif)
+}
+
+/// What a video's container headers say about it.
+#[derive(Debug, Default, PartialEq)]
+pub struct VideoInfo {
+    /// Display size of the first video track, after its rotation
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_secs: Option<f64>,
+}
+
+/// Largest MP4 `moov` box read into memory.
+const MAX_MOOV_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How much of a WebM file is searched for its headers. Muxers put them
+/// ahead of the first cluster.
+const WEBM_HEADER_BYTES: u64 = 1024 * 1024;
+
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+
+/// Reads the dimensions and duration of an MP4, QuickTime or WebM file
+/// from its headers, without decoding anything. Returns `None` when the
+/// headers are missing or cannot be parsed.
+pub fn probe_video(file: &mut (impl Read + Seek)) -> Option<VideoInfo> {
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic).ok()?;
+    file.rewind().ok()?;
+    let info = if magic == EBML_MAGIC { probe_webm(file)? } else { probe_mp4(file)? };
+    (info != VideoInfo::default()).then_some(info)
+}
+
+/// Walks the top-level boxes, which may be large, by seeking past them and
+/// only reads `moov`.
+fn probe_mp4(file: &mut (impl Read + Seek)) -> Option<VideoInfo> {
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+    let mut pos = 0;
+    while pos + 8 <= len {
+        file.seek(SeekFrom::Start(pos)).ok()?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8]).ok()?;
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (len - pos, 8),
+            1 => {
+                file.read_exact(&mut header[8..]).ok()?;
+                (u64::from_be_bytes(header[8..].try_into().unwrap()), 16)
+            }
+            size => (size.into(), 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        if &header[4..8] == b"moov" {
+            if size - header_len > MAX_MOOV_BYTES {
+                return None;
+            }
+            let mut moov = vec![0; (size - header_len) as usize];
+            file.read_exact(&mut moov).ok()?;
+            return Some(parse_moov(&moov));
+        }
+        pos = pos.checked_add(size)?;
+    }
+    None
+}
+
+fn parse_moov(moov: &[u8]) -> VideoInfo {
+    let mut info = VideoInfo::default();
+    for (kind, body) in mp4_boxes(moov) {
+        match kind {
+            b"mvhd" => info.duration_secs = mvhd_duration(body),
+            b"trak" if info.width.is_none() && is_video_track(body) => {
+                if let Some((_, tkhd)) = mp4_boxes(body).find(|(kind, _)| *kind == b"tkhd") {
+                    if let Some((width, height)) = tkhd_size(tkhd) {
+                        info.width = Some(width);
+                        info.height = Some(height);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    info
+}
+
+/// The type and body of each box in `data`, stopping at the first one that
+/// does not fit.
+fn mp4_boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().unwrap()) as u64;
+        let kind: &[u8; 4] = data.get(4..8)?.try_into().unwrap();
+        let (size, header_len) = match size {
+            0 => (data.len() as u64, 8),
+            1 => (u64::from_be_bytes(data.get(8..16)?.try_into().unwrap()), 16),
+            size => (size, 8),
+        };
+        if size < header_len || size > data.len() as u64 {
+            return None;
+        }
+        let body = &data[header_len as usize..size as usize];
+        data = &data[size as usize..];
+        Some((kind, body))
+    })
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().unwrap()))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().unwrap()))
+}
+
+fn mvhd_duration(mvhd: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (be_u32(mvhd, 12)?, be_u32(mvhd, 16).map(u64::from).filter(|d| *d != u32::MAX as u64)?),
+        1 => (be_u32(mvhd, 20)?, be_u64(mvhd, 24).filter(|d| *d != u64::MAX)?),
+        _ => return None,
+    };
+    (timescale > 0 && duration > 0).then(|| duration as f64 / timescale as f64)
+}
+
+/// Whether the track's `mdia/hdlr` marks it as video.
+fn is_video_track(trak: &[u8]) -> bool {
+    mp4_boxes(trak)
+        .filter(|(kind, _)| *kind == b"mdia")
+        .flat_map(|(_, mdia)| mp4_boxes(mdia))
+        .any(|(kind, hdlr)| kind == b"hdlr" && hdlr.get(8..12) == Some(b"vide"))
+}
+
+/// The track's size in 16.16 fixed point, swapped when its matrix turns
+/// it a quarter turn as phones do for portrait video.
+fn tkhd_size(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let matrix = match tkhd.first()? {
+        0 => 40,
+        1 => 52,
+        _ => return None,
+    };
+    let (width, height) = (be_u32(tkhd, matrix + 36)? >> 16, be_u32(tkhd, matrix + 40)? >> 16);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let (a, d) = (be_u32(tkhd, matrix)?, be_u32(tkhd, matrix + 16)?);
+    Some(if a == 0 && d == 0 { (height, width) } else { (width, height) })
+}
+
+// Matroska element IDs
+const SEGMENT: u64 = 0x1853_8067;
+const INFO: u64 = 0x1549_A966;
+const TIMECODE_SCALE: u64 = 0x2A_D7B1;
+const DURATION: u64 = 0x4489;
+const TRACKS: u64 = 0x1654_AE6B;
+const TRACK_ENTRY: u64 = 0xAE;
+const VIDEO: u64 = 0xE0;
+const PIXEL_WIDTH: u64 = 0xB0;
+const PIXEL_HEIGHT: u64 = 0xBA;
+const CLUSTER: u64 = 0x1F43_B675;
+
+fn probe_webm(file: &mut impl Read) -> Option<VideoInfo> {
+    let mut head = Vec::new();
+    file.take(WEBM_HEADER_BYTES).read_to_end(&mut head).ok()?;
+    let (_, segment) = ebml_elements(&head).find(|(id, _)| *id == SEGMENT)?;
+
+    let mut info = VideoInfo::default();
+    let mut timecode_scale = 1_000_000;
+    let mut duration = None;
+    for (id, body) in ebml_elements(segment) {
+        match id {
+            INFO => {
+                for (id, body) in ebml_elements(body) {
+                    match id {
+                        TIMECODE_SCALE => timecode_scale = ebml_uint(body).unwrap_or(timecode_scale),
+                        DURATION => duration = ebml_float(body),
+                        _ => {}
+                    }
+                }
+            }
+            TRACKS => {
+                let video = ebml_elements(body)
+                    .filter(|(id, _)| *id == TRACK_ENTRY)
+                    .find_map(|(_, entry)| ebml_elements(entry).find(|(id, _)| *id == VIDEO));
+                for (id, body) in video.map(|(_, video)| ebml_elements(video)).into_iter().flatten() {
+                    let pixels = ebml_uint(body).and_then(|v| u32::try_from(v).ok()).filter(|v| *v > 0);
+                    match id {
+                        PIXEL_WIDTH => info.width = pixels,
+                        PIXEL_HEIGHT => info.height = pixels,
+                        _ => {}
+                    }
+                }
+            }
+            CLUSTER => break,
+            _ => {}
+        }
+    }
+    // Durations count in units of the timecode scale, in nanoseconds
+    info.duration_secs = duration
+        .map(|d| d * timecode_scale as f64 / 1e9)
+        .filter(|d| d.is_finite() && *d > 0.0);
+    Some(info)
+}
+
+/// An EBML variable-length integer: its value, with the length marker
+/// removed for sizes and kept for IDs, and how many bytes it took.
+fn ebml_vint(data: &[u8], strip_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let rest = data.get(1..len)?;
+    let first = if strip_marker { first & (0xFFu16 >> len) as u8 } else { first };
+    Some((rest.iter().fold(first.into(), |value, b| value << 8 | u64::from(*b)), len))
+}
+
+/// The ID and body of each element in `data`. An element of unknown size,
+/// or one cut off by the end of `data`, runs to the end.
+fn ebml_elements(mut data: &[u8]) -> impl Iterator<Item = (u64, &[u8])> {
+    std::iter::from_fn(move || {
+        let (id, id_len) = ebml_vint(data, false)?;
+        let (size, size_len) = ebml_vint(data.get(id_len..)?, true)?;
+        let start = id_len + size_len;
+        let unknown = size == (1 << (7 * size_len)) - 1;
+        let available = (data.len() - start) as u64;
+        let end = start + if unknown { available } else { size.min(available) } as usize;
+        let body = &data[start..end];
+        data = &data[end..];
+        Some((id, body))
+    })
+}
+
+fn ebml_uint(data: &[u8]) -> Option<u64> {
+    (data.len() <= 8).then(|| data.iter().fold(0, |value, b| value << 8 | u64::from(*b)))
+}
+
+fn ebml_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().unwrap()).into()),
+        8 => Some(f64::from_be_bytes(data.try_into().unwrap())),
+        _ => None,
+    }
+}
@@ -0,0 +1,61 @@
+//! `@username`, `@here` and `@everyone` in message content.
+
+/// Mentions found in a message, before they are resolved to members.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ParsedMentions {
+    /// Distinct usernames, in order of first appearance
+    pub usernames: Vec<String>,
+    pub here: bool,
+    pub everyone: bool,
+}
+
+impl ParsedMentions {
+    pub fn is_empty(&self) -> bool {
+        self.usernames.is_empty() && !self.here && !self.everyone
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Finds mentions in `content`. An `@` only starts a mention at the start of
+/// the text or after a character that cannot be part of a name, so email
+/// addresses are ignored. Trailing dots are treated as punctuation.
+pub fn parse(content: &str) -> ParsedMentions {
+    let mut parsed = ParsedMentions::default();
+    let mut prev: Option<char> = None;
+    let mut chars = content.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let starts_mention = c == '@' && !prev.is_some_and(is_name_char);
+        prev = Some(c);
+        if !starts_mention {
+            continue;
+        }
+
+        let start = i + 1;
+        let mut end = start;
+        while let Some(&(j, n)) = chars.peek() {
+            if !is_name_char(n) {
+                break;
+            }
+            end = j + n.len_utf8();
+            prev = Some(n);
+            chars.next();
+        }
+        let name = content[start..end].trim_end_matches('.');
+
+        match name {
+            "" => {}
+            "here" => parsed.here = true,
+            "everyone" => parsed.everyone = true,
+            _ => {
+                if !parsed.usernames.iter().any(|u| u == name) {
+                    parsed.usernames.push(name.to_string());
+                }
+            }
+        }
+    }
+    parsed
+}
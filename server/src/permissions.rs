@@ -0,0 +1,203 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bitflags::bitflags;
+
+use crate::db::Database;
+
+bitflags! {
+    /// Server permission bitset stored in `roles.permissions` and in the
+    /// allow/deny columns of `channel_permission_overrides`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Permissions: i64 {
+        const MANAGE_CHANNELS = 1 << 0;
+        const MANAGE_MESSAGES = 1 << 1;
+        const PIN_MESSAGES    = 1 << 2;
+        const KICK_MEMBERS    = 1 << 3;
+        const MANAGE_ROLES    = 1 << 4;
+        const CONNECT_VOICE   = 1 << 5;
+        const SPEAK           = 1 << 6;
+        const MANAGE_SERVER   = 1 << 7;
+        const CREATE_INVITE   = 1 << 8;
+        const MUTE_MEMBERS    = 1 << 9;
+        const DEAFEN_MEMBERS  = 1 << 10;
+        const MOVE_MEMBERS    = 1 << 11;
+    }
+}
+
+impl Permissions {
+    /// Granted to the @everyone role of newly created servers.
+    pub const EVERYONE_DEFAULT: Permissions = Permissions::CONNECT_VOICE
+        .union(Permissions::SPEAK)
+        .union(Permissions::CREATE_INVITE);
+
+    /// Lower-case names of the set flags, as reported in 403 responses.
+    pub fn names(self) -> Vec<String> {
+        self.iter_names().map(|(name, _)| name.to_lowercase()).collect()
+    }
+}
+
+/// What a permission check is evaluated against. Channels, messages and
+/// threads are resolved to their server before membership is checked.
+#[derive(Debug, Clone, Copy)]
+pub enum Target<'a> {
+    Server(&'a str),
+    Channel(&'a str),
+    Message(&'a str),
+    Thread(&'a str),
+}
+
+/// Why a permission check failed. Converts into a JSON error response.
+#[derive(Debug)]
+pub enum PermissionError {
+    NotFound,
+    NotMember,
+    Missing(Permissions),
+    Db(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for PermissionError {
+    fn from(e: rusqlite::Error) -> Self {
+        PermissionError::Db(e)
+    }
+}
+
+impl PermissionError {
+    /// Sends the failure back over a WebSocket connection as an `error` event.
+    pub fn to_ws_error(&self) -> shared::ws_messages::WsError {
+        let (message, code) = match self {
+            PermissionError::NotFound => ("Not found".to_string(), "not_found"),
+            PermissionError::NotMember => ("You are not a member of this server".to_string(), "not_member"),
+            PermissionError::Missing(missing) => (
+                format!("Missing permissions: {}", missing.names().join(", ")),
+                "missing_permissions",
+            ),
+            PermissionError::Db(_) => ("Internal error".to_string(), "internal"),
+        };
+        shared::ws_messages::WsError {
+            message,
+            code: Some(code.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for PermissionError {
+    fn into_response(self) -> Response {
+        match self {
+            PermissionError::NotFound => (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error": "Not found", "code": "not_found"})),
+            )
+                .into_response(),
+            PermissionError::NotMember => (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "error": "You are not a member of this server",
+                    "code": "not_member",
+                })),
+            )
+                .into_response(),
+            PermissionError::Missing(missing) => (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "error": "Missing permissions",
+                    "code": "missing_permissions",
+                    "missing": missing.names(),
+                })),
+            )
+                .into_response(),
+            PermissionError::Db(e) => {
+                tracing::error!("Permission check failed: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Checked context returned by [`require`].
+#[derive(Debug, Clone)]
+pub struct Granted {
+    pub server_id: String,
+    /// Set when the target was a channel or a message or thread in one
+    pub channel_id: Option<String>,
+    pub permissions: Permissions,
+}
+
+/// Resolves the user's effective permissions on `target` and checks that all
+/// of `required` are present. Passing `Permissions::empty()` only checks
+/// membership.
+pub fn require(
+    db: &Database,
+    user_id: &str,
+    target: Target<'_>,
+    required: Permissions,
+) -> Result<Granted, PermissionError> {
+    let server_of = |channel_id: &str| -> Result<String, PermissionError> {
+        db.get_channel_server_id(channel_id)?
+            .ok_or(PermissionError::NotFound)
+    };
+    let (server_id, channel_id) = match target {
+        Target::Server(server_id) => (server_id.to_string(), None),
+        Target::Channel(channel_id) => (server_of(channel_id)?, Some(channel_id.to_string())),
+        Target::Message(message_id) => {
+            let channel_id = db
+                .get_message_channel(message_id)?
+                .ok_or(PermissionError::NotFound)?;
+            (server_of(&channel_id)?, Some(channel_id))
+        }
+        Target::Thread(thread_id) => {
+            let channel_id = db
+                .get_thread(thread_id)?
+                .ok_or(PermissionError::NotFound)?
+                .channel_id;
+            (server_of(&channel_id)?, Some(channel_id))
+        }
+    };
+
+    let info = db
+        .get_member_permission_info(user_id, &server_id)?
+        .ok_or(PermissionError::NotMember)?;
+
+    let permissions = if info.owner_id == user_id || info.legacy_role == "owner" || info.legacy_role == "admin" {
+        Permissions::all()
+    } else {
+        let mut perms = info
+            .roles
+            .iter()
+            .fold(Permissions::empty(), |acc, (_, bits)| {
+                acc | Permissions::from_bits_truncate(*bits)
+            });
+
+        if let Some(channel_id) = &channel_id {
+            let overrides = db.get_channel_overrides(channel_id)?;
+            // @everyone first, then the union of the member's role overrides
+            if let Some(o) = overrides.iter().find(|o| o.role_id == server_id) {
+                perms = (perms - Permissions::from_bits_truncate(o.deny))
+                    | Permissions::from_bits_truncate(o.allow);
+            }
+            let (allow, deny) = overrides
+                .iter()
+                .filter(|o| o.role_id != server_id && info.roles.iter().any(|(id, _)| *id == o.role_id))
+                .fold((Permissions::empty(), Permissions::empty()), |(a, d), o| {
+                    (
+                        a | Permissions::from_bits_truncate(o.allow),
+                        d | Permissions::from_bits_truncate(o.deny),
+                    )
+                });
+            perms = (perms - deny) | allow;
+        }
+        perms
+    };
+
+    if !permissions.contains(required) {
+        return Err(PermissionError::Missing(required - permissions));
+    }
+
+    Ok(Granted {
+        server_id,
+        channel_id,
+        permissions,
+    })
+}
@@ -0,0 +1,63 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use shared::models::AuditLogEntry;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    db::AuditLogRow,
+    permissions::{self, Permissions, Target},
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct AuditLogQuery {
+    pub limit: Option<i32>,
+    /// Id of the oldest entry already seen
+    pub before: Option<String>,
+}
+
+fn parse_id(id: Option<String>) -> Option<Uuid> {
+    id.and_then(|id| Uuid::parse_str(&id).ok())
+}
+
+fn entry_from_row(row: AuditLogRow) -> AuditLogEntry {
+    AuditLogEntry {
+        id: Uuid::parse_str(&row.id).unwrap(),
+        server_id: Uuid::parse_str(&row.server_id).unwrap(),
+        actor_id: parse_id(row.actor_id),
+        action: row.action,
+        target_user_id: parse_id(row.target_user_id),
+        channel_id: parse_id(row.channel_id),
+        to_channel_id: parse_id(row.to_channel_id),
+        created_at: row.created_at,
+    }
+}
+
+/// A server's moderator actions, newest first. Needs MANAGE_SERVER.
+pub async fn get_audit_log(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    Query(query): Query<AuditLogQuery>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_SERVER) {
+        return e.into_response();
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+
+    match state.db.get_audit_log(&server_id, query.before.as_deref(), limit) {
+        Ok(rows) => Json(rows.into_iter().map(entry_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to load audit log: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -4,28 +4,120 @@ use axum::{
     response::IntoResponse,
     Json,
 };
+use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{auth::AuthUser, AppState};
-use shared::models::{Channel, CreateChannelRequest};
+use crate::{
+    auth::AuthUser,
+    db::{ChannelCategoryRow, ChannelReadRow, ChannelRow},
+    permissions::{self, Permissions, Target},
+    AppState,
+};
+use shared::models::{
+    Channel, ChannelCategory, CreateCategoryRequest, CreateChannelRequest, ReorderChannelsRequest,
+    UpdateCategoryRequest, UpdateChannelRequest,
+};
+use shared::ws_messages::{
+    WsCategoryCreated, WsCategoryDeleted, WsCategoryUpdated, WsChannelCreated, WsChannelDeleted, WsChannelUpdated,
+    WsEnvelope,
+};
+
+pub const MAX_NAME_CHARS: usize = 100;
+pub const MAX_TOPIC_CHARS: usize = 1024;
+/// Six hours, the longest slowmode a channel can have
+pub const MAX_SLOWMODE_SECS: i64 = 6 * 60 * 60;
+pub const VOICE_MODES: [&str; 2] = ["mesh", "sfu"];
+
+/// Builds the API channel; without a read state the channel counts as read.
+pub fn channel_from_row(r: ChannelRow, read_state: Option<&ChannelReadRow>) -> Channel {
+    Channel {
+        id: Uuid::parse_str(&r.id).unwrap(),
+        server_id: Uuid::parse_str(&r.server_id).unwrap(),
+        name: r.name,
+        channel_type: r.channel_type,
+        position: r.position,
+        created_at: r.created_at,
+        updated_at: r.updated_at,
+        unread_count: read_state.map_or(0, |rs| rs.unread_count),
+        mention_count: read_state.map_or(0, |rs| rs.mention_count),
+        last_read_message_id: read_state
+            .and_then(|rs| rs.last_read_message_id.as_deref())
+            .map(|id| Uuid::parse_str(id).unwrap()),
+        category_id: r.category_id.map(|id| Uuid::parse_str(&id).unwrap()),
+        topic: r.topic,
+        slowmode_secs: r.slowmode_secs,
+        nsfw: r.nsfw,
+        voice_mode: r.voice_mode,
+    }
+}
+
+fn category_from_row(r: ChannelCategoryRow) -> ChannelCategory {
+    ChannelCategory {
+        id: Uuid::parse_str(&r.id).unwrap(),
+        server_id: Uuid::parse_str(&r.server_id).unwrap(),
+        name: r.name,
+        position: r.position,
+        created_at: r.created_at,
+    }
+}
+
+fn bad_request(error: &str, code: &str) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({"error": error, "code": code})),
+    )
+        .into_response()
+}
+
+/// Trims a channel or category name, rejecting empty and overlong ones.
+fn valid_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    (!name.is_empty() && name.chars().count() <= MAX_NAME_CHARS).then_some(name)
+}
+
+async fn broadcast<T: Serialize>(state: &AppState, server_id: &str, msg_type: &str, payload: T) {
+    let env = WsEnvelope {
+        msg_type: msg_type.to_string(),
+        payload: serde_json::to_value(payload).unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_server(server_id, &serde_json::to_string(&env).unwrap())
+        .await;
+}
+
+async fn read_body<T: serde::de::DeserializeOwned>(req: axum::http::Request<axum::body::Body>) -> Option<T> {
+    let bytes = axum::body::to_bytes(req.into_body(), 1_000_000).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
 
 pub async fn list_channels(
     State(state): State<Arc<AppState>>,
     Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    let read_states: HashMap<String, ChannelReadRow> = match state.db.get_channel_read_states(&user.user_id, Some(&server_id)) {
+        Ok(rows) => rows.into_iter().map(|r| (r.channel_id.clone(), r)).collect(),
+        Err(e) => {
+            tracing::error!("Failed to get read states: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
     match state.db.get_channels_for_server(&server_id) {
         Ok(rows) => {
             let channels: Vec<Channel> = rows
                 .into_iter()
-                .map(|r| Channel {
-                    id: Uuid::parse_str(&r.id).unwrap(),
-                    server_id: Uuid::parse_str(&r.server_id).unwrap(),
-                    name: r.name,
-                    channel_type: r.channel_type,
-                    position: r.position,
-                    created_at: r.created_at,
-                    updated_at: r.updated_at,
+                .map(|r| {
+                    let read_state = read_states.get(&r.id);
+                    channel_from_row(r, read_state)
                 })
                 .collect();
             Json(channels).into_response()
@@ -42,33 +134,44 @@ pub async fn create_channel(
     Path(server_id): Path<String>,
     req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
-    let _user = req.extensions().get::<AuthUser>().unwrap().clone();
-
-    let body: CreateChannelRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
-        Ok(bytes) => match serde_json::from_slice(&bytes) {
-            Ok(b) => b,
-            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
-        },
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_CHANNELS) {
+        return e.into_response();
+    }
+
+    let Some(body) = read_body::<CreateChannelRequest>(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some(name) = valid_name(&body.name) else {
+        return bad_request("Channel names must be 1 to 100 characters", "invalid_name");
     };
+    if body.channel_type != "text" && body.channel_type != "voice" {
+        return bad_request("Channels are either text or voice", "invalid_type");
+    }
+    let category_id = body.category_id.map(|id| id.to_string());
+    if let Some(category_id) = &category_id {
+        match state.db.get_category(category_id) {
+            Ok(Some(category)) if category.server_id == server_id => {}
+            Ok(_) => return bad_request("Category not found in this server", "invalid_category"),
+            Err(e) => {
+                tracing::error!("Failed to get category: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
 
     let id = Uuid::new_v4();
-    match state
+    let created = state
         .db
-        .create_channel(&id, &server_id, &body.name, &body.channel_type)
-    {
-        Ok(()) => {
-            let channel = Channel {
-                id,
-                server_id: Uuid::parse_str(&server_id).unwrap(),
-                name: body.name,
-                channel_type: body.channel_type,
-                position: 0,
-                created_at: String::new(),
-                updated_at: String::new(),
-            };
+        .create_channel(&id, &server_id, name, &body.channel_type, category_id.as_deref())
+        .and_then(|()| state.db.get_channel(&id.to_string()));
+    match created {
+        Ok(Some(row)) => {
+            let channel = channel_from_row(row, None);
+            broadcast(&state, &server_id, "channel_created", WsChannelCreated { channel: channel.clone() }).await;
             (StatusCode::CREATED, Json(channel)).into_response()
         }
+        Ok(None) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         Err(e) => {
             tracing::error!("Failed to create channel: {e}");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -76,15 +179,273 @@ pub async fn create_channel(
     }
 }
 
+/// Renames a channel or changes its topic, slowmode, NSFW flag or, for
+/// voice channels, whether media goes peer to peer or through the SFU.
+pub async fn update_channel(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let Some(body) = read_body::<UpdateChannelRequest>(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::MANAGE_CHANNELS) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+
+    let name = match body.name.as_deref().map(valid_name) {
+        Some(None) => return bad_request("Channel names must be 1 to 100 characters", "invalid_name"),
+        Some(name) => name,
+        None => None,
+    };
+    let topic = body.topic.as_deref().map(str::trim);
+    if topic.is_some_and(|t| t.chars().count() > MAX_TOPIC_CHARS) {
+        return bad_request("Topics must be at most 1024 characters", "invalid_topic");
+    }
+    if body.slowmode_secs.is_some_and(|s| !(0..=MAX_SLOWMODE_SECS).contains(&s)) {
+        return bad_request("Slowmode must be between 0 and 21600 seconds", "invalid_slowmode");
+    }
+    if let Some(mode) = body.voice_mode.as_deref() {
+        if !VOICE_MODES.contains(&mode) {
+            return bad_request("Voice mode must be mesh or sfu", "invalid_voice_mode");
+        }
+        let is_voice = matches!(state.db.get_channel(&channel_id), Ok(Some(c)) if c.channel_type == "voice");
+        if !is_voice {
+            return bad_request("Only voice channels have a voice mode", "invalid_voice_mode");
+        }
+        if mode == "sfu" && state.sfu.is_none() {
+            return bad_request("This server has no SFU set up", "sfu_unavailable");
+        }
+    }
+
+    let updated = state
+        .db
+        .update_channel(&channel_id, name, topic, body.slowmode_secs, body.nsfw, body.voice_mode.as_deref())
+        .and_then(|()| state.db.get_channel(&channel_id));
+    match updated {
+        Ok(Some(row)) => {
+            let channel = channel_from_row(row, None);
+            broadcast(&state, &granted.server_id, "channel_updated", WsChannelUpdated { channel: channel.clone() }).await;
+            Json(channel).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to update channel: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 pub async fn delete_channel(
     State(state): State<Arc<AppState>>,
     Path(channel_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::MANAGE_CHANNELS) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+
     match state.db.delete_channel(&channel_id) {
-        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Ok(()) => {
+            let deleted = WsChannelDeleted {
+                server_id: Uuid::parse_str(&granted.server_id).unwrap(),
+                channel_id: Uuid::parse_str(&channel_id).unwrap(),
+            };
+            broadcast(&state, &granted.server_id, "channel_deleted", deleted).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
         Err(e) => {
             tracing::error!("Failed to delete channel: {e}");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
+
+/// Moves channels within and between categories, and reorders categories,
+/// in one step. Either every listed item moves or none does. Each moved
+/// channel is broadcast as `channel_updated` and each category as
+/// `category_updated`.
+pub async fn reorder_channels(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let Some(body) = read_body::<ReorderChannelsRequest>(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_CHANNELS) {
+        return e.into_response();
+    }
+
+    let channels: Vec<(String, Option<String>, i32)> = body
+        .channels
+        .iter()
+        .map(|c| (c.id.to_string(), c.category_id.map(|id| id.to_string()), c.position))
+        .collect();
+    let categories: Vec<(String, i32)> = body.categories.iter().map(|c| (c.id.to_string(), c.position)).collect();
+    match state.db.reorder_channels(&server_id, &channels, &categories) {
+        Ok(true) => {}
+        Ok(false) => {
+            return bad_request("Every channel and category must belong to this server", "invalid_layout");
+        }
+        Err(e) => {
+            tracing::error!("Failed to reorder channels: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    for (category_id, _) in &categories {
+        if let Ok(Some(row)) = state.db.get_category(category_id) {
+            let category = category_from_row(row);
+            broadcast(&state, &server_id, "category_updated", WsCategoryUpdated { category }).await;
+        }
+    }
+    for (channel_id, _, _) in &channels {
+        if let Ok(Some(row)) = state.db.get_channel(channel_id) {
+            let channel = channel_from_row(row, None);
+            broadcast(&state, &server_id, "channel_updated", WsChannelUpdated { channel }).await;
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub async fn list_categories(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    match state.db.get_categories_for_server(&server_id) {
+        Ok(rows) => Json(rows.into_iter().map(category_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list categories: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn create_category(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_CHANNELS) {
+        return e.into_response();
+    }
+
+    let Some(body) = read_body::<CreateCategoryRequest>(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some(name) = valid_name(&body.name) else {
+        return bad_request("Category names must be 1 to 100 characters", "invalid_name");
+    };
+
+    let id = Uuid::new_v4();
+    let created = state
+        .db
+        .create_category(&id, &server_id, name)
+        .and_then(|()| state.db.get_category(&id.to_string()));
+    match created {
+        Ok(Some(row)) => {
+            let category = category_from_row(row);
+            broadcast(&state, &server_id, "category_created", WsCategoryCreated { category: category.clone() }).await;
+            (StatusCode::CREATED, Json(category)).into_response()
+        }
+        Ok(None) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to create category: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn update_category(
+    State(state): State<Arc<AppState>>,
+    Path(category_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let Some(body) = read_body::<UpdateCategoryRequest>(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let category = match state.db.get_category(&category_id) {
+        Ok(Some(c)) => c,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get category: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&category.server_id), Permissions::MANAGE_CHANNELS) {
+        return e.into_response();
+    }
+    let Some(name) = valid_name(&body.name) else {
+        return bad_request("Category names must be 1 to 100 characters", "invalid_name");
+    };
+
+    let updated = state
+        .db
+        .rename_category(&category_id, name)
+        .and_then(|()| state.db.get_category(&category_id));
+    match updated {
+        Ok(Some(row)) => {
+            let category = category_from_row(row);
+            let server_id = category.server_id.to_string();
+            broadcast(&state, &server_id, "category_updated", WsCategoryUpdated { category: category.clone() }).await;
+            Json(category).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to update category: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Deletes a category. Its channels stay, outside any category.
+pub async fn delete_category(
+    State(state): State<Arc<AppState>>,
+    Path(category_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let category = match state.db.get_category(&category_id) {
+        Ok(Some(c)) => c,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get category: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&category.server_id), Permissions::MANAGE_CHANNELS) {
+        return e.into_response();
+    }
+
+    match state.db.delete_category(&category_id) {
+        Ok(()) => {
+            let deleted = WsCategoryDeleted {
+                server_id: Uuid::parse_str(&category.server_id).unwrap(),
+                category_id: Uuid::parse_str(&category_id).unwrap(),
+            };
+            broadcast(&state, &category.server_id, "category_deleted", deleted).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete category: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
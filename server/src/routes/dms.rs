@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, Request, State},
+    extract::{Path, Query, Request, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
@@ -8,12 +8,71 @@ use serde_json::json;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{auth::AuthUser, AppState};
+use crate::{
+    auth::AuthUser,
+    db::DmMessageRow,
+    permissions::PermissionError,
+    routes::{
+        messages::{revision_from_row, unknown_anchor, MessageQuery},
+        uploads,
+    },
+    AppState,
+};
 use shared::models::{
-    Attachment, CreateDmMessageRequest, CreateDmRequest, DmConversation, DmMessage, ReactionGroup,
+    CreateDmMessageRequest, CreateDmRequest, DmConversation, DmMessage, ReactionGroup,
     UserPublic,
 };
 
+/// 403 for someone changing the other participant's message.
+fn not_author(action: &str) -> axum::response::Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({"error": format!("Only the author can {action} this message"), "code": "not_author"})),
+    )
+        .into_response()
+}
+
+/// Builds the API model for a stored DM, loading its attachments and the
+/// reactions as seen by `viewer_id`.
+pub fn dm_message_from_row(state: &AppState, row: DmMessageRow, viewer_id: &str) -> DmMessage {
+    let attachments = state
+        .db
+        .get_dm_attachments(&row.id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|a| uploads::attachment_from_row(&state.jwt_secret, a))
+        .collect();
+
+    let reactions = state
+        .db
+        .get_dm_reactions(&row.id, viewer_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|r| ReactionGroup {
+            emoji: r.emoji,
+            count: r.count,
+            me: r.me,
+        })
+        .collect();
+
+    DmMessage {
+        id: Uuid::parse_str(&row.id).unwrap(),
+        conversation_id: Uuid::parse_str(&row.conversation_id).unwrap(),
+        author_id: Uuid::parse_str(&row.author_id).unwrap(),
+        content: row.content,
+        created_at: row.created_at,
+        edited_at: row.edited_at,
+        author: Some(UserPublic {
+            id: Uuid::parse_str(&row.author_id).unwrap(),
+            username: row.author_username,
+            avatar_url: row.author_avatar_url,
+            status: None,
+        }),
+        attachments,
+        reactions,
+    }
+}
+
 // ────────────────────────────────────────────────────────────────────────────
 // List DM Conversations
 // ────────────────────────────────────────────────────────────────────────────
@@ -23,6 +82,14 @@ pub async fn list_conversations(
     req: Request,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let read_states: std::collections::HashMap<String, crate::db::DmReadRow> = match state.db.get_dm_read_states(&user.user_id) {
+        Ok(rows) => rows.into_iter().map(|r| (r.conversation_id.clone(), r)).collect(),
+        Err(e) => {
+            tracing::error!("Failed to get DM read states: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
     match state.db.get_dm_conversations(&user.user_id) {
         Ok(rows) => {
             let mut conversations = Vec::new();
@@ -52,6 +119,8 @@ pub async fn list_conversations(
                         attachments: vec![],
                         reactions: vec![],
                     });
+                let read_state = read_states.get(&row.id);
+                let unread_count = read_state.map_or(0, |r| r.unread_count);
 
                 conversations.push(DmConversation {
                     id: conv_id,
@@ -66,6 +135,12 @@ pub async fn list_conversations(
                     last_message,
                     created_at: row.created_at,
                     updated_at: row.updated_at,
+                    unread_count,
+                    // Every DM is addressed to the reader, so each unread one counts
+                    mention_count: unread_count,
+                    last_read_message_id: read_state
+                        .and_then(|r| r.last_read_message_id.as_deref())
+                        .map(|id| Uuid::parse_str(id).unwrap()),
                 });
             }
 
@@ -168,6 +243,9 @@ pub async fn create_conversation(
         last_message: None,
         created_at: conv_row.created_at,
         updated_at: conv_row.updated_at,
+        unread_count: 0,
+        mention_count: 0,
+        last_read_message_id: None,
     })
     .into_response()
 }
@@ -179,6 +257,7 @@ pub async fn create_conversation(
 pub async fn get_messages(
     State(state): State<Arc<AppState>>,
     Path(conversation_id): Path<Uuid>,
+    Query(query): Query<MessageQuery>,
     req: Request,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
@@ -203,61 +282,28 @@ pub async fn get_messages(
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
-    // Fetch messages
-    match state.db.get_dm_messages(&conversation_id.to_string()) {
-        Ok(rows) => {
-            let mut messages = Vec::new();
-
-            for row in rows {
-                let msg_id = Uuid::parse_str(&row.id).unwrap();
-
-                // Fetch attachments
-                let attachments = state
-                    .db
-                    .get_dm_attachments(&msg_id.to_string())
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|a| Attachment {
-                        id: Uuid::parse_str(&a.id).unwrap(),
-                        message_id: Uuid::parse_str(&a.message_id).unwrap(),
-                        file_url: a.file_url,
-                        file_name: a.file_name,
-                        mime_type: a.mime_type,
-                        size_bytes: a.size_bytes,
-                        created_at: a.created_at,
-                    })
-                    .collect();
-
-                // Fetch reactions
-                let reactions = state
-                    .db
-                    .get_dm_reactions(&msg_id.to_string(), &user.user_id)
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|r| ReactionGroup {
-                        emoji: r.emoji,
-                        count: r.count,
-                        me: r.me,
-                    })
-                    .collect();
-
-                messages.push(DmMessage {
-                    id: msg_id,
-                    conversation_id: Uuid::parse_str(&row.conversation_id).unwrap(),
-                    author_id: Uuid::parse_str(&row.author_id).unwrap(),
-                    content: row.content,
-                    created_at: row.created_at,
-                    edited_at: row.edited_at,
-                    author: Some(UserPublic {
-                        id: Uuid::parse_str(&row.author_id).unwrap(),
-                        username: row.author_username,
-                        avatar_url: row.author_avatar_url,
-                        status: None,
-                    }),
-                    attachments,
-                    reactions,
-                });
+    let conversation_id = conversation_id.to_string();
+    let (cursor, anchor) = match query.cursor() {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(anchor) = anchor {
+        match state.db.get_dm_message_info(anchor) {
+            Ok(Some((_, cid))) if cid == conversation_id => {}
+            Ok(_) => return unknown_anchor(),
+            Err(e) => {
+                tracing::error!("Failed to look up cursor message: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
+        }
+    }
+
+    match state.db.get_dm_messages(&conversation_id, query.limit(), cursor) {
+        Ok(rows) => {
+            let messages: Vec<DmMessage> = rows
+                .into_iter()
+                .map(|row| dm_message_from_row(&state, row, &user.user_id))
+                .collect();
 
             Json(messages).into_response()
         }
@@ -308,6 +354,10 @@ pub async fn create_message(
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
+    let Some(upload_ids) = uploads::attachment_ids(&body.attachment_ids) else {
+        return uploads::invalid_attachment();
+    };
+
     // Create message
     let msg_id = Uuid::new_v4();
     match state.db.create_dm_message(
@@ -315,24 +365,10 @@ pub async fn create_message(
         &conversation_id.to_string(),
         &user.user_id,
         body.content.as_deref(),
+        &upload_ids,
     ) {
-        Ok(row) => {
-            let message = DmMessage {
-                id: msg_id,
-                conversation_id: Uuid::parse_str(&row.conversation_id).unwrap(),
-                author_id: Uuid::parse_str(&row.author_id).unwrap(),
-                content: row.content,
-                created_at: row.created_at,
-                edited_at: row.edited_at,
-                author: Some(UserPublic {
-                    id: Uuid::parse_str(&row.author_id).unwrap(),
-                    username: row.author_username,
-                    avatar_url: row.author_avatar_url,
-                    status: None,
-                }),
-                attachments: vec![],
-                reactions: vec![],
-            };
+        Ok(Some(row)) => {
+            let message = dm_message_from_row(&state, row, &user.user_id);
 
             // Broadcast via WebSocket
             let ws_msg = shared::ws_messages::WsEnvelope {
@@ -355,6 +391,7 @@ pub async fn create_message(
 
             (StatusCode::CREATED, Json(message)).into_response()
         }
+        Ok(None) => uploads::invalid_attachment(),
         Err(e) => {
             tracing::error!("Failed to create DM message: {e}");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -393,7 +430,7 @@ pub async fn edit_message(
             Uuid::parse_str(&conv).unwrap(),
         ),
         Ok(None) => {
-            return (StatusCode::NOT_FOUND, "Message not found").into_response();
+            return PermissionError::NotFound.into_response();
         }
         Err(e) => {
             tracing::error!("Failed to get message info: {e}");
@@ -401,28 +438,32 @@ pub async fn edit_message(
         }
     };
 
+    // Get conversation users; the message doesn't exist for anyone else
+    let (user1, user2) = match state
+        .db
+        .get_dm_conversation_users(&conversation_id.to_string())
+    {
+        Ok(Some((u1, u2))) if u1 == user.user_id || u2 == user.user_id => (u1, u2),
+        Ok(_) => return PermissionError::NotFound.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get conversation users: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
     if author_id != user_id {
-        return (StatusCode::FORBIDDEN, "Not your message").into_response();
+        return not_author("edit");
     }
 
     // Update message
     if let Err(e) = state
         .db
-        .edit_dm_message(&message_id.to_string(), body.content.as_deref().unwrap_or(""))
+        .edit_dm_message(&message_id.to_string(), &user.user_id, body.content.as_deref().unwrap_or(""))
     {
         tracing::error!("Failed to edit DM message: {e}");
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
 
-    // Get conversation users for broadcast
-    let (user1, user2) = match state
-        .db
-        .get_dm_conversation_users(&conversation_id.to_string())
-    {
-        Ok(Some((u1, u2))) => (u1, u2),
-        _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-    };
-
     // Broadcast update
     let ws_msg = shared::ws_messages::WsEnvelope {
         msg_type: "dm_message_updated".to_string(),
@@ -441,6 +482,45 @@ pub async fn edit_message(
     Json(json!({ "success": true })).into_response()
 }
 
+// ────────────────────────────────────────────────────────────────────────────
+// DM Message History
+// ────────────────────────────────────────────────────────────────────────────
+
+/// Earlier versions of a DM, oldest first. Visible to both participants.
+pub async fn get_message_history(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<Uuid>,
+    req: Request,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let message_id = message_id.to_string();
+
+    let conversation_id = match state.db.get_dm_message_info(&message_id) {
+        Ok(Some((_, conv))) => conv,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Message not found").into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message info: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match state.db.get_dm_conversation_users(&conversation_id) {
+        Ok(Some((u1, u2))) if u1 == user.user_id || u2 == user.user_id => {}
+        Ok(_) => return (StatusCode::FORBIDDEN, "Access denied").into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get conversation users: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match state.db.get_dm_message_revisions(&message_id) {
+        Ok(rows) => Json(rows.into_iter().map(revision_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get DM message history: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 // ────────────────────────────────────────────────────────────────────────────
 // Delete DM Message
 // ────────────────────────────────────────────────────────────────────────────
@@ -463,7 +543,7 @@ pub async fn delete_message(
             Uuid::parse_str(&conv).unwrap(),
         ),
         Ok(None) => {
-            return (StatusCode::NOT_FOUND, "Message not found").into_response();
+            return PermissionError::NotFound.into_response();
         }
         Err(e) => {
             tracing::error!("Failed to get message info: {e}");
@@ -471,19 +551,23 @@ pub async fn delete_message(
         }
     };
 
-    if author_id != user_id {
-        return (StatusCode::FORBIDDEN, "Not your message").into_response();
-    }
-
-    // Get conversation users before deleting
+    // Get conversation users; the message doesn't exist for anyone else
     let (user1, user2) = match state
         .db
         .get_dm_conversation_users(&conversation_id.to_string())
     {
-        Ok(Some((u1, u2))) => (u1, u2),
-        _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Ok(Some((u1, u2))) if u1 == user.user_id || u2 == user.user_id => (u1, u2),
+        Ok(_) => return PermissionError::NotFound.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get conversation users: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
     };
 
+    if author_id != user_id {
+        return not_author("delete");
+    }
+
     // Delete message
     if let Err(e) = state.db.delete_dm_message(&message_id.to_string()) {
         tracing::error!("Failed to delete DM message: {e}");
@@ -0,0 +1,232 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use rand::Rng;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    db::InviteRow,
+    permissions::{self, Permissions, Target},
+    routes::servers::{announce_member_joined, server_from_row},
+    AppState,
+};
+use shared::models::{CreateInviteRequest, Invite, InviteAccepted, InvitePreview};
+
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+const CODE_LEN: usize = 10;
+
+/// Random base62 invite code, each character drawn uniformly.
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn invite_from_row(r: InviteRow) -> Invite {
+    Invite {
+        code: r.code,
+        server_id: Uuid::parse_str(&r.server_id).unwrap(),
+        channel_id: r.channel_id.map(|c| Uuid::parse_str(&c).unwrap()),
+        creator_id: Uuid::parse_str(&r.creator_id).unwrap(),
+        max_uses: r.max_uses,
+        uses: r.uses,
+        expires_at: r.expires_at,
+        created_at: r.created_at,
+    }
+}
+
+fn invalid_invite() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({
+            "error": "Invite is invalid or has expired",
+            "code": "invalid_invite",
+        })),
+    )
+        .into_response()
+}
+
+pub async fn create_invite(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let bytes = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    // An empty body creates an unlimited, non-expiring invite
+    let body: CreateInviteRequest = if bytes.is_empty() {
+        CreateInviteRequest::default()
+    } else {
+        match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+    };
+
+    if body.max_uses.is_some_and(|n| n < 0) || body.max_age_secs.is_some_and(|n| n < 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "max_uses and max_age_secs must not be negative"})),
+        )
+            .into_response();
+    }
+
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::CREATE_INVITE) {
+        return e.into_response();
+    }
+
+    let channel_id = body.channel_id.map(|c| c.to_string());
+    if let Some(channel_id) = &channel_id {
+        match state.db.get_channel_server_id(channel_id) {
+            Ok(Some(sid)) if sid == server_id => {}
+            Ok(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "Channel does not belong to this server"})),
+                )
+                    .into_response();
+            }
+            Err(e) => {
+                tracing::error!("Failed to look up channel: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    let code = generate_code();
+    tracing::info!("Creating invite: code={}, server_id={}, user_id={}", code, server_id, user.user_id);
+    match state.db.create_invite(
+        &code,
+        &server_id,
+        channel_id.as_deref(),
+        &user.user_id,
+        body.max_uses.filter(|n| *n > 0),
+        body.max_age_secs.filter(|n| *n > 0),
+    ) {
+        Ok(row) => (StatusCode::CREATED, Json(invite_from_row(row))).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to create invite: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn list_invites(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_SERVER) {
+        return e.into_response();
+    }
+
+    match state.db.get_invites_for_server(&server_id) {
+        Ok(rows) => Json(rows.into_iter().map(invite_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list invites: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Creators may revoke their own invites; anyone else needs MANAGE_SERVER.
+pub async fn revoke_invite(
+    State(state): State<Arc<AppState>>,
+    Path(code): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let invite = match state.db.get_valid_invite(&code) {
+        Ok(Some(invite)) => invite,
+        Ok(None) => return invalid_invite(),
+        Err(e) => {
+            tracing::error!("Failed to get invite: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let required = if invite.creator_id == user.user_id {
+        Permissions::empty()
+    } else {
+        Permissions::MANAGE_SERVER
+    };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&invite.server_id), required) {
+        return e.into_response();
+    }
+
+    match state.db.delete_invite(&code) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to revoke invite: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Public: lets someone see where an invite leads before signing in.
+pub async fn get_invite(
+    State(state): State<Arc<AppState>>,
+    Path(code): Path<String>,
+) -> impl IntoResponse {
+    match state.db.get_invite_preview(&code) {
+        Ok(Some(p)) => Json(InvitePreview {
+            code: p.code,
+            server_id: Uuid::parse_str(&p.server_id).unwrap(),
+            server_name: p.server_name,
+            server_icon_url: p.server_icon_url,
+            member_count: p.member_count,
+            channel_id: p.channel_id.map(|c| Uuid::parse_str(&c).unwrap()),
+            channel_name: p.channel_name,
+            expires_at: p.expires_at,
+        })
+        .into_response(),
+        Ok(None) => invalid_invite(),
+        Err(e) => {
+            tracing::error!("Failed to get invite preview: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn accept_invite(
+    State(state): State<Arc<AppState>>,
+    Path(code): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let accepted = match state.db.accept_invite(&code, &user.user_id) {
+        Ok(Some(accepted)) => accepted,
+        Ok(None) => return invalid_invite(),
+        Err(e) => {
+            tracing::error!("Failed to accept invite: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if accepted.joined {
+        tracing::info!("User joined via invite: code={}, server_id={}, user_id={}", code, accepted.server_id, user.user_id);
+        announce_member_joined(&state, &accepted.server_id, &user.user_id).await;
+    }
+
+    match state.db.get_server_by_id(&accepted.server_id) {
+        Ok(Some(server)) => Json(InviteAccepted {
+            server: server_from_row(server),
+            channel_id: accepted.channel_id.map(|c| Uuid::parse_str(&c).unwrap()),
+        })
+        .into_response(),
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
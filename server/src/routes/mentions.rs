@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{auth::AuthUser, routes::messages::message_from_row, AppState};
+use shared::models::{MarkMentionsReadRequest, Mention};
+
+#[derive(Deserialize)]
+pub struct MentionQuery {
+    pub limit: Option<i32>,
+    /// Message id of the last mention already seen
+    pub before: Option<String>,
+    #[serde(default)]
+    pub unread: bool,
+}
+
+/// The caller's mentions inbox, newest first.
+pub async fn list_mentions(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MentionQuery>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+
+    match state
+        .db
+        .get_mentions_for_user(&user.user_id, limit, query.before.as_deref(), query.unread)
+    {
+        Ok(rows) => {
+            let mentions: Vec<Mention> = rows
+                .into_iter()
+                .map(|r| Mention {
+                    server_id: Uuid::parse_str(&r.server_id).unwrap(),
+                    read: r.read,
+                    message: message_from_row(&state, r.message, &user.user_id, false),
+                })
+                .collect();
+            Json(mentions).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to get mentions: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Marks the listed mentions as read, or all of them when the body is empty
+/// or has no `message_ids`.
+pub async fn mark_mentions_read(
+    State(state): State<Arc<AppState>>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let bytes = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let body: MarkMentionsReadRequest = if bytes.is_empty() {
+        MarkMentionsReadRequest::default()
+    } else {
+        match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+    };
+
+    let ids: Option<Vec<String>> = body
+        .message_ids
+        .map(|ids| ids.iter().map(Uuid::to_string).collect());
+    match state.db.mark_mentions_read(&user.user_id, ids.as_deref()) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to mark mentions read: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -5,16 +5,441 @@ use axum::{
     Json,
 };
 use serde::Deserialize;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use uuid::Uuid;
 
-use crate::{auth::AuthUser, AppState};
-use shared::models::{Attachment, CreateMessageRequest, Message, ReactionGroup, UserPublic};
+use crate::{
+    auth::AuthUser,
+    db::{MessageCursor, MessageRejected, MessageRow, RevisionRow, ThreadRow},
+    embeds, mentions,
+    permissions::{self, PermissionError, Permissions, Target},
+    routes::{threads::thread_from_row, uploads},
+    AppState,
+};
+use shared::models::{
+    CreateMessageRequest, Message, MessageReference, MessageRevision, ReactionGroup, UserPublic,
+};
+use shared::ws_messages::{
+    WsEnvelope, WsError, WsMentionCreated, WsMessageCreated, WsMessageRestored, WsThreadMessageCreated,
+};
+
+/// How much of a parent message a reply preview carries.
+const REPLY_PREVIEW_CHARS: usize = 100;
+
+/// How long deleted messages are kept. Authors can restore their own
+/// within `undelete_window`; tombstones are purged, with their files,
+/// once `retention` has passed.
+#[derive(Debug, Clone)]
+pub struct MessageDeletion {
+    pub undelete_window: Duration,
+    pub retention: Duration,
+}
+
+impl Default for MessageDeletion {
+    fn default() -> Self {
+        Self {
+            undelete_window: Duration::from_secs(5 * 60),
+            retention: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl MessageDeletion {
+    /// Reads `MESSAGE_UNDELETE_WINDOW_SECS` and
+    /// `DELETED_MESSAGE_RETENTION_DAYS`, keeping the default for anything
+    /// unset or unparsable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        Self {
+            undelete_window: var("MESSAGE_UNDELETE_WINDOW_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.undelete_window),
+            retention: var("DELETED_MESSAGE_RETENTION_DAYS")
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+                .unwrap_or(defaults.retention),
+        }
+    }
+}
 
+/// History paging shared by channel and DM routes. `before`, `after` and
+/// `around` take message ids and are mutually exclusive.
 #[derive(Deserialize)]
 pub struct MessageQuery {
     pub limit: Option<i32>,
     pub before: Option<String>,
+    pub after: Option<String>,
+    pub around: Option<String>,
+}
+
+impl MessageQuery {
+    pub fn limit(&self) -> i32 {
+        self.limit.unwrap_or(50).clamp(1, 100)
+    }
+
+    /// The requested cursor plus its anchor id.
+    pub fn cursor(&self) -> Result<(MessageCursor<'_>, Option<&str>), ConflictingCursors> {
+        let given = [&self.before, &self.after, &self.around]
+            .iter()
+            .filter(|c| c.is_some())
+            .count();
+        if given > 1 {
+            return Err(ConflictingCursors);
+        }
+        Ok(match (&self.before, &self.after, &self.around) {
+            (Some(id), _, _) => (MessageCursor::Before(id), Some(id)),
+            (_, Some(id), _) => (MessageCursor::After(id), Some(id)),
+            (_, _, Some(id)) => (MessageCursor::Around(id), Some(id)),
+            _ => (MessageCursor::Latest, None),
+        })
+    }
+}
+
+/// More than one of `before`, `after` and `around` was given.
+pub struct ConflictingCursors;
+
+impl IntoResponse for ConflictingCursors {
+    fn into_response(self) -> axum::response::Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "Only one of before, after and around may be given",
+                "code": "conflicting_cursors",
+            })),
+        )
+            .into_response()
+    }
+}
+
+/// 404 for a cursor that names a message outside the history being paged.
+pub fn unknown_anchor() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({
+            "error": "Cursor message not found",
+            "code": "unknown_message",
+        })),
+    )
+        .into_response()
+}
+
+/// Preview of the parent of a reply. A deleted parent keeps its id but loses
+/// its author and content.
+fn reply_preview(state: &AppState, parent_id: &str) -> MessageReference {
+    let parent = state.db.get_message(parent_id).ok().flatten().filter(|p| p.deleted_at.is_none());
+    MessageReference {
+        message_id: Uuid::parse_str(parent_id).unwrap(),
+        author: parent.as_ref().map(|p| UserPublic {
+            id: Uuid::parse_str(&p.author_id).unwrap(),
+            username: p.author_username.clone(),
+            avatar_url: p.author_avatar_url.clone(),
+            status: None,
+        }),
+        content: parent
+            .and_then(|p| p.content)
+            .map(|c| c.chars().take(REPLY_PREVIEW_CHARS).collect()),
+    }
+}
+
+/// Builds the API model for a stored message, loading its attachments,
+/// embeds, the reactions as seen by `viewer_id`, its reply preview and any
+/// thread rooted at it. A deleted message is reduced to a tombstone unless
+/// the viewer is a `moderator`, one who can manage messages in the channel.
+pub fn message_from_row(state: &AppState, r: MessageRow, viewer_id: &str, moderator: bool) -> Message {
+    let tombstone = r.deleted_at.is_some() && !moderator;
+    let attachments = if tombstone {
+        Vec::new()
+    } else {
+        state
+            .db
+            .get_attachments_for_message(&r.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| uploads::attachment_from_row(&state.jwt_secret, a))
+            .collect()
+    };
+
+    let embeds = if tombstone {
+        Vec::new()
+    } else {
+        state
+            .db
+            .get_embeds_for_message(&r.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(embeds::embed_from_row)
+            .collect()
+    };
+
+    let reactions = if tombstone {
+        Vec::new()
+    } else {
+        state
+            .db
+            .get_reactions_for_message(&r.id, viewer_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|rg| ReactionGroup {
+                emoji: rg.emoji,
+                count: rg.count,
+                me: rg.me,
+            })
+            .collect()
+    };
+    let deleted_by = if moderator || r.author_id == viewer_id {
+        r.deleted_by.as_deref().and_then(|id| Uuid::parse_str(id).ok())
+    } else {
+        None
+    };
+
+    Message {
+        id: Uuid::parse_str(&r.id).unwrap(),
+        channel_id: Uuid::parse_str(&r.channel_id).unwrap(),
+        author_id: Uuid::parse_str(&r.author_id).unwrap(),
+        content: if tombstone { None } else { r.content },
+        pinned: r.pinned && !tombstone,
+        created_at: r.created_at,
+        edited_at: r.edited_at,
+        author: Some(UserPublic {
+            id: Uuid::parse_str(&r.author_id).unwrap(),
+            username: r.author_username,
+            avatar_url: r.author_avatar_url,
+            status: None,
+        }),
+        attachments,
+        reactions,
+        reply_to: r.reply_to_id.as_deref().map(|id| reply_preview(state, id)),
+        thread_id: r.thread_id.as_deref().map(|id| Uuid::parse_str(id).unwrap()),
+        thread: match r.thread_id {
+            Some(_) => None,
+            None => state.db.get_thread_for_root(&r.id).ok().flatten().map(thread_from_row),
+        },
+        embeds,
+        deleted_at: r.deleted_at,
+        deleted_by,
+    }
+}
+
+/// Why a message could not be posted.
+pub enum PostMessageError {
+    /// `reply_to` names a message outside the channel or thread
+    InvalidReply,
+    /// `attachment_ids` are too many or not all pending uploads of the author
+    InvalidAttachment,
+    /// The attachments would take the server past its storage quota
+    ServerQuotaExceeded,
+    /// The author posted in this channel less than its slowmode ago
+    Slowmode,
+    Database(rusqlite::Error),
+}
+
+impl PostMessageError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidReply => "invalid_reply",
+            Self::InvalidAttachment => "invalid_attachment",
+            Self::ServerQuotaExceeded => "server_quota_exceeded",
+            Self::Slowmode => "slowmode",
+            Self::Database(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::InvalidReply => "Replies must target a message in the same channel or thread",
+            Self::InvalidAttachment => "Attachments must be at most 10 of your own pending uploads",
+            Self::ServerQuotaExceeded => "This server has run out of space for attachments",
+            Self::Slowmode => "This channel is in slowmode; wait before sending another message",
+            Self::Database(_) => "Failed to send message",
+        }
+    }
+
+    pub fn to_ws_error(&self) -> WsError {
+        WsError {
+            message: self.message().to_string(),
+            code: Some(self.code().to_string()),
+        }
+    }
+}
+
+impl IntoResponse for PostMessageError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            Self::InvalidReply | Self::InvalidAttachment => StatusCode::BAD_REQUEST,
+            Self::ServerQuotaExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Slowmode => StatusCode::TOO_MANY_REQUESTS,
+            Self::Database(ref e) => {
+                tracing::error!("Failed to create message: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (
+            status,
+            Json(serde_json::json!({"error": self.message(), "code": self.code()})),
+        )
+            .into_response()
+    }
+}
+
+/// Stores a message in a channel, or in `thread` when given, attaches the
+/// author's pending uploads and broadcasts it to the server as
+/// `message_created` or `thread_message_created`. Link previews follow in
+/// `message_updated`. Callers have already checked that the author can
+/// post in the channel.
+#[allow(clippy::too_many_arguments)]
+pub async fn post_message(
+    state: &Arc<AppState>,
+    server_id: &str,
+    channel_id: &str,
+    thread: Option<&ThreadRow>,
+    author_id: &str,
+    content: Option<&str>,
+    reply_to: Option<&str>,
+    attachment_ids: &[Uuid],
+) -> Result<Message, PostMessageError> {
+    let upload_ids = uploads::attachment_ids(attachment_ids).ok_or(PostMessageError::InvalidAttachment)?;
+    check_slowmode(state, channel_id, author_id)?;
+    let thread_id = thread.map(|t| t.id.as_str());
+    if let Some(parent_id) = reply_to {
+        match state.db.get_message(parent_id) {
+            Ok(Some(parent))
+                if parent.channel_id == channel_id
+                    && parent.thread_id.as_deref() == thread_id
+                    && parent.deleted_at.is_none() => {}
+            Ok(_) => return Err(PostMessageError::InvalidReply),
+            Err(e) => return Err(PostMessageError::Database(e)),
+        }
+    }
+
+    let id = Uuid::new_v4();
+    let quota_bytes = state.upload_limits.server_quota_bytes;
+    let row = state
+        .db
+        .create_message(&id, channel_id, author_id, content, reply_to, thread_id, &upload_ids, quota_bytes)
+        .map_err(PostMessageError::Database)?
+        .map_err(|rejected| match rejected {
+            MessageRejected::InvalidAttachment => PostMessageError::InvalidAttachment,
+            MessageRejected::ServerQuotaExceeded => PostMessageError::ServerQuotaExceeded,
+        })?;
+    let message = message_from_row(state, row, author_id, false);
+
+    let env = match thread {
+        None => WsEnvelope {
+            msg_type: "message_created".to_string(),
+            payload: serde_json::to_value(WsMessageCreated { message: message.clone() }).unwrap(),
+        },
+        Some(thread) => {
+            // Re-read for the counts bumped by this reply
+            let thread = match state.db.get_thread(&thread.id) {
+                Ok(Some(t)) => t,
+                _ => thread.clone(),
+            };
+            WsEnvelope {
+                msg_type: "thread_message_created".to_string(),
+                payload: serde_json::to_value(WsThreadMessageCreated {
+                    thread: thread_from_row(thread),
+                    message: message.clone(),
+                })
+                .unwrap(),
+            }
+        }
+    };
+    state
+        .ws_state
+        .broadcast_to_server(server_id, &serde_json::to_string(&env).unwrap())
+        .await;
+
+    if let Some(content) = content {
+        record_mentions(state, server_id, &message, content).await;
+    }
+    embeds::refresh_message_embeds(state, server_id, &id.to_string(), content);
+
+    Ok(message)
+}
+
+/// Members without `manage_messages` or `manage_channels` wait out the
+/// channel's slowmode between messages, in threads as well.
+fn check_slowmode(state: &AppState, channel_id: &str, author_id: &str) -> Result<(), PostMessageError> {
+    let slowmode_secs = match state.db.get_channel(channel_id).map_err(PostMessageError::Database)? {
+        Some(channel) if channel.slowmode_secs > 0 => channel.slowmode_secs,
+        _ => return Ok(()),
+    };
+    let exempt = Permissions::MANAGE_MESSAGES | Permissions::MANAGE_CHANNELS;
+    match permissions::require(&state.db, author_id, Target::Channel(channel_id), Permissions::empty()) {
+        Ok(granted) if granted.permissions.intersects(exempt) => return Ok(()),
+        Err(PermissionError::Db(e)) => return Err(PostMessageError::Database(e)),
+        _ => {}
+    }
+    match state
+        .db
+        .seconds_since_last_message(channel_id, author_id)
+        .map_err(PostMessageError::Database)?
+    {
+        Some(elapsed) if elapsed < slowmode_secs as f64 => Err(PostMessageError::Slowmode),
+        _ => Ok(()),
+    }
+}
+
+/// Stores the mentions in a new message, adds it to the inbox of every
+/// member it reaches and sends each of them `mention_created`. `@here` only
+/// reaches members with an open connection. Authors never mention
+/// themselves.
+async fn record_mentions(state: &AppState, server_id: &str, message: &Message, content: &str) {
+    let parsed = mentions::parse(content);
+    if parsed.is_empty() {
+        return;
+    }
+    let members = match state.db.get_server_members(server_id) {
+        Ok(members) => members,
+        Err(e) => {
+            tracing::error!("Failed to load members for mentions: {e}");
+            return;
+        }
+    };
+
+    let message_id = message.id.to_string();
+    let author_id = message.author_id.to_string();
+    let mut recipients: Vec<String> = Vec::new();
+
+    for member in members.iter().filter(|m| parsed.usernames.contains(&m.username)) {
+        let _ = state.db.create_mention(&Uuid::new_v4(), &message_id, "user", Some(&member.user_id));
+        recipients.push(member.user_id.clone());
+    }
+    if parsed.everyone {
+        let _ = state.db.create_mention(&Uuid::new_v4(), &message_id, "everyone", None);
+        recipients.extend(members.iter().map(|m| m.user_id.clone()));
+    } else if parsed.here {
+        let _ = state.db.create_mention(&Uuid::new_v4(), &message_id, "here", None);
+        for member in &members {
+            if state.ws_state.is_connected(&member.user_id).await {
+                recipients.push(member.user_id.clone());
+            }
+        }
+    }
+
+    recipients.retain(|id| *id != author_id);
+    recipients.sort();
+    recipients.dedup();
+    if recipients.is_empty() {
+        return;
+    }
+    if let Err(e) = state.db.add_mention_recipients(&message_id, &recipients) {
+        tracing::error!("Failed to store mentions: message_id={}, error={}", message_id, e);
+        return;
+    }
+
+    let env = WsEnvelope {
+        msg_type: "mention_created".to_string(),
+        payload: serde_json::to_value(WsMentionCreated {
+            server_id: Uuid::parse_str(server_id).unwrap(),
+            message: message.clone(),
+        })
+        .unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_users(recipients.iter().map(String::as_str), &serde_json::to_string(&env).unwrap())
+        .await;
 }
 
 pub async fn get_messages(
@@ -24,69 +449,32 @@ pub async fn get_messages(
     req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
-    let limit = query.limit.unwrap_or(50).min(100);
+    let moderator = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::empty()) {
+        Ok(granted) => granted.permissions.contains(Permissions::MANAGE_MESSAGES),
+        Err(e) => return e.into_response(),
+    };
+    let (cursor, anchor) = match query.cursor() {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+    // Tombstones are part of the history, so they work as cursors too
+    if let Some(anchor) = anchor {
+        match state.db.get_message(anchor) {
+            Ok(Some(m)) if m.channel_id == channel_id => {}
+            Ok(_) => return unknown_anchor(),
+            Err(e) => {
+                tracing::error!("Failed to look up cursor message: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
 
-    match state
-        .db
-        .get_messages(&channel_id, limit, query.before.as_deref())
-    {
+    match state.db.get_messages(&channel_id, query.limit(), cursor) {
         Ok(rows) => {
-            let mut messages: Vec<Message> = rows
+            let messages: Vec<Message> = rows
                 .into_iter()
-                .map(|r| {
-                    // Get attachments for this message
-                    let attachments = state
-                        .db
-                        .get_attachments_for_message(&r.id)
-                        .unwrap_or_default()
-                        .into_iter()
-                        .map(|a| Attachment {
-                            id: Uuid::parse_str(&a.id).unwrap(),
-                            message_id: Uuid::parse_str(&a.message_id).unwrap(),
-                            file_url: a.file_url,
-                            file_name: a.file_name,
-                            mime_type: a.mime_type,
-                            size_bytes: a.size_bytes,
-                            created_at: a.created_at,
-                        })
-                        .collect();
-
-                    // Get reactions for this message
-                    let reactions = state
-                        .db
-                        .get_reactions_for_message(&r.id, &user.user_id)
-                        .unwrap_or_default()
-                        .into_iter()
-                        .map(|rg| ReactionGroup {
-                            emoji: rg.emoji,
-                            count: rg.count,
-                            me: rg.me,
-                        })
-                        .collect();
-
-                    Message {
-                        id: Uuid::parse_str(&r.id).unwrap(),
-                        channel_id: Uuid::parse_str(&r.channel_id).unwrap(),
-                        author_id: Uuid::parse_str(&r.author_id).unwrap(),
-                        content: r.content,
-                        pinned: r.pinned,
-                        created_at: r.created_at,
-                        edited_at: r.edited_at,
-                        author: Some(UserPublic {
-                            id: Uuid::parse_str(&r.author_id).unwrap(),
-                            username: r.author_username,
-                            avatar_url: r.author_avatar_url,
-                            status: None,
-                        }),
-                        attachments,
-                        reactions,
-                    }
-                })
+                .map(|r| message_from_row(&state, r, &user.user_id, moderator))
                 .collect();
-
-            // Reverse so oldest first
-            messages.reverse();
-
             Json(messages).into_response()
         }
         Err(e) => {
@@ -102,64 +490,17 @@ pub async fn get_pinned_messages(
     req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::empty()) {
+        return e.into_response();
+    }
 
     match state.db.get_pinned_messages(&channel_id) {
         Ok(rows) => {
-             let messages: Vec<Message> = rows
+            // Pinned messages stay newest first, as returned by the query.
+            let messages: Vec<Message> = rows
                 .into_iter()
-                .map(|r| {
-                     // Get attachments for this message
-                    let attachments = state
-                        .db
-                        .get_attachments_for_message(&r.id)
-                        .unwrap_or_default()
-                        .into_iter()
-                        .map(|a| Attachment {
-                            id: Uuid::parse_str(&a.id).unwrap(),
-                            message_id: Uuid::parse_str(&a.message_id).unwrap(),
-                            file_url: a.file_url,
-                            file_name: a.file_name,
-                            mime_type: a.mime_type,
-                            size_bytes: a.size_bytes,
-                            created_at: a.created_at,
-                        })
-                        .collect();
-
-                    // Get reactions for this message
-                    let reactions = state
-                        .db
-                        .get_reactions_for_message(&r.id, &user.user_id)
-                        .unwrap_or_default()
-                        .into_iter()
-                        .map(|rg| ReactionGroup {
-                            emoji: rg.emoji,
-                            count: rg.count,
-                            me: rg.me,
-                        })
-                        .collect();
-
-                    Message {
-                        id: Uuid::parse_str(&r.id).unwrap(),
-                        channel_id: Uuid::parse_str(&r.channel_id).unwrap(),
-                        author_id: Uuid::parse_str(&r.author_id).unwrap(),
-                        content: r.content,
-                        pinned: r.pinned,
-                        created_at: r.created_at,
-                        edited_at: r.edited_at,
-                        author: Some(UserPublic {
-                            id: Uuid::parse_str(&r.author_id).unwrap(),
-                            username: r.author_username,
-                            avatar_url: r.author_avatar_url,
-                            status: None,
-                        }),
-                        attachments,
-                        reactions,
-                    }
-                })
+                .map(|r| message_from_row(&state, r, &user.user_id, false))
                 .collect();
-            // No reverse needed as we order by created_at DESC in query and probably want recent first?
-            // Actually usually pinned messages are shown in a list, order matters less or we want recent?
-            // Let's keep DESC (newest first).
             Json(messages).into_response()
         }
         Err(e) => {
@@ -184,53 +525,27 @@ pub async fn create_message(
         Err(_) => return StatusCode::BAD_REQUEST.into_response(),
     };
 
-    let id = Uuid::new_v4();
-    tracing::info!("Creating message via HTTP: message_id={}, channel_id={}, user_id={}", id, channel_id, user.user_id);
-    
-    match state
-        .db
-        .create_message(&id, &channel_id, &user.user_id, body.content.as_deref())
-    {
-        Ok(row) => {
-            let message = Message {
-                id: Uuid::parse_str(&row.id).unwrap(),
-                channel_id: Uuid::parse_str(&row.channel_id).unwrap(),
-                author_id: Uuid::parse_str(&row.author_id).unwrap(),
-                content: row.content,
-                pinned: row.pinned,
-                created_at: row.created_at,
-                edited_at: row.edited_at,
-                author: Some(UserPublic {
-                    id: Uuid::parse_str(&row.author_id).unwrap(),
-                    username: row.author_username,
-                    avatar_url: row.author_avatar_url,
-                    status: None,
-                }),
-                attachments: vec![],
-                reactions: vec![],
-            };
-
-            // Broadcast via WebSocket
-            if let Some(server_id) = state.db.get_channel_server_id(&channel_id).ok().flatten() {
-                let ws_msg = shared::ws_messages::WsEnvelope {
-                    msg_type: "message_created".to_string(),
-                    payload: serde_json::to_value(&shared::ws_messages::WsMessageCreated {
-                        message: message.clone(),
-                    })
-                    .unwrap(),
-                };
-                state
-                    .ws_state
-                    .broadcast_to_server(&server_id, &serde_json::to_string(&ws_msg).unwrap())
-                    .await;
-            }
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::empty()) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
+    };
 
-            (StatusCode::CREATED, Json(message)).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to create message: channel_id={}, user_id={}, error={}", channel_id, user.user_id, e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
+    let reply_to = body.reply_to.map(|r| r.to_string());
+    tracing::info!("Creating message via HTTP: channel_id={}, user_id={}", channel_id, user.user_id);
+    match post_message(
+        &state,
+        &server_id,
+        &channel_id,
+        None,
+        &user.user_id,
+        body.content.as_deref(),
+        reply_to.as_deref(),
+        &body.attachment_ids,
+    )
+    .await
+    {
+        Ok(message) => (StatusCode::CREATED, Json(message)).into_response(),
+        Err(e) => e.into_response(),
     }
 }
 
@@ -242,37 +557,60 @@ pub struct EditMessageBody {
 pub async fn edit_message(
     State(state): State<Arc<AppState>>,
     Path(message_id): Path<String>,
-    Json(body): Json<EditMessageBody>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
-    // Get channel_id and server_id for broadcasting
-    let channel_id = state.db.get_message_channel(&message_id).ok().flatten();
-    let server_id = if let Some(cid) = &channel_id {
-        state.db.get_channel_server_id(cid).ok().flatten()
-    } else {
-        None
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let body: EditMessageBody = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    let (author_id, channel_id) = match state.db.get_message_info(&message_id) {
+        Ok(Some(info)) => info,
+        Ok(None) => return PermissionError::NotFound.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message info: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
     };
+    let sid = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::empty()) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
+    };
+    // Moderators may delete other people's messages but never edit them
+    if author_id != user.user_id {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Only the author can edit this message", "code": "not_author"})),
+        )
+            .into_response();
+    }
 
-    match state.db.edit_message(&message_id, &body.content) {
+    match state.db.edit_message(&message_id, &user.user_id, &body.content) {
         Ok(()) => {
-            if let Some(sid) = server_id {
-                let edited_at = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    .to_string();
-
-                let ws_msg = shared::ws_messages::WsEnvelope {
-                    msg_type: "message_updated".to_string(),
-                    payload: serde_json::to_value(&shared::ws_messages::WsMessageUpdated {
-                        message_id: Uuid::parse_str(&message_id).unwrap(),
-                        content: Some(body.content),
-                        edited_at: Some(edited_at),
-                        pinned: None,
-                    })
-                    .unwrap(),
-                };
-                let _ = state.ws_state.broadcast_to_server(&sid, &serde_json::to_string(&ws_msg).unwrap()).await;
-            }
+            let edited_at = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string();
+            embeds::refresh_message_embeds(&state, &sid, &message_id, Some(&body.content));
+
+            let ws_msg = shared::ws_messages::WsEnvelope {
+                msg_type: "message_updated".to_string(),
+                payload: serde_json::to_value(&shared::ws_messages::WsMessageUpdated {
+                    message_id: Uuid::parse_str(&message_id).unwrap(),
+                    content: Some(body.content),
+                    edited_at: Some(edited_at),
+                    pinned: None,
+                    embeds: None,
+                })
+                .unwrap(),
+            };
+            let _ = state.ws_state.broadcast_to_server(&sid, &serde_json::to_string(&ws_msg).unwrap()).await;
             StatusCode::OK.into_response()
         }
         Err(e) => {
@@ -282,33 +620,110 @@ pub async fn edit_message(
     }
 }
 
+pub fn revision_from_row(r: RevisionRow) -> MessageRevision {
+    MessageRevision {
+        revision: r.revision,
+        content: r.content,
+        created_at: r.created_at,
+        replaced_at: r.replaced_at,
+        replaced_by: r.replaced_by.as_deref().and_then(|id| Uuid::parse_str(id).ok()),
+    }
+}
+
+/// Earlier versions of a message, oldest first, within the server's
+/// retention. Visible to the author and to members who can manage
+/// messages in the channel.
+pub async fn get_message_history(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Message(&message_id), Permissions::empty()) {
+        Ok(granted) => granted,
+        Err(e) => return e.into_response(),
+    };
+    let author_id = match state.db.get_message_info(&message_id) {
+        Ok(Some((author_id, _))) => author_id,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message info: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if author_id != user.user_id && !granted.permissions.contains(Permissions::MANAGE_MESSAGES) {
+        return PermissionError::Missing(Permissions::MANAGE_MESSAGES).into_response();
+    }
+
+    let retention_days = match state.db.get_server_by_id(&granted.server_id) {
+        Ok(server) => server.map_or(0, |s| s.edit_history_days),
+        Err(e) => {
+            tracing::error!("Failed to get server: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match state.db.get_message_revisions(&message_id, retention_days) {
+        Ok(rows) => Json(rows.into_iter().map(revision_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message history: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Deletes message revisions past their server's retention, every
+/// `interval`.
+pub async fn run_revision_gc(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match state.db.prune_message_revisions() {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Removed {n} expired message revisions"),
+            Err(e) => tracing::error!("Failed to prune message revisions: {e}"),
+        }
+    }
+}
+
 pub async fn delete_message(
     State(state): State<Arc<AppState>>,
     Path(message_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
     tracing::info!("Deleting message: message_id={}", message_id);
-    
-    // Get channel_id before deleting
-    let channel_id = state.db.get_message_channel(&message_id).ok().flatten();
-    let server_id = if let Some(cid) = &channel_id {
-        state.db.get_channel_server_id(cid).ok().flatten()
+
+    let (author_id, channel_id) = match state.db.get_message_info(&message_id) {
+        Ok(Some(info)) => info,
+        Ok(None) => return PermissionError::NotFound.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message info: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // Authors may always delete their own messages
+    let required = if author_id == user.user_id {
+        Permissions::empty()
     } else {
-        None
+        Permissions::MANAGE_MESSAGES
+    };
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), required) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
     };
 
-    match state.db.delete_message(&message_id) {
-        Ok(()) => {
-            if let Some(sid) = server_id {
-                let ws_msg = shared::ws_messages::WsEnvelope {
-                    msg_type: "message_deleted".to_string(),
-                    payload: serde_json::to_value(&shared::ws_messages::WsMessageDeleted {
-                        message_id: Uuid::parse_str(&message_id).unwrap(),
-                        channel_id: Uuid::parse_str(&channel_id.unwrap()).unwrap(),
-                    })
-                    .unwrap(),
-                };
-                let _ = state.ws_state.broadcast_to_server(&sid, &serde_json::to_string(&ws_msg).unwrap()).await;
-            }
+    match state.db.delete_message(&message_id, &user.user_id) {
+        Ok(false) => PermissionError::NotFound.into_response(),
+        Ok(true) => {
+            let ws_msg = shared::ws_messages::WsEnvelope {
+                msg_type: "message_deleted".to_string(),
+                payload: serde_json::to_value(&shared::ws_messages::WsMessageDeleted {
+                    message_id: Uuid::parse_str(&message_id).unwrap(),
+                    channel_id: Uuid::parse_str(&channel_id).unwrap(),
+                })
+                .unwrap(),
+            };
+            let _ = state.ws_state.broadcast_to_server(&server_id, &serde_json::to_string(&ws_msg).unwrap()).await;
             StatusCode::NO_CONTENT.into_response()
         }
         Err(e) => {
@@ -318,32 +733,125 @@ pub async fn delete_message(
     }
 }
 
+/// Brings back a message its author deleted, within the undelete window.
+/// Messages removed by a moderator stay deleted.
+pub async fn restore_message(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let row = match state.db.get_message(&message_id) {
+        Ok(Some(row)) if row.deleted_at.is_some() => row,
+        Ok(_) => return PermissionError::NotFound.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Channel(&row.channel_id), Permissions::empty()) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
+    };
+    if row.author_id != user.user_id || row.deleted_by.as_deref() != Some(user.user_id.as_str()) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": "Only the author can restore a message they deleted",
+                "code": "not_restorable",
+            })),
+        )
+            .into_response();
+    }
+
+    match state.db.restore_message(&message_id, state.message_deletion.undelete_window.as_secs()) {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::GONE,
+                Json(serde_json::json!({
+                    "error": "This message was deleted too long ago to restore",
+                    "code": "undelete_window_expired",
+                })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to restore message: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let message = match state.db.get_message(&message_id) {
+        Ok(Some(row)) => message_from_row(&state, row, &user.user_id, false),
+        _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let env = WsEnvelope {
+        msg_type: "message_restored".to_string(),
+        payload: serde_json::to_value(WsMessageRestored { message: message.clone() }).unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_server(&server_id, &serde_json::to_string(&env).unwrap())
+        .await;
+    Json(message).into_response()
+}
+
+/// Removes tombstones past the retention, then their attachment files.
+pub async fn purge_deleted_messages(state: &AppState) {
+    let (purged, urls) = match state.db.purge_deleted_messages(state.message_deletion.retention.as_secs()) {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::error!("Failed to purge deleted messages: {e}");
+            return;
+        }
+    };
+    for key in urls.iter().filter_map(|url| uploads::storage_key(url)) {
+        if let Err(e) = state.storage.delete(key).await {
+            tracing::warn!("Failed to remove file {key} of a purged message: {e}");
+        }
+    }
+    if purged > 0 {
+        tracing::info!("Purged {purged} deleted messages and {} files", urls.len());
+    }
+}
+
+/// Runs [`purge_deleted_messages`] forever, checking every `interval`.
+pub async fn run_message_purge(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        purge_deleted_messages(&state).await;
+    }
+}
+
 pub async fn pin_message(
     State(state): State<Arc<AppState>>,
     Path(message_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
-    let channel_id = state.db.get_message_channel(&message_id).ok().flatten();
-    let server_id = if let Some(cid) = &channel_id {
-        state.db.get_channel_server_id(cid).ok().flatten()
-    } else {
-        None
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Message(&message_id), Permissions::PIN_MESSAGES) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
     };
 
     match state.db.pin_message(&message_id, true) {
-        Ok(()) => {
-            if let Some(sid) = server_id {
-                 let ws_msg = shared::ws_messages::WsEnvelope {
-                    msg_type: "message_updated".to_string(),
-                    payload: serde_json::to_value(&shared::ws_messages::WsMessageUpdated {
-                        message_id: Uuid::parse_str(&message_id).unwrap(),
-                        content: None,
-                        edited_at: None,
-                        pinned: Some(true),
-                    })
-                    .unwrap(),
-                };
-                let _ = state.ws_state.broadcast_to_server(&sid, &serde_json::to_string(&ws_msg).unwrap()).await;
-            }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Ok(true) => {
+            let ws_msg = shared::ws_messages::WsEnvelope {
+                msg_type: "message_updated".to_string(),
+                payload: serde_json::to_value(&shared::ws_messages::WsMessageUpdated {
+                    message_id: Uuid::parse_str(&message_id).unwrap(),
+                    content: None,
+                    edited_at: None,
+                    pinned: Some(true),
+                    embeds: None,
+                })
+                .unwrap(),
+            };
+            let _ = state.ws_state.broadcast_to_server(&server_id, &serde_json::to_string(&ws_msg).unwrap()).await;
             StatusCode::OK.into_response()
         }
         Err(e) => {
@@ -356,29 +864,29 @@ pub async fn pin_message(
 pub async fn unpin_message(
     State(state): State<Arc<AppState>>,
     Path(message_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
-    let channel_id = state.db.get_message_channel(&message_id).ok().flatten();
-    let server_id = if let Some(cid) = &channel_id {
-        state.db.get_channel_server_id(cid).ok().flatten()
-    } else {
-        None
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Message(&message_id), Permissions::PIN_MESSAGES) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
     };
 
     match state.db.pin_message(&message_id, false) {
-        Ok(()) => {
-             if let Some(sid) = server_id {
-                 let ws_msg = shared::ws_messages::WsEnvelope {
-                    msg_type: "message_updated".to_string(),
-                    payload: serde_json::to_value(&shared::ws_messages::WsMessageUpdated {
-                        message_id: Uuid::parse_str(&message_id).unwrap(),
-                        content: None,
-                        edited_at: None,
-                        pinned: Some(false),
-                    })
-                    .unwrap(),
-                };
-                let _ = state.ws_state.broadcast_to_server(&sid, &serde_json::to_string(&ws_msg).unwrap()).await;
-            }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Ok(true) => {
+            let ws_msg = shared::ws_messages::WsEnvelope {
+                msg_type: "message_updated".to_string(),
+                payload: serde_json::to_value(&shared::ws_messages::WsMessageUpdated {
+                    message_id: Uuid::parse_str(&message_id).unwrap(),
+                    content: None,
+                    edited_at: None,
+                    pinned: Some(false),
+                    embeds: None,
+                })
+                .unwrap(),
+            };
+            let _ = state.ws_state.broadcast_to_server(&server_id, &serde_json::to_string(&ws_msg).unwrap()).await;
             StatusCode::OK.into_response()
         }
         Err(e) => {
@@ -409,19 +917,17 @@ pub async fn add_reaction(
         Err(_) => return StatusCode::BAD_REQUEST.into_response(),
     };
 
-    // Get context for broadcasting
-    let channel_id = state.db.get_message_channel(&message_id).ok().flatten();
-    let server_id = if let Some(cid) = &channel_id {
-        state.db.get_channel_server_id(cid).ok().flatten()
-    } else {
-        None
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Message(&message_id), Permissions::empty()) {
+        Ok(granted) => Some(granted.server_id),
+        Err(e) => return e.into_response(),
     };
 
     match state
         .db
         .add_reaction(&message_id, &user.user_id, &body.emoji)
     {
-        Ok(()) => {
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Ok(true) => {
             if let Some(sid) = server_id {
                 // Fetch updated reactions
                 if let Ok(reactions) = state.db.get_reactions_for_message(&message_id, &user.user_id) {
@@ -464,11 +970,9 @@ pub async fn remove_reaction(
         Err(_) => return StatusCode::BAD_REQUEST.into_response(),
     };
 
-     let channel_id = state.db.get_message_channel(&message_id).ok().flatten();
-    let server_id = if let Some(cid) = &channel_id {
-        state.db.get_channel_server_id(cid).ok().flatten()
-    } else {
-        None
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Message(&message_id), Permissions::empty()) {
+        Ok(granted) => Some(granted.server_id),
+        Err(e) => return e.into_response(),
     };
 
     match state
@@ -1,11 +1,21 @@
+pub mod audit_log;
 pub mod channels;
 pub mod dms;
+pub mod invites;
+pub mod mentions;
 pub mod messages;
+pub mod read_states;
+pub mod roles;
+pub mod search;
 pub mod servers;
+pub mod sfu;
+pub mod threads;
+pub mod uploads;
 pub mod users;
 pub mod turn;
 pub mod turn_test;
 pub mod version;
+pub mod voice;
 
 use std::sync::Arc;
 use axum::{
@@ -29,37 +39,80 @@ pub fn api_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
     let public = Router::new()
         .route("/register", axum::routing::post(auth::register))
         .route("/login", axum::routing::post(auth::login))
-        .route("/version", axum::routing::get(version::get_version));
+        .route("/version", axum::routing::get(version::get_version))
+        .route("/invites/{code}", axum::routing::get(invites::get_invite));
 
     let protected = Router::new()
         .route("/me", axum::routing::get(users::get_me))
         .route("/me", axum::routing::patch(users::update_me))
+        .route("/me/mentions", axum::routing::get(mentions::list_mentions))
+        .route("/me/mentions/read", axum::routing::post(mentions::mark_mentions_read))
         .route("/servers", axum::routing::get(servers::list_servers))
         .route("/servers", axum::routing::post(servers::create_server))
         .route("/servers/{server_id}", axum::routing::get(servers::get_server))
-        .route("/servers/{server_id}/join", axum::routing::post(servers::join_server))
+        .route("/servers/{server_id}", axum::routing::patch(servers::update_server))
+        .route("/servers/{server_id}/invites", axum::routing::get(invites::list_invites))
+        .route("/servers/{server_id}/invites", axum::routing::post(invites::create_invite))
+        .route("/invites/{code}", axum::routing::delete(invites::revoke_invite))
+        .route("/invites/{code}/accept", axum::routing::post(invites::accept_invite))
         .route("/servers/{server_id}/leave", axum::routing::post(servers::leave_server))
         .route("/servers/{server_id}/members", axum::routing::get(servers::get_members))
+        .route("/servers/{server_id}/members/{user_id}", axum::routing::delete(servers::kick_member))
+        .route("/servers/{server_id}/members/{user_id}/voice", axum::routing::patch(voice::update_member_voice))
+        .route("/servers/{server_id}/members/{user_id}/voice", axum::routing::delete(voice::disconnect_member_voice))
+        .route("/servers/{server_id}/audit_log", axum::routing::get(audit_log::get_audit_log))
+        .route("/servers/{server_id}/members/{user_id}/roles/{role_id}", axum::routing::put(roles::add_member_role))
+        .route("/servers/{server_id}/members/{user_id}/roles/{role_id}", axum::routing::delete(roles::remove_member_role))
+        .route("/servers/{server_id}/roles", axum::routing::get(roles::list_roles))
+        .route("/servers/{server_id}/roles", axum::routing::post(roles::create_role))
+        .route("/roles/{role_id}", axum::routing::patch(roles::update_role))
+        .route("/roles/{role_id}", axum::routing::delete(roles::delete_role))
         .route("/servers/{server_id}/channels", axum::routing::get(channels::list_channels))
         .route("/servers/{server_id}/channels", axum::routing::post(channels::create_channel))
+        .route("/servers/{server_id}/channels", axum::routing::patch(channels::reorder_channels))
+        .route("/channels/{channel_id}", axum::routing::patch(channels::update_channel))
         .route("/channels/{channel_id}", axum::routing::delete(channels::delete_channel))
+        .route("/servers/{server_id}/categories", axum::routing::get(channels::list_categories))
+        .route("/servers/{server_id}/categories", axum::routing::post(channels::create_category))
+        .route("/categories/{category_id}", axum::routing::patch(channels::update_category))
+        .route("/categories/{category_id}", axum::routing::delete(channels::delete_category))
+        .route("/channels/{channel_id}/sfu", axum::routing::get(sfu::get_sfu_session))
+        .route("/channels/{channel_id}/permissions", axum::routing::get(roles::list_channel_overrides))
+        .route("/channels/{channel_id}/permissions/{role_id}", axum::routing::put(roles::set_channel_override))
+        .route("/channels/{channel_id}/permissions/{role_id}", axum::routing::delete(roles::delete_channel_override))
         .route("/channels/{channel_id}/messages", axum::routing::get(messages::get_messages))
+        .route("/channels/{channel_id}/ack", axum::routing::post(read_states::ack_channel))
         .route("/channels/{channel_id}/pins", axum::routing::get(messages::get_pinned_messages))
         .route("/channels/{channel_id}/messages", axum::routing::post(messages::create_message))
         .route("/messages/{message_id}", axum::routing::patch(messages::edit_message))
         .route("/messages/{message_id}", axum::routing::delete(messages::delete_message))
+        .route("/messages/{message_id}/history", axum::routing::get(messages::get_message_history))
+        .route("/messages/{message_id}/restore", axum::routing::post(messages::restore_message))
         .route("/messages/{message_id}/pin", axum::routing::post(messages::pin_message))
         .route("/messages/{message_id}/pin", axum::routing::delete(messages::unpin_message))
         .route("/messages/{message_id}/reactions", axum::routing::post(messages::add_reaction))
         .route("/messages/{message_id}/reactions", axum::routing::delete(messages::remove_reaction))
-        .route("/upload", axum::routing::post(users::upload_file))
+        .route("/messages/{message_id}/thread", axum::routing::post(threads::create_thread))
+        .route("/channels/{channel_id}/threads", axum::routing::get(threads::list_threads))
+        .route("/threads/{thread_id}", axum::routing::get(threads::get_thread))
+        .route("/threads/{thread_id}/messages", axum::routing::get(threads::get_thread_messages))
+        .route("/threads/{thread_id}/messages", axum::routing::post(threads::create_thread_message))
+        .route("/threads/{thread_id}/participants", axum::routing::get(threads::get_thread_participants))
+        .route("/search", axum::routing::get(search::search))
+        // Size limits are enforced per type while the file streams in
+        .route(
+            "/upload",
+            axum::routing::post(uploads::upload_file).layer(axum::extract::DefaultBodyLimit::disable()),
+        )
         .route("/turn", axum::routing::get(turn::get_turn_credentials))
         .route("/dms", axum::routing::get(dms::list_conversations))
         .route("/dms", axum::routing::post(dms::create_conversation))
         .route("/dms/{conversation_id}/messages", axum::routing::get(dms::get_messages))
         .route("/dms/{conversation_id}/messages", axum::routing::post(dms::create_message))
+        .route("/dms/{conversation_id}/ack", axum::routing::post(read_states::ack_dm))
         .route("/dm_messages/{message_id}", axum::routing::patch(dms::edit_message))
         .route("/dm_messages/{message_id}", axum::routing::delete(dms::delete_message))
+        .route("/dm_messages/{message_id}/history", axum::routing::get(dms::get_message_history))
         .route("/dm_messages/{message_id}/reactions", axum::routing::post(dms::add_reaction))
         .route("/dm_messages/{message_id}/reactions", axum::routing::delete(dms::remove_reaction))
         .layer(middleware::from_fn_with_state(state, require_auth));
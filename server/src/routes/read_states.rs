@@ -0,0 +1,176 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    permissions::{self, PermissionError, Permissions, Target},
+    ws::ConnectionSender,
+    AppState,
+};
+use shared::models::{AckRequest, ReadState};
+use shared::ws_messages::{WsEnvelope, WsError, WsReadStateUpdate};
+
+/// Why a read marker could not be moved.
+pub enum AckError {
+    Permission(PermissionError),
+    /// The message is not in that channel's timeline or conversation
+    UnknownMessage,
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for AckError {
+    fn from(e: rusqlite::Error) -> Self {
+        AckError::Database(e)
+    }
+}
+
+impl AckError {
+    pub fn to_ws_error(&self) -> WsError {
+        match self {
+            AckError::Permission(e) => e.to_ws_error(),
+            AckError::UnknownMessage => WsError {
+                message: "Message not found".to_string(),
+                code: Some("unknown_message".to_string()),
+            },
+            AckError::Database(_) => WsError {
+                message: "Failed to update read state".to_string(),
+                code: None,
+            },
+        }
+    }
+}
+
+impl IntoResponse for AckError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AckError::Permission(e) => e.into_response(),
+            AckError::UnknownMessage => crate::routes::messages::unknown_anchor(),
+            AckError::Database(e) => {
+                tracing::error!("Failed to update read state: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Sends the new state to the user's sessions, except `origin` when the
+/// ack came in over a socket.
+async fn sync_sessions(state: &AppState, user_id: &str, read_state: &ReadState, origin: Option<&ConnectionSender>) {
+    let env = WsEnvelope {
+        msg_type: "read_state_update".to_string(),
+        payload: serde_json::to_value(WsReadStateUpdate { read_state: read_state.clone() }).unwrap(),
+    };
+    let text = serde_json::to_string(&env).unwrap();
+    match origin {
+        Some(origin) => state.ws_state.broadcast_to_user_except(user_id, origin, &text).await,
+        None => state.ws_state.broadcast_to_user(user_id, &text).await,
+    }
+}
+
+/// Moves the user's marker in a channel and syncs their other sessions.
+pub async fn ack_channel_message(
+    state: &AppState,
+    user_id: &str,
+    channel_id: &str,
+    message_id: &str,
+    origin: Option<&ConnectionSender>,
+) -> Result<ReadState, AckError> {
+    let granted = permissions::require(&state.db, user_id, Target::Channel(channel_id), Permissions::empty())
+        .map_err(AckError::Permission)?;
+    if !state.db.ack_channel(user_id, channel_id, message_id)? {
+        return Err(AckError::UnknownMessage);
+    }
+
+    let row = state
+        .db
+        .get_channel_read_states(user_id, Some(&granted.server_id))?
+        .into_iter()
+        .find(|r| r.channel_id == channel_id)
+        .ok_or(AckError::UnknownMessage)?;
+    let read_state = ReadState {
+        channel_id: Some(Uuid::parse_str(channel_id).unwrap()),
+        conversation_id: None,
+        last_read_message_id: row.last_read_message_id.map(|id| Uuid::parse_str(&id).unwrap()),
+        unread_count: row.unread_count,
+        mention_count: row.mention_count,
+    };
+    sync_sessions(state, user_id, &read_state, origin).await;
+    Ok(read_state)
+}
+
+/// Moves the user's marker in a DM conversation and syncs their other
+/// sessions. Conversations the user is not part of are reported as missing.
+pub async fn ack_dm_message(
+    state: &AppState,
+    user_id: &str,
+    conversation_id: &str,
+    message_id: &str,
+    origin: Option<&ConnectionSender>,
+) -> Result<ReadState, AckError> {
+    match state.db.get_dm_conversation_users(conversation_id)? {
+        Some((u1, u2)) if u1 == user_id || u2 == user_id => {}
+        _ => return Err(AckError::Permission(PermissionError::NotFound)),
+    }
+    if !state.db.ack_dm(user_id, conversation_id, message_id)? {
+        return Err(AckError::UnknownMessage);
+    }
+
+    let row = state
+        .db
+        .get_dm_read_states(user_id)?
+        .into_iter()
+        .find(|r| r.conversation_id == conversation_id)
+        .ok_or(AckError::UnknownMessage)?;
+    let read_state = ReadState {
+        channel_id: None,
+        conversation_id: Some(Uuid::parse_str(conversation_id).unwrap()),
+        last_read_message_id: row.last_read_message_id.map(|id| Uuid::parse_str(&id).unwrap()),
+        unread_count: row.unread_count,
+        mention_count: row.unread_count,
+    };
+    sync_sessions(state, user_id, &read_state, origin).await;
+    Ok(read_state)
+}
+
+async fn parse_ack(req: axum::http::Request<axum::body::Body>) -> Option<AckRequest> {
+    let bytes = axum::body::to_bytes(req.into_body(), 1_000_000).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+pub async fn ack_channel(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let Some(body) = parse_ack(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match ack_channel_message(&state, &user.user_id, &channel_id, &body.message_id.to_string(), None).await {
+        Ok(read_state) => Json(read_state).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn ack_dm(
+    State(state): State<Arc<AppState>>,
+    Path(conversation_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let Some(body) = parse_ack(req).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match ack_dm_message(&state, &user.user_id, &conversation_id, &body.message_id.to_string(), None).await {
+        Ok(read_state) => Json(read_state).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
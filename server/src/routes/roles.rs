@@ -0,0 +1,371 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    db::RoleRow,
+    permissions::{self, Permissions, Target},
+    AppState,
+};
+use shared::models::{
+    ChannelPermissionOverride, CreateRoleRequest, Role, SetChannelOverrideRequest,
+    UpdateRoleRequest,
+};
+
+fn role_from_row(r: RoleRow) -> Role {
+    Role {
+        id: Uuid::parse_str(&r.id).unwrap(),
+        server_id: Uuid::parse_str(&r.server_id).unwrap(),
+        name: r.name,
+        permissions: r.permissions,
+        position: r.position,
+        created_at: r.created_at,
+    }
+}
+
+/// A role manager may only hand out permissions they hold themselves.
+fn check_grantable(granted: &permissions::Granted, bits: i64) -> Result<(), permissions::PermissionError> {
+    let requested = Permissions::from_bits_truncate(bits);
+    if granted.permissions.contains(requested) {
+        Ok(())
+    } else {
+        Err(permissions::PermissionError::Missing(requested - granted.permissions))
+    }
+}
+
+/// The allow and deny bits of a role's current override on a channel, 0 if
+/// it has none.
+fn existing_override_bits(state: &AppState, channel_id: &str, role_id: &str) -> Result<i64, permissions::PermissionError> {
+    let overrides = state.db.get_channel_overrides(channel_id)?;
+    Ok(overrides
+        .into_iter()
+        .find(|o| o.role_id == role_id)
+        .map_or(0, |o| o.allow | o.deny))
+}
+
+pub async fn list_roles(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    match state.db.get_roles_for_server(&server_id) {
+        Ok(rows) => Json(rows.into_iter().map(role_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list roles: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn create_role(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let body: CreateRoleRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = check_grantable(&granted, body.permissions) {
+        return e.into_response();
+    }
+
+    let id = Uuid::new_v4();
+    tracing::info!("Creating role: role_id={}, server_id={}, user_id={}", id, server_id, user.user_id);
+    if let Err(e) = state.db.create_role(&id, &server_id, &body.name, body.permissions) {
+        tracing::error!("Failed to create role: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match state.db.get_role(&id.to_string()) {
+        Ok(Some(row)) => (StatusCode::CREATED, Json(role_from_row(row))).into_response(),
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn update_role(
+    State(state): State<Arc<AppState>>,
+    Path(role_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let body: UpdateRoleRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    let role = match state.db.get_role(&role_id) {
+        Ok(Some(r)) => r,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get role: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Server(&role.server_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+    // Both what the role holds now and what it would hold afterwards
+    if let Err(e) = check_grantable(&granted, role.permissions | body.permissions.unwrap_or(0)) {
+        return e.into_response();
+    }
+
+    if let Err(e) = state
+        .db
+        .update_role(&role_id, body.name.as_deref(), body.permissions)
+    {
+        tracing::error!("Failed to update role: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match state.db.get_role(&role_id) {
+        Ok(Some(row)) => Json(role_from_row(row)).into_response(),
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn delete_role(
+    State(state): State<Arc<AppState>>,
+    Path(role_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let role = match state.db.get_role(&role_id) {
+        Ok(Some(r)) => r,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get role: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Server(&role.server_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = check_grantable(&granted, role.permissions) {
+        return e.into_response();
+    }
+    if role.id == role.server_id {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "The @everyone role cannot be deleted"})),
+        )
+            .into_response();
+    }
+
+    match state.db.delete_role(&role_id) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to delete role: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn add_member_role(
+    State(state): State<Arc<AppState>>,
+    Path((server_id, member_id, role_id)): Path<(String, String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+
+    let role = match state.db.get_role(&role_id) {
+        Ok(Some(r)) if r.server_id == server_id && r.id != server_id => r,
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get role: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Err(e) = check_grantable(&granted, role.permissions) {
+        return e.into_response();
+    }
+    match state.db.is_user_member_of_server(&member_id, &server_id) {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to check membership: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match state.db.add_member_role(&member_id, &server_id, &role_id) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to assign role: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn remove_member_role(
+    State(state): State<Arc<AppState>>,
+    Path((server_id, member_id, role_id)): Path<(String, String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+
+    let role = match state.db.get_role(&role_id) {
+        Ok(Some(r)) if r.server_id == server_id => r,
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get role: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Err(e) = check_grantable(&granted, role.permissions) {
+        return e.into_response();
+    }
+
+    match state.db.remove_member_role(&member_id, &role_id) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to remove role: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn list_channel_overrides(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    match state.db.get_channel_overrides(&channel_id) {
+        Ok(rows) => {
+            let overrides: Vec<ChannelPermissionOverride> = rows
+                .into_iter()
+                .map(|o| ChannelPermissionOverride {
+                    channel_id: Uuid::parse_str(&o.channel_id).unwrap(),
+                    role_id: Uuid::parse_str(&o.role_id).unwrap(),
+                    allow: o.allow,
+                    deny: o.deny,
+                })
+                .collect();
+            Json(overrides).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to list channel overrides: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn set_channel_override(
+    State(state): State<Arc<AppState>>,
+    Path((channel_id, role_id)): Path<(String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let body: SetChannelOverrideRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+    // Replacing an override also lifts whatever it covered before
+    let existing = match existing_override_bits(&state, &channel_id, &role_id) {
+        Ok(bits) => bits,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = check_grantable(&granted, body.allow | body.deny | existing) {
+        return e.into_response();
+    }
+    match state.db.get_role(&role_id) {
+        Ok(Some(r)) if r.server_id == granted.server_id => {}
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get role: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    match state
+        .db
+        .set_channel_override(&channel_id, &role_id, body.allow, body.deny)
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to set channel override: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn delete_channel_override(
+    State(state): State<Arc<AppState>>,
+    Path((channel_id, role_id)): Path<(String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::MANAGE_ROLES) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+    let existing = match existing_override_bits(&state, &channel_id, &role_id) {
+        Ok(bits) => bits,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = check_grantable(&granted, existing) {
+        return e.into_response();
+    }
+
+    match state.db.delete_channel_override(&channel_id, &role_id) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to delete channel override: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    auth::AuthUser,
+    db::{DmMessageRow, MessageRow, SearchFilter},
+    routes::{dms::dm_message_from_row, messages::message_from_row},
+    AppState,
+};
+use shared::models::{DmMessageSearchHit, MessageSearchHit, SearchResults};
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// Free text. May contain the `has:attachment` filter token.
+    pub q: Option<String>,
+    pub server_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub conversation_id: Option<String>,
+    pub author_id: Option<String>,
+    /// Inclusive lower bound on `created_at` (ISO 8601)
+    pub since: Option<String>,
+    /// Exclusive upper bound on `created_at` (ISO 8601)
+    pub until: Option<String>,
+    pub limit: Option<i32>,
+    pub offset: Option<i32>,
+}
+
+/// Splits the raw query into an FTS5 expression and the `has:` filters.
+///
+/// Every remaining word is quoted so user input can never be interpreted as
+/// FTS5 syntax; the words are implicitly AND-ed together.
+fn parse_query(raw: &str) -> (Option<String>, bool) {
+    let mut has_attachment = false;
+    let mut terms = Vec::new();
+
+    for word in raw.split_whitespace() {
+        if word.eq_ignore_ascii_case("has:attachment") {
+            has_attachment = true;
+        } else {
+            terms.push(format!("\"{}\"", word.replace('"', "\"\"")));
+        }
+    }
+
+    let fts_query = if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    };
+    (fts_query, has_attachment)
+}
+
+/// Turns a raw snippet from the database into HTML: the message text is
+/// escaped and only the matched terms are wrapped in `<mark>` tags.
+fn snippet_html(raw: &str) -> String {
+    let mut html = String::with_capacity(raw.len());
+    let mut open = false;
+    for c in raw.chars() {
+        match c {
+            '\u{1}' if !open => {
+                html.push_str("<mark>");
+                open = true;
+            }
+            '\u{2}' if open => {
+                html.push_str("</mark>");
+                open = false;
+            }
+            '\u{1}' | '\u{2}' => {}
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    if open {
+        html.push_str("</mark>");
+    }
+    html
+}
+
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let limit = query.limit.unwrap_or(25).clamp(1, 100);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let (fts_query, has_attachment) = parse_query(query.q.as_deref().unwrap_or(""));
+    if fts_query.is_none() && !has_attachment && query.author_id.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Search query is empty"})),
+        )
+            .into_response();
+    }
+
+    let filter = SearchFilter {
+        fts_query,
+        include_channels: query.conversation_id.is_none(),
+        include_dms: query.server_id.is_none() && query.channel_id.is_none(),
+        server_id: query.server_id,
+        channel_id: query.channel_id,
+        conversation_id: query.conversation_id,
+        author_id: query.author_id,
+        since: query.since,
+        until: query.until,
+        has_attachment,
+    };
+
+    tracing::debug!("Search: user_id={}, filter={:?}", user.user_id, filter);
+
+    // Fetch one extra row to learn whether another page exists
+    let mut rows = match state
+        .db
+        .search_messages(&user.user_id, &filter, limit + 1, offset)
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Failed to search messages: user_id={}, error={}", user.user_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let has_more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+
+    let mut results = SearchResults {
+        messages: vec![],
+        dm_messages: vec![],
+        has_more,
+    };
+
+    for hit in rows {
+        if hit.kind == "dm" {
+            let row = DmMessageRow {
+                id: hit.id,
+                conversation_id: hit.parent_id,
+                author_id: hit.author_id,
+                content: hit.content,
+                created_at: hit.created_at,
+                edited_at: hit.edited_at,
+                author_username: hit.author_username,
+                author_avatar_url: hit.author_avatar_url,
+            };
+            results.dm_messages.push(DmMessageSearchHit {
+                message: dm_message_from_row(&state, row, &user.user_id),
+                snippet: snippet_html(&hit.snippet),
+            });
+        } else {
+            let row = MessageRow {
+                id: hit.id,
+                channel_id: hit.parent_id,
+                author_id: hit.author_id,
+                content: hit.content,
+                pinned: hit.pinned,
+                created_at: hit.created_at,
+                edited_at: hit.edited_at,
+                author_username: hit.author_username,
+                author_avatar_url: hit.author_avatar_url,
+                reply_to_id: hit.reply_to_id,
+                thread_id: hit.thread_id,
+                // Search leaves tombstones out
+                deleted_at: None,
+                deleted_by: None,
+            };
+            results.messages.push(MessageSearchHit {
+                message: message_from_row(&state, row, &user.user_id, false),
+                snippet: snippet_html(&hit.snippet),
+            });
+        }
+    }
+
+    Json(results).into_response()
+}
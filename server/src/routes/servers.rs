@@ -7,25 +7,54 @@ use axum::{
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{auth::AuthUser, AppState};
-use shared::models::{CreateServerRequest, Server, ServerMember};
+use crate::{
+    auth::AuthUser,
+    db::ServerRow,
+    permissions::{self, Permissions, Target},
+    ws, AppState,
+};
+use shared::models::{CreateServerRequest, Server, ServerMember, UpdateServerRequest};
+
+pub fn server_from_row(r: ServerRow) -> Server {
+    Server {
+        id: Uuid::parse_str(&r.id).unwrap(),
+        name: r.name,
+        icon_url: r.icon_url,
+        owner_id: Uuid::parse_str(&r.owner_id).unwrap(),
+        created_at: r.created_at,
+        updated_at: r.updated_at,
+        link_previews: r.link_previews,
+        edit_history_days: r.edit_history_days,
+        unread_count: 0,
+        mention_count: 0,
+    }
+}
 
 pub async fn list_servers(
     State(state): State<Arc<AppState>>,
     req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let read_states = match state.db.get_channel_read_states(&user.user_id, None) {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Failed to get read states: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
     match state.db.get_servers_for_user(&user.user_id) {
         Ok(rows) => {
             let servers: Vec<Server> = rows
                 .into_iter()
-                .map(|r| Server {
-                    id: Uuid::parse_str(&r.id).unwrap(),
-                    name: r.name,
-                    icon_url: r.icon_url,
-                    owner_id: Uuid::parse_str(&r.owner_id).unwrap(),
-                    created_at: r.created_at,
-                    updated_at: r.updated_at,
+                .map(|r| {
+                    let mut server = server_from_row(r);
+                    let id = server.id.to_string();
+                    for rs in read_states.iter().filter(|rs| rs.server_id == id) {
+                        server.unread_count += rs.unread_count;
+                        server.mention_count += rs.mention_count;
+                    }
+                    server
                 })
                 .collect();
             Json(servers).into_response()
@@ -57,6 +86,13 @@ pub async fn create_server(
         .create_server(&id, &body.name, body.icon_url.as_deref(), &user.user_id)
     {
         Ok(()) => {
+            if let Some(icon_url) = &body.icon_url {
+                let _ = state.db.keep_upload(&user.user_id, icon_url);
+            }
+            state
+                .ws_state
+                .subscribe_user_to_server(&user.user_id, &id.to_string())
+                .await;
             let server = Server {
                 id,
                 name: body.name,
@@ -64,6 +100,10 @@ pub async fn create_server(
                 owner_id: Uuid::parse_str(&user.user_id).unwrap(),
                 created_at: String::new(),
                 updated_at: String::new(),
+                link_previews: true,
+                edit_history_days: 0,
+                unread_count: 0,
+                mention_count: 0,
             };
             (StatusCode::CREATED, Json(server)).into_response()
         }
@@ -77,19 +117,15 @@ pub async fn create_server(
 pub async fn get_server(
     State(state): State<Arc<AppState>>,
     Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
     match state.db.get_server_by_id(&server_id) {
-        Ok(Some(r)) => {
-            let server = Server {
-                id: Uuid::parse_str(&r.id).unwrap(),
-                name: r.name,
-                icon_url: r.icon_url,
-                owner_id: Uuid::parse_str(&r.owner_id).unwrap(),
-                created_at: r.created_at,
-                updated_at: r.updated_at,
-            };
-            Json(server).into_response()
-        }
+        Ok(Some(r)) => Json(server_from_row(r)).into_response(),
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(e) => {
             tracing::error!("Failed to get server: {e}");
@@ -98,63 +134,112 @@ pub async fn get_server(
     }
 }
 
-pub async fn join_server(
+/// Subscribes a newly joined member's open sockets to the server and sends
+/// `member_joined` to everyone in it.
+pub async fn announce_member_joined(state: &Arc<AppState>, server_id: &str, user_id: &str) {
+    // Start delivering this server's events to the user's open sockets
+    state
+        .ws_state
+        .subscribe_user_to_server(user_id, server_id)
+        .await;
+
+    let joined_at_secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .to_string();
+
+    let user_row = match state.db.get_user_by_id(user_id) {
+        Ok(Some(row)) => row,
+        _ => {
+            tracing::error!("Failed to load joining user: user_id={}", user_id);
+            return;
+        }
+    };
+
+    let status = state.db.get_user_status(user_id).ok().flatten().map(|s| shared::models::UserStatus {
+        user_id: Uuid::parse_str(&s.user_id).unwrap(),
+        status: s.status,
+        custom_text: s.custom_text,
+        activity_type: s.activity_type,
+        activity_name: s.activity_name,
+        last_seen: s.last_seen,
+        updated_at: s.updated_at,
+    });
+
+    let member = shared::models::ServerMember {
+        user_id: Uuid::parse_str(user_id).unwrap(),
+        server_id: Uuid::parse_str(server_id).unwrap(),
+        role: "member".to_string(),
+        joined_at: joined_at_secs,
+        username: user_row.username.clone(),
+        avatar_url: user_row.avatar_url.clone(),
+        status,
+        roles: vec![],
+    };
+
+    let ws_msg = shared::ws_messages::WsEnvelope {
+        msg_type: "member_joined".to_string(),
+        payload: serde_json::to_value(shared::ws_messages::WsMemberJoined {
+            server_id: Uuid::parse_str(server_id).unwrap(),
+            member,
+        })
+        .unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_server(server_id, &serde_json::to_string(&ws_msg).unwrap())
+        .await;
+}
+
+pub async fn update_server(
     State(state): State<Arc<AppState>>,
     Path(server_id): Path<String>,
     req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
-    match state.db.join_server(&user.user_id, &server_id) {
-        Ok(()) => {
 
-            let joined_at_secs = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-                .to_string();
-
-            let user_row = match state.db.get_user_by_id(&user.user_id) {
-                Ok(Some(row)) => row,
-                _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            };
+    let body: UpdateServerRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
 
-            let status = state.db.get_user_status(&user.user_id).ok().flatten().map(|s| shared::models::UserStatus {
-                user_id: Uuid::parse_str(&s.user_id).unwrap(),
-                status: s.status,
-                custom_text: s.custom_text,
-                activity_type: s.activity_type,
-                activity_name: s.activity_name,
-                last_seen: s.last_seen,
-                updated_at: s.updated_at,
-            });
-
-            let member = shared::models::ServerMember {
-                user_id: Uuid::parse_str(&user.user_id).unwrap(),
-                server_id: Uuid::parse_str(&server_id).unwrap(),
-                role: "member".to_string(),
-                joined_at: joined_at_secs,
-                username: user_row.username.clone(),
-                avatar_url: user_row.avatar_url.clone(),
-                status,
-            };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::MANAGE_SERVER) {
+        return e.into_response();
+    }
+    if body.edit_history_days.is_some_and(|days| days < 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "edit_history_days cannot be negative",
+                "code": "invalid_retention",
+            })),
+        )
+            .into_response();
+    }
 
-            let ws_msg = shared::ws_messages::WsEnvelope {
-                msg_type: "member_joined".to_string(),
-                payload: serde_json::to_value(shared::ws_messages::WsMemberJoined {
-                    server_id: Uuid::parse_str(&server_id).unwrap(),
-                    member,
-                })
-                .unwrap(),
-            };
-            state
-                .ws_state
-                .broadcast_to_server(&server_id, &serde_json::to_string(&ws_msg).unwrap())
-                .await;
+    if let Err(e) = state.db.update_server(
+        &server_id,
+        body.name.as_deref(),
+        body.icon_url.as_deref(),
+        body.link_previews,
+        body.edit_history_days,
+    ) {
+        tracing::error!("Failed to update server: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    if let Some(icon_url) = &body.icon_url {
+        let _ = state.db.keep_upload(&user.user_id, icon_url);
+    }
 
-            StatusCode::NO_CONTENT.into_response()
-        }
+    match state.db.get_server_by_id(&server_id) {
+        Ok(Some(r)) => Json(server_from_row(r)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(e) => {
-            tracing::error!("Failed to join server: {e}");
+            tracing::error!("Failed to get server: {e}");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
@@ -166,7 +251,7 @@ pub async fn leave_server(
     req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
     let user = req.extensions().get::<AuthUser>().unwrap().clone();
-    match state.db.leave_server(&user.user_id, &server_id) {
+    match remove_member(&state, &server_id, &user.user_id).await {
         Ok(()) => StatusCode::OK.into_response(),
         Err(e) => {
             tracing::error!("Failed to leave server: {e}");
@@ -175,12 +260,94 @@ pub async fn leave_server(
     }
 }
 
+/// Removes a member, drops them from the server's voice channels, tells the
+/// remaining members and stops the removed user's subscription.
+async fn remove_member(state: &Arc<AppState>, server_id: &str, user_id: &str) -> Result<(), rusqlite::Error> {
+    state.db.leave_server(user_id, server_id)?;
+
+    if let Some(channel_id) = state.db.get_user_voice_channel(user_id)? {
+        if state.db.get_channel_server_id(&channel_id)?.as_deref() == Some(server_id) {
+            state.db.leave_voice_channel(user_id)?;
+            ws::broadcast_voice_state_update(state, &channel_id).await;
+        }
+    }
+
+    let ws_msg = shared::ws_messages::WsEnvelope {
+        msg_type: "member_left".to_string(),
+        payload: serde_json::to_value(shared::ws_messages::WsMemberLeft {
+            server_id: Uuid::parse_str(server_id).unwrap(),
+            user_id: Uuid::parse_str(user_id).unwrap(),
+        })
+        .unwrap(),
+    };
+    let ws_msg = serde_json::to_string(&ws_msg).unwrap();
+    state
+        .ws_state
+        .unsubscribe_user_from_server(user_id, server_id)
+        .await;
+    state.ws_state.broadcast_to_server(server_id, &ws_msg).await;
+    // The removed user's own sessions are no longer subscribed
+    state.ws_state.broadcast_to_user(user_id, &ws_msg).await;
+    Ok(())
+}
+
+pub async fn kick_member(
+    State(state): State<Arc<AppState>>,
+    Path((server_id, member_id)): Path<(String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::KICK_MEMBERS) {
+        return e.into_response();
+    }
+
+    match state.db.get_member_permission_info(&member_id, &server_id) {
+        Ok(Some(target)) if target.owner_id == member_id || member_id == user.user_id => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "This member cannot be kicked"})),
+            )
+                .into_response();
+        }
+        Ok(Some(_)) => {}
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to look up member: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    tracing::info!("Kicking member: server_id={}, user_id={}, by={}", server_id, member_id, user.user_id);
+    match remove_member(&state, &server_id, &member_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to kick member: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 pub async fn get_members(
     State(state): State<Arc<AppState>>,
     Path(server_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
     match state.db.get_server_members(&server_id) {
         Ok(rows) => {
+            let mut role_map: std::collections::HashMap<String, Vec<Uuid>> =
+                std::collections::HashMap::new();
+            for (user_id, role_id) in state.db.get_member_role_ids(&server_id).unwrap_or_default() {
+                role_map
+                    .entry(user_id)
+                    .or_default()
+                    .push(Uuid::parse_str(&role_id).unwrap());
+            }
+
             // Get user IDs for status lookup
             let user_ids: Vec<String> = rows.iter().map(|r| r.user_id.clone()).collect();
             let statuses = state.db.get_user_statuses(&user_ids).unwrap_or_default();
@@ -210,6 +377,7 @@ pub async fn get_members(
                         username: r.username,
                         avatar_url: r.avatar_url,
                         status,
+                        roles: role_map.remove(&r.user_id).unwrap_or_default(),
                     }
                 })
                 .collect();
@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::Serialize;
+use shared::sfu::SfuClaims;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    auth::AuthUser,
+    permissions::{self, Permissions, Target},
+    AppState,
+};
+
+/// Where the SFU (the `sfu` crate) accepts clients, and the secret the
+/// tokens it checks are signed with. Voice channels can only be switched
+/// to SFU mode when this is set.
+#[derive(Debug, Clone)]
+pub struct SfuConfig {
+    /// WebSocket URL of its signaling endpoint, e.g. `wss://sfu.example.com/rtc`
+    pub url: String,
+    pub secret: String,
+    /// How long a token can be used to connect; connections outlive it
+    pub ttl: Duration,
+}
+
+impl SfuConfig {
+    /// Reads `SFU_URL` and `SFU_SECRET`; `None` unless both are set.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("SFU_URL").ok().filter(|s| !s.is_empty());
+        let secret = std::env::var("SFU_SECRET").ok().filter(|s| !s.is_empty());
+        match (url, secret) {
+            (Some(url), Some(secret)) => Some(Self::new(url, secret)),
+            (None, None) => None,
+            _ => {
+                tracing::warn!("SFU_URL and SFU_SECRET must both be set to use an SFU; voice channels stay peer to peer");
+                None
+            }
+        }
+    }
+
+    pub fn new(url: impl Into<String>, secret: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            secret: secret.into(),
+            ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SfuSession {
+    pub url: String,
+    /// Sent to the SFU in its `auth` message
+    pub token: String,
+}
+
+fn error(status: StatusCode, error: &str, code: &str) -> axum::response::Response {
+    (status, Json(serde_json::json!({"error": error, "code": code}))).into_response()
+}
+
+/// Hands a member of an SFU voice channel a token to connect to the SFU
+/// with. They have to have joined the channel over the WebSocket first;
+/// without the speak permission, or while server muted, they can only
+/// listen.
+pub async fn get_sfu_session(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let granted = match permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::CONNECT_VOICE) {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+
+    match state.db.get_channel(&channel_id) {
+        Ok(Some(channel)) if channel.voice_mode == "sfu" => {}
+        Ok(Some(_)) => return error(StatusCode::BAD_REQUEST, "This channel does not use the SFU", "not_sfu"),
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to load channel: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let Some(sfu) = &state.sfu else {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "This server has no SFU set up", "sfu_unavailable");
+    };
+    let voice_state = match state.db.get_user_voice_state(&user.user_id) {
+        Ok(Some(current)) if current.channel_id == channel_id => current,
+        Ok(_) => return error(StatusCode::CONFLICT, "Join the voice channel first", "not_in_voice"),
+        Err(e) => {
+            tracing::error!("Failed to load voice state: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let exp = (SystemTime::now() + sfu.ttl).duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as usize;
+    let claims = SfuClaims {
+        sub: user.user_id,
+        room: channel_id,
+        publish: granted.permissions.contains(Permissions::SPEAK) && !voice_state.server_muted && !voice_state.server_deafened,
+        exp,
+    };
+    match encode(&Header::default(), &claims, &EncodingKey::from_secret(sfu.secret.as_bytes())) {
+        Ok(token) => Json(SfuSession {
+            url: sfu.url.clone(),
+            token,
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to sign SFU token: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    db::ThreadRow,
+    permissions::{self, Permissions, Target},
+    routes::messages::{message_from_row, post_message, unknown_anchor, MessageQuery},
+    AppState,
+};
+use shared::models::{CreateMessageRequest, CreateThreadRequest, Message, Thread, UserPublic};
+use shared::ws_messages::{WsEnvelope, WsThreadCreated};
+
+const MAX_NAME_CHARS: usize = 100;
+/// Length of a name taken from the root message when none is given
+const DEFAULT_NAME_CHARS: usize = 40;
+
+pub fn thread_from_row(r: ThreadRow) -> Thread {
+    Thread {
+        id: Uuid::parse_str(&r.id).unwrap(),
+        channel_id: Uuid::parse_str(&r.channel_id).unwrap(),
+        root_message_id: Uuid::parse_str(&r.root_message_id).unwrap(),
+        creator_id: Uuid::parse_str(&r.creator_id).unwrap(),
+        name: r.name,
+        message_count: r.message_count,
+        last_message_at: r.last_message_at,
+        created_at: r.created_at,
+    }
+}
+
+fn bad_request(error: &str, code: &str) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({"error": error, "code": code})),
+    )
+        .into_response()
+}
+
+/// Starts a thread rooted at a channel message. Each message has at most one
+/// thread, and replies inside a thread cannot start another.
+pub async fn create_thread(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let bytes = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let body: CreateThreadRequest = if bytes.is_empty() {
+        CreateThreadRequest::default()
+    } else {
+        match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+    };
+
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Message(&message_id), Permissions::empty()) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
+    };
+
+    let root = match state.db.get_message(&message_id) {
+        Ok(Some(root)) => root,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get message: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if root.thread_id.is_some() {
+        return bad_request("Threads cannot be started inside a thread", "invalid_thread_root");
+    }
+    match state.db.get_thread_for_root(&message_id) {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "error": "This message already has a thread",
+                    "code": "thread_exists",
+                })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to look up thread: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let name = match body.name.as_deref().map(str::trim) {
+        Some("") => return bad_request("Thread name must not be empty", "invalid_name"),
+        Some(name) if name.chars().count() > MAX_NAME_CHARS => {
+            return bad_request("Thread name is too long", "invalid_name");
+        }
+        Some(name) => name.to_string(),
+        None => match root.content.as_deref().map(str::trim) {
+            Some(content) if !content.is_empty() => content.chars().take(DEFAULT_NAME_CHARS).collect(),
+            _ => "Thread".to_string(),
+        },
+    };
+
+    let id = Uuid::new_v4();
+    tracing::info!("Creating thread: thread_id={}, message_id={}, user_id={}", id, message_id, user.user_id);
+    let thread = match state.db.create_thread(&id, &root.channel_id, &message_id, &user.user_id, &name) {
+        Ok(row) => thread_from_row(row),
+        Err(e) => {
+            tracing::error!("Failed to create thread: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let env = WsEnvelope {
+        msg_type: "thread_created".to_string(),
+        payload: serde_json::to_value(WsThreadCreated { thread: thread.clone() }).unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_server(&server_id, &serde_json::to_string(&env).unwrap())
+        .await;
+
+    (StatusCode::CREATED, Json(thread)).into_response()
+}
+
+pub async fn list_threads(
+    State(state): State<Arc<AppState>>,
+    Path(channel_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    match state.db.get_threads_for_channel(&channel_id) {
+        Ok(rows) => Json(rows.into_iter().map(thread_from_row).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list threads: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_thread(
+    State(state): State<Arc<AppState>>,
+    Path(thread_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Thread(&thread_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    match state.db.get_thread(&thread_id) {
+        Ok(Some(row)) => Json(thread_from_row(row)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get thread: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Paged like channel history; the root message itself is not included.
+pub async fn get_thread_messages(
+    State(state): State<Arc<AppState>>,
+    Path(thread_id): Path<String>,
+    Query(query): Query<MessageQuery>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let moderator = match permissions::require(&state.db, &user.user_id, Target::Thread(&thread_id), Permissions::empty()) {
+        Ok(granted) => granted.permissions.contains(Permissions::MANAGE_MESSAGES),
+        Err(e) => return e.into_response(),
+    };
+    let (cursor, anchor) = match query.cursor() {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(anchor) = anchor {
+        match state.db.get_message(anchor) {
+            Ok(Some(m)) if m.thread_id.as_deref() == Some(thread_id.as_str()) => {}
+            Ok(_) => return unknown_anchor(),
+            Err(e) => {
+                tracing::error!("Failed to look up cursor message: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    match state.db.get_thread_messages(&thread_id, query.limit(), cursor) {
+        Ok(rows) => {
+            let messages: Vec<Message> = rows
+                .into_iter()
+                .map(|r| message_from_row(&state, r, &user.user_id, moderator))
+                .collect();
+            Json(messages).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to get thread messages: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn create_thread_message(
+    State(state): State<Arc<AppState>>,
+    Path(thread_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+
+    let body: CreateMessageRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    let server_id = match permissions::require(&state.db, &user.user_id, Target::Thread(&thread_id), Permissions::empty()) {
+        Ok(granted) => granted.server_id,
+        Err(e) => return e.into_response(),
+    };
+    let thread = match state.db.get_thread(&thread_id) {
+        Ok(Some(thread)) => thread,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get thread: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let reply_to = body.reply_to.map(|r| r.to_string());
+    tracing::info!("Creating thread message via HTTP: thread_id={}, user_id={}", thread_id, user.user_id);
+    match post_message(
+        &state,
+        &server_id,
+        &thread.channel_id,
+        Some(&thread),
+        &user.user_id,
+        body.content.as_deref(),
+        reply_to.as_deref(),
+        &body.attachment_ids,
+    )
+    .await
+    {
+        Ok(message) => (StatusCode::CREATED, Json(message)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn get_thread_participants(
+    State(state): State<Arc<AppState>>,
+    Path(thread_id): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Thread(&thread_id), Permissions::empty()) {
+        return e.into_response();
+    }
+
+    match state.db.get_thread_participants(&thread_id) {
+        Ok(rows) => Json(
+            rows.into_iter()
+                .map(|(id, username, avatar_url)| UserPublic {
+                    id: Uuid::parse_str(&id).unwrap(),
+                    username,
+                    avatar_url,
+                    status: None,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to get thread participants: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -1,29 +1,136 @@
-use axum::{Json, extract::State};
-use crate::AppState;
-use std::sync::Arc;
+use axum::{extract::State, Json};
+use base64::Engine;
+use hmac::{Hmac, Mac};
 use serde::Serialize;
+use sha1::Sha1;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{auth::AuthUser, AppState};
 
-#[derive(Serialize)]
+/// ICE servers handed to clients. TURN credentials follow coturn's
+/// `use-auth-secret` scheme: they are derived from `secret`, which coturn
+/// knows as `static-auth-secret`, and stop working after `ttl`.
+#[derive(Debug, Clone)]
 pub struct TurnConfig {
+    /// `None` when no TURN server is set up; clients then only get STUN
+    pub secret: Option<String>,
+    /// `stun:`, `stuns:`, `turn:` and `turns:` URIs, e.g.
+    /// `turn:turn.example.com:3478?transport=tcp`
     pub uris: Vec<String>,
-    pub username: String,
-    pub credential: String,
+    pub ttl: Duration,
 }
 
-pub async fn get_turn_credentials(
-    State(_state): State<Arc<AppState>>,
-) -> Json<TurnConfig> {
-    let username = std::env::var("TURN_USERNAME").unwrap_or("subspace".to_string());
-    let password = std::env::var("TURN_PASSWORD").expect("TURN_PASSSWORD must be set");
-    
-    let uris = match std::env::var("TURN_URL") {
-        Ok(url) => vec![url], 
-        Err(_) => vec![],
+impl Default for TurnConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            uris: Vec::new(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl TurnConfig {
+    /// Reads `TURN_SECRET` (or the older `TURN_PASSWORD`), the
+    /// comma-separated `TURN_URLS` plus `TURN_URL`, and
+    /// `TURN_CREDENTIAL_TTL_SECS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secret = std::env::var("TURN_SECRET")
+            .or_else(|_| {
+                std::env::var("TURN_PASSWORD").inspect(|_| {
+                    tracing::warn!("TURN_PASSWORD is deprecated, set TURN_SECRET to coturn's static-auth-secret instead");
+                })
+            })
+            .ok()
+            .filter(|s| !s.is_empty());
+        let uris = [std::env::var("TURN_URLS"), std::env::var("TURN_URL")]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(",");
+        let config = Self {
+            secret,
+            uris: parse_uris(&uris),
+            ttl: std::env::var("TURN_CREDENTIAL_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ttl),
+        };
+        if config.secret.is_none() && config.uris.iter().any(|uri| is_turn(uri)) {
+            tracing::warn!("TURN_URLS lists TURN servers but TURN_SECRET is not set; clients only get STUN");
+        }
+        config
+    }
+}
+
+/// Splits a comma-separated list of ICE server URIs, dropping blanks and
+/// anything that is not a STUN or TURN URI.
+pub fn parse_uris(list: &str) -> Vec<String> {
+    let mut uris: Vec<String> = Vec::new();
+    for uri in list.split(',').map(str::trim).filter(|uri| !uri.is_empty()) {
+        let known = ["stun:", "stuns:", "turn:", "turns:"].iter().any(|scheme| uri.starts_with(scheme));
+        if !known {
+            tracing::warn!("Ignoring ICE server URI without a stun:, stuns:, turn: or turns: scheme: {uri}");
+        } else if !uris.iter().any(|u| u == uri) {
+            uris.push(uri.to_string());
+        }
+    }
+    uris
+}
+
+fn is_turn(uri: &str) -> bool {
+    uri.starts_with("turn:") || uri.starts_with("turns:")
+}
+
+/// The coturn REST API password for `username`: base64 of its HMAC-SHA1
+/// under the shared secret.
+pub fn credential(secret: &str, username: &str) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key length");
+    mac.update(username.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[derive(Debug, Serialize)]
+pub struct TurnCredentials {
+    /// Empty when the server has none configured; clients fall back to
+    /// their own default
+    pub uris: Vec<String>,
+    /// `{expiry}:{user_id}`, with the expiry in Unix seconds. `None`,
+    /// like `credential` and `ttl`, when there is no TURN server.
+    pub username: Option<String>,
+    pub credential: Option<String>,
+    /// Seconds the credential stays valid
+    pub ttl: Option<u64>,
+}
+
+/// Credentials for `user_id` that expire `config.ttl` after `now`.
+pub fn credentials_for(config: &TurnConfig, user_id: &str, now: SystemTime) -> TurnCredentials {
+    let Some(secret) = &config.secret else {
+        return TurnCredentials {
+            uris: config.uris.iter().filter(|uri| !is_turn(uri)).cloned().collect(),
+            username: None,
+            credential: None,
+            ttl: None,
+        };
     };
+    let expiry = (now + config.ttl).duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let username = format!("{expiry}:{user_id}");
+    TurnCredentials {
+        uris: config.uris.clone(),
+        credential: Some(credential(secret, &username)),
+        username: Some(username),
+        ttl: Some(config.ttl.as_secs()),
+    }
+}
 
-    Json(TurnConfig {
-        uris,
-        username,
-        credential: password,
-    })
+pub async fn get_turn_credentials(
+    State(state): State<Arc<AppState>>,
+    req: axum::http::Request<axum::body::Body>,
+) -> Json<TurnCredentials> {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    Json(credentials_for(&state.turn, &user.user_id, SystemTime::now()))
 }
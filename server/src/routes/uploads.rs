@@ -0,0 +1,751 @@
+use axum::{
+    extract::{Multipart, Path, Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, AuthUser},
+    db::{AttachmentRow, FileAccess, MediaInfo},
+    media,
+    permissions::{self, Permissions, Target},
+    storage::ServeHeaders,
+    AppState,
+};
+use shared::models::{Attachment, Upload};
+
+/// Most uploads one message may reference.
+pub const MAX_ATTACHMENTS: usize = 10;
+
+/// Size limits and storage quotas for uploads, in bytes, and how images
+/// are processed.
+#[derive(Debug, Clone)]
+pub struct UploadLimits {
+    pub max_image_bytes: u64,
+    pub max_video_bytes: u64,
+    pub max_audio_bytes: u64,
+    /// Documents, archives and plain text
+    pub max_file_bytes: u64,
+    /// Everything a user has uploaded, pending or attached
+    pub user_quota_bytes: u64,
+    /// Everything attached to messages in one server
+    pub server_quota_bytes: u64,
+    /// Remove EXIF (including GPS) and XMP metadata from images
+    pub strip_metadata: bool,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        const MIB: u64 = 1024 * 1024;
+        Self {
+            max_image_bytes: 10 * MIB,
+            max_video_bytes: 100 * MIB,
+            max_audio_bytes: 25 * MIB,
+            max_file_bytes: 25 * MIB,
+            user_quota_bytes: 1024 * MIB,
+            server_quota_bytes: 5 * 1024 * MIB,
+            strip_metadata: true,
+        }
+    }
+}
+
+impl UploadLimits {
+    /// Reads `UPLOAD_MAX_*_BYTES`, `UPLOAD_*_QUOTA_BYTES` and
+    /// `UPLOAD_STRIP_METADATA`, keeping the default for anything unset or
+    /// unparsable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str, default: u64| {
+            std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+        Self {
+            max_image_bytes: var("UPLOAD_MAX_IMAGE_BYTES", defaults.max_image_bytes),
+            max_video_bytes: var("UPLOAD_MAX_VIDEO_BYTES", defaults.max_video_bytes),
+            max_audio_bytes: var("UPLOAD_MAX_AUDIO_BYTES", defaults.max_audio_bytes),
+            max_file_bytes: var("UPLOAD_MAX_FILE_BYTES", defaults.max_file_bytes),
+            user_quota_bytes: var("UPLOAD_USER_QUOTA_BYTES", defaults.user_quota_bytes),
+            server_quota_bytes: var("UPLOAD_SERVER_QUOTA_BYTES", defaults.server_quota_bytes),
+            strip_metadata: std::env::var("UPLOAD_STRIP_METADATA")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.strip_metadata),
+        }
+    }
+
+    fn max_bytes(&self, kind: FileKind) -> u64 {
+        match kind {
+            FileKind::Image => self.max_image_bytes,
+            FileKind::Video => self.max_video_bytes,
+            FileKind::Audio => self.max_audio_bytes,
+            FileKind::File => self.max_file_bytes,
+        }
+    }
+}
+
+/// Which size limit applies to an upload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Image,
+    Video,
+    Audio,
+    File,
+}
+
+impl FileKind {
+    fn name(self) -> &'static str {
+        match self {
+            FileKind::Image => "image",
+            FileKind::Video => "video",
+            FileKind::Audio => "audio",
+            FileKind::File => "file",
+        }
+    }
+}
+
+/// Accepted types, as detected from the content rather than declared by
+/// the client.
+const ALLOWED_TYPES: &[(&str, FileKind)] = &[
+    ("image/png", FileKind::Image),
+    ("image/jpeg", FileKind::Image),
+    ("image/gif", FileKind::Image),
+    ("image/webp", FileKind::Image),
+    ("image/avif", FileKind::Image),
+    ("video/mp4", FileKind::Video),
+    ("video/webm", FileKind::Video),
+    ("video/quicktime", FileKind::Video),
+    ("audio/mpeg", FileKind::Audio),
+    ("audio/ogg", FileKind::Audio),
+    ("audio/opus", FileKind::Audio),
+    ("audio/x-wav", FileKind::Audio),
+    ("audio/x-flac", FileKind::Audio),
+    ("audio/m4a", FileKind::Audio),
+    ("audio/aac", FileKind::Audio),
+    ("application/pdf", FileKind::File),
+    ("application/zip", FileKind::File),
+    ("application/gzip", FileKind::File),
+    ("application/x-7z-compressed", FileKind::File),
+    ("application/x-tar", FileKind::File),
+    ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", FileKind::File),
+    ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", FileKind::File),
+    ("application/vnd.openxmlformats-officedocument.presentationml.presentation", FileKind::File),
+    ("application/vnd.oasis.opendocument.text", FileKind::File),
+    ("application/vnd.oasis.opendocument.spreadsheet", FileKind::File),
+    ("application/vnd.oasis.opendocument.presentation", FileKind::File),
+];
+
+/// How much of an upload is read before deciding its type.
+const SNIFF_BYTES: usize = 8 * 1024;
+
+/// Why an upload was refused.
+#[derive(Debug)]
+pub enum UploadError {
+    /// The body had no file field or could not be read
+    MissingFile,
+    Empty,
+    TooLarge { kind: &'static str, limit_bytes: u64 },
+    UnsupportedType { detected: &'static str },
+    QuotaExceeded { quota_bytes: u64, used_bytes: u64 },
+    Database(rusqlite::Error),
+    Io(std::io::Error),
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            UploadError::MissingFile => (
+                StatusCode::BAD_REQUEST,
+                serde_json::json!({"error": "Expected a multipart body with one file", "code": "missing_file"}),
+            ),
+            UploadError::Empty => (
+                StatusCode::BAD_REQUEST,
+                serde_json::json!({"error": "The file is empty", "code": "empty_file"}),
+            ),
+            UploadError::TooLarge { kind, limit_bytes } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                serde_json::json!({
+                    "error": format!("Files of type {kind} may be at most {limit_bytes} bytes"),
+                    "code": "file_too_large",
+                    "kind": kind,
+                    "limit_bytes": limit_bytes,
+                }),
+            ),
+            UploadError::UnsupportedType { detected } => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                serde_json::json!({
+                    "error": format!("Files of type {detected} are not accepted"),
+                    "code": "unsupported_type",
+                    "detected": detected,
+                }),
+            ),
+            UploadError::QuotaExceeded { quota_bytes, used_bytes } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                serde_json::json!({
+                    "error": "This upload would exceed your storage quota",
+                    "code": "quota_exceeded",
+                    "quota_bytes": quota_bytes,
+                    "used_bytes": used_bytes,
+                }),
+            ),
+            UploadError::Database(e) => {
+                tracing::error!("Failed to record upload: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            UploadError::Io(e) => {
+                tracing::error!("Failed to write file: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Detects the type of an upload from its first bytes. Content without a
+/// known signature is accepted as plain text if it is UTF-8. So is markup,
+/// which is only ever served as a text download.
+fn sniff(head: &[u8]) -> Result<(&'static str, &'static str, FileKind), UploadError> {
+    if let Some(detected) = infer::get(head).filter(|t| !matches!(t.mime_type(), "text/html" | "text/xml")) {
+        let mime = detected.mime_type();
+        return ALLOWED_TYPES
+            .iter()
+            .find(|(allowed, _)| *allowed == mime)
+            .map(|(mime, kind)| (*mime, detected.extension(), *kind))
+            .ok_or(UploadError::UnsupportedType { detected: mime });
+    }
+
+    // A multi-byte character may be cut off at the end of `head`
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap(),
+        Err(_) => return Err(UploadError::UnsupportedType { detected: "application/octet-stream" }),
+    };
+    if text.contains('\0') {
+        return Err(UploadError::UnsupportedType { detected: "application/octet-stream" });
+    }
+    Ok(("text/plain", "txt", FileKind::File))
+}
+
+/// Streams the first file field of the multipart body to disk, enforcing
+/// the type allowlist, the size limit for its type and the caller's quota.
+async fn store_upload(state: &AppState, user_id: &str, multipart: &mut Multipart) -> Result<Upload, UploadError> {
+    // Plain form fields sent alongside the file are skipped
+    let mut field = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.file_name().is_some() => break field,
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => return Err(UploadError::MissingFile),
+        }
+    };
+    let file_name = field.file_name().unwrap_or("unknown").to_string();
+
+    let mut head = Vec::new();
+    while head.len() < SNIFF_BYTES {
+        match field.chunk().await {
+            Ok(Some(chunk)) => head.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(_) => return Err(UploadError::MissingFile),
+        }
+    }
+    if head.is_empty() {
+        return Err(UploadError::Empty);
+    }
+    let (mime_type, ext, kind) = sniff(&head)?;
+    let limit_bytes = state.upload_limits.max_bytes(kind);
+    let too_large = UploadError::TooLarge { kind: kind.name(), limit_bytes };
+
+    // Rejects early; the quota is checked again when the upload is recorded
+    let quota_bytes = state.upload_limits.user_quota_bytes;
+    let used_bytes = state.db.get_user_storage_bytes(user_id).map_err(UploadError::Database)?;
+    if used_bytes >= quota_bytes {
+        return Err(UploadError::QuotaExceeded { quota_bytes, used_bytes });
+    }
+
+    let id = Uuid::new_v4();
+    let stored_name = format!("{id}.{ext}");
+    // Staged locally until it has been checked and processed
+    let partial = std::env::temp_dir()
+        .join(format!("subspace-upload-{id}.part"))
+        .to_string_lossy()
+        .into_owned();
+    let written = async {
+        let mut file = tokio::fs::File::create(&partial).await.map_err(UploadError::Io)?;
+        let mut size = head.len() as u64;
+        if size > limit_bytes {
+            return Err(too_large);
+        }
+        file.write_all(&head).await.map_err(UploadError::Io)?;
+        loop {
+            match field.chunk().await {
+                Ok(Some(chunk)) => {
+                    size += chunk.len() as u64;
+                    if size > limit_bytes {
+                        return Err(too_large);
+                    }
+                    file.write_all(&chunk).await.map_err(UploadError::Io)?;
+                }
+                Ok(None) => break,
+                Err(_) => return Err(UploadError::MissingFile),
+            }
+        }
+        if used_bytes + size > quota_bytes {
+            return Err(UploadError::QuotaExceeded { quota_bytes, used_bytes });
+        }
+        file.flush().await.map_err(UploadError::Io)?;
+        Ok(size)
+    }
+    .await;
+    let size = match written {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+    };
+
+    let mut size = size;
+    let media = match kind {
+        FileKind::Image => {
+            let (media, stripped_size) = process_image(state, &id, &partial).await;
+            size = stripped_size.unwrap_or(size);
+            media
+        }
+        FileKind::Video => probe_video(&partial).await,
+        _ => MediaInfo::default(),
+    };
+
+    let url = format!("/uploads/{stored_name}");
+    let stored = match state.storage.put_file(&stored_name, std::path::Path::new(&partial)).await {
+        Ok(()) => match state
+            .db
+            .create_upload(&id, user_id, &url, &file_name, mime_type, size as i64, &media, quota_bytes)
+        {
+            Ok(true) => Ok(()),
+            // Another upload finished first
+            Ok(false) => Err(UploadError::QuotaExceeded {
+                quota_bytes,
+                used_bytes: state.db.get_user_storage_bytes(user_id).unwrap_or(used_bytes),
+            }),
+            Err(e) => Err(UploadError::Database(e)),
+        },
+        Err(e) => Err(UploadError::Io(e)),
+    };
+    if let Err(e) = stored {
+        let _ = tokio::fs::remove_file(&partial).await;
+        let renditions = [&media.thumbnail_url, &media.preview_url];
+        for stale in [&url].into_iter().chain(renditions.into_iter().flatten()) {
+            if let Some(key) = storage_key(stale) {
+                let _ = state.storage.delete(key).await;
+            }
+        }
+        return Err(e);
+    }
+
+    Ok(Upload {
+        id,
+        url,
+        file_name,
+        mime_type: mime_type.to_string(),
+        size_bytes: size as i64,
+        width: media.width.map(|w| w as u32),
+        height: media.height.map(|h| h as u32),
+        blurhash: media.blurhash,
+        thumbnail_url: media.thumbnail_url,
+        preview_url: media.preview_url,
+        duration_secs: media.duration_secs,
+    })
+}
+
+/// Thumbnails and previews are stored as `{upload id}{suffix}`.
+const THUMBNAIL_SUFFIX: &str = "_thumb.webp";
+const PREVIEW_SUFFIX: &str = "_preview.webp";
+
+/// The storage key of an `/uploads/` URL; `None` for other URLs.
+pub fn storage_key(url: &str) -> Option<&str> {
+    url.strip_prefix("/uploads/")
+}
+
+/// Derives dimensions, a blurhash and WebP renditions for the image at
+/// `path` on the blocking pool, and strips its metadata in place if
+/// configured. Returns the new size of the file if stripping changed it.
+/// Images that cannot be decoded are kept without any of these.
+async fn process_image(state: &AppState, id: &Uuid, path: &str) -> (MediaInfo, Option<u64>) {
+    let strip_metadata = state.upload_limits.strip_metadata;
+    let source = path.to_string();
+    let processed = tokio::task::spawn_blocking(move || {
+        let data = std::fs::read(&source).ok()?;
+        media::process_image(&data, strip_metadata)
+    })
+    .await;
+    let processed = match processed {
+        Ok(Some(processed)) => processed,
+        Ok(None) => return (MediaInfo::default(), None),
+        Err(e) => {
+            tracing::error!("Image processing task failed: {e}");
+            return (MediaInfo::default(), None);
+        }
+    };
+
+    let mut stripped_size = None;
+    if let Some(stripped) = processed.stripped {
+        // Still the staged copy, not yet in storage
+        match tokio::fs::write(path, &stripped).await {
+            Ok(()) => stripped_size = Some(stripped.len() as u64),
+            Err(e) => tracing::warn!("Failed to strip metadata from {path}: {e}"),
+        }
+    }
+
+    let mut media = MediaInfo {
+        width: Some(processed.width.into()),
+        height: Some(processed.height.into()),
+        blurhash: processed.blurhash,
+        ..MediaInfo::default()
+    };
+    for (data, suffix, url) in [
+        (processed.thumbnail, THUMBNAIL_SUFFIX, &mut media.thumbnail_url),
+        (processed.preview, PREVIEW_SUFFIX, &mut media.preview_url),
+    ] {
+        let Some(data) = data else { continue };
+        let key = format!("{id}{suffix}");
+        match state.storage.put(&key, data).await {
+            Ok(()) => *url = Some(format!("/uploads/{key}")),
+            Err(e) => tracing::warn!("Failed to store {key}: {e}"),
+        }
+    }
+    (media, stripped_size)
+}
+
+/// Reads the dimensions and duration of the video at `path` from its
+/// headers on the blocking pool. Videos whose headers cannot be read are
+/// kept without them.
+async fn probe_video(path: &str) -> MediaInfo {
+    let source = path.to_string();
+    let probed = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&source).ok()?;
+        media::probe_video(&mut file)
+    })
+    .await;
+    match probed {
+        Ok(Some(video)) => MediaInfo {
+            width: video.width.map(Into::into),
+            height: video.height.map(Into::into),
+            duration_secs: video.duration_secs,
+            ..MediaInfo::default()
+        },
+        Ok(None) => MediaInfo::default(),
+        Err(e) => {
+            tracing::error!("Video probing task failed: {e}");
+            MediaInfo::default()
+        }
+    }
+}
+
+/// Builds the API model for a stored attachment, signing its file URLs
+/// for readers of the message.
+pub fn attachment_from_row(secret: &str, a: AttachmentRow) -> Attachment {
+    Attachment {
+        id: Uuid::parse_str(&a.id).unwrap(),
+        message_id: Uuid::parse_str(&a.message_id).unwrap(),
+        file_url: signed_url(secret, &a.file_url),
+        file_name: a.file_name,
+        mime_type: a.mime_type,
+        size_bytes: a.size_bytes,
+        created_at: a.created_at,
+        width: a.media.width.map(|w| w as u32),
+        height: a.media.height.map(|h| h as u32),
+        blurhash: a.media.blurhash,
+        thumbnail_url: a.media.thumbnail_url.map(|url| signed_url(secret, &url)),
+        preview_url: a.media.preview_url.map(|url| signed_url(secret, &url)),
+        duration_secs: a.media.duration_secs,
+    }
+}
+
+/// Stores the first file in the multipart body as a pending upload owned
+/// by the caller. The returned id is passed as `attachment_ids` when posting.
+pub async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<AuthUser>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    match store_upload(&state, &user.user_id, &mut multipart).await {
+        Ok(upload) => (StatusCode::CREATED, Json(upload)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Normalises `attachment_ids` from a request: duplicates are dropped and
+/// `None` is returned if there are more than [`MAX_ATTACHMENTS`].
+pub fn attachment_ids(ids: &[Uuid]) -> Option<Vec<String>> {
+    let mut ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+    ids.sort();
+    ids.dedup();
+    (ids.len() <= MAX_ATTACHMENTS).then_some(ids)
+}
+
+/// 400 for `attachment_ids` that are not the caller's pending uploads.
+pub fn invalid_attachment() -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({
+            "error": "Attachments must be at most 10 of your own pending uploads",
+            "code": "invalid_attachment",
+        })),
+    )
+        .into_response()
+}
+
+/// Deletes pending uploads older than `max_age`, rows and files, once.
+pub async fn collect_orphaned_uploads(state: &AppState, max_age: Duration) {
+    let urls = match state.db.delete_expired_uploads(max_age.as_secs()) {
+        Ok(urls) => urls,
+        Err(e) => {
+            tracing::error!("Failed to collect orphaned uploads: {e}");
+            return;
+        }
+    };
+    for key in urls.iter().filter_map(|url| storage_key(url)) {
+        if let Err(e) = state.storage.delete(key).await {
+            tracing::warn!("Failed to remove orphaned upload {key}: {e}");
+        }
+    }
+    if !urls.is_empty() {
+        tracing::info!("Removed {} orphaned uploads", urls.len());
+    }
+}
+
+/// Runs [`collect_orphaned_uploads`] forever, checking every `interval`.
+pub async fn run_upload_gc(state: Arc<AppState>, max_age: Duration, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        collect_orphaned_uploads(&state, max_age).await;
+    }
+}
+
+/// How long a signed file URL stays valid.
+pub const SIGNED_URL_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Expiries are rounded up to this many seconds so a file keeps the same
+/// URL, and stays in the browser cache, for a while.
+const SIGNED_URL_GRANULARITY: u64 = 10 * 60;
+
+/// Types browsers may render inline. Plain text, which may hold markup, is
+/// served as a `text/plain` download and everything else as an
+/// `application/octet-stream` one.
+const INLINE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "video/mp4",
+    "video/webm",
+    "video/quicktime",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/opus",
+    "audio/x-wav",
+    "audio/x-flac",
+    "audio/m4a",
+    "audio/aac",
+];
+
+/// Type for a stored file that has no recorded type, from the extension
+/// it was stored under.
+fn type_from_extension(stored_name: &str) -> Option<&'static str> {
+    let ext = stored_name.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/x-wav",
+        "flac" => "audio/x-flac",
+        "m4a" => "audio/m4a",
+        "txt" => "text/plain",
+        _ => return None,
+    })
+}
+
+fn url_signature(secret: &str, stored_name: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{stored_name}:{expires}").as_bytes());
+    mac
+}
+
+/// Adds a short-lived signature to an `/uploads/` URL so it can be loaded
+/// without an `Authorization` header, e.g. from `<img>`. Callers must
+/// only hand it to users who may read the file. Other URLs are returned
+/// unchanged.
+pub fn signed_url(secret: &str, file_url: &str) -> String {
+    let Some(stored_name) = file_url.strip_prefix("/uploads/") else {
+        return file_url.to_string();
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let expires = (now + SIGNED_URL_TTL.as_secs()).div_ceil(SIGNED_URL_GRANULARITY) * SIGNED_URL_GRANULARITY;
+    let sig = hex::encode(url_signature(secret, stored_name, expires).finalize().into_bytes());
+    format!("{file_url}?expires={expires}&sig={sig}")
+}
+
+fn signature_valid(secret: &str, stored_name: &str, expires: u64, sig: &str) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    expires >= now && url_signature(secret, stored_name, expires).verify_slice(&sig).is_ok()
+}
+
+/// Whether `user_id` may read a file with the given owner.
+fn can_read(state: &AppState, user_id: &str, access: &FileAccess) -> bool {
+    match access {
+        FileAccess::Message(message_id) => {
+            permissions::require(&state.db, user_id, Target::Message(message_id), Permissions::empty()).is_ok()
+        }
+        FileAccess::DmConversation(conversation_id) => matches!(
+            state.db.get_dm_conversation_users(conversation_id),
+            Ok(Some((u1, u2))) if u1 == user_id || u2 == user_id
+        ),
+        FileAccess::Pending(owner_id) => owner_id == user_id,
+        FileAccess::Public => true,
+    }
+}
+
+/// `filename*` per RFC 6266, with a plain ASCII fallback.
+fn content_disposition(kind: &str, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+    format!("{kind}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+#[derive(Deserialize)]
+pub struct FileQuery {
+    pub expires: Option<u64>,
+    pub sig: Option<String>,
+}
+
+/// Serves a stored file to callers allowed to see it: anyone for avatars
+/// and server icons, otherwise holders of a valid signed URL or a bearer
+/// token of a member of the owning channel or conversation. Supports range
+/// requests; unsafe types are forced to download.
+pub async fn serve_upload(
+    State(state): State<Arc<AppState>>,
+    Path(stored_name): Path<String>,
+    Query(query): Query<FileQuery>,
+    req: Request,
+) -> Response {
+    if stored_name.starts_with('.') || stored_name.contains(['/', '\\']) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    // Renditions share the access rules of the upload they were made from
+    let rendition_of = [THUMBNAIL_SUFFIX, PREVIEW_SUFFIX]
+        .into_iter()
+        .find_map(|suffix| stored_name.strip_suffix(suffix))
+        .filter(|id| Uuid::parse_str(id).is_ok());
+    let found = match rendition_of {
+        Some(id) => state.db.find_stored_file_by_id(id),
+        None => state.db.find_stored_file(&format!("/uploads/{stored_name}")),
+    };
+    let mut file = match found {
+        Ok(Some(file)) => file,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to look up upload: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if rendition_of.is_some() {
+        file.mime_type = Some("image/webp".to_string());
+        file.file_name = file.file_name.map(|name| {
+            let stem = name.rsplit_once('.').map_or(name.as_str(), |(stem, _)| stem);
+            format!("{stem}.webp")
+        });
+    }
+
+    let signed = match (query.expires, query.sig.as_deref()) {
+        (Some(expires), Some(sig)) => signature_valid(&state.jwt_secret, &stored_name, expires, sig),
+        _ => false,
+    };
+    if file.access != FileAccess::Public && !signed {
+        let Some(user_id) = auth::bearer_user(req.headers(), &state.jwt_secret) else {
+            return StatusCode::UNAUTHORIZED.into_response();
+        };
+        // Same answer as for a missing file, so names cannot be probed
+        if !can_read(&state, &user_id, &file.access) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    }
+
+    // Avatars and icons have no declared type; fall back to the extension
+    let declared = file.mime_type.or_else(|| type_from_extension(&stored_name).map(str::to_string));
+    let essence = declared
+        .as_deref()
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_ascii_lowercase());
+    let inline = INLINE_TYPES.iter().copied().find(|t| essence.as_deref() == Some(*t));
+    let file_name = file.file_name.unwrap_or_else(|| stored_name.clone());
+    let serve_headers = match inline {
+        Some(t) => ServeHeaders {
+            content_type: t,
+            content_disposition: content_disposition("inline", &file_name),
+        },
+        None if essence.as_deref() == Some("text/plain") => ServeHeaders {
+            content_type: "text/plain",
+            content_disposition: content_disposition("attachment", &file_name),
+        },
+        None => ServeHeaders {
+            content_type: "application/octet-stream",
+            content_disposition: content_disposition("attachment", &file_name),
+        },
+    };
+
+    let mut res = match state.storage.serve(&stored_name, req, &serve_headers).await {
+        Ok(res) => res,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("Failed to serve upload {stored_name}: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if !(res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED) {
+        // Including redirects to the storage backend, which sets the
+        // headers itself
+        return res;
+    }
+
+    let headers = res.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(serve_headers.content_type));
+    if let Ok(value) = HeaderValue::from_str(&serve_headers.content_disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("default-src 'none'; sandbox"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private, max-age=600"));
+    res
+}
@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Multipart, State},
+    extract::State,
     http::StatusCode,
     response::IntoResponse,
     Json,
@@ -55,54 +55,15 @@ pub async fn update_me(
         body.language.as_deref(),
         body.notifications_enabled,
     ) {
-        Ok(()) => StatusCode::OK.into_response(),
+        Ok(()) => {
+            if let Some(avatar_url) = &body.avatar_url {
+                let _ = state.db.keep_upload(&user.user_id, avatar_url);
+            }
+            StatusCode::OK.into_response()
+        }
         Err(e) => {
             tracing::error!("Failed to update user: {e}");
             StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
-
-pub async fn upload_file(
-    State(state): State<Arc<AppState>>,
-    mut multipart: Multipart,
-) -> impl IntoResponse {
-    while let Ok(Some(field)) = multipart.next_field().await {
-        let file_name = field
-            .file_name()
-            .unwrap_or("unknown")
-            .to_string();
-        let content_type = field
-            .content_type()
-            .unwrap_or("application/octet-stream")
-            .to_string();
-
-        let data = match field.bytes().await {
-            Ok(d) => d,
-            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
-        };
-
-        let ext = file_name
-            .rsplit('.')
-            .next()
-            .unwrap_or("bin");
-        let stored_name = format!("{}.{}", Uuid::new_v4(), ext);
-        let path = format!("{}/{}", state.upload_dir, stored_name);
-
-        if let Err(e) = tokio::fs::write(&path, &data).await {
-            tracing::error!("Failed to write file: {e}");
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-        }
-
-        let url = format!("/uploads/{}", stored_name);
-        return Json(serde_json::json!({
-            "url": url,
-            "file_name": file_name,
-            "mime_type": content_type,
-            "size_bytes": data.len(),
-        }))
-        .into_response();
-    }
-
-    StatusCode::BAD_REQUEST.into_response()
-}
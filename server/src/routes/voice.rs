@@ -0,0 +1,231 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use shared::models::UpdateMemberVoiceRequest;
+use shared::ws_messages::{WsEnvelope, WsVoiceMoved};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    auth::AuthUser,
+    permissions::{self, Permissions, Target},
+    ws::broadcast_voice_state_update,
+    AppState,
+};
+
+fn error(status: StatusCode, error: &str, code: &str) -> Response {
+    (status, Json(serde_json::json!({"error": error, "code": code}))).into_response()
+}
+
+fn internal(context: &str, e: rusqlite::Error) -> Response {
+    tracing::error!("{context}: {e}");
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Why a member cannot be acted on.
+enum TargetError {
+    NotFound,
+    Owner,
+    Db(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for TargetError {
+    fn from(e: rusqlite::Error) -> Self {
+        TargetError::Db(e)
+    }
+}
+
+impl IntoResponse for TargetError {
+    fn into_response(self) -> Response {
+        match self {
+            TargetError::NotFound => error(StatusCode::NOT_FOUND, "Member not found", "not_found"),
+            TargetError::Owner => error(StatusCode::FORBIDDEN, "The server owner cannot be moderated", "cannot_moderate"),
+            TargetError::Db(e) => internal("Failed to look up member", e),
+        }
+    }
+}
+
+/// Finds the member a moderator acts on and the voice channel they are in
+/// on this server, if any. The owner can only be acted on by themselves.
+fn load_target(state: &AppState, server_id: &str, actor_id: &str, member_id: &str) -> Result<Option<String>, TargetError> {
+    match state.db.get_member_permission_info(member_id, server_id)? {
+        Some(target) if target.owner_id == member_id && actor_id != member_id => return Err(TargetError::Owner),
+        Some(_) => {}
+        None => return Err(TargetError::NotFound),
+    }
+    let Some(voice_state) = state.db.get_user_voice_state(member_id)? else {
+        return Ok(None);
+    };
+    let in_server = state.db.get_channel_server_id(&voice_state.channel_id)?.is_some_and(|id| id == server_id);
+    Ok(in_server.then_some(voice_state.channel_id))
+}
+
+/// Tells the member's clients where a moderator put them; `None` means
+/// they were disconnected.
+async fn notify_moved(state: &Arc<AppState>, member_id: &str, channel_id: Option<&str>, moved_by: &str) {
+    let env = WsEnvelope {
+        msg_type: "voice_moved".to_string(),
+        payload: serde_json::to_value(WsVoiceMoved {
+            channel_id: channel_id.map(|id| Uuid::parse_str(id).unwrap()),
+            moved_by: Uuid::parse_str(moved_by).unwrap(),
+        })
+        .unwrap(),
+    };
+    state
+        .ws_state
+        .broadcast_to_user(member_id, &serde_json::to_string(&env).unwrap())
+        .await;
+}
+
+/// Server mutes, server deafens or moves a member. Each change needs its
+/// own permission in the member's current voice channel, or on the server
+/// when they are not in voice; moving also needs MOVE_MEMBERS in the
+/// destination. Changes are recorded in the audit log.
+pub async fn update_member_voice(
+    State(state): State<Arc<AppState>>,
+    Path((server_id, member_id)): Path<(String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    let body: UpdateMemberVoiceRequest = match axum::body::to_bytes(req.into_body(), 1_000_000).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(b) => b,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+    let current = match load_target(&state, &server_id, &user.user_id, &member_id) {
+        Ok(current) => current,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut required = Permissions::empty();
+    required.set(Permissions::MUTE_MEMBERS, body.server_muted.is_some());
+    required.set(Permissions::DEAFEN_MEMBERS, body.server_deafened.is_some());
+    required.set(Permissions::MOVE_MEMBERS, body.channel_id.is_some());
+    let scope = match &current {
+        Some(channel_id) => Target::Channel(channel_id),
+        None => Target::Server(&server_id),
+    };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, scope, required) {
+        return e.into_response();
+    }
+
+    let destination = match body.channel_id.map(|id| id.to_string()) {
+        Some(to) => {
+            let Some(from) = &current else {
+                return error(StatusCode::CONFLICT, "This member is not in a voice channel", "not_in_voice");
+            };
+            match permissions::require(&state.db, &user.user_id, Target::Channel(&to), Permissions::MOVE_MEMBERS) {
+                Ok(granted) if granted.server_id == server_id => {}
+                Ok(_) => return error(StatusCode::BAD_REQUEST, "That channel is in another server", "invalid_channel"),
+                Err(e) => return e.into_response(),
+            }
+            match state.db.get_channel(&to) {
+                Ok(Some(channel)) if channel.channel_type == "voice" => {}
+                Ok(_) => return error(StatusCode::BAD_REQUEST, "Members can only be moved to voice channels", "not_voice_channel"),
+                Err(e) => return internal("Failed to load channel", e),
+            }
+            (to != *from).then_some(to)
+        }
+        None => None,
+    };
+
+    let (was_muted, was_deafened) = match state.db.get_member_voice_flags(&server_id, &member_id) {
+        Ok(flags) => flags.unwrap_or_default(),
+        Err(e) => return internal("Failed to load voice flags", e),
+    };
+    if let Err(e) = state
+        .db
+        .set_member_voice_flags(&server_id, &member_id, body.server_muted, body.server_deafened)
+    {
+        return internal("Failed to update voice flags", e);
+    }
+    let mut actions = Vec::new();
+    match body.server_muted {
+        Some(true) if !was_muted => actions.push("server_mute"),
+        Some(false) if was_muted => actions.push("server_unmute"),
+        _ => {}
+    }
+    match body.server_deafened {
+        Some(true) if !was_deafened => actions.push("server_deafen"),
+        Some(false) if was_deafened => actions.push("server_undeafen"),
+        _ => {}
+    }
+    for action in &actions {
+        tracing::info!("Voice moderation: server_id={}, user_id={}, action={}, by={}", server_id, member_id, action, user.user_id);
+        if let Err(e) = state
+            .db
+            .add_audit_log_entry(&server_id, &user.user_id, action, &member_id, current.as_deref(), None)
+        {
+            tracing::error!("Failed to record audit log entry: {e}");
+        }
+    }
+
+    if let Some(to) = &destination {
+        match state.db.move_voice_state(&member_id, to) {
+            Ok(true) => {}
+            Ok(false) => return error(StatusCode::CONFLICT, "This member is not in a voice channel", "not_in_voice"),
+            Err(e) => return internal("Failed to move voice state", e),
+        }
+        tracing::info!("Moving member: server_id={}, user_id={}, to={}, by={}", server_id, member_id, to, user.user_id);
+        if let Err(e) = state
+            .db
+            .add_audit_log_entry(&server_id, &user.user_id, "voice_move", &member_id, current.as_deref(), Some(to))
+        {
+            tracing::error!("Failed to record audit log entry: {e}");
+        }
+    }
+
+    if let Some(from) = &current {
+        if !actions.is_empty() || destination.is_some() {
+            broadcast_voice_state_update(&state, from).await;
+        }
+    }
+    if let Some(to) = &destination {
+        broadcast_voice_state_update(&state, to).await;
+        notify_moved(&state, &member_id, Some(to), &user.user_id).await;
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Takes a member out of voice. Needs MOVE_MEMBERS in their channel.
+pub async fn disconnect_member_voice(
+    State(state): State<Arc<AppState>>,
+    Path((server_id, member_id)): Path<(String, String)>,
+    req: axum::http::Request<axum::body::Body>,
+) -> impl IntoResponse {
+    let user = req.extensions().get::<AuthUser>().unwrap().clone();
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Server(&server_id), Permissions::empty()) {
+        return e.into_response();
+    }
+    let channel_id = match load_target(&state, &server_id, &user.user_id, &member_id) {
+        Ok(Some(channel_id)) => channel_id,
+        Ok(None) => return error(StatusCode::CONFLICT, "This member is not in a voice channel", "not_in_voice"),
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = permissions::require(&state.db, &user.user_id, Target::Channel(&channel_id), Permissions::MOVE_MEMBERS) {
+        return e.into_response();
+    }
+
+    if let Err(e) = state.db.leave_voice_channel(&member_id) {
+        return internal("Failed to leave voice channel", e);
+    }
+    tracing::info!("Disconnecting member from voice: server_id={}, user_id={}, by={}", server_id, member_id, user.user_id);
+    if let Err(e) = state
+        .db
+        .add_audit_log_entry(&server_id, &user.user_id, "voice_disconnect", &member_id, Some(&channel_id), None)
+    {
+        tracing::error!("Failed to record audit log entry: {e}");
+    }
+    broadcast_voice_state_update(&state, &channel_id).await;
+    notify_moved(&state, &member_id, None, &user.user_id).await;
+    StatusCode::NO_CONTENT.into_response()
+}
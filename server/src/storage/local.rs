@@ -0,0 +1,84 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use axum::{extract::Request, response::Response};
+use tower_http::services::ServeFile;
+
+use super::{ServeHeaders, Storage};
+
+/// Files in a directory on the server's disk.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Uses `root`, creating it if needed.
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    /// Keys are single file names; anything that could leave `root` is
+    /// treated as missing.
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        if key.is_empty() || key.starts_with('.') || key.contains(['/', '\\']) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let dest = self.path(key)?;
+        if tokio::fs::rename(source, &dest).await.is_ok() {
+            return Ok(());
+        }
+        // The staging directory may be on another filesystem
+        tokio::fs::copy(source, &dest).await?;
+        tokio::fs::remove_file(source).await
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        tokio::fs::write(self.path(key)?, data).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.path(key)?).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            other => other,
+        }
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str().filter(|n| !n.starts_with('.')) {
+                keys.push(name.to_string());
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn serve(&self, key: &str, req: Request, _headers: &ServeHeaders) -> io::Result<Response> {
+        let res = ServeFile::new(self.path(key)?).try_call(req).await?;
+        Ok(res.map(axum::body::Body::new))
+    }
+}
@@ -0,0 +1,116 @@
+//! Where uploaded files live. Files are addressed by key, the name after
+//! `/uploads/` in their URL, so the database does not care which backend
+//! holds them.
+
+mod local;
+mod s3;
+
+pub use local::LocalStorage;
+pub use s3::{S3Config, S3Storage};
+
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Redirect, Response},
+};
+
+/// How long a redirect to a backend's own signed URL stays usable.
+const REDIRECT_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Headers a download should be served with.
+#[derive(Debug, Clone)]
+pub struct ServeHeaders {
+    pub content_type: &'static str,
+    pub content_disposition: String,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Short name for logs and the migration command
+    fn name(&self) -> &'static str;
+
+    /// Moves the finished local file at `source` into storage under `key`.
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()>;
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()>;
+
+    /// Reads a whole file; fails with `NotFound` for unknown keys.
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+
+    /// Removes a file. Unknown keys are not an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// Every key currently stored.
+    async fn list(&self) -> io::Result<Vec<String>>;
+
+    /// A URL the file can be fetched from directly, without going through
+    /// this server, for `ttl`. `None` if the backend has no such URLs.
+    fn signed_url(&self, _key: &str, _ttl: Duration, _headers: &ServeHeaders) -> Option<String> {
+        None
+    }
+
+    /// Answers `req`, which may ask for a byte range, with the file. By
+    /// default this redirects to [`Storage::signed_url`].
+    async fn serve(&self, key: &str, req: Request, headers: &ServeHeaders) -> io::Result<Response> {
+        let _ = req;
+        match self.signed_url(key, REDIRECT_TTL, headers) {
+            Some(url) => Ok(Redirect::temporary(&url).into_response()),
+            None => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+/// Builds the backend named by `backend` (`local` or `s3`) from the
+/// environment: `UPLOAD_DIR` for local storage and `S3_*` for S3.
+pub fn from_env(backend: &str) -> Result<Arc<dyn Storage>, String> {
+    match backend {
+        "local" => {
+            let dir = std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".into());
+            let storage = LocalStorage::new(&dir).map_err(|e| format!("Failed to create {dir}: {e}"))?;
+            Ok(Arc::new(storage))
+        }
+        "s3" => Ok(Arc::new(S3Storage::new(S3Config::from_env()?)?)),
+        other => Err(format!("Unknown storage backend {other:?}; expected \"local\" or \"s3\"")),
+    }
+}
+
+/// What [`migrate`] did.
+#[derive(Debug, Default, PartialEq)]
+pub struct MigrationReport {
+    pub copied: usize,
+    /// Already present in the destination
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Copies every file in `from` that `to` does not have yet. With
+/// `delete_source`, files that are now in `to` are removed from `from`. Failures are
+/// logged and counted so one bad file does not stop the run.
+pub async fn migrate(from: &dyn Storage, to: &dyn Storage, delete_source: bool) -> io::Result<MigrationReport> {
+    let existing: std::collections::HashSet<String> = to.list().await?.into_iter().collect();
+    let mut report = MigrationReport::default();
+    for key in from.list().await? {
+        if existing.contains(&key) {
+            report.skipped += 1;
+        } else {
+            let copied = match from.get(&key).await {
+                Ok(data) => to.put(&key, data).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = copied {
+                report.failed += 1;
+                tracing::error!("Failed to copy {key} from {} to {}: {e}", from.name(), to.name());
+                continue;
+            }
+            report.copied += 1;
+        }
+        if delete_source {
+            if let Err(e) = from.delete(&key).await {
+                tracing::warn!("Copied {key} but failed to remove it from {}: {e}", from.name());
+            }
+        }
+    }
+    Ok(report)
+}
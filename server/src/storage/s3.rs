@@ -0,0 +1,314 @@
+use std::{
+    io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{header, StatusCode, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{ServeHeaders, Storage};
+
+/// Connection settings for an S3-compatible bucket (AWS, MinIO, R2, ...).
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Address the bucket as `{endpoint}/{bucket}` rather than
+    /// `{bucket}.{endpoint host}`; MinIO and most stand-ins need this
+    pub path_style: bool,
+}
+
+impl S3Config {
+    /// Reads `S3_ENDPOINT`, `S3_BUCKET`, `S3_ACCESS_KEY_ID` and
+    /// `S3_SECRET_ACCESS_KEY`, plus optional `S3_REGION` (default
+    /// `us-east-1`) and `S3_PATH_STYLE` (default `true`).
+    pub fn from_env() -> Result<Self, String> {
+        let required = |name: &str| std::env::var(name).map_err(|_| format!("{name} must be set for S3 storage"));
+        Ok(Self {
+            endpoint: required("S3_ENDPOINT")?,
+            bucket: required("S3_BUCKET")?,
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".into()),
+            access_key_id: required("S3_ACCESS_KEY_ID")?,
+            secret_access_key: required("S3_SECRET_ACCESS_KEY")?,
+            path_style: std::env::var("S3_PATH_STYLE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(true),
+        })
+    }
+}
+
+/// Files in an S3-compatible bucket. Every request, including the ones
+/// this server makes, is authorised with a SigV4 presigned URL.
+pub struct S3Storage {
+    config: S3Config,
+    /// Bucket root, always ending in `/`
+    base: Url,
+    client: reqwest::Client,
+}
+
+/// How long the presigned URLs used for our own requests stay valid.
+const REQUEST_TTL: Duration = Duration::from_secs(5 * 60);
+
+impl S3Storage {
+    pub fn new(config: S3Config) -> Result<Self, String> {
+        let endpoint = Url::parse(&config.endpoint).map_err(|e| format!("Invalid S3_ENDPOINT: {e}"))?;
+        let base = if config.path_style {
+            endpoint.join(&format!("{}/", config.bucket))
+        } else {
+            let host = endpoint.host_str().ok_or("S3_ENDPOINT has no host")?;
+            let mut url = endpoint.clone();
+            url.set_host(Some(&format!("{}.{host}", config.bucket)))
+                .map_err(|e| format!("Invalid bucket host: {e}"))?;
+            Ok(url)
+        }
+        .map_err(|e| format!("Invalid S3 bucket URL: {e}"))?;
+        Ok(Self {
+            config,
+            base,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// A SigV4 query-signed URL for `method` on `key` (empty for the
+    /// bucket itself) valid for `ttl` from `now`. `query` holds extra
+    /// parameters such as `response-content-type`.
+    pub fn presigned_url_at(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        ttl: Duration,
+        now: SystemTime,
+    ) -> String {
+        let (date, timestamp) = amz_date(now);
+        let scope = format!("{date}/{}/s3/aws4_request", self.config.region);
+        let credential = format!("{}/{scope}", self.config.access_key_id);
+        let expires = ttl.as_secs().clamp(1, 7 * 24 * 60 * 60).to_string();
+
+        let mut params: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        for (k, v) in [
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256"),
+            ("X-Amz-Credential", credential.as_str()),
+            ("X-Amz-Date", timestamp.as_str()),
+            ("X-Amz-Expires", expires.as_str()),
+            ("X-Amz-SignedHeaders", "host"),
+        ] {
+            params.push((k.to_string(), uri_encode(v, true)));
+        }
+        params.sort();
+        let canonical_query = params
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let path = format!("{}{}", self.base.path(), uri_encode(key, false));
+        let host = match self.base.port() {
+            Some(port) => format!("{}:{port}", self.base.host_str().unwrap_or_default()),
+            None => self.base.host_str().unwrap_or_default().to_string(),
+        };
+        let canonical_request =
+            format!("{method}\n{path}\n{canonical_query}\nhost:{host}\n\nhost\nUNSIGNED-PAYLOAD");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key_bytes = hmac(format!("AWS4{}", self.config.secret_access_key).as_bytes(), date.as_bytes());
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            key_bytes = hmac(&key_bytes, part.as_bytes());
+        }
+        let signature = hex::encode(hmac(&key_bytes, string_to_sign.as_bytes()));
+
+        format!(
+            "{}://{host}{path}?{canonical_query}&X-Amz-Signature={signature}",
+            self.base.scheme()
+        )
+    }
+
+    fn presigned_url(&self, method: &str, key: &str, query: &[(&str, &str)]) -> String {
+        self.presigned_url_at(method, key, query, REQUEST_TTL, SystemTime::now())
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters, and `/` too when
+/// `encode_slash` is set, as SigV4 requires.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// `(YYYYMMDD, YYYYMMDDTHHMMSSZ)` in UTC.
+fn amz_date(time: SystemTime) -> (String, String) {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let date = format!("{year:04}{month:02}{day:02}");
+    let timestamp = format!("{date}T{:02}{:02}{:02}Z", rem / 3_600, rem / 60 % 60, rem % 60);
+    (date, timestamp)
+}
+
+fn request_error(e: reqwest::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Turns an unexpected S3 status into an error, keeping 404 recognisable.
+async fn check(res: reqwest::Response) -> io::Result<reqwest::Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = res.text().await.unwrap_or_default();
+    let kind = if status == StatusCode::NOT_FOUND {
+        io::ErrorKind::NotFound
+    } else {
+        io::ErrorKind::Other
+    };
+    Err(io::Error::new(kind, format!("S3 returned {status}: {body}")))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedObject {
+    key: String,
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let file = tokio::fs::File::open(source).await?;
+        let len = file.metadata().await?.len();
+        let body = reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
+        let res = self
+            .client
+            .put(self.presigned_url("PUT", key, &[]))
+            .header(header::CONTENT_LENGTH, len)
+            .body(body)
+            .send()
+            .await
+            .map_err(request_error)?;
+        check(res).await?;
+        tokio::fs::remove_file(source).await
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let res = self
+            .client
+            .put(self.presigned_url("PUT", key, &[]))
+            .body(data)
+            .send()
+            .await
+            .map_err(request_error)?;
+        check(res).await.map(|_| ())
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let res = self
+            .client
+            .get(self.presigned_url("GET", key, &[]))
+            .send()
+            .await
+            .map_err(request_error)?;
+        let bytes = check(res).await?.bytes().await.map_err(request_error)?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let res = self
+            .client
+            .delete(self.presigned_url("DELETE", key, &[]))
+            .send()
+            .await
+            .map_err(request_error)?;
+        match check(res).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            other => other.map(|_| ()),
+        }
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2")];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+            let res = self
+                .client
+                .get(self.presigned_url("GET", "", &query))
+                .send()
+                .await
+                .map_err(request_error)?;
+            let body = check(res).await?.text().await.map_err(request_error)?;
+            let page: ListBucketResult = quick_xml::de::from_str(&body)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            keys.extend(page.contents.into_iter().map(|o| o.key));
+            match page.next_continuation_token {
+                Some(next) if page.is_truncated => token = Some(next),
+                _ => return Ok(keys),
+            }
+        }
+    }
+
+    fn signed_url(&self, key: &str, ttl: Duration, headers: &ServeHeaders) -> Option<String> {
+        Some(self.presigned_url_at(
+            "GET",
+            key,
+            &[
+                ("response-content-type", headers.content_type),
+                ("response-content-disposition", &headers.content_disposition),
+            ],
+            ttl,
+            SystemTime::now(),
+        ))
+    }
+}
@@ -0,0 +1,557 @@
+//! Built-in STUN/TURN server, compiled in with the `embedded-turn` feature
+//! and started with `TURN_EMBEDDED=true`. It accepts the same short-lived
+//! credentials `GET /api/turn` hands out, so a single binary can serve
+//! voice without a coturn sidecar. Only UDP is supported.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use axum::{http::header, routing::get, Router};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use turn::auth::{generate_auth_key, AuthHandler};
+use turn::relay::relay_range::RelayAddressGeneratorRanges;
+use turn::relay::RelayAddressGenerator;
+use turn::server::config::{ConnConfig, ServerConfig};
+use turn::server::Server;
+use webrtc_util::vnet::net::Net;
+use webrtc_util::Conn;
+
+use crate::routes::turn::{credential, TurnConfig};
+
+#[derive(Debug, Clone)]
+pub struct EmbeddedTurnConfig {
+    /// UDP address the server listens on for STUN and TURN requests
+    pub listen: SocketAddr,
+    /// Address given to clients for their relays; must be reachable by
+    /// their peers
+    pub relay_ip: IpAddr,
+    /// Inclusive range of UDP ports relays are bound to
+    pub relay_ports: (u16, u16),
+    pub realm: String,
+    /// Relays one user may hold at once; a mesh call needs one per peer
+    pub max_allocations_per_user: usize,
+    /// Bytes per second relayed for one user across all their relays, in
+    /// both directions. 0 means unlimited.
+    pub user_bandwidth: u64,
+    /// TCP address `GET /metrics` is served on, kept off the public API.
+    /// Not served when `None`.
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+impl Default for EmbeddedTurnConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 3478)),
+            relay_ip: Ipv4Addr::LOCALHOST.into(),
+            relay_ports: (49160, 49200),
+            realm: "subspace".into(),
+            max_allocations_per_user: 20,
+            user_bandwidth: 0,
+            metrics_listen: None,
+        }
+    }
+}
+
+impl EmbeddedTurnConfig {
+    /// `None` unless `TURN_EMBEDDED` is `true`. Reads `TURN_LISTEN_ADDR`,
+    /// `TURN_RELAY_IP`, `TURN_RELAY_PORTS` (`min-max`), `TURN_REALM`,
+    /// `TURN_MAX_ALLOCATIONS_PER_USER`, `TURN_USER_BANDWIDTH_KBPS` and
+    /// `TURN_METRICS_LISTEN`, keeping the default for anything unset or
+    /// unparsable.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok();
+        if !var("TURN_EMBEDDED").and_then(|v| v.parse().ok()).unwrap_or(false) {
+            return None;
+        }
+        let defaults = Self::default();
+        let listen = var("TURN_LISTEN_ADDR")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.listen);
+        let relay_ports = match var("TURN_RELAY_PORTS").map(|v| parse_port_range(&v)) {
+            Some(Some(range)) => range,
+            Some(None) => {
+                tracing::warn!("Ignoring TURN_RELAY_PORTS, expected a range like 49160-49200");
+                defaults.relay_ports
+            }
+            None => defaults.relay_ports,
+        };
+        Some(Self {
+            listen,
+            relay_ip: var("TURN_RELAY_IP")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| default_relay_ip(listen)),
+            relay_ports,
+            realm: var("TURN_REALM").filter(|v| !v.is_empty()).unwrap_or(defaults.realm),
+            max_allocations_per_user: var("TURN_MAX_ALLOCATIONS_PER_USER")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_allocations_per_user),
+            user_bandwidth: var("TURN_USER_BANDWIDTH_KBPS")
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(|kbps| kbps * 1000 / 8)
+                .unwrap_or(defaults.user_bandwidth),
+            metrics_listen: var("TURN_METRICS_LISTEN").and_then(|v| v.parse().ok()),
+        })
+    }
+}
+
+/// Parses `min-max` into a non-empty range of non-zero ports.
+pub fn parse_port_range(range: &str) -> Option<(u16, u16)> {
+    let (min, max) = range.split_once('-')?;
+    let (min, max) = (min.trim().parse::<u16>().ok()?, max.trim().parse::<u16>().ok()?);
+    (min > 0 && min <= max).then_some((min, max))
+}
+
+/// The listen address when it is a concrete one, otherwise the address of
+/// the interface that routes to the internet.
+fn default_relay_ip(listen: SocketAddr) -> IpAddr {
+    if !listen.ip().is_unspecified() {
+        return listen.ip();
+    }
+    // Connecting a UDP socket only picks a route, nothing is sent
+    let routed = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).map(|_| socket))
+        .and_then(|socket| socket.local_addr());
+    match routed {
+        Ok(addr) => {
+            tracing::warn!("TURN_RELAY_IP is not set, giving clients relays on {}", addr.ip());
+            addr.ip()
+        }
+        Err(e) => {
+            tracing::warn!("TURN_RELAY_IP is not set and no route was found ({e}), relays will only work locally");
+            Ipv4Addr::LOCALHOST.into()
+        }
+    }
+}
+
+/// Counters exported by `GET /metrics` in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct TurnMetrics {
+    pub allocations_active: AtomicU64,
+    pub allocations_total: AtomicU64,
+    /// Allocations refused because the user already held their quota
+    pub allocations_rejected: AtomicU64,
+    /// Requests with a malformed or expired username
+    pub auth_rejected: AtomicU64,
+    pub relayed_bytes: AtomicU64,
+    /// Packets dropped for going over a user's bandwidth
+    pub dropped_packets: AtomicU64,
+}
+
+impl TurnMetrics {
+    pub fn render(&self) -> String {
+        let metrics = [
+            ("subspace_turn_allocations", "gauge", "TURN allocations currently open", &self.allocations_active),
+            ("subspace_turn_allocations_total", "counter", "TURN allocations created", &self.allocations_total),
+            (
+                "subspace_turn_allocations_rejected_total",
+                "counter",
+                "TURN allocations refused by the per-user quota",
+                &self.allocations_rejected,
+            ),
+            (
+                "subspace_turn_auth_rejected_total",
+                "counter",
+                "TURN requests with a malformed or expired username",
+                &self.auth_rejected,
+            ),
+            ("subspace_turn_relayed_bytes_total", "counter", "Bytes relayed to and from peers", &self.relayed_bytes),
+            (
+                "subspace_turn_dropped_packets_total",
+                "counter",
+                "Relayed packets dropped by the per-user bandwidth limit",
+                &self.dropped_packets,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {}\n",
+                value.load(Ordering::Relaxed)
+            ));
+        }
+        out
+    }
+}
+
+/// A user's open relays and their bandwidth token bucket.
+struct Usage {
+    allocations: usize,
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Per-user bookkeeping shared by the auth handler, the relay address
+/// generator and every relay.
+struct Quotas {
+    max_allocations: usize,
+    bandwidth: u64,
+    users: Mutex<HashMap<String, Usage>>,
+    /// Users whose requests passed the auth handler, by the client address
+    /// the request came from. An allocate request is authenticated right
+    /// before its relay is created, so the relay is charged to the user its
+    /// own client authenticated as.
+    authenticated: Mutex<HashMap<SocketAddr, String>>,
+    metrics: Arc<TurnMetrics>,
+}
+
+impl Quotas {
+    fn acquire(&self, user_id: &str) -> bool {
+        let mut users = self.users.lock().unwrap();
+        let usage = users.entry(user_id.to_string()).or_insert_with(|| Usage {
+            allocations: 0,
+            tokens: self.bandwidth as f64,
+            refilled: Instant::now(),
+        });
+        if usage.allocations >= self.max_allocations {
+            self.metrics.allocations_rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        usage.allocations += 1;
+        self.metrics.allocations_active.fetch_add(1, Ordering::Relaxed);
+        self.metrics.allocations_total.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    fn release(&self, user_id: &str) {
+        let mut users = self.users.lock().unwrap();
+        if let Some(usage) = users.get_mut(user_id) {
+            usage.allocations -= 1;
+            if usage.allocations == 0 {
+                users.remove(user_id);
+            }
+            self.metrics.allocations_active.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes `bytes` from the user's bucket, which holds up to one second
+    /// of their bandwidth. `false` means the packet should be dropped.
+    fn spend(&self, user_id: &str, bytes: usize) -> bool {
+        if self.bandwidth > 0 {
+            let mut users = self.users.lock().unwrap();
+            if let Some(usage) = users.get_mut(user_id) {
+                let now = Instant::now();
+                let refill = now.duration_since(usage.refilled).as_secs_f64() * self.bandwidth as f64;
+                usage.tokens = (usage.tokens + refill).min(self.bandwidth as f64);
+                usage.refilled = now;
+                if usage.tokens < bytes as f64 {
+                    self.metrics.dropped_packets.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                usage.tokens -= bytes as f64;
+            }
+        }
+        self.metrics.relayed_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Checks the `{expiry}:{user_id}` usernames issued by
+/// [`crate::routes::turn::credentials_for`].
+struct SharedSecretAuth {
+    secret: String,
+    quotas: Arc<Quotas>,
+}
+
+impl AuthHandler for SharedSecretAuth {
+    fn auth_handle(&self, username: &str, realm: &str, src_addr: SocketAddr) -> Result<Vec<u8>, turn::Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let user_id = match username.split_once(':') {
+            Some((expiry, user_id)) if expiry.parse::<u64>().is_ok_and(|expiry| expiry >= now) => user_id,
+            _ => {
+                self.quotas.metrics.auth_rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(turn::Error::Other(format!("malformed or expired TURN username {username}")));
+            }
+        };
+        self.quotas.authenticated.lock().unwrap().insert(src_addr, user_id.to_string());
+        Ok(generate_auth_key(username, realm, &credential(&self.secret, username)))
+    }
+}
+
+/// The server's UDP socket, noting which client sent the request being
+/// handled. The server reads a listener's next request only once it is done
+/// with the last one.
+struct Listener {
+    inner: Arc<dyn Conn + Send + Sync>,
+    quotas: Arc<Quotas>,
+    handling: Mutex<Option<SocketAddr>>,
+}
+
+#[async_trait]
+impl Conn for Listener {
+    async fn connect(&self, addr: SocketAddr) -> webrtc_util::Result<()> {
+        self.inner.connect(addr).await
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc_util::Result<usize> {
+        self.recv_from(buf).await.map(|(n, _)| n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc_util::Result<(usize, SocketAddr)> {
+        let (n, from) = self.inner.recv_from(buf).await?;
+        // Whatever earlier requests authenticated is done with
+        self.quotas.authenticated.lock().unwrap().clear();
+        *self.handling.lock().unwrap() = Some(from);
+        Ok((n, from))
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc_util::Result<usize> {
+        self.inner.send(buf).await
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc_util::Result<usize> {
+        self.inner.send_to(buf, target).await
+    }
+
+    fn local_addr(&self) -> webrtc_util::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    async fn close(&self) -> webrtc_util::Result<()> {
+        self.inner.close().await
+    }
+}
+
+/// Hands out relays from the configured port range while the user is under
+/// their allocation quota.
+struct QuotaRelayGenerator {
+    ranges: RelayAddressGeneratorRanges,
+    listener: Arc<Listener>,
+    quotas: Arc<Quotas>,
+}
+
+#[async_trait]
+impl RelayAddressGenerator for QuotaRelayGenerator {
+    fn validate(&self) -> Result<(), turn::Error> {
+        self.ranges.validate()
+    }
+
+    async fn allocate_conn(
+        &self,
+        use_ipv4: bool,
+        requested_port: u16,
+    ) -> Result<(Arc<dyn Conn + Send + Sync>, SocketAddr), turn::Error> {
+        let client = *self.listener.handling.lock().unwrap();
+        let user_id = client
+            .and_then(|client| self.quotas.authenticated.lock().unwrap().remove(&client))
+            .ok_or_else(|| turn::Error::Other("allocation without an authenticated user".into()))?;
+        if !self.quotas.acquire(&user_id) {
+            return Err(turn::Error::Other(format!("{user_id} reached their TURN allocation quota")));
+        }
+        match self.ranges.allocate_conn(use_ipv4, requested_port).await {
+            Ok((conn, relay_addr)) => {
+                let relay = RelayConn {
+                    inner: conn,
+                    user_id,
+                    quotas: self.quotas.clone(),
+                    closed: CancellationToken::new(),
+                    released: AtomicBool::new(false),
+                };
+                Ok((Arc::new(relay), relay_addr))
+            }
+            Err(e) => {
+                self.quotas.release(&user_id);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A relay socket that counts and rate-limits what passes through it and
+/// gives its allocation back to the user's quota when closed.
+struct RelayConn {
+    inner: Arc<dyn Conn + Send + Sync>,
+    user_id: String,
+    quotas: Arc<Quotas>,
+    /// Closing a UDP socket does not wake a pending receive, so the
+    /// allocation's read loop waits on this too
+    closed: CancellationToken,
+    released: AtomicBool,
+}
+
+impl RelayConn {
+    fn release(&self) {
+        if !self.released.swap(true, Ordering::AcqRel) {
+            self.quotas.release(&self.user_id);
+        }
+    }
+}
+
+impl Drop for RelayConn {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[async_trait]
+impl Conn for RelayConn {
+    async fn connect(&self, addr: SocketAddr) -> webrtc_util::Result<()> {
+        self.inner.connect(addr).await
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc_util::Result<usize> {
+        self.recv_from(buf).await.map(|(n, _)| n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc_util::Result<(usize, SocketAddr)> {
+        loop {
+            let (n, from) = tokio::select! {
+                received = self.inner.recv_from(buf) => received?,
+                _ = self.closed.cancelled() => return Err(webrtc_util::Error::ErrUseClosedNetworkConn),
+            };
+            if self.quotas.spend(&self.user_id, n) {
+                return Ok((n, from));
+            }
+        }
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc_util::Result<usize> {
+        if !self.quotas.spend(&self.user_id, buf.len()) {
+            return Ok(buf.len());
+        }
+        self.inner.send(buf).await
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc_util::Result<usize> {
+        // Dropped like any other UDP packet that doesn't make it
+        if !self.quotas.spend(&self.user_id, buf.len()) {
+            return Ok(buf.len());
+        }
+        self.inner.send_to(buf, target).await
+    }
+
+    fn local_addr(&self) -> webrtc_util::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    async fn close(&self) -> webrtc_util::Result<()> {
+        self.closed.cancel();
+        self.release();
+        self.inner.close().await
+    }
+}
+
+pub struct EmbeddedTurn {
+    server: Server,
+    local_addr: SocketAddr,
+    relay_ip: IpAddr,
+    pub metrics: Arc<TurnMetrics>,
+}
+
+impl EmbeddedTurn {
+    /// Binds `config.listen` and starts serving, checking credentials
+    /// against `secret`.
+    pub async fn start(config: EmbeddedTurnConfig, secret: String) -> Result<Self, turn::Error> {
+        let socket = UdpSocket::bind(config.listen).await?;
+        let local_addr = socket.local_addr()?;
+        let metrics = Arc::new(TurnMetrics::default());
+        let quotas = Arc::new(Quotas {
+            max_allocations: config.max_allocations_per_user,
+            bandwidth: config.user_bandwidth,
+            users: Mutex::new(HashMap::new()),
+            authenticated: Mutex::new(HashMap::new()),
+            metrics: metrics.clone(),
+        });
+        let listener = Arc::new(Listener {
+            inner: Arc::new(socket),
+            quotas: quotas.clone(),
+            handling: Mutex::new(None),
+        });
+        let relay_addr_generator = QuotaRelayGenerator {
+            ranges: RelayAddressGeneratorRanges {
+                relay_address: config.relay_ip,
+                min_port: config.relay_ports.0,
+                max_port: config.relay_ports.1,
+                max_retries: 0,
+                address: if config.listen.is_ipv4() { "0.0.0.0" } else { "::" }.into(),
+                net: Arc::new(Net::new(None)),
+            },
+            listener: listener.clone(),
+            quotas: quotas.clone(),
+        };
+        let server = Server::new(ServerConfig {
+            conn_configs: vec![ConnConfig {
+                conn: listener,
+                relay_addr_generator: Box::new(relay_addr_generator),
+            }],
+            realm: config.realm,
+            auth_handler: Arc::new(SharedSecretAuth { secret, quotas }),
+            channel_bind_timeout: std::time::Duration::ZERO,
+            alloc_close_notify: None,
+        })
+        .await?;
+        Ok(Self {
+            server,
+            local_addr,
+            relay_ip: config.relay_ip,
+            metrics,
+        })
+    }
+
+    /// Starts the server when `TURN_EMBEDDED` is set. The embedded server
+    /// shares `turn`'s secret, generating one if there is none, and is
+    /// advertised to clients unless `TURN_URLS` already lists a TURN server.
+    pub async fn from_env(turn: &mut TurnConfig) -> Option<Self> {
+        let config = EmbeddedTurnConfig::from_env()?;
+        let metrics_listen = config.metrics_listen;
+        let secret = turn
+            .secret
+            .get_or_insert_with(|| format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple()))
+            .clone();
+        let embedded = Self::start(config, secret)
+            .await
+            .expect("Failed to start the embedded TURN server");
+        if !turn.uris.iter().any(|uri| uri.starts_with("turn:") || uri.starts_with("turns:")) {
+            turn.uris.extend(embedded.uris());
+        }
+        tracing::info!("Embedded TURN server listening on udp/{}", embedded.local_addr);
+        if let Some(addr) = metrics_listen {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .expect("Failed to bind TURN_METRICS_LISTEN");
+            tracing::info!("TURN metrics served on http://{addr}/metrics");
+            let router = embedded.metrics_router();
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, router).await {
+                    tracing::error!("TURN metrics listener failed: {e}");
+                }
+            });
+        }
+        Some(embedded)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// STUN and TURN URIs reaching this server through the relay address.
+    pub fn uris(&self) -> Vec<String> {
+        let host = SocketAddr::new(self.relay_ip, self.local_addr.port());
+        vec![format!("stun:{host}"), format!("turn:{host}?transport=udp")]
+    }
+
+    /// `GET /metrics`, served on its own listener rather than the app's.
+    pub fn metrics_router(&self) -> Router {
+        let metrics = self.metrics.clone();
+        Router::new().route(
+            "/metrics",
+            get(move || async move { ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics.render()) }),
+        )
+    }
+
+    pub async fn close(&self) -> Result<(), turn::Error> {
+        self.server.close().await
+    }
+}
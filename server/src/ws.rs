@@ -7,27 +7,92 @@ use axum::{
 };
 use futures_util::{SinkExt, StreamExt};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::Arc,
+    time::Duration,
 };
-use tokio::sync::{broadcast, RwLock};
+use tokio::{
+    sync::{broadcast, mpsc, RwLock},
+    task::AbortHandle,
+};
+use uuid::Uuid;
 
-use crate::{auth, AppState};
+use crate::{
+    auth,
+    permissions::{self, Permissions, Target},
+    routes::read_states,
+    AppState,
+};
 use shared::ws_messages::WsEnvelope;
 
-/// Tracks which user IDs are connected and which servers they belong to.
+/// Outbound queue of a single WebSocket connection.
+pub type ConnectionSender = mpsc::Sender<String>;
+
+/// A live connection: its outbound queue and one forwarding task per
+/// subscribed server.
+struct Connection {
+    tx: ConnectionSender,
+    subscriptions: HashMap<String, AbortHandle>,
+}
+
+/// How connections are kept alive. The server pings every `interval`;
+/// a connection that sends nothing, pongs included, for `timeout` is
+/// closed as if the client had disconnected.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Reads `WS_HEARTBEAT_INTERVAL_SECS` and `WS_HEARTBEAT_TIMEOUT_SECS`,
+    /// keeping the default for anything unset or unparsable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+        };
+        Self {
+            interval: var("WS_HEARTBEAT_INTERVAL_SECS").unwrap_or(defaults.interval),
+            timeout: var("WS_HEARTBEAT_TIMEOUT_SECS").unwrap_or(defaults.timeout),
+        }
+    }
+}
+
+/// Routes outbound events: server-scoped events go through one broadcast
+/// channel per server, user-targeted events go straight to that user's
+/// connections.
+#[derive(Default)]
 pub struct WsState {
     /// Maps server_id -> broadcast sender
     server_channels: RwLock<HashMap<String, broadcast::Sender<String>>>,
-    /// Maps user_id -> list of server_ids they're subscribed to
-    user_servers: RwLock<HashMap<String, Vec<String>>>,
+    /// Maps user_id -> session_id -> connection
+    connections: RwLock<HashMap<String, HashMap<Uuid, Connection>>>,
+    heartbeat: Heartbeat,
 }
 
 impl WsState {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_heartbeat(heartbeat: Heartbeat) -> Self {
         Self {
-            server_channels: RwLock::new(HashMap::new()),
-            user_servers: RwLock::new(HashMap::new()),
+            heartbeat,
+            ..Self::default()
         }
     }
 
@@ -42,9 +107,10 @@ impl WsState {
             }
         }
         let mut channels = self.server_channels.write().await;
-        let (tx, _) = broadcast::channel(256);
-        channels.insert(server_id.to_string(), tx.clone());
-        tx
+        channels
+            .entry(server_id.to_string())
+            .or_insert_with(|| broadcast::channel(256).0)
+            .clone()
     }
 
     pub async fn broadcast_to_server(&self, server_id: &str, message: &str) {
@@ -54,40 +120,138 @@ impl WsState {
         }
     }
 
-    pub async fn subscribe_user_to_server(
+    /// Registers a new connection for `user_id` and returns its session id
+    /// together with both ends of its outbound queue.
+    pub async fn register_connection(
         &self,
         user_id: &str,
-        server_id: &str,
-    ) -> broadcast::Receiver<String> {
-        let tx = self.get_or_create_server_channel(server_id).await;
-        let rx = tx.subscribe();
-
-        let mut user_servers = self.user_servers.write().await;
-        user_servers
+    ) -> (Uuid, ConnectionSender, mpsc::Receiver<String>) {
+        let session_id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(256);
+        self.connections
+            .write()
+            .await
             .entry(user_id.to_string())
             .or_default()
-            .push(server_id.to_string());
+            .insert(
+                session_id,
+                Connection {
+                    tx: tx.clone(),
+                    subscriptions: HashMap::new(),
+                },
+            );
+        (session_id, tx, rx)
+    }
+
+    /// Removes a connection and stops its server subscriptions. Returns
+    /// `true` if it was the user's last one.
+    pub async fn unregister_connection(&self, user_id: &str, session_id: Uuid) -> bool {
+        let mut connections = self.connections.write().await;
+        let Some(sessions) = connections.get_mut(user_id) else {
+            return true;
+        };
+        if let Some(conn) = sessions.remove(&session_id) {
+            for handle in conn.subscriptions.values() {
+                handle.abort();
+            }
+        }
+        if sessions.is_empty() {
+            connections.remove(user_id);
+            true
+        } else {
+            false
+        }
+    }
 
-        rx
+    /// Starts forwarding `server_id` broadcasts to every live connection of
+    /// `user_id`. Connections that are already subscribed are left alone.
+    pub async fn subscribe_user_to_server(&self, user_id: &str, server_id: &str) {
+        let server_tx = self.get_or_create_server_channel(server_id).await;
+        let mut connections = self.connections.write().await;
+        let Some(sessions) = connections.get_mut(user_id) else {
+            return;
+        };
+        for conn in sessions.values_mut() {
+            if conn.subscriptions.contains_key(server_id) {
+                continue;
+            }
+            let handle = spawn_forwarder(server_tx.subscribe(), conn.tx.clone());
+            conn.subscriptions.insert(server_id.to_string(), handle);
+        }
     }
 
-    pub async fn unsubscribe_user(&self, user_id: &str) {
-        let mut user_servers = self.user_servers.write().await;
-        user_servers.remove(user_id);
+    /// Stops forwarding `server_id` broadcasts to all of `user_id`'s
+    /// connections.
+    pub async fn unsubscribe_user_from_server(&self, user_id: &str, server_id: &str) {
+        let mut connections = self.connections.write().await;
+        if let Some(sessions) = connections.get_mut(user_id) {
+            for conn in sessions.values_mut() {
+                if let Some(handle) = conn.subscriptions.remove(server_id) {
+                    handle.abort();
+                }
+            }
+        }
     }
 
-    /// Broadcast a message to a specific user across all their subscribed servers
+    /// Whether the user has at least one open connection.
+    pub async fn is_connected(&self, user_id: &str) -> bool {
+        self.connections.read().await.contains_key(user_id)
+    }
+
+    /// Sends a message to every connection of a single user
     pub async fn broadcast_to_user(&self, user_id: &str, message: &str) {
-        let user_servers = self.user_servers.read().await;
-        if let Some(server_ids) = user_servers.get(user_id) {
-            let channels = self.server_channels.read().await;
-            for server_id in server_ids {
-                if let Some(tx) = channels.get(server_id) {
-                    let _ = tx.send(message.to_string());
+        let connections = self.connections.read().await;
+        if let Some(sessions) = connections.get(user_id) {
+            for (session_id, conn) in sessions {
+                if conn.tx.try_send(message.to_string()).is_err() {
+                    tracing::warn!("Dropping event for slow connection: user_id={}, session_id={}", user_id, session_id);
                 }
             }
         }
     }
+
+    /// Like [`WsState::broadcast_to_user`], skipping the connection that
+    /// `except` sends to.
+    pub async fn broadcast_to_user_except(&self, user_id: &str, except: &ConnectionSender, message: &str) {
+        let connections = self.connections.read().await;
+        if let Some(sessions) = connections.get(user_id) {
+            for (session_id, conn) in sessions {
+                if conn.tx.same_channel(except) {
+                    continue;
+                }
+                if conn.tx.try_send(message.to_string()).is_err() {
+                    tracing::warn!("Dropping event for slow connection: user_id={}, session_id={}", user_id, session_id);
+                }
+            }
+        }
+    }
+
+    /// Sends a message once to each of the given users
+    pub async fn broadcast_to_users<'a>(&self, user_ids: impl IntoIterator<Item = &'a str>, message: &str) {
+        let unique: HashSet<&str> = user_ids.into_iter().collect();
+        for user_id in unique {
+            self.broadcast_to_user(user_id, message).await;
+        }
+    }
+}
+
+/// Copies server broadcasts into a connection's queue until aborted or the
+/// connection goes away.
+fn spawn_forwarder(mut rx: broadcast::Receiver<String>, tx: ConnectionSender) -> AbortHandle {
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => {
+                    if tx.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    })
+    .abort_handle()
 }
 
 /// WebSocket upgrade handler
@@ -100,10 +264,11 @@ pub async fn ws_handler(
 
 async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     let (mut sender, mut receiver) = socket.split();
+    let heartbeat = state.ws_state.heartbeat.clone();
 
     // First message must be auth
-    let user_id = match receiver.next().await {
-        Some(Ok(Message::Text(text))) => {
+    let user_id = match tokio::time::timeout(heartbeat.timeout, receiver.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => {
             match serde_json::from_str::<WsEnvelope>(&text) {
                 Ok(env) if env.msg_type == "auth" => {
                     match serde_json::from_value::<shared::ws_messages::WsAuth>(env.payload) {
@@ -118,6 +283,7 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                                                 payload: serde_json::to_value(
                                                     shared::ws_messages::WsError {
                                                         message: "Invalid token".to_string(),
+                                                        code: None,
                                                     },
                                                 )
                                                 .unwrap(),
@@ -141,47 +307,28 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 
     tracing::info!("WebSocket authenticated: user_id={user_id}");
 
+    let (session_id, conn_tx, mut conn_rx) = state.ws_state.register_connection(&user_id).await;
+
     // Set user status to online
     let _ = state.db.set_user_status(&user_id, "online", None);
 
-    // Subscribe to all servers the user is a member of
     let servers = state
         .db
         .get_servers_for_user(&user_id)
         .unwrap_or_default();
 
-    // Broadcast online status to all shared servers
-    broadcast_user_status_update(&state, &user_id, &servers).await;
+    // Broadcast online status to everyone sharing a server
+    broadcast_user_status_update(&state, &user_id).await;
 
-    let mut receivers = Vec::new();
+    // Subscribe to all servers the user is a member of
     for server in &servers {
-        let rx = state
+        state
             .ws_state
             .subscribe_user_to_server(&user_id, &server.id)
             .await;
-        receivers.push(rx);
     }
-
-    // Merge all server broadcast receivers into one stream
-    let (merged_tx, mut merged_rx) = tokio::sync::mpsc::channel::<String>(256);
-
-    for mut rx in receivers {
-        let merged_tx = merged_tx.clone();
-        tokio::spawn(async move {
-            loop {
-                match rx.recv().await {
-                    Ok(msg) => {
-                        if merged_tx.send(msg).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
-                    Err(_) => break,
-                }
-            }
-        });
-    }
-    drop(merged_tx); // Drop the original so channel closes when all tasks finish
+    // Kept by the receive loop so errors can be sent back to this client only
+    let reply_tx = conn_tx;
 
     // Send auth success
     let _ = sender
@@ -198,21 +345,41 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     let user_id_clone = user_id.clone();
     let state_clone = state.clone();
 
-    // Spawn task to forward broadcast messages to this client
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = merged_rx.recv().await {
-            if sender.send(Message::Text(msg.into())).await.is_err() {
+    // Spawn task to forward queued messages to this client, pinging it
+    // whenever the heartbeat interval comes round
+    let mut send_task = tokio::spawn(async move {
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + heartbeat.interval, heartbeat.interval);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            let msg = tokio::select! {
+                msg = conn_rx.recv() => match msg {
+                    Some(msg) => Message::Text(msg.into()),
+                    None => break,
+                },
+                _ = ping.tick() => Message::Ping(Default::default()),
+            };
+            if sender.send(msg).await.is_err() {
                 break;
             }
         }
     });
 
-    // Handle incoming messages from client
-    let recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
+    // Handle incoming messages from client. Anything it sends counts as a
+    // sign of life; silence for longer than the timeout ends the session.
+    let idle_timeout = heartbeat.timeout;
+    let mut recv_task = tokio::spawn(async move {
+        loop {
+            let msg = match tokio::time::timeout(idle_timeout, receiver.next()).await {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(_) => break,
+                Err(_) => {
+                    tracing::info!("WebSocket heartbeat timed out: user_id={}, session_id={}", user_id_clone, session_id);
+                    break;
+                }
+            };
             match msg {
                 Message::Text(text) => {
-                    handle_client_message(&text, &user_id_clone, &state_clone).await;
+                    handle_client_message(&text, &user_id_clone, session_id, &state_clone, &reply_tx).await;
                 }
                 Message::Close(_) => break,
                 _ => {}
@@ -222,25 +389,39 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 
     // Wait for either task to finish
     tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
+        _ = &mut send_task => recv_task.abort(),
+        _ = &mut recv_task => send_task.abort(),
     }
 
-    // Cleanup: set offline, leave voice if in one, unsubscribe
-    let _ = state.db.set_user_offline(&user_id);
-    
-    // Broadcast offline status to all shared servers
-    let servers = state.db.get_servers_for_user(&user_id).unwrap_or_default();
-    broadcast_user_status_update(&state, &user_id, &servers).await;
-    
-    if let Ok(Some(channel_id)) = state.db.leave_voice_channel(&user_id) {
+    // Cleanup: leave voice if this session joined it, and once the user's
+    // last connection is gone, set offline
+    if state.ws_state.unregister_connection(&user_id, session_id).await {
+        let _ = state.db.set_user_offline(&user_id);
+        broadcast_user_status_update(&state, &user_id).await;
+    }
+    if let Ok(Some(channel_id)) = state.db.leave_voice_session(&user_id, &session_id.to_string()) {
+        tracing::info!("Voice session ended: user_id={}, channel_id={}", user_id, channel_id);
         broadcast_voice_state_update(&state, &channel_id).await;
     }
-    state.ws_state.unsubscribe_user(&user_id).await;
-    tracing::info!("WebSocket disconnected: user_id={user_id}");
+    tracing::info!("WebSocket disconnected: user_id={user_id}, session_id={session_id}");
 }
 
-async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>) {
+/// Replies to the sending client with an `error` event.
+async fn send_error(reply: &tokio::sync::mpsc::Sender<String>, error: shared::ws_messages::WsError) {
+    let env = WsEnvelope {
+        msg_type: "error".to_string(),
+        payload: serde_json::to_value(error).unwrap(),
+    };
+    let _ = reply.send(serde_json::to_string(&env).unwrap()).await;
+}
+
+async fn handle_client_message(
+    text: &str,
+    user_id: &str,
+    session_id: Uuid,
+    state: &Arc<AppState>,
+    reply: &tokio::sync::mpsc::Sender<String>,
+) {
     let env: WsEnvelope = match serde_json::from_str(text) {
         Ok(e) => e,
         Err(e) => {
@@ -256,57 +437,77 @@ async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>)
             if let Ok(msg) =
                 serde_json::from_value::<shared::ws_messages::WsSendMessage>(env.payload)
             {
-                let id = uuid::Uuid::new_v4();
                 let channel_id = msg.channel_id.to_string();
-                
-                tracing::info!("Message sent via WebSocket: message_id={}, channel_id={}, user_id={}", id, channel_id, user_id);
-                
-                if let Ok(row) = state
-                    .db
-                    .create_message(&id, &channel_id, user_id, Some(&msg.content))
-                {
-                    let message = shared::models::Message {
-                        id: uuid::Uuid::parse_str(&row.id).unwrap(),
-                        channel_id: uuid::Uuid::parse_str(&row.channel_id).unwrap(),
-                        author_id: uuid::Uuid::parse_str(&row.author_id).unwrap(),
-                        content: row.content,
-                        pinned: row.pinned,
-                        created_at: row.created_at,
-                        edited_at: row.edited_at,
-                        author: Some(shared::models::UserPublic {
-                            id: uuid::Uuid::parse_str(&row.author_id).unwrap(),
-                            username: row.author_username,
-                            avatar_url: row.author_avatar_url,
-                            status: None,
-                        }),
-                        attachments: vec![],
-                        reactions: vec![],
-                    };
-
-                    if let Some(server_id) =
-                        state.db.get_channel_server_id(&channel_id).ok().flatten()
-                    {
-                        let ws_msg = WsEnvelope {
-                            msg_type: "message_created".to_string(),
-                            payload: serde_json::to_value(
-                                shared::ws_messages::WsMessageCreated { message },
-                            )
-                            .unwrap(),
-                        };
-                        state
-                            .ws_state
-                            .broadcast_to_server(
-                                &server_id,
-                                &serde_json::to_string(&ws_msg).unwrap(),
-                            )
+                let server_id = match permissions::require(&state.db, user_id, Target::Channel(&channel_id), Permissions::empty()) {
+                    Ok(granted) => granted.server_id,
+                    Err(e) => {
+                        send_error(reply, e.to_ws_error()).await;
+                        return;
+                    }
+                };
+                let thread = match msg.thread_id {
+                    Some(thread_id) => match state.db.get_thread(&thread_id.to_string()) {
+                        Ok(Some(thread)) if thread.channel_id == channel_id => Some(thread),
+                        _ => {
+                            send_error(reply, shared::ws_messages::WsError {
+                                message: "Thread not found".to_string(),
+                                code: Some("not_found".to_string()),
+                            })
                             .await;
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+
+                tracing::info!("Message sent via WebSocket: channel_id={}, user_id={}", channel_id, user_id);
+                let reply_to = msg.reply_to.map(|r| r.to_string());
+                if let Err(e) = crate::routes::messages::post_message(
+                    state,
+                    &server_id,
+                    &channel_id,
+                    thread.as_ref(),
+                    user_id,
+                    Some(&msg.content),
+                    reply_to.as_deref(),
+                    &msg.attachment_ids,
+                )
+                .await
+                {
+                    send_error(reply, e.to_ws_error()).await;
+                }
+            }
+        }
+        "ack" => {
+            if let Ok(msg) = serde_json::from_value::<shared::ws_messages::WsAck>(env.payload) {
+                let message_id = msg.message_id.to_string();
+                let result = match (msg.channel_id, msg.conversation_id) {
+                    (Some(channel_id), None) => {
+                        read_states::ack_channel_message(state, user_id, &channel_id.to_string(), &message_id, Some(reply)).await
                     }
+                    (None, Some(conversation_id)) => {
+                        read_states::ack_dm_message(state, user_id, &conversation_id.to_string(), &message_id, Some(reply)).await
+                    }
+                    _ => {
+                        send_error(reply, shared::ws_messages::WsError {
+                            message: "Exactly one of channel_id and conversation_id is required".to_string(),
+                            code: Some("invalid_ack".to_string()),
+                        })
+                        .await;
+                        return;
+                    }
+                };
+                if let Err(e) = result {
+                    send_error(reply, e.to_ws_error()).await;
                 }
             }
         }
         "typing" => {
             if let Ok(msg) = serde_json::from_value::<shared::ws_messages::WsTyping>(env.payload) {
                 let channel_id = msg.channel_id.to_string();
+                if permissions::require(&state.db, user_id, Target::Channel(&channel_id), Permissions::empty()).is_err() {
+                    return;
+                }
                 if let Some(server_id) =
                     state.db.get_channel_server_id(&channel_id).ok().flatten()
                 {
@@ -341,23 +542,37 @@ async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>)
             {
                 let channel_id = msg.channel_id.to_string();
                 tracing::info!("User joining voice channel: user_id={}, channel_id={}", user_id, channel_id);
+                if let Err(e) = permissions::require(&state.db, user_id, Target::Channel(&channel_id), Permissions::CONNECT_VOICE) {
+                    tracing::warn!("Voice join denied: user_id={}, channel_id={}", user_id, channel_id);
+                    send_error(reply, e.to_ws_error()).await;
+                    return;
+                }
                 
-                // Leave previous voice channel if any
-                if let Ok(Some(prev_channel)) = state.db.leave_voice_channel(user_id) {
+                // Leave this session's previous voice channel, if any
+                if let Ok(Some(prev_channel)) = state.db.leave_voice_session(user_id, &session_id.to_string()).map(|prev| prev.filter(|c| *c != channel_id)) {
                     tracing::debug!("User left previous voice channel: user_id={}, prev_channel_id={}", user_id, prev_channel);
                     broadcast_voice_state_update(state, &prev_channel).await;
                 }
-                if state
+                // A call held by another session moves here. Rejoining the
+                // same channel is announced only once.
+                if let Ok(moved_from) = state
                     .db
-                    .join_voice_channel(user_id, &channel_id)
-                    .is_ok()
+                    .join_voice_channel(user_id, &channel_id, &session_id.to_string())
                 {
+                    // Members without SPEAK join muted
+                    if permissions::require(&state.db, user_id, Target::Channel(&channel_id), Permissions::SPEAK).is_err() {
+                        let _ = state.db.update_voice_state(user_id, true, false);
+                    }
+                    if let Some(prev_channel) = moved_from.filter(|c| *c != channel_id) {
+                        broadcast_voice_state_update(state, &prev_channel).await;
+                    }
                     broadcast_voice_state_update(state, &channel_id).await;
                 }
             }
         }
         "leave_voice" => {
-            if let Ok(Some(channel_id)) = state.db.leave_voice_channel(user_id) {
+            // Only the session in the call can hang it up
+            if let Ok(Some(channel_id)) = state.db.leave_voice_session(user_id, &session_id.to_string()) {
                 tracing::info!("User left voice channel: user_id={}, channel_id={}", user_id, channel_id);
                 broadcast_voice_state_update(state, &channel_id).await;
             }
@@ -366,6 +581,34 @@ async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>)
             if let Ok(msg) =
                 serde_json::from_value::<shared::ws_messages::WsVoiceMuteDeafen>(env.payload)
             {
+                // Unmuting requires SPEAK in the current voice channel, and
+                // is refused while a moderator has muted or deafened them
+                if let Ok(Some(current)) = state.db.get_user_voice_state(user_id) {
+                    if !msg.muted {
+                        if let Err(e) = permissions::require(&state.db, user_id, Target::Channel(&current.channel_id), Permissions::SPEAK) {
+                            send_error(reply, e.to_ws_error()).await;
+                            return;
+                        }
+                    }
+                    let refused = if current.deafened && !msg.deafened && current.server_deafened {
+                        Some(("You have been deafened by a moderator", "server_deafened"))
+                    } else if current.muted && !msg.muted && (current.server_muted || current.server_deafened) {
+                        Some(("You have been muted by a moderator", "server_muted"))
+                    } else {
+                        None
+                    };
+                    if let Some((message, code)) = refused {
+                        send_error(
+                            reply,
+                            shared::ws_messages::WsError {
+                                message: message.to_string(),
+                                code: Some(code.to_string()),
+                            },
+                        )
+                        .await;
+                        return;
+                    }
+                }
                 if state
                     .db
                     .update_voice_state(user_id, msg.muted, msg.deafened)
@@ -381,65 +624,51 @@ async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>)
             if let Ok(msg) =
                 serde_json::from_value::<shared::ws_messages::WsSignalSdp>(env.payload)
             {
-                // Relay to target user — for now broadcast to the voice channel
-                // In production you'd have per-user channels
-                if let Ok(Some(channel_id)) = state.db.get_user_voice_channel(user_id) {
-                    if let Some(server_id) =
-                        state.db.get_channel_server_id(&channel_id).ok().flatten()
-                    {
-                        let relay = WsEnvelope {
-                            msg_type: "signal_sdp".to_string(),
-                            payload: serde_json::to_value(
-                                shared::ws_messages::WsSignalSdpRelay {
-                                    from_user_id: uuid::Uuid::parse_str(user_id).unwrap(),
-                                    target_user_id: msg.target_user_id,
-                                    sdp: msg.sdp,
-                                    sdp_type: msg.sdp_type,
-                                },
-                            )
-                            .unwrap(),
-                        };
-                        state
-                            .ws_state
-                            .broadcast_to_server(
-                                &server_id,
-                                &serde_json::to_string(&relay).unwrap(),
-                            )
-                            .await;
-                    }
+                let target_user_id = msg.target_user_id.to_string();
+                if !shares_voice_channel(state, user_id, &target_user_id) {
+                    tracing::debug!("Dropping SDP to user outside caller's voice channel: from={}, to={}", user_id, target_user_id);
+                    return;
                 }
+                let relay = WsEnvelope {
+                    msg_type: "signal_sdp".to_string(),
+                    payload: serde_json::to_value(shared::ws_messages::WsSignalSdpRelay {
+                        from_user_id: uuid::Uuid::parse_str(user_id).unwrap(),
+                        target_user_id: msg.target_user_id,
+                        sdp: msg.sdp,
+                        sdp_type: msg.sdp_type,
+                    })
+                    .unwrap(),
+                };
+                state
+                    .ws_state
+                    .broadcast_to_user(&target_user_id, &serde_json::to_string(&relay).unwrap())
+                    .await;
             }
         }
         "signal_ice" => {
             if let Ok(msg) =
                 serde_json::from_value::<shared::ws_messages::WsSignalIce>(env.payload)
             {
-                if let Ok(Some(channel_id)) = state.db.get_user_voice_channel(user_id) {
-                    if let Some(server_id) =
-                        state.db.get_channel_server_id(&channel_id).ok().flatten()
-                    {
-                        let relay = WsEnvelope {
-                            msg_type: "signal_ice".to_string(),
-                            payload: serde_json::to_value(
-                                shared::ws_messages::WsSignalIceRelay {
-                                    from_user_id: uuid::Uuid::parse_str(user_id).unwrap(),
-                                    target_user_id: msg.target_user_id,
-                                    candidate: msg.candidate,
-                                    sdp_mid: msg.sdp_mid,
-                                    sdp_mline_index: msg.sdp_mline_index,
-                                },
-                            )
-                            .unwrap(),
-                        };
-                        state
-                            .ws_state
-                            .broadcast_to_server(
-                                &server_id,
-                                &serde_json::to_string(&relay).unwrap(),
-                            )
-                            .await;
-                    }
+                let target_user_id = msg.target_user_id.to_string();
+                if !shares_voice_channel(state, user_id, &target_user_id) {
+                    tracing::debug!("Dropping ICE candidate to user outside caller's voice channel: from={}, to={}", user_id, target_user_id);
+                    return;
                 }
+                let relay = WsEnvelope {
+                    msg_type: "signal_ice".to_string(),
+                    payload: serde_json::to_value(shared::ws_messages::WsSignalIceRelay {
+                        from_user_id: uuid::Uuid::parse_str(user_id).unwrap(),
+                        target_user_id: msg.target_user_id,
+                        candidate: msg.candidate,
+                        sdp_mid: msg.sdp_mid,
+                        sdp_mline_index: msg.sdp_mline_index,
+                    })
+                    .unwrap(),
+                };
+                state
+                    .ws_state
+                    .broadcast_to_user(&target_user_id, &serde_json::to_string(&relay).unwrap())
+                    .await;
             }
         }
         "update_status" => {
@@ -461,9 +690,7 @@ async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>)
                     .set_user_status(user_id, &msg.status, msg.custom_text.as_deref())
                     .is_ok()
                 {
-                    // Broadcast to all shared servers
-                    let servers = state.db.get_servers_for_user(user_id).unwrap_or_default();
-                    broadcast_user_status_update(state, user_id, &servers).await;
+                    broadcast_user_status_update(state, user_id).await;
                 }
             }
         }
@@ -473,7 +700,7 @@ async fn handle_client_message(text: &str, user_id: &str, state: &Arc<AppState>)
     }
 }
 
-async fn broadcast_voice_state_update(state: &Arc<AppState>, channel_id: &str) {
+pub async fn broadcast_voice_state_update(state: &Arc<AppState>, channel_id: &str) {
     if let Ok(states) = state.db.get_voice_states_for_channel(channel_id) {
         let voice_states: Vec<shared::models::VoiceState> = states
             .into_iter()
@@ -482,6 +709,8 @@ async fn broadcast_voice_state_update(state: &Arc<AppState>, channel_id: &str) {
                 channel_id: uuid::Uuid::parse_str(&vs.channel_id).unwrap(),
                 muted: vs.muted,
                 deafened: vs.deafened,
+                server_muted: vs.server_muted,
+                server_deafened: vs.server_deafened,
                 joined_at: vs.joined_at,
                 username: Some(vs.username),
                 avatar_url: vs.avatar_url,
@@ -505,11 +734,18 @@ async fn broadcast_voice_state_update(state: &Arc<AppState>, channel_id: &str) {
     }
 }
 
-async fn broadcast_user_status_update(
-    state: &Arc<AppState>,
-    user_id: &str,
-    servers: &[crate::db::ServerRow],
-) {
+/// Signaling is only relayed between users in the same voice channel.
+fn shares_voice_channel(state: &Arc<AppState>, user_id: &str, other_user_id: &str) -> bool {
+    match (
+        state.db.get_user_voice_channel(user_id),
+        state.db.get_user_voice_channel(other_user_id),
+    ) {
+        (Ok(Some(a)), Ok(Some(b))) => a == b,
+        _ => false,
+    }
+}
+
+async fn broadcast_user_status_update(state: &Arc<AppState>, user_id: &str) {
     // Get the user's current status
     if let Ok(Some(status_row)) = state.db.get_user_status(user_id) {
         let status = shared::models::UserStatus {
@@ -531,11 +767,12 @@ async fn broadcast_user_status_update(
             .unwrap(),
         };
 
-        let msg_str = serde_json::to_string(&ws_msg).unwrap();
-
-        // Broadcast to all servers the user is a member of
-        for server in servers {
-            state.ws_state.broadcast_to_server(&server.id, &msg_str).await;
-        }
+        // One copy per user who shares at least one server, including the
+        // user's own other sessions
+        let recipients = state.db.get_users_sharing_server(user_id).unwrap_or_default();
+        state
+            .ws_state
+            .broadcast_to_users(recipients.iter().map(String::as_str), &serde_json::to_string(&ws_msg).unwrap())
+            .await;
     }
 }
@@ -0,0 +1,247 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser, WsClient};
+use serde_json::{json, Value};
+
+struct Fixture {
+    app: TestApp,
+    owner: TestUser,
+    member: TestUser,
+    server_id: String,
+}
+
+async fn fixture() -> Fixture {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+    Fixture {
+        app,
+        owner,
+        member,
+        server_id,
+    }
+}
+
+impl Fixture {
+    async fn create_category(&self, name: &str) -> String {
+        let (status, body) = self
+            .app
+            .request(
+                Method::POST,
+                &format!("/api/servers/{}/categories", self.server_id),
+                Some(&self.owner.token),
+                Some(json!({"name": name})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    async fn create_channel_in(&self, name: &str, category_id: &str) -> Value {
+        let (status, body) = self
+            .app
+            .request(
+                Method::POST,
+                &format!("/api/servers/{}/channels", self.server_id),
+                Some(&self.owner.token),
+                Some(json!({"name": name, "type": "text", "category_id": category_id})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        body
+    }
+
+    async fn update(&self, user: &TestUser, channel_id: &str, body: Value) -> (StatusCode, Value) {
+        self.app
+            .request(Method::PATCH, &format!("/api/channels/{channel_id}"), Some(&user.token), Some(body))
+            .await
+    }
+
+    async fn reorder(&self, user: &TestUser, body: Value) -> (StatusCode, Value) {
+        self.app
+            .request(Method::PATCH, &format!("/api/servers/{}/channels", self.server_id), Some(&user.token), Some(body))
+            .await
+    }
+
+    /// `(name, category_id, position)` of every channel, in list order.
+    async fn layout(&self) -> Vec<(String, Value, i64)> {
+        let (_, channels) = self
+            .app
+            .request(Method::GET, &format!("/api/servers/{}/channels", self.server_id), Some(&self.member.token), None)
+            .await;
+        channels
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| (c["name"].as_str().unwrap().to_string(), c["category_id"].clone(), c["position"].as_i64().unwrap()))
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn channels_are_edited_by_managers_and_changes_are_broadcast() {
+    let f = fixture().await;
+    let mut ws = WsClient::connect(f.app.spawn().await, &f.member.token).await;
+
+    f.app.create_channel(&f.owner, &f.server_id, "lobby").await;
+    let channel_id = f.app.create_channel(&f.owner, &f.server_id, "random").await;
+    let lobby = ws.expect("channel_created").await;
+    assert_eq!(lobby["channel"]["name"], "lobby");
+    let created = ws.expect("channel_created").await;
+    assert_eq!(created["channel"]["id"], channel_id.as_str());
+    assert_eq!(created["channel"]["position"], lobby["channel"]["position"].as_i64().unwrap() + 1);
+    assert!(!created["channel"]["created_at"].as_str().unwrap().is_empty());
+
+    let (status, body) = f.update(&f.member, &channel_id, json!({"name": "mine"})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["missing"], json!(["manage_channels"]));
+
+    let (status, channel) = f
+        .update(
+            &f.owner,
+            &channel_id,
+            json!({"name": "  off-topic ", "topic": "Anything goes", "slowmode_secs": 30, "nsfw": true}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(channel["name"], "off-topic");
+    assert_eq!(channel["topic"], "Anything goes");
+    assert_eq!(channel["slowmode_secs"], 30);
+    assert_eq!(channel["nsfw"], true);
+    let updated = ws.expect("channel_updated").await;
+    assert_eq!(updated["channel"], channel);
+
+    // Unset fields stay, an empty topic clears it
+    let (_, channel) = f.update(&f.owner, &channel_id, json!({"topic": ""})).await;
+    assert!(channel["topic"].is_null());
+    assert_eq!(channel["name"], "off-topic");
+    assert_eq!(channel["slowmode_secs"], 30);
+    assert!(ws.expect("channel_updated").await["channel"]["topic"].is_null());
+
+    for (body, code) in [
+        (json!({"name": "   "}), "invalid_name"),
+        (json!({"name": "x".repeat(101)}), "invalid_name"),
+        (json!({"topic": "x".repeat(1025)}), "invalid_topic"),
+        (json!({"slowmode_secs": -1}), "invalid_slowmode"),
+        (json!({"slowmode_secs": 21601}), "invalid_slowmode"),
+    ] {
+        let (status, error) = f.update(&f.owner, &channel_id, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["code"], code);
+    }
+    ws.expect_none("channel_updated").await;
+
+    let (status, _) = f
+        .app
+        .request(Method::DELETE, &format!("/api/channels/{channel_id}"), Some(&f.owner.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let deleted = ws.expect("channel_deleted").await;
+    assert_eq!(deleted["channel_id"], channel_id.as_str());
+    assert_eq!(deleted["server_id"], f.server_id.as_str());
+}
+
+#[tokio::test]
+async fn channels_move_within_and_between_categories_atomically() {
+    let f = fixture().await;
+    let text = f.create_category("Text").await;
+    let games = f.create_category("Games").await;
+    let general = f.app.create_channel(&f.owner, &f.server_id, "general").await;
+    let chat = f.create_channel_in("chat", &text).await;
+    let memes = f.create_channel_in("memes", &text).await;
+    assert_eq!(chat["position"], 0);
+    assert_eq!(memes["position"], 1);
+    let memes = memes["id"].as_str().unwrap().to_string();
+    let chat = chat["id"].as_str().unwrap().to_string();
+
+    let (status, body) = f
+        .reorder(&f.member, json!({"channels": [{"id": memes, "category_id": null, "position": 0}]}))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+
+    let mut ws = WsClient::connect(f.app.spawn().await, &f.member.token).await;
+    let (status, _) = f
+        .reorder(
+            &f.owner,
+            json!({
+                "channels": [
+                    {"id": memes, "category_id": games, "position": 0},
+                    {"id": chat, "category_id": text, "position": 0},
+                    {"id": general, "category_id": null, "position": 0},
+                ],
+                "categories": [{"id": games, "position": 0}, {"id": text, "position": 1}],
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let moved = ws.expect("channel_updated").await;
+    assert_eq!(moved["channel"]["id"], memes.as_str());
+    assert_eq!(moved["channel"]["category_id"], games.as_str());
+    let (_, categories) = f
+        .app
+        .request(Method::GET, &format!("/api/servers/{}/categories", f.server_id), Some(&f.member.token), None)
+        .await;
+    let names: Vec<&str> = categories.as_array().unwrap().iter().map(|c| c["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["Games", "Text"]);
+    let layout = f.layout().await;
+    assert!(layout.contains(&("memes".to_string(), json!(games), 0)));
+    assert!(layout.contains(&("chat".to_string(), json!(text), 0)));
+
+    // A category or channel from another server rolls the whole move back
+    let other_server = f.app.create_server(&f.owner, "elsewhere").await;
+    let elsewhere = f.app.create_channel(&f.owner, &other_server, "elsewhere").await;
+    let before = f.layout().await;
+    for channels in [
+        json!([{"id": chat, "category_id": games, "position": 1}, {"id": elsewhere, "category_id": null, "position": 0}]),
+        json!([{"id": chat, "category_id": games, "position": 1}, {"id": memes, "category_id": other_server, "position": 0}]),
+    ] {
+        let (status, body) = f.reorder(&f.owner, json!({"channels": channels})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_layout");
+    }
+    assert_eq!(f.layout().await, before);
+
+    // Deleting a category keeps its channels
+    let (status, _) = f
+        .app
+        .request(Method::DELETE, &format!("/api/categories/{games}"), Some(&f.owner.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(ws.expect("category_deleted").await["category_id"], games.as_str());
+    assert!(f.layout().await.contains(&("memes".to_string(), Value::Null, 0)));
+
+    let (status, category) = f
+        .app
+        .request(Method::PATCH, &format!("/api/categories/{text}"), Some(&f.owner.token), Some(json!({"name": "Talk"})))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(category["name"], "Talk");
+    assert_eq!(ws.expect("category_updated").await["category"]["name"], "Talk");
+}
+
+#[tokio::test]
+async fn slowmode_spaces_out_messages_from_members() {
+    let f = fixture().await;
+    let channel_id = f.app.create_channel(&f.owner, &f.server_id, "general").await;
+    f.update(&f.owner, &channel_id, json!({"slowmode_secs": 60})).await;
+    let path = format!("/api/channels/{channel_id}/messages");
+
+    let (status, _) = f.app.request(Method::POST, &path, Some(&f.member.token), Some(json!({"content": "one"}))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = f.app.request(Method::POST, &path, Some(&f.member.token), Some(json!({"content": "two"}))).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["code"], "slowmode");
+
+    // Managers are not slowed down
+    for content in ["three", "four"] {
+        let (status, _) = f.app.request(Method::POST, &path, Some(&f.owner.token), Some(json!({"content": content}))).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    f.update(&f.owner, &channel_id, json!({"slowmode_secs": 0})).await;
+    let (status, _) = f.app.request(Method::POST, &path, Some(&f.member.token), Some(json!({"content": "five"}))).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
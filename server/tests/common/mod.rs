@@ -0,0 +1,323 @@
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use server::{
+    db::Database,
+    embeds::{LinkPreviewConfig, LinkPreviews},
+    routes::{messages::MessageDeletion, sfu::SfuConfig, turn::TurnConfig, uploads::UploadLimits},
+    storage::{LocalStorage, Storage},
+    ws::{Heartbeat, WsState},
+    AppState,
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tower::ServiceExt;
+
+/// A fresh directory for a test's uploads.
+pub fn temp_upload_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("subspace-test-{}", uuid::Uuid::new_v4()))
+}
+
+/// An in-process server backed by an in-memory database.
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    pub router: Router,
+}
+
+pub struct TestUser {
+    pub id: String,
+    pub token: String,
+}
+
+/// Settings for a [`TestApp`]; tests set what they need and take the
+/// defaults for the rest.
+#[derive(Default)]
+pub struct TestConfig {
+    pub upload_limits: UploadLimits,
+    /// Local storage in a fresh directory if unset
+    pub storage: Option<Arc<dyn Storage>>,
+    pub link_previews: LinkPreviewConfig,
+    pub message_deletion: MessageDeletion,
+    pub turn: TurnConfig,
+    pub sfu: Option<SfuConfig>,
+    pub heartbeat: Heartbeat,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::with_config(TestConfig::default())
+    }
+
+    pub fn with_config(config: TestConfig) -> Self {
+        let db = Database::new(":memory:").unwrap();
+        db.run_migrations().unwrap();
+        let storage = config
+            .storage
+            .unwrap_or_else(|| Arc::new(LocalStorage::new(temp_upload_dir()).unwrap()));
+        let state = Arc::new(AppState {
+            db,
+            jwt_secret: "test-secret".into(),
+            ws_state: WsState::with_heartbeat(config.heartbeat),
+            storage,
+            upload_limits: config.upload_limits,
+            link_previews: LinkPreviews::new(config.link_previews),
+            message_deletion: config.message_deletion,
+            turn: config.turn,
+            sfu: config.sfu,
+        });
+        let router = server::app(state.clone());
+        Self { state, router }
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut builder = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {token}"));
+        }
+        let req = match body {
+            Some(body) => builder
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        };
+
+        let res = self.router.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let json = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, json)
+    }
+
+    pub async fn register(&self, username: &str) -> TestUser {
+        let (status, body) = self
+            .request(
+                Method::POST,
+                "/api/register",
+                None,
+                Some(serde_json::json!({"username": username, "password": "password123"})),
+            )
+            .await;
+        assert!(status.is_success(), "register failed: {status} {body}");
+        TestUser {
+            id: body["user"]["id"].as_str().unwrap().to_string(),
+            token: body["token"].as_str().unwrap().to_string(),
+        }
+    }
+
+    pub async fn create_server(&self, owner: &TestUser, name: &str) -> String {
+        let (status, body) = self
+            .request(
+                Method::POST,
+                "/api/servers",
+                Some(&owner.token),
+                Some(serde_json::json!({"name": name})),
+            )
+            .await;
+        assert!(status.is_success(), "create server failed: {status} {body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    pub async fn create_channel(&self, owner: &TestUser, server_id: &str, name: &str) -> String {
+        self.create_channel_of_type(owner, server_id, name, "text").await
+    }
+
+    pub async fn create_channel_of_type(
+        &self,
+        owner: &TestUser,
+        server_id: &str,
+        name: &str,
+        channel_type: &str,
+    ) -> String {
+        let (status, body) = self
+            .request(
+                Method::POST,
+                &format!("/api/servers/{server_id}/channels"),
+                Some(&owner.token),
+                Some(serde_json::json!({"name": name, "type": channel_type})),
+            )
+            .await;
+        assert!(status.is_success(), "create channel failed: {status} {body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Joins through a single-use invite from the server's owner.
+    pub async fn join_server(&self, user: &TestUser, server_id: &str) {
+        let owner_id = self.state.db.get_server_by_id(server_id).unwrap().unwrap().owner_id;
+        let code = uuid::Uuid::new_v4().simple().to_string();
+        self.state
+            .db
+            .create_invite(&code, server_id, None, &owner_id, Some(1), None)
+            .unwrap();
+        let (status, body) = self
+            .request(Method::POST, &format!("/api/invites/{code}/accept"), Some(&user.token), None)
+            .await;
+        assert!(status.is_success(), "join failed: {status} {body}");
+    }
+
+    pub async fn post_message(&self, author: &TestUser, channel_id: &str, content: &str) -> String {
+        let (status, body) = self
+            .request(
+                Method::POST,
+                &format!("/api/channels/{channel_id}/messages"),
+                Some(&author.token),
+                Some(serde_json::json!({"content": content})),
+            )
+            .await;
+        assert!(status.is_success(), "post message failed: {status} {body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Sends a GET with extra headers and returns the raw response parts,
+    /// for routes that do not answer with JSON.
+    pub async fn get_raw(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, axum::http::HeaderMap, Vec<u8>) {
+        let mut builder = Request::builder().method(Method::GET).uri(path);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let res = self.router.clone().oneshot(builder.body(Body::empty()).unwrap()).await.unwrap();
+        let (parts, body) = res.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, bytes.to_vec())
+    }
+
+    /// Uploads `data` through `POST /api/upload` and returns the response.
+    pub async fn upload(&self, user: &TestUser, file_name: &str, mime_type: &str, data: &[u8]) -> Value {
+        let (status, json) = self.try_upload(user, file_name, mime_type, data).await;
+        assert!(status.is_success(), "upload failed: {status} {json}");
+        json
+    }
+
+    /// Like `upload`, but returns failures instead of asserting.
+    pub async fn try_upload(&self, user: &TestUser, file_name: &str, mime_type: &str, data: &[u8]) -> (StatusCode, Value) {
+        self.try_upload_with_fields(user, &[], file_name, mime_type, data).await
+    }
+
+    /// Like [`TestApp::try_upload`], with plain form fields sent before the file.
+    pub async fn try_upload_with_fields(
+        &self,
+        user: &TestUser,
+        fields: &[(&str, &str)],
+        file_name: &str,
+        mime_type: &str,
+        data: &[u8],
+    ) -> (StatusCode, Value) {
+        let boundary = "subspace-test-boundary";
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend_from_slice(
+                format!("--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n").as_bytes(),
+            );
+        }
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\nContent-Type: {mime_type}\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/upload")
+            .header("authorization", format!("Bearer {}", user.token))
+            .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+            .body(Body::from(body))
+            .unwrap();
+        let res = self.router.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, json)
+    }
+
+    /// Serves the router on an ephemeral port, for WebSocket tests.
+    pub async fn spawn(&self) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = self.router.clone();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        addr
+    }
+}
+
+/// An authenticated WebSocket client.
+pub struct WsClient {
+    stream: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+}
+
+impl WsClient {
+    /// Connects and waits for `auth_success`.
+    pub async fn connect(addr: std::net::SocketAddr, token: &str) -> Self {
+        let (stream, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws"))
+            .await
+            .unwrap();
+        let mut client = Self { stream };
+        client
+            .send("auth", serde_json::json!({"token": token}))
+            .await;
+        client.expect("auth_success").await;
+        client
+    }
+
+    pub async fn send(&mut self, msg_type: &str, payload: Value) {
+        let env = serde_json::json!({"type": msg_type, "payload": payload});
+        self.stream
+            .send(Message::Text(env.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Next envelope, or `None` if nothing arrives within `timeout`.
+    pub async fn recv_within(&mut self, timeout: std::time::Duration) -> Option<Value> {
+        loop {
+            let msg = tokio::time::timeout(timeout, self.stream.next())
+                .await
+                .ok()??
+                .ok()?;
+            if let Message::Text(text) = msg {
+                return Some(serde_json::from_str(&text).unwrap());
+            }
+        }
+    }
+
+    /// Skips other events until one of `msg_type` arrives; returns its payload.
+    pub async fn expect(&mut self, msg_type: &str) -> Value {
+        loop {
+            let env = self
+                .recv_within(std::time::Duration::from_secs(5))
+                .await
+                .unwrap_or_else(|| panic!("timed out waiting for {msg_type}"));
+            if env["type"] == msg_type {
+                return env["payload"].clone();
+            }
+        }
+    }
+
+    /// Asserts that no event of `msg_type` arrives within a short window.
+    pub async fn expect_none(&mut self, msg_type: &str) {
+        while let Some(env) = self.recv_within(std::time::Duration::from_millis(300)).await {
+            assert_ne!(env["type"], msg_type, "unexpected {msg_type}: {env}");
+        }
+    }
+}
@@ -0,0 +1,275 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestConfig, TestUser, WsClient};
+use serde_json::{json, Value};
+use server::routes::{messages::MessageDeletion, uploads::storage_key};
+
+struct Fixture {
+    app: TestApp,
+    author: TestUser,
+    member: TestUser,
+    moderator: TestUser,
+    channel_id: String,
+}
+
+/// A channel with an author, a plain member and a moderator holding only
+/// `manage_messages`.
+async fn fixture(deletion: MessageDeletion) -> Fixture {
+    let app = TestApp::with_config(TestConfig {
+        message_deletion: deletion,
+        ..Default::default()
+    });
+    let owner = app.register("owner").await;
+    let author = app.register("author").await;
+    let member = app.register("member").await;
+    let moderator = app.register("moderator").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let channel_id = app.create_channel(&owner, &server_id, "general").await;
+    for user in [&author, &member, &moderator] {
+        app.join_server(user, &server_id).await;
+    }
+    let (_, role) = app
+        .request(
+            Method::POST,
+            &format!("/api/servers/{server_id}/roles"),
+            Some(&owner.token),
+            Some(json!({"name": "Moderators", "permissions": 1 << 1})),
+        )
+        .await;
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            &format!("/api/servers/{server_id}/members/{}/roles/{}", moderator.id, role["id"].as_str().unwrap()),
+            Some(&owner.token),
+            None,
+        )
+        .await;
+    assert!(status.is_success());
+    Fixture {
+        app,
+        author,
+        member,
+        moderator,
+        channel_id,
+    }
+}
+
+impl Fixture {
+    async fn post_with_file(&self, content: &str) -> (String, String) {
+        let upload = self.app.upload(&self.author, "notes.txt", "text/plain", b"secret notes").await;
+        let (status, message) = self
+            .app
+            .request(
+                Method::POST,
+                &format!("/api/channels/{}/messages", self.channel_id),
+                Some(&self.author.token),
+                Some(json!({"content": content, "attachment_ids": [upload["id"]]})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let url = upload["url"].as_str().unwrap().to_string();
+        (message["id"].as_str().unwrap().to_string(), url)
+    }
+
+    async fn history(&self, user: &TestUser) -> Vec<Value> {
+        let (status, body) = self
+            .app
+            .request(Method::GET, &format!("/api/channels/{}/messages", self.channel_id), Some(&user.token), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        body.as_array().unwrap().clone()
+    }
+
+    async fn delete(&self, user: &TestUser, message_id: &str) -> StatusCode {
+        let path = format!("/api/messages/{message_id}");
+        self.app.request(Method::DELETE, &path, Some(&user.token), None).await.0
+    }
+
+    async fn restore(&self, user: &TestUser, message_id: &str) -> (StatusCode, Value) {
+        let path = format!("/api/messages/{message_id}/restore");
+        self.app.request(Method::POST, &path, Some(&user.token), None).await
+    }
+}
+
+#[tokio::test]
+async fn deleted_messages_become_tombstones_that_moderators_can_read() {
+    let f = fixture(MessageDeletion::default()).await;
+    let (message_id, _) = f.post_with_file("remember the milk").await;
+    let path = format!("/api/messages/{message_id}");
+    f.app
+        .request(Method::POST, &format!("{path}/reactions"), Some(&f.member.token), Some(json!({"emoji": "👍"})))
+        .await;
+    f.app.request(Method::POST, &format!("{path}/pin"), Some(&f.moderator.token), None).await;
+    let reply = f.app.post_message(&f.member, &f.channel_id, "will do").await;
+    f.app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{}/messages", f.channel_id),
+            Some(&f.member.token),
+            Some(json!({"content": "and eggs", "reply_to": message_id})),
+        )
+        .await;
+    let mut ws = WsClient::connect(f.app.spawn().await, &f.member.token).await;
+
+    assert_eq!(f.delete(&f.author, &message_id).await, StatusCode::NO_CONTENT);
+    assert_eq!(ws.expect("message_deleted").await["message_id"], message_id.as_str());
+
+    // Members see that something was deleted, and nothing more
+    let history = f.history(&f.member).await;
+    assert_eq!(history.len(), 3);
+    let tombstone = &history[0];
+    assert_eq!(tombstone["id"], message_id.as_str());
+    assert!(tombstone["deleted_at"].is_string());
+    assert!(tombstone["deleted_by"].is_null());
+    assert!(tombstone["content"].is_null());
+    assert_eq!(tombstone["attachments"], json!([]));
+    assert_eq!(tombstone["reactions"], json!([]));
+    assert_eq!(tombstone["pinned"], false);
+    assert!(history[2]["reply_to"]["content"].is_null());
+    assert!(history[2]["reply_to"]["author"].is_null());
+    assert_eq!(f.history(&f.author).await[0]["deleted_by"], f.author.id.as_str());
+
+    // Moderators still get all of it
+    let tombstone = &f.history(&f.moderator).await[0];
+    assert_eq!(tombstone["content"], "remember the milk");
+    assert_eq!(tombstone["deleted_by"], f.author.id.as_str());
+    assert_eq!(tombstone["attachments"][0]["file_name"], "notes.txt");
+    assert_eq!(tombstone["reactions"][0]["count"], 1);
+
+    // It is gone from everywhere else, and can't be acted on
+    let (_, pins) = f
+        .app
+        .request(Method::GET, &format!("/api/channels/{}/pins", f.channel_id), Some(&f.moderator.token), None)
+        .await;
+    assert_eq!(pins, json!([]));
+    let (_, results) = f.app.request(Method::GET, "/api/search?q=milk", Some(&f.moderator.token), None).await;
+    assert_eq!(results["messages"], json!([]));
+    let (status, _) = f
+        .app
+        .request(Method::PATCH, &path, Some(&f.author.token), Some(json!({"content": "changed"})))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = f
+        .app
+        .request(Method::POST, &format!("{path}/reactions"), Some(&f.member.token), Some(json!({"emoji": "🎉"})))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = f
+        .app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{}/messages", f.channel_id),
+            Some(&f.member.token),
+            Some(json!({"content": "late reply", "reply_to": message_id})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_reply");
+    assert_eq!(f.delete(&f.moderator, &message_id).await, StatusCode::NOT_FOUND);
+    // Even past the permission checks, as a message can be deleted after them
+    assert!(!f.app.state.db.pin_message(&message_id, true).unwrap());
+    assert!(!f.app.state.db.add_reaction(&message_id, &f.member.id, "🎉").unwrap());
+    assert_eq!(f.history(&f.moderator).await[0]["reactions"].as_array().unwrap().len(), 1);
+
+    // Tombstones still page like any other message
+    let (status, page) = f
+        .app
+        .request(
+            Method::GET,
+            &format!("/api/channels/{}/messages?after={message_id}", f.channel_id),
+            Some(&f.member.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page[0]["id"], reply.as_str());
+}
+
+#[tokio::test]
+async fn authors_can_undo_their_own_deletes_for_a_while() {
+    let f = fixture(MessageDeletion::default()).await;
+    let (message_id, _) = f.post_with_file("oops").await;
+    let mut ws = WsClient::connect(f.app.spawn().await, &f.member.token).await;
+
+    let (status, body) = f.restore(&f.author, &message_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "not deleted yet: {body}");
+
+    f.delete(&f.author, &message_id).await;
+    let (status, body) = f.restore(&f.member, &message_id).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "not_restorable");
+    let (status, _) = f.restore(&f.moderator, &message_id).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, restored) = f.restore(&f.author, &message_id).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(restored["content"], "oops");
+    assert!(restored["deleted_at"].is_null());
+    assert_eq!(restored["attachments"][0]["file_name"], "notes.txt");
+    let event = ws.expect("message_restored").await;
+    assert_eq!(event["message"]["id"], message_id.as_str());
+    assert_eq!(event["message"]["content"], "oops");
+    assert_eq!(f.history(&f.member).await[0]["content"], "oops");
+
+    // What a moderator removed stays removed
+    f.delete(&f.moderator, &message_id).await;
+    let (status, body) = f.restore(&f.author, &message_id).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "not_restorable");
+    ws.expect_none("message_restored").await;
+}
+
+#[tokio::test]
+async fn the_undelete_window_closes() {
+    let f = fixture(MessageDeletion {
+        undelete_window: Duration::ZERO,
+        ..MessageDeletion::default()
+    })
+    .await;
+    let message_id = f.app.post_message(&f.author, &f.channel_id, "gone for good").await;
+    f.delete(&f.author, &message_id).await;
+
+    let (status, body) = f.restore(&f.author, &message_id).await;
+    assert_eq!(status, StatusCode::GONE);
+    assert_eq!(body["code"], "undelete_window_expired");
+    assert!(f.history(&f.member).await[0]["deleted_at"].is_string());
+}
+
+#[tokio::test]
+async fn tombstones_and_their_files_are_purged_after_the_retention() {
+    let f = fixture(MessageDeletion {
+        retention: Duration::ZERO,
+        ..MessageDeletion::default()
+    })
+    .await;
+    let (deleted, deleted_url) = f.post_with_file("delete me").await;
+    let (kept, kept_url) = f.post_with_file("keep me").await;
+    // A deleted thread root stays while its thread has replies
+    let root = f.app.post_message(&f.author, &f.channel_id, "thread root").await;
+    let (_, thread) = f
+        .app
+        .request(Method::POST, &format!("/api/messages/{root}/thread"), Some(&f.author.token), None)
+        .await;
+    f.app
+        .request(
+            Method::POST,
+            &format!("/api/threads/{}/messages", thread["id"].as_str().unwrap()),
+            Some(&f.member.token),
+            Some(json!({"content": "a reply"})),
+        )
+        .await;
+    f.delete(&f.author, &deleted).await;
+    f.delete(&f.author, &root).await;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    server::routes::messages::purge_deleted_messages(&f.app.state).await;
+
+    let ids: Vec<Value> = f.history(&f.moderator).await.iter().map(|m| m["id"].clone()).collect();
+    assert_eq!(ids, [json!(kept), json!(root)]);
+    let storage = &f.app.state.storage;
+    assert!(storage.get(storage_key(&deleted_url).unwrap()).await.is_err());
+    assert_eq!(storage.get(storage_key(&kept_url).unwrap()).await.unwrap(), b"secret notes");
+}
@@ -0,0 +1,279 @@
+mod common;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use common::{TestApp, TestConfig, WsClient};
+use reqwest::Url;
+use serde_json::json;
+use server::embeds::{self, LinkPreviewConfig};
+
+const ARTICLE: &str = r#"<!doctype html>
+<html><head>
+  <title>Fallback title</title>
+  <meta property="og:title" content="Tom &amp; Jerry&#39;s   guide">
+  <meta property="og:site_name" content='Cartoons'>
+  <meta name="description" content="Plain description">
+  <meta property="og:description" content="All about &quot;chasing&quot;">
+  <meta property="og:image" content="/images/cover.png" />
+  <meta name="twitter:card" content="summary_large_image">
+</head><body><meta property="og:title" content="Not in the head"></body></html>"#;
+
+/// Pages for the server to preview, counting the requests it gets.
+async fn page_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let html = |body: &'static str| ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body);
+    let router = Router::new()
+        .route("/article", get(move || async move { html(ARTICLE) }))
+        .route("/moved", get(|| async { Redirect::to("/article") }))
+        .route("/plain", get(|| async { "<meta property=\"og:title\" content=\"text\">" }))
+        .route("/bare", get(move || async move { html("<html><head><title> Just a\n title </title></head></html>") }))
+        .route(
+            "/slow",
+            get(move || async move {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                html(ARTICLE)
+            }),
+        )
+        .route(
+            "/padded",
+            get(|| async {
+                let page = format!("<html><head><!--{}-->{ARTICLE}", "x".repeat(4096));
+                ([(header::CONTENT_TYPE, "text/html")], page)
+            }),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            hits.clone(),
+            |State(hits): State<Arc<AtomicUsize>>, req, next: axum::middleware::Next| async move {
+                hits.fetch_add(1, Ordering::SeqCst);
+                Ok::<Response, StatusCode>(next.run(req).await.into_response())
+            },
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    (addr, hits)
+}
+
+fn private_networks() -> LinkPreviewConfig {
+    LinkPreviewConfig {
+        allow_private_networks: true,
+        ..LinkPreviewConfig::default()
+    }
+}
+
+#[test]
+fn links_are_found_outside_code_and_angle_brackets() {
+    let urls = embeds::extract_urls(
+        "see https://example.com/a, (https://en.wikipedia.org/wiki/Rust_(language)) and \
+         [docs](https://docs.rs/). Not `https://code.example` or <https://quiet.example>. \
+         Again: https://example.com/a!\n```\nhttps://block.example\n```\nftp://nope.example http://",
+    );
+    assert_eq!(
+        urls,
+        [
+            "https://example.com/a",
+            "https://en.wikipedia.org/wiki/Rust_(language)",
+            "https://docs.rs/",
+        ]
+    );
+
+    let many: Vec<String> = (0..8).map(|i| format!("https://example.com/{i}")).collect();
+    assert_eq!(embeds::extract_urls(&many.join(" ")).len(), embeds::MAX_EMBEDS);
+}
+
+#[test]
+fn only_public_addresses_are_allowed() {
+    for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946", "64:ff9b::5db8:d822"] {
+        assert!(embeds::is_public_address(ip.parse().unwrap()), "{ip}");
+    }
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "255.255.255.255",
+        "224.0.0.1",
+        "::1",
+        "::",
+        "::ffff:127.0.0.1",
+        "::ffff:10.0.0.1",
+        "64:ff9b::7f00:1",
+        "2002:a9fe:a9fe::",
+        "fd00::1",
+        "fe80::1",
+        "2001:db8::1",
+    ] {
+        let ip: IpAddr = ip.parse().unwrap();
+        assert!(!embeds::is_public_address(ip), "{ip}");
+    }
+}
+
+#[test]
+fn pages_are_read_from_opengraph_then_twitter_then_html() {
+    let page_url = Url::parse("https://cartoons.example/posts/1").unwrap();
+    let embed = embeds::parse_page("https://short.example/x", &page_url, ARTICLE).unwrap();
+    assert_eq!(embed.url, "https://short.example/x");
+    assert_eq!(embed.title.as_deref(), Some("Tom & Jerry's guide"));
+    assert_eq!(embed.description.as_deref(), Some("All about \"chasing\""));
+    assert_eq!(embed.site_name.as_deref(), Some("Cartoons"));
+    assert_eq!(embed.image_url.as_deref(), Some("https://cartoons.example/images/cover.png"));
+    assert!(embed.large_image);
+
+    let twitter = r#"<head><meta name="twitter:title" content="Tweet"><meta name="twitter:image" content="javascript:alert(1)"></head>"#;
+    let embed = embeds::parse_page("https://t.example", &page_url, twitter).unwrap();
+    assert_eq!(embed.title.as_deref(), Some("Tweet"));
+    assert_eq!(embed.image_url, None);
+    assert!(!embed.large_image);
+
+    let long = format!("<title>{}</title>", "a".repeat(1000));
+    let embed = embeds::parse_page("https://l.example", &page_url, &long).unwrap();
+    assert_eq!(embed.title.unwrap().chars().count(), 256);
+
+    assert!(embeds::parse_page("https://e.example", &page_url, "<html><body>hi</body></html>").is_none());
+}
+
+#[tokio::test]
+async fn fetches_are_refused_for_private_addresses_and_capped() {
+    let (addr, hits) = page_server().await;
+    let url = |path: &str| format!("http://{addr}{path}");
+
+    assert!(embeds::fetch_preview(&LinkPreviewConfig::default(), &url("/article")).await.is_none());
+    assert!(embeds::fetch_preview(&LinkPreviewConfig::default(), "http://localhost/").await.is_none());
+    assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+    let config = private_networks();
+    let embed = embeds::fetch_preview(&config, &url("/moved")).await.unwrap();
+    assert_eq!(embed.url, url("/moved"));
+    assert_eq!(embed.image_url.unwrap(), url("/images/cover.png"));
+    let bare = embeds::fetch_preview(&config, &url("/bare")).await.unwrap();
+    assert_eq!(bare.title.as_deref(), Some("Just a title"));
+    assert_eq!(bare.description, None);
+    // Not HTML
+    assert!(embeds::fetch_preview(&config, &url("/plain")).await.is_none());
+    assert!(embeds::fetch_preview(&config, &url("/missing")).await.is_none());
+
+    let capped = LinkPreviewConfig {
+        timeout: Duration::from_millis(200),
+        max_bytes: 1024,
+        ..private_networks()
+    };
+    let started = std::time::Instant::now();
+    assert!(embeds::fetch_preview(&capped, &url("/slow")).await.is_none());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(embeds::fetch_preview(&capped, &url("/padded")).await.is_none());
+    assert!(embeds::fetch_preview(&config, &url("/padded")).await.is_some());
+}
+
+#[tokio::test]
+async fn links_in_messages_get_embeds_through_message_updated() {
+    let (pages, hits) = page_server().await;
+    let app = TestApp::with_config(TestConfig {
+        link_previews: private_networks(),
+        ..Default::default()
+    });
+    let alice = app.register("alice").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    let mut ws = WsClient::connect(app.spawn().await, &alice.token).await;
+
+    let link = format!("http://{pages}/moved");
+    let content = format!("look {link} and http://{pages}/plain");
+    let message_id = app.post_message(&alice, &channel_id, &content).await;
+    let update = ws.expect("message_updated").await;
+    assert_eq!(update["message_id"], message_id.as_str());
+    assert!(update["content"].is_null());
+    let embeds = update["embeds"].as_array().unwrap();
+    assert_eq!(embeds.len(), 1);
+    assert_eq!(embeds[0]["url"], link.as_str());
+    assert_eq!(embeds[0]["title"], "Tom & Jerry's guide");
+    assert_eq!(embeds[0]["large_image"], true);
+
+    let (_, history) = app
+        .request(Method::GET, &format!("/api/channels/{channel_id}/messages"), Some(&alice.token), None)
+        .await;
+    assert_eq!(history[0]["embeds"][0]["site_name"], "Cartoons");
+
+    // The same links again come from the cache
+    let fetched = hits.load(Ordering::SeqCst);
+    let again = app.post_message(&alice, &channel_id, &content).await;
+    let update = ws.expect("message_updated").await;
+    assert_eq!(update["message_id"], again.as_str());
+    assert_eq!(update["embeds"][0]["url"], link.as_str());
+    assert_eq!(hits.load(Ordering::SeqCst), fetched);
+
+    // Editing the link away clears the embed
+    let (status, _) = app
+        .request(
+            Method::PATCH,
+            &format!("/api/messages/{message_id}"),
+            Some(&alice.token),
+            Some(json!({"content": "never mind"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ws.expect("message_updated").await["content"], "never mind");
+    let update = ws.expect("message_updated").await;
+    assert_eq!(update["embeds"], json!([]));
+
+    // Messages without links are left alone
+    app.post_message(&alice, &channel_id, "no links here").await;
+    ws.expect_none("message_updated").await;
+}
+
+#[tokio::test]
+async fn servers_can_turn_link_previews_off() {
+    let (pages, hits) = page_server().await;
+    let app = TestApp::with_config(TestConfig {
+        link_previews: private_networks(),
+        ..Default::default()
+    });
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let (status, _) = app
+        .request(
+            Method::PATCH,
+            &format!("/api/servers/{server_id}"),
+            Some(&bob.token),
+            Some(json!({"link_previews": false})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, server) = app
+        .request(
+            Method::PATCH,
+            &format!("/api/servers/{server_id}"),
+            Some(&alice.token),
+            Some(json!({"link_previews": false})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(server["link_previews"], false);
+
+    let mut ws = WsClient::connect(app.spawn().await, &alice.token).await;
+    app.post_message(&alice, &channel_id, &format!("http://{pages}/article")).await;
+    ws.expect_none("message_updated").await;
+    assert_eq!(hits.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+use server::db::Database;
+use uuid::Uuid;
+
+async fn edit(app: &TestApp, token: &str, path: &str, content: &str) {
+    let (status, body) = app
+        .request(Method::PATCH, path, Some(token), Some(json!({"content": content})))
+        .await;
+    assert!(status.is_success(), "edit failed: {status} {body}");
+}
+
+fn contents(history: &Value) -> Vec<&str> {
+    history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["content"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn edits_keep_earlier_versions_for_the_author_and_moderators() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let message_id = app.post_message(&bob, &channel_id, "first").await;
+    let path = format!("/api/messages/{message_id}");
+    let history_path = format!("{path}/history");
+
+    let (status, history) = app.request(Method::GET, &history_path, Some(&bob.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(history, json!([]));
+
+    edit(&app, &bob.token, &path, "second").await;
+    // Saving the same content again is not a new version
+    edit(&app, &bob.token, &path, "second").await;
+    edit(&app, &bob.token, &path, "third").await;
+
+    let (_, history) = app.request(Method::GET, &history_path, Some(&bob.token), None).await;
+    assert_eq!(contents(&history), ["first", "second"]);
+    assert_eq!(history[0]["revision"], 1);
+    assert_eq!(history[1]["revision"], 2);
+    assert_eq!(history[0]["replaced_by"], bob.id.as_str());
+    assert!(history[0]["created_at"].as_str() < history[0]["replaced_at"].as_str());
+    assert!(history[0]["replaced_at"].as_str() <= history[1]["created_at"].as_str());
+
+    // The owner can manage messages; other members only see their own
+    let (status, owner_view) = app.request(Method::GET, &history_path, Some(&alice.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(owner_view, history);
+    let own = app.post_message(&alice, &channel_id, "mine").await;
+    let (status, body) = app
+        .request(Method::GET, &format!("/api/messages/{own}/history"), Some(&bob.token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["missing"], json!(["manage_messages"]));
+    let (status, _) = app.request(Method::GET, &history_path, Some(&carol.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/messages/{}/history", Uuid::new_v4()), Some(&bob.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Deleted messages have no history to show
+    let (status, _) = app.request(Method::DELETE, &path, Some(&bob.token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = app.request(Method::GET, &history_path, Some(&alice.token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn dm_history_is_visible_to_both_participants() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+    let (_, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&alice.token), Some(json!({"recipient_username": "bob"})))
+        .await;
+    let (status, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/dms/{}/messages", conversation["id"].as_str().unwrap()),
+            Some(&alice.token),
+            Some(json!({"content": "helo"})),
+        )
+        .await;
+    assert!(status.is_success(), "{status} {message}");
+    let path = format!("/api/dm_messages/{}", message["id"].as_str().unwrap());
+    edit(&app, &alice.token, &path, "hello").await;
+
+    for user in [&alice, &bob] {
+        let (status, history) = app
+            .request(Method::GET, &format!("{path}/history"), Some(&user.token), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(contents(&history), ["helo"]);
+        assert_eq!(history[0]["replaced_by"], alice.id.as_str());
+    }
+    let (status, _) = app
+        .request(Method::GET, &format!("{path}/history"), Some(&carol.token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn servers_choose_how_long_history_is_kept() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let server_path = format!("/api/servers/{server_id}");
+
+    let (_, server) = app.request(Method::GET, &server_path, Some(&alice.token), None).await;
+    assert_eq!(server["edit_history_days"], 0);
+    let (status, body) = app
+        .request(Method::PATCH, &server_path, Some(&alice.token), Some(json!({"edit_history_days": -1})))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_retention");
+    let (status, server) = app
+        .request(Method::PATCH, &server_path, Some(&alice.token), Some(json!({"edit_history_days": 30})))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(server["edit_history_days"], 30);
+}
+
+#[test]
+fn revisions_past_the_retention_are_hidden_and_pruned() {
+    let path = std::env::temp_dir().join(format!("subspace-history-{}.db", Uuid::new_v4()));
+    let db = Database::new(path.to_str().unwrap()).unwrap();
+    db.run_migrations().unwrap();
+    let user_id = Uuid::new_v4().to_string();
+    db.create_user(&Uuid::parse_str(&user_id).unwrap(), "alice", "hash").unwrap();
+    let server_id = Uuid::new_v4();
+    db.create_server(&server_id, "club", None, &user_id).unwrap();
+    let channel_id = Uuid::new_v4();
+    db.create_channel(&channel_id, &server_id.to_string(), "general", "text", None).unwrap();
+    let message_id = Uuid::new_v4();
+    db.create_message(&message_id, &channel_id.to_string(), &user_id, Some("v1"), None, None, &[], 0)
+        .unwrap()
+        .unwrap();
+    let message_id = message_id.to_string();
+    db.edit_message(&message_id, &user_id, "v2").unwrap();
+    db.edit_message(&message_id, &user_id, "v3").unwrap();
+
+    // Age the first revision by a year
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute(
+        "UPDATE message_revisions SET replaced_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now', '-365 days')
+         WHERE revision = 1",
+        [],
+    )
+    .unwrap();
+
+    // Kept forever by default
+    assert_eq!(db.get_message_revisions(&message_id, 0).unwrap().len(), 2);
+    assert_eq!(db.prune_message_revisions().unwrap(), 0);
+
+    db.update_server(&server_id.to_string(), None, None, None, Some(30)).unwrap();
+    let visible = db.get_message_revisions(&message_id, 30).unwrap();
+    assert_eq!(visible.len(), 1);
+    assert_eq!(visible[0].content.as_deref(), Some("v2"));
+    assert_eq!(db.prune_message_revisions().unwrap(), 1);
+    assert_eq!(db.get_message_revisions(&message_id, 0).unwrap().len(), 1);
+
+    // Numbering carries on after pruning
+    db.edit_message(&message_id, &user_id, "v4").unwrap();
+    let revisions = db.get_message_revisions(&message_id, 0).unwrap();
+    assert_eq!(revisions.iter().map(|r| r.revision).collect::<Vec<_>>(), [2, 3]);
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser};
+use serde_json::{json, Value};
+
+async fn create_invite(app: &TestApp, user: &TestUser, server_id: &str, body: Value) -> (StatusCode, Value) {
+    app.request(
+        Method::POST,
+        &format!("/api/servers/{server_id}/invites"),
+        Some(&user.token),
+        Some(body),
+    )
+    .await
+}
+
+async fn accept(app: &TestApp, user: &TestUser, code: &str) -> (StatusCode, Value) {
+    app.request(
+        Method::POST,
+        &format!("/api/invites/{code}/accept"),
+        Some(&user.token),
+        None,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn preview_is_public_and_accept_joins() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let guest = app.register("guest").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let channel_id = app.create_channel(&owner, &server_id, "welcome").await;
+
+    let (status, invite) = create_invite(&app, &owner, &server_id, json!({"channel_id": channel_id})).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let code = invite["code"].as_str().unwrap();
+
+    let (status, preview) = app
+        .request(Method::GET, &format!("/api/invites/{code}"), None, None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(preview["server_name"], "club");
+    assert_eq!(preview["member_count"], 1);
+    assert_eq!(preview["channel_name"], "welcome");
+
+    let (status, accepted) = accept(&app, &guest, code).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(accepted["server"]["id"], server_id.as_str());
+    assert_eq!(accepted["channel_id"], channel_id.as_str());
+    assert!(app.state.db.is_user_member_of_server(&guest.id, &server_id).unwrap());
+}
+
+#[tokio::test]
+async fn max_uses_is_enforced_and_members_do_not_consume_uses() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let first = app.register("first").await;
+    let second = app.register("second").await;
+    let server_id = app.create_server(&owner, "club").await;
+
+    let (_, invite) = create_invite(&app, &owner, &server_id, json!({"max_uses": 1})).await;
+    let code = invite["code"].as_str().unwrap();
+
+    // Already a member: no use consumed
+    let (status, _) = accept(&app, &owner, code).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = accept(&app, &first, code).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = accept(&app, &second, code).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "invalid_invite");
+    assert!(!app.state.db.is_user_member_of_server(&second.id, &server_id).unwrap());
+}
+
+#[tokio::test]
+async fn expired_and_revoked_invites_are_rejected() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let guest = app.register("guest").await;
+    let server_id = app.create_server(&owner, "club").await;
+
+    let (_, invite) = create_invite(&app, &owner, &server_id, json!({})).await;
+    let code = invite["code"].as_str().unwrap().to_string();
+    let (status, _) = app
+        .request(Method::DELETE, &format!("/api/invites/{code}"), Some(&owner.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = accept(&app, &guest, &code).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, invite) = create_invite(&app, &owner, &server_id, json!({"max_age_secs": 1})).await;
+    let code = invite["code"].as_str().unwrap().to_string();
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/invites/{code}"), None, None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = accept(&app, &guest, &code).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn servers_are_only_joined_through_invites() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let guest = app.register("guest").await;
+    let server_id = app.create_server(&owner, "club").await;
+
+    // Knowing the server id is not enough
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &format!("/api/servers/{server_id}/join"),
+            Some(&guest.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = app
+        .request(Method::GET, &format!("/api/servers/{server_id}"), Some(&guest.token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "not_member");
+
+    let (_, invite) = create_invite(&app, &owner, &server_id, json!({})).await;
+    let (status, _) = accept(&app, &guest, invite["code"].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn only_members_create_and_managers_list_invites() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+
+    let (status, _) = create_invite(&app, &outsider, &server_id, json!({})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, invite) = create_invite(&app, &member, &server_id, json!({})).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let list = format!("/api/servers/{server_id}/invites");
+    let (status, _) = app.request(Method::GET, &list, Some(&member.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, invites) = app.request(Method::GET, &list, Some(&owner.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(invites.as_array().unwrap().len(), 1);
+
+    // The creator can revoke their own invite
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            &format!("/api/invites/{}", invite["code"].as_str().unwrap()),
+            Some(&member.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser, WsClient};
+use serde_json::{json, Value};
+
+struct Fixture {
+    app: TestApp,
+    owner: TestUser,
+    outsider: TestUser,
+    server_id: String,
+    channel_id: String,
+    message_id: String,
+}
+
+async fn fixture() -> Fixture {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&owner, "private").await;
+    let channel_id = app.create_channel(&owner, &server_id, "general").await;
+    let message_id = app.post_message(&owner, &channel_id, "members only").await;
+    Fixture {
+        app,
+        owner,
+        outsider,
+        server_id,
+        channel_id,
+        message_id,
+    }
+}
+
+fn assert_not_member(status: StatusCode, body: &Value) {
+    assert_eq!(status, StatusCode::FORBIDDEN, "body: {body}");
+    assert_eq!(body["code"], "not_member");
+}
+
+#[tokio::test]
+async fn non_member_cannot_read_server_or_channels() {
+    let f = fixture().await;
+    let token = Some(f.outsider.token.as_str());
+
+    for path in [
+        format!("/api/servers/{}", f.server_id),
+        format!("/api/servers/{}/members", f.server_id),
+        format!("/api/servers/{}/channels", f.server_id),
+        format!("/api/channels/{}/messages", f.channel_id),
+        format!("/api/channels/{}/pins", f.channel_id),
+    ] {
+        let (status, body) = f.app.request(Method::GET, &path, token, None).await;
+        assert_not_member(status, &body);
+    }
+}
+
+#[tokio::test]
+async fn non_member_cannot_write_messages() {
+    let f = fixture().await;
+    let token = Some(f.outsider.token.as_str());
+
+    let (status, body) = f
+        .app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{}/messages", f.channel_id),
+            token,
+            Some(json!({"content": "hello?"})),
+        )
+        .await;
+    assert_not_member(status, &body);
+
+    let (status, body) = f
+        .app
+        .request(
+            Method::PATCH,
+            &format!("/api/messages/{}", f.message_id),
+            token,
+            Some(json!({"content": "defaced"})),
+        )
+        .await;
+    assert_not_member(status, &body);
+
+    let (status, body) = f
+        .app
+        .request(Method::DELETE, &format!("/api/messages/{}", f.message_id), token, None)
+        .await;
+    assert_not_member(status, &body);
+
+    // Nothing changed
+    let (status, body) = f
+        .app
+        .request(
+            Method::GET,
+            &format!("/api/channels/{}/messages", f.channel_id),
+            Some(&f.owner.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let messages = body.as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["content"], "members only");
+}
+
+#[tokio::test]
+async fn non_member_cannot_react_or_pin() {
+    let f = fixture().await;
+    let token = Some(f.outsider.token.as_str());
+    let reactions = format!("/api/messages/{}/reactions", f.message_id);
+    let pin = format!("/api/messages/{}/pin", f.message_id);
+
+    let (status, body) = f
+        .app
+        .request(Method::POST, &reactions, token, Some(json!({"emoji": "👍"})))
+        .await;
+    assert_not_member(status, &body);
+
+    let (status, body) = f
+        .app
+        .request(Method::DELETE, &reactions, token, Some(json!({"emoji": "👍"})))
+        .await;
+    assert_not_member(status, &body);
+
+    let (status, body) = f.app.request(Method::POST, &pin, token, None).await;
+    assert_not_member(status, &body);
+
+    let (status, body) = f.app.request(Method::DELETE, &pin, token, None).await;
+    assert_not_member(status, &body);
+}
+
+#[tokio::test]
+async fn member_access_is_allowed_and_revoked_on_leave() {
+    let f = fixture().await;
+    let path = format!("/api/channels/{}/messages", f.channel_id);
+
+    f.app.join_server(&f.outsider, &f.server_id).await;
+
+    let (status, _) = f
+        .app
+        .request(Method::GET, &path, Some(&f.outsider.token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = f
+        .app
+        .request(
+            Method::POST,
+            &format!("/api/servers/{}/leave", f.server_id),
+            Some(&f.outsider.token),
+            None,
+        )
+        .await;
+    assert!(status.is_success());
+
+    let (status, body) = f
+        .app
+        .request(Method::GET, &path, Some(&f.outsider.token), None)
+        .await;
+    assert_not_member(status, &body);
+}
+
+#[tokio::test]
+async fn unknown_channel_is_not_found() {
+    let f = fixture().await;
+    let (status, body) = f
+        .app
+        .request(
+            Method::GET,
+            &format!("/api/channels/{}/messages", uuid::Uuid::new_v4()),
+            Some(&f.owner.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+}
+
+#[tokio::test]
+async fn non_member_ws_send_message_is_rejected() {
+    let f = fixture().await;
+    let addr = f.app.spawn().await;
+    let mut ws = WsClient::connect(addr, &f.outsider.token).await;
+
+    ws.send(
+        "send_message",
+        json!({"channel_id": f.channel_id, "content": "sneaky"}),
+    )
+    .await;
+    let error = ws.expect("error").await;
+    assert_eq!(error["code"], "not_member");
+
+    let rows = f.app.state.db.get_messages(&f.channel_id, 50, server::db::MessageCursor::Latest).unwrap();
+    assert_eq!(rows.len(), 1);
+}
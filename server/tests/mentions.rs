@@ -0,0 +1,115 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser, WsClient};
+use serde_json::{json, Value};
+
+async fn inbox(app: &TestApp, user: &TestUser, query: &str) -> Vec<Value> {
+    let (status, body) = app
+        .request(Method::GET, &format!("/api/me/mentions{query}"), Some(&user.token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    body.as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn username_mentions_notify_and_fill_the_inbox() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    let message_id = app
+        .post_message(&alice, &channel_id, "hey @bob, and @alice and @outsider. mail me at x@bob")
+        .await;
+    let event = bob_ws.expect("mention_created").await;
+    assert_eq!(event["message"]["id"], message_id.as_str());
+    assert_eq!(event["server_id"], server_id.as_str());
+    alice_ws.expect_none("mention_created").await;
+
+    let mentions = inbox(&app, &bob, "").await;
+    assert_eq!(mentions.len(), 1);
+    assert_eq!(mentions[0]["message"]["id"], message_id.as_str());
+    assert_eq!(mentions[0]["read"], false);
+    assert!(inbox(&app, &alice, "").await.is_empty());
+    assert!(inbox(&app, &outsider, "").await.is_empty());
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/me/mentions/read",
+            Some(&bob.token),
+            Some(json!({"message_ids": [message_id]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(inbox(&app, &bob, "?unread=true").await.is_empty());
+    assert_eq!(inbox(&app, &bob, "").await[0]["read"], true);
+}
+
+#[tokio::test]
+async fn here_reaches_connected_members_and_everyone_reaches_all() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+    app.join_server(&carol, &server_id).await;
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    // Only bob is online besides the author
+    alice_ws
+        .send("send_message", json!({"channel_id": channel_id, "content": "@here standup"}))
+        .await;
+    bob_ws.expect("mention_created").await;
+    assert_eq!(inbox(&app, &bob, "").await.len(), 1);
+    assert!(inbox(&app, &carol, "").await.is_empty());
+
+    alice_ws
+        .send("send_message", json!({"channel_id": channel_id, "content": "@everyone release day"}))
+        .await;
+    bob_ws.expect("mention_created").await;
+    assert_eq!(inbox(&app, &carol, "").await.len(), 1);
+
+    let (status, _) = app
+        .request(Method::POST, "/api/me/mentions/read", Some(&bob.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(inbox(&app, &bob, "?unread=true").await.is_empty());
+}
+
+#[tokio::test]
+async fn inbox_is_paginated_newest_first() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let mut ids = Vec::new();
+    for i in 0..5 {
+        ids.push(app.post_message(&alice, &channel_id, &format!("@bob ping {i}")).await);
+    }
+    ids.reverse();
+
+    let first = inbox(&app, &bob, "?limit=2").await;
+    let first: Vec<_> = first.iter().map(|m| m["message"]["id"].as_str().unwrap()).collect();
+    assert_eq!(first, ids[..2]);
+
+    let rest = inbox(&app, &bob, &format!("?limit=10&before={}", first[1])).await;
+    let rest: Vec<_> = rest.iter().map(|m| m["message"]["id"].as_str().unwrap()).collect();
+    assert_eq!(rest, ids[2..]);
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser, WsClient};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+struct Fixture {
+    app: TestApp,
+    owner: TestUser,
+    author: TestUser,
+    member: TestUser,
+    moderator: TestUser,
+    channel_id: String,
+    message_id: String,
+}
+
+/// A server where `author` has posted a message, with a plain member
+/// and a moderator holding only `manage_messages`.
+async fn fixture() -> Fixture {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let author = app.register("author").await;
+    let member = app.register("member").await;
+    let moderator = app.register("moderator").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let channel_id = app.create_channel(&owner, &server_id, "general").await;
+    for user in [&author, &member, &moderator] {
+        app.join_server(user, &server_id).await;
+    }
+    let (status, role) = app
+        .request(
+            Method::POST,
+            &format!("/api/servers/{server_id}/roles"),
+            Some(&owner.token),
+            Some(json!({"name": "Moderators", "permissions": 1 << 1})),
+        )
+        .await;
+    assert!(status.is_success(), "{status} {role}");
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            &format!("/api/servers/{server_id}/members/{}/roles/{}", moderator.id, role["id"].as_str().unwrap()),
+            Some(&owner.token),
+            None,
+        )
+        .await;
+    assert!(status.is_success());
+    let message_id = app.post_message(&author, &channel_id, "original").await;
+    Fixture {
+        app,
+        owner,
+        author,
+        member,
+        moderator,
+        channel_id,
+        message_id,
+    }
+}
+
+async fn edit(app: &TestApp, user: &TestUser, path: &str) -> (StatusCode, Value) {
+    app.request(Method::PATCH, path, Some(&user.token), Some(json!({"content": "edited"})))
+        .await
+}
+
+async fn dm(app: &TestApp, from: &TestUser, recipient: &str, content: &str) -> String {
+    let (_, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&from.token), Some(json!({"recipient_username": recipient})))
+        .await;
+    let (status, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/dms/{}/messages", conversation["id"].as_str().unwrap()),
+            Some(&from.token),
+            Some(json!({"content": content})),
+        )
+        .await;
+    assert!(status.is_success(), "{status} {message}");
+    message["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn only_the_author_can_edit_a_message() {
+    let f = fixture().await;
+    let mut ws = WsClient::connect(f.app.spawn().await, &f.member.token).await;
+    let path = format!("/api/messages/{}", f.message_id);
+
+    for (name, user) in [("member", &f.member), ("moderator", &f.moderator), ("owner", &f.owner)] {
+        let (status, body) = edit(&f.app, user, &path).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{name}");
+        assert_eq!(body["code"], "not_author");
+    }
+    ws.expect_none("message_updated").await;
+    let (_, messages) = f
+        .app
+        .request(Method::GET, &format!("/api/channels/{}/messages", f.channel_id), Some(&f.member.token), None)
+        .await;
+    assert_eq!(messages[0]["content"], "original");
+    assert!(messages[0]["edited_at"].is_null());
+
+    let (status, body) = edit(&f.app, &f.member, &format!("/api/messages/{}", Uuid::new_v4())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+
+    let (status, _) = edit(&f.app, &f.author, &path).await;
+    assert_eq!(status, StatusCode::OK);
+    let update = ws.expect("message_updated").await;
+    assert_eq!(update["message_id"], f.message_id.as_str());
+    assert_eq!(update["content"], "edited");
+}
+
+#[tokio::test]
+async fn authors_and_moderators_can_delete_a_message() {
+    let f = fixture().await;
+    let mut ws = WsClient::connect(f.app.spawn().await, &f.member.token).await;
+    let path = format!("/api/messages/{}", f.message_id);
+
+    let (status, body) = f.app.request(Method::DELETE, &path, Some(&f.member.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "missing_permissions");
+    assert_eq!(body["missing"], json!(["manage_messages"]));
+    ws.expect_none("message_deleted").await;
+
+    let (status, _) = f.app.request(Method::DELETE, &path, Some(&f.moderator.token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let deleted = ws.expect("message_deleted").await;
+    assert_eq!(deleted["message_id"], f.message_id.as_str());
+    assert_eq!(deleted["channel_id"], f.channel_id.as_str());
+
+    let (status, body) = f.app.request(Method::DELETE, &path, Some(&f.author.token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+
+    let own = f.app.post_message(&f.member, &f.channel_id, "mine").await;
+    let (status, _) = f
+        .app
+        .request(Method::DELETE, &format!("/api/messages/{own}"), Some(&f.member.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(ws.expect("message_deleted").await["message_id"], own.as_str());
+}
+
+#[tokio::test]
+async fn dm_messages_are_only_changed_by_their_author() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let mallory = app.register("mallory").await;
+    let message_id = dm(&app, &alice, "bob", "hi bob").await;
+    let path = format!("/api/dm_messages/{message_id}");
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    // The other side of the conversation can see it but not change it
+    let (status, body) = edit(&app, &bob, &path).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "not_author");
+    let (status, body) = app.request(Method::DELETE, &path, Some(&bob.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "not_author");
+    // Outsiders don't learn it exists
+    let (status, body) = edit(&app, &mallory, &path).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+    let (status, body) = app.request(Method::DELETE, &path, Some(&mallory.token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+    bob_ws.expect_none("dm_message_updated").await;
+    bob_ws.expect_none("dm_message_deleted").await;
+
+    let (status, _) = edit(&app, &alice, &path).await;
+    assert_eq!(status, StatusCode::OK);
+    for ws in [&mut alice_ws, &mut bob_ws] {
+        assert_eq!(ws.expect("dm_message_updated").await["content"], "edited");
+    }
+
+    let (status, _) = app.request(Method::DELETE, &path, Some(&alice.token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    for ws in [&mut alice_ws, &mut bob_ws] {
+        assert_eq!(ws.expect("dm_message_deleted").await["message_id"], message_id.as_str());
+    }
+    let (status, _) = edit(&app, &alice, &path).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
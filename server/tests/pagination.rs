@@ -0,0 +1,124 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser};
+use serde_json::{json, Value};
+
+async fn page(app: &TestApp, user: &TestUser, path: &str) -> Vec<String> {
+    let (status, body) = app.request(Method::GET, path, Some(&user.token), None).await;
+    assert_eq!(status, StatusCode::OK, "{path}: {body}");
+    ids(&body)
+}
+
+fn ids(body: &Value) -> Vec<String> {
+    body.as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn channel_cursors_walk_history_without_gaps() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let channel_id = app.create_channel(&owner, &server_id, "general").await;
+
+    // Posted back to back, so many share a created_at and rely on the id
+    // tie-break for a stable order.
+    for i in 0..30 {
+        app.post_message(&owner, &channel_id, &format!("msg {i}")).await;
+    }
+    let base = format!("/api/channels/{channel_id}/messages");
+    let all = page(&app, &owner, &format!("{base}?limit=100")).await;
+    assert_eq!(all.len(), 30);
+
+    let latest = page(&app, &owner, &format!("{base}?limit=7")).await;
+    assert_eq!(latest, all[23..]);
+
+    // Walk backwards from the newest page
+    let mut walked = latest;
+    loop {
+        let before = walked[0].clone();
+        let older = page(&app, &owner, &format!("{base}?limit=7&before={before}")).await;
+        if older.is_empty() {
+            break;
+        }
+        walked.splice(0..0, older);
+    }
+    assert_eq!(walked, all);
+
+    let after = page(&app, &owner, &format!("{base}?limit=5&after={}", all[10])).await;
+    assert_eq!(after, all[11..16]);
+
+    let around = page(&app, &owner, &format!("{base}?limit=6&around={}", all[10])).await;
+    assert_eq!(around, all[7..13]);
+}
+
+#[tokio::test]
+async fn conflicting_or_foreign_cursors_are_rejected() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let general = app.create_channel(&owner, &server_id, "general").await;
+    let random = app.create_channel(&owner, &server_id, "random").await;
+    let here = app.post_message(&owner, &general, "here").await;
+    let there = app.post_message(&owner, &random, "there").await;
+
+    let (status, body) = app
+        .request(
+            Method::GET,
+            &format!("/api/channels/{general}/messages?before={here}&after={here}"),
+            Some(&owner.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "conflicting_cursors");
+
+    let (status, body) = app
+        .request(
+            Method::GET,
+            &format!("/api/channels/{general}/messages?around={there}"),
+            Some(&owner.token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_message");
+}
+
+#[tokio::test]
+async fn dm_history_is_limited_and_paginated() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let (status, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&alice.token), Some(json!({"recipient_username": "bob"})))
+        .await;
+    assert!(status.is_success(), "{status} {conversation}");
+    let conversation_id = conversation["id"].as_str().unwrap().to_string();
+
+    for i in 0..120 {
+        app.state
+            .db
+            .create_dm_message(&uuid::Uuid::new_v4(), &conversation_id, &alice.id, Some(&format!("dm {i}")), &[])
+            .unwrap();
+    }
+    let base = format!("/api/dms/{conversation_id}/messages");
+
+    let latest = page(&app, &bob, &base).await;
+    assert_eq!(latest.len(), 50);
+
+    let capped = page(&app, &bob, &format!("{base}?limit=500")).await;
+    assert_eq!(capped.len(), 100);
+    assert_eq!(capped[50..], latest[..]);
+
+    let older = page(&app, &bob, &format!("{base}?limit=100&before={}", capped[0])).await;
+    assert_eq!(older.len(), 20);
+
+    let newer = page(&app, &bob, &format!("{base}?after={}", older[19])).await;
+    assert_eq!(newer, capped[..50]);
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser, WsClient};
+use serde_json::{json, Value};
+
+async fn channel_state(app: &TestApp, user: &TestUser, server_id: &str, channel_id: &str) -> Value {
+    let (status, body) = app
+        .request(Method::GET, &format!("/api/servers/{server_id}/channels"), Some(&user.token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    body.as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["id"] == channel_id)
+        .unwrap()
+        .clone()
+}
+
+async fn server_state(app: &TestApp, user: &TestUser, server_id: &str) -> Value {
+    let (status, body) = app.request(Method::GET, "/api/servers", Some(&user.token), None).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    body.as_array()
+        .unwrap()
+        .iter()
+        .find(|s| s["id"] == server_id)
+        .unwrap()
+        .clone()
+}
+
+#[tokio::test]
+async fn channel_counts_drop_as_the_marker_advances() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let general = app.create_channel(&alice, &server_id, "general").await;
+    let random = app.create_channel(&alice, &server_id, "random").await;
+    app.join_server(&bob, &server_id).await;
+
+    let first = app.post_message(&alice, &general, "hello").await;
+    let second = app.post_message(&alice, &general, "hey @bob").await;
+    app.post_message(&alice, &random, "elsewhere").await;
+    app.post_message(&bob, &general, "my own message").await;
+
+    let channel = channel_state(&app, &bob, &server_id, &general).await;
+    assert_eq!(channel["unread_count"], 2);
+    assert_eq!(channel["mention_count"], 1);
+    assert!(channel["last_read_message_id"].is_null());
+    let server = server_state(&app, &bob, &server_id).await;
+    assert_eq!(server["unread_count"], 3);
+    assert_eq!(server["mention_count"], 1);
+
+    let ack_path = format!("/api/channels/{general}/ack");
+    let ack = |message_id: &str| {
+        app.request(Method::POST, &ack_path, Some(&bob.token), Some(json!({"message_id": message_id})))
+    };
+    let (status, state) = ack(&second).await;
+    assert_eq!(status, StatusCode::OK, "{state}");
+    assert_eq!(state["channel_id"], general.as_str());
+    assert_eq!(state["last_read_message_id"], second.as_str());
+    assert_eq!(state["unread_count"], 0);
+    assert_eq!(state["mention_count"], 0);
+
+    // Acking an older message never moves the marker back
+    let (status, state) = ack(&first).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(state["last_read_message_id"], second.as_str());
+
+    let server = server_state(&app, &bob, &server_id).await;
+    assert_eq!(server["unread_count"], 1);
+    assert_eq!(server["mention_count"], 0);
+
+    // Reading the channel clears the mention from the inbox too
+    let (_, inbox) = app
+        .request(Method::GET, "/api/me/mentions?unread=true", Some(&bob.token), None)
+        .await;
+    assert!(inbox.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn acks_reject_foreign_messages_and_outsiders() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let general = app.create_channel(&alice, &server_id, "general").await;
+    let random = app.create_channel(&alice, &server_id, "random").await;
+    let elsewhere = app.post_message(&alice, &random, "elsewhere").await;
+    let here = app.post_message(&alice, &general, "here").await;
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{general}/ack"),
+            Some(&alice.token),
+            Some(json!({"message_id": elsewhere})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "unknown_message");
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{general}/ack"),
+            Some(&outsider.token),
+            Some(json!({"message_id": here})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn ws_acks_sync_the_users_other_sessions() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let (status, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&alice.token), Some(json!({"recipient_username": "bob"})))
+        .await;
+    assert!(status.is_success(), "{status} {conversation}");
+    let conversation_id = conversation["id"].as_str().unwrap().to_string();
+    let mut last = String::new();
+    for i in 0..3 {
+        let (status, message) = app
+            .request(
+                Method::POST,
+                &format!("/api/dms/{conversation_id}/messages"),
+                Some(&alice.token),
+                Some(json!({"content": format!("dm {i}")})),
+            )
+            .await;
+        assert!(status.is_success(), "{status} {message}");
+        last = message["id"].as_str().unwrap().to_string();
+    }
+
+    let (_, conversations) = app.request(Method::GET, "/api/dms", Some(&bob.token), None).await;
+    assert_eq!(conversations[0]["unread_count"], 3);
+    assert_eq!(conversations[0]["mention_count"], 3);
+
+    let addr = app.spawn().await;
+    let mut desktop = WsClient::connect(addr, &bob.token).await;
+    let mut phone = WsClient::connect(addr, &bob.token).await;
+
+    desktop
+        .send("ack", json!({"conversation_id": conversation_id, "message_id": last}))
+        .await;
+    let update = phone.expect("read_state_update").await;
+    assert_eq!(update["read_state"]["conversation_id"], conversation_id.as_str());
+    assert_eq!(update["read_state"]["last_read_message_id"], last.as_str());
+    assert_eq!(update["read_state"]["unread_count"], 0);
+    desktop.expect_none("read_state_update").await;
+
+    let (_, conversations) = app.request(Method::GET, "/api/dms", Some(&bob.token), None).await;
+    assert_eq!(conversations[0]["unread_count"], 0);
+    assert_eq!(conversations[0]["last_read_message_id"], last.as_str());
+
+    // Outsiders cannot ack someone else's conversation
+    let outsider = app.register("outsider").await;
+    let mut outsider_ws = WsClient::connect(addr, &outsider.token).await;
+    outsider_ws
+        .send("ack", json!({"conversation_id": conversation_id, "message_id": last}))
+        .await;
+    let error = outsider_ws.expect("error").await;
+    assert_eq!(error["code"], "not_found");
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser};
+use serde_json::{json, Value};
+
+const MANAGE_CHANNELS: i64 = 1 << 0;
+const KICK_MEMBERS: i64 = 1 << 3;
+const MANAGE_ROLES: i64 = 1 << 4;
+const MANAGE_SERVER: i64 = 1 << 7;
+
+struct Fixture {
+    app: TestApp,
+    owner: TestUser,
+    member: TestUser,
+    server_id: String,
+}
+
+async fn fixture() -> Fixture {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+    Fixture {
+        app,
+        owner,
+        member,
+        server_id,
+    }
+}
+
+impl Fixture {
+    async fn create_role(&self, by: &TestUser, name: &str, permissions: i64) -> (StatusCode, Value) {
+        self.app
+            .request(
+                Method::POST,
+                &format!("/api/servers/{}/roles", self.server_id),
+                Some(&by.token),
+                Some(json!({"name": name, "permissions": permissions})),
+            )
+            .await
+    }
+
+    /// Creates a role as the owner and gives it to `user`.
+    async fn grant_role(&self, user: &TestUser, name: &str, permissions: i64) -> String {
+        let (status, role) = self.create_role(&self.owner, name, permissions).await;
+        assert!(status.is_success(), "{status} {role}");
+        let role_id = role["id"].as_str().unwrap().to_string();
+        let path = format!("/api/servers/{}/members/{}/roles/{role_id}", self.server_id, user.id);
+        let (status, _) = self.app.request(Method::PUT, &path, Some(&self.owner.token), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        role_id
+    }
+
+    async fn set_override(&self, by: &TestUser, channel_id: &str, role_id: &str, allow: i64, deny: i64) -> StatusCode {
+        let path = format!("/api/channels/{channel_id}/permissions/{role_id}");
+        let (status, _) = self
+            .app
+            .request(Method::PUT, &path, Some(&by.token), Some(json!({"allow": allow, "deny": deny})))
+            .await;
+        status
+    }
+
+    async fn rename_channel(&self, channel_id: &str, name: &str) -> (StatusCode, Value) {
+        self.app
+            .request(
+                Method::PATCH,
+                &format!("/api/channels/{channel_id}"),
+                Some(&self.member.token),
+                Some(json!({"name": name})),
+            )
+            .await
+    }
+}
+
+#[tokio::test]
+async fn channel_overrides_apply_everyone_then_roles() {
+    let f = fixture().await;
+    let channel_id = f.app.create_channel(&f.owner, &f.server_id, "general").await;
+    let other_id = f.app.create_channel(&f.owner, &f.server_id, "other").await;
+
+    let (status, body) = f.rename_channel(&channel_id, "renamed").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "missing_permissions");
+    assert_eq!(body["missing"], json!(["manage_channels"]));
+
+    // Everyone may manage channels, except in this one
+    let (_, roles) = f
+        .app
+        .request(Method::GET, &format!("/api/servers/{}/roles", f.server_id), Some(&f.member.token), None)
+        .await;
+    let everyone = roles
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["id"] == f.server_id.as_str())
+        .unwrap();
+    assert_eq!(everyone["name"], "@everyone");
+    let everyone_bits = everyone["permissions"].as_i64().unwrap();
+    let (status, _) = f
+        .app
+        .request(
+            Method::PATCH,
+            &format!("/api/roles/{}", f.server_id),
+            Some(&f.owner.token),
+            Some(json!({"permissions": everyone_bits | MANAGE_CHANNELS})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(f.rename_channel(&channel_id, "renamed").await.0, StatusCode::OK);
+    assert_eq!(f.set_override(&f.owner, &channel_id, &f.server_id, 0, MANAGE_CHANNELS).await, StatusCode::NO_CONTENT);
+    assert_eq!(f.rename_channel(&channel_id, "again").await.0, StatusCode::FORBIDDEN);
+    assert_eq!(f.rename_channel(&other_id, "still-fine").await.0, StatusCode::OK);
+
+    // A role allow beats the @everyone deny
+    let helpers = f.grant_role(&f.member, "Helpers", 0).await;
+    assert_eq!(f.set_override(&f.owner, &channel_id, &helpers, MANAGE_CHANNELS, 0).await, StatusCode::NO_CONTENT);
+    assert_eq!(f.rename_channel(&channel_id, "again").await.0, StatusCode::OK);
+
+    // A role deny beats the server-wide grant
+    assert_eq!(f.set_override(&f.owner, &channel_id, &f.server_id, 0, 0).await, StatusCode::NO_CONTENT);
+    assert_eq!(f.set_override(&f.owner, &channel_id, &helpers, 0, MANAGE_CHANNELS).await, StatusCode::NO_CONTENT);
+    assert_eq!(f.rename_channel(&channel_id, "denied").await.0, StatusCode::FORBIDDEN);
+
+    // ...unless another of the member's roles allows it
+    let trusted = f.grant_role(&f.member, "Trusted", 0).await;
+    assert_eq!(f.set_override(&f.owner, &channel_id, &trusted, MANAGE_CHANNELS, 0).await, StatusCode::NO_CONTENT);
+    assert_eq!(f.rename_channel(&channel_id, "allowed").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn role_managers_cannot_touch_permissions_they_lack() {
+    let f = fixture().await;
+    let manager = &f.member;
+    f.grant_role(manager, "Role managers", MANAGE_ROLES).await;
+    let channel_id = f.app.create_channel(&f.owner, &f.server_id, "general").await;
+
+    let (status, body) = f.create_role(manager, "Kickers", KICK_MEMBERS).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["missing"], json!(["kick_members"]));
+
+    let (_, admins) = f.create_role(&f.owner, "Admins", MANAGE_SERVER | KICK_MEMBERS).await;
+    let admins = admins["id"].as_str().unwrap().to_string();
+    let path = format!("/api/roles/{admins}");
+    for body in [json!({"permissions": 0}), json!({"name": "Nobodies"})] {
+        let (status, _) = f.app.request(Method::PATCH, &path, Some(&manager.token), Some(body)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    let (status, _) = f.app.request(Method::DELETE, &path, Some(&manager.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let assign = format!("/api/servers/{}/members/{}/roles/{admins}", f.server_id, manager.id);
+    let (status, _) = f.app.request(Method::PUT, &assign, Some(&manager.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, role) = f.app.request(Method::GET, &format!("/api/servers/{}/roles", f.server_id), Some(&f.owner.token), None).await;
+    let role = role.as_array().unwrap().iter().find(|r| r["id"] == admins.as_str()).unwrap().clone();
+    assert_eq!(role["permissions"], MANAGE_SERVER | KICK_MEMBERS);
+
+    // Overrides covering bits they lack cannot be replaced or removed
+    assert_eq!(f.set_override(&f.owner, &channel_id, &f.server_id, 0, MANAGE_SERVER).await, StatusCode::NO_CONTENT);
+    assert_eq!(f.set_override(manager, &channel_id, &f.server_id, 0, 0).await, StatusCode::FORBIDDEN);
+    let path = format!("/api/channels/{channel_id}/permissions/{}", f.server_id);
+    let (status, _) = f.app.request(Method::DELETE, &path, Some(&manager.token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, overrides) = f
+        .app
+        .request(Method::GET, &format!("/api/channels/{channel_id}/permissions"), Some(&f.owner.token), None)
+        .await;
+    assert_eq!(overrides[0]["deny"], MANAGE_SERVER);
+
+    // Within their own permissions they can do all of it
+    let (status, role) = f.create_role(manager, "Helpers", MANAGE_ROLES).await;
+    assert!(status.is_success());
+    let path = format!("/api/roles/{}", role["id"].as_str().unwrap());
+    let (status, _) = f.app.request(Method::PATCH, &path, Some(&manager.token), Some(json!({"permissions": 0}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = f.app.request(Method::DELETE, &path, Some(&manager.token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(f.set_override(manager, &channel_id, &admins, 0, MANAGE_ROLES).await, StatusCode::NO_CONTENT);
+    let path = format!("/api/channels/{channel_id}/permissions/{admins}");
+    let (status, _) = f.app.request(Method::DELETE, &path, Some(&manager.token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
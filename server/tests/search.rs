@@ -0,0 +1,191 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser};
+use serde_json::{json, Value};
+
+/// Percent-encodes everything but letters and digits.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (b as char).to_string(),
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+async fn search(app: &TestApp, user: &TestUser, q: &str, extra: &str) -> Value {
+    let path = format!("/api/search?q={}{extra}", encode(q));
+    let (status, results) = app.request(Method::GET, &path, Some(&user.token), None).await;
+    assert_eq!(status, StatusCode::OK, "{q}: {results}");
+    results
+}
+
+fn contents(results: &Value, key: &str) -> Vec<String> {
+    let mut contents: Vec<String> = results[key]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["message"]["content"].as_str().unwrap().to_string())
+        .collect();
+    contents.sort();
+    contents
+}
+
+async fn open_dm(app: &TestApp, user: &TestUser, recipient_username: &str) -> String {
+    let (status, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&user.token), Some(json!({"recipient_username": recipient_username})))
+        .await;
+    assert!(status.is_success(), "{status} {conversation}");
+    conversation["id"].as_str().unwrap().to_string()
+}
+
+async fn post_dm(app: &TestApp, author: &TestUser, conversation_id: &str, body: Value) -> String {
+    let path = format!("/api/dms/{conversation_id}/messages");
+    let (status, message) = app.request(Method::POST, &path, Some(&author.token), Some(body)).await;
+    assert!(status.is_success(), "{status} {message}");
+    message["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn results_cover_only_the_callers_servers_and_conversations() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+    let general = app.create_channel(&owner, &server_id, "general").await;
+    let random = app.create_channel(&owner, &server_id, "random").await;
+    let elsewhere = app.create_server(&outsider, "elsewhere").await;
+    let hidden = app.create_channel(&outsider, &elsewhere, "hidden").await;
+
+    app.post_message(&owner, &general, "banana bread").await;
+    app.post_message(&member, &general, "banana split").await;
+    app.post_message(&member, &random, "banana republic").await;
+    app.post_message(&outsider, &hidden, "banana secrets").await;
+    let ours = open_dm(&app, &owner, "member").await;
+    post_dm(&app, &member, &ours, json!({"content": "banana for you"})).await;
+    let theirs = open_dm(&app, &outsider, "member").await;
+    post_dm(&app, &outsider, &theirs, json!({"content": "banana between us"})).await;
+
+    let results = search(&app, &owner, "banana", "").await;
+    assert_eq!(contents(&results, "messages"), ["banana bread", "banana republic", "banana split"]);
+    assert_eq!(contents(&results, "dm_messages"), ["banana for you"]);
+    assert_eq!(results["has_more"], false);
+    let results = search(&app, &outsider, "banana", "").await;
+    assert_eq!(contents(&results, "messages"), ["banana secrets"]);
+    assert_eq!(contents(&results, "dm_messages"), ["banana between us"]);
+
+    // Asking for a server or conversation the caller is not in finds nothing
+    let results = search(&app, &outsider, "banana", &format!("&server_id={server_id}")).await;
+    assert_eq!(results["messages"], json!([]));
+    let results = search(&app, &owner, "banana", &format!("&conversation_id={theirs}")).await;
+    assert_eq!(results["dm_messages"], json!([]));
+
+    let results = search(&app, &owner, "banana", &format!("&server_id={server_id}")).await;
+    assert_eq!(contents(&results, "messages").len(), 3);
+    assert_eq!(results["dm_messages"], json!([]));
+    let results = search(&app, &owner, "banana", &format!("&channel_id={random}")).await;
+    assert_eq!(contents(&results, "messages"), ["banana republic"]);
+    let results = search(&app, &owner, "banana", &format!("&author_id={}", member.id)).await;
+    assert_eq!(contents(&results, "messages"), ["banana republic", "banana split"]);
+    assert_eq!(contents(&results, "dm_messages"), ["banana for you"]);
+    // Pages run across channel messages and DMs together
+    let results = search(&app, &owner, "banana", "&limit=2").await;
+    assert_eq!(contents(&results, "messages").len() + contents(&results, "dm_messages").len(), 2);
+    assert_eq!(results["has_more"], true);
+
+    // has:attachment works on its own and alongside words
+    let upload = app.upload(&owner, "recipe.txt", "text/plain", b"flour").await;
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{general}/messages"),
+            Some(&owner.token),
+            Some(json!({"content": "banana recipe", "attachment_ids": [upload["id"]]})),
+        )
+        .await;
+    assert!(status.is_success());
+    let upload = app.upload(&member, "photo.txt", "text/plain", b"snap").await;
+    post_dm(&app, &member, &ours, json!({"content": "holiday", "attachment_ids": [upload["id"]]})).await;
+    let results = search(&app, &owner, "banana has:attachment", "").await;
+    assert_eq!(contents(&results, "messages"), ["banana recipe"]);
+    assert_eq!(results["dm_messages"], json!([]));
+    let results = search(&app, &owner, "has:attachment", "").await;
+    assert_eq!(contents(&results, "messages"), ["banana recipe"]);
+    assert_eq!(contents(&results, "dm_messages"), ["holiday"]);
+}
+
+#[tokio::test]
+async fn query_syntax_is_searched_for_literally() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let channel_id = app.create_channel(&owner, &server_id, "general").await;
+    app.post_message(&owner, &channel_id, "bananas are yellow").await;
+    app.post_message(&owner, &channel_id, "meet me NEAR the station").await;
+    app.post_message(&owner, &channel_id, "she said \"hello\" twice").await;
+    app.post_message(&owner, &channel_id, "<img src=x onerror=alert(1)> hello").await;
+
+    // Quoted, so `*` is no prefix match and `NEAR` no operator
+    assert_eq!(search(&app, &owner, "banana*", "").await["messages"], json!([]));
+    assert_eq!(contents(&search(&app, &owner, "bananas*", "").await, "messages"), ["bananas are yellow"]);
+    assert_eq!(contents(&search(&app, &owner, "NEAR", "").await, "messages"), ["meet me NEAR the station"]);
+    assert_eq!(search(&app, &owner, "NEAR(bananas yellow)", "").await["messages"], json!([]));
+    assert_eq!(search(&app, &owner, "meet NEAR station", "").await["messages"].as_array().unwrap().len(), 1);
+
+    for q in ["\"", "\"hello", "hello\"", "\"\"\"", "OR", "-", "a:b", "(", "^"] {
+        search(&app, &owner, q, "").await;
+    }
+    assert_eq!(
+        contents(&search(&app, &owner, "\"hello\"", "").await, "messages"),
+        ["<img src=x onerror=alert(1)> hello", "she said \"hello\" twice"]
+    );
+
+    // Snippets are escaped HTML with only the matches marked
+    let results = search(&app, &owner, "onerror", "").await;
+    assert_eq!(
+        results["messages"][0]["snippet"],
+        "&lt;img src=x <mark>onerror</mark>=alert(1)&gt; hello"
+    );
+
+    let (status, _) = app.request(Method::GET, "/api/search?q=", Some(&owner.token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn edits_and_deletes_update_the_index() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    app.register("member").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let channel_id = app.create_channel(&owner, &server_id, "general").await;
+    let message_id = app.post_message(&owner, &channel_id, "apple pie").await;
+    let conversation_id = open_dm(&app, &owner, "member").await;
+    let dm_id = post_dm(&app, &owner, &conversation_id, json!({"content": "apple crumble"})).await;
+
+    let results = search(&app, &owner, "apple", "").await;
+    assert_eq!(contents(&results, "messages"), ["apple pie"]);
+    assert_eq!(contents(&results, "dm_messages"), ["apple crumble"]);
+
+    for path in [format!("/api/messages/{message_id}"), format!("/api/dm_messages/{dm_id}")] {
+        let (status, _) = app
+            .request(Method::PATCH, &path, Some(&owner.token), Some(json!({"content": "pear tart"})))
+            .await;
+        assert!(status.is_success());
+    }
+    let results = search(&app, &owner, "apple", "").await;
+    assert_eq!((results["messages"].clone(), results["dm_messages"].clone()), (json!([]), json!([])));
+    let results = search(&app, &owner, "pear", "").await;
+    assert_eq!(contents(&results, "messages"), ["pear tart"]);
+    assert_eq!(contents(&results, "dm_messages"), ["pear tart"]);
+
+    for path in [format!("/api/messages/{message_id}"), format!("/api/dm_messages/{dm_id}")] {
+        let (status, _) = app.request(Method::DELETE, &path, Some(&owner.token), None).await;
+        assert!(status.is_success());
+    }
+    let results = search(&app, &owner, "pear", "").await;
+    assert_eq!((results["messages"].clone(), results["dm_messages"].clone()), (json!([]), json!([])));
+}
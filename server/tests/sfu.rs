@@ -0,0 +1,97 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestConfig, WsClient};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde_json::json;
+use server::routes::sfu::SfuConfig;
+use shared::sfu::SfuClaims;
+
+fn claims(token: &str) -> SfuClaims {
+    decode::<SfuClaims>(token, &DecodingKey::from_secret(b"north"), &Validation::default())
+        .unwrap()
+        .claims
+}
+
+#[tokio::test]
+async fn sfu_channels_hand_members_in_voice_a_token() {
+    let app = TestApp::with_config(TestConfig {
+        sfu: Some(SfuConfig::new("wss://sfu.example.com/rtc", "north")),
+        ..Default::default()
+    });
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+    let text_id = app.create_channel(&owner, &server_id, "general").await;
+    let voice_id = app.create_channel_of_type(&owner, &server_id, "lounge", "voice").await;
+    let session = format!("/api/channels/{voice_id}/sfu");
+
+    let (status, body) = app.request(Method::GET, &session, Some(&member.token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "not_sfu");
+
+    let path = format!("/api/channels/{voice_id}");
+    let (status, _) = app.request(Method::PATCH, &path, Some(&member.token), Some(json!({"voice_mode": "sfu"}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    for (channel, mode) in [(&text_id, "sfu"), (&voice_id, "hub")] {
+        let (status, body) = app
+            .request(Method::PATCH, &format!("/api/channels/{channel}"), Some(&owner.token), Some(json!({"voice_mode": mode})))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_voice_mode");
+    }
+
+    let mut ws = WsClient::connect(app.spawn().await, &member.token).await;
+    let (status, channel) = app.request(Method::PATCH, &path, Some(&owner.token), Some(json!({"voice_mode": "sfu"}))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(channel["voice_mode"], "sfu");
+    assert_eq!(ws.expect("channel_updated").await["channel"]["voice_mode"], "sfu");
+
+    // Only once they are in the channel
+    let (status, body) = app.request(Method::GET, &session, Some(&member.token), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "not_in_voice");
+
+    ws.send("join_voice", json!({"channel_id": voice_id})).await;
+    ws.expect("voice_state_update").await;
+    let (status, body) = app.request(Method::GET, &session, Some(&member.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["url"], "wss://sfu.example.com/rtc");
+    let granted = claims(body["token"].as_str().unwrap());
+    assert_eq!(granted.sub, member.id);
+    assert_eq!(granted.room, voice_id);
+    assert!(granted.publish);
+
+    // Members who may not speak only get to listen
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            &format!("/api/channels/{voice_id}/permissions/{server_id}"),
+            Some(&owner.token),
+            Some(json!({"allow": 0, "deny": 1 << 6})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, body) = app.request(Method::GET, &session, Some(&member.token), None).await;
+    assert!(!claims(body["token"].as_str().unwrap()).publish);
+}
+
+#[tokio::test]
+async fn voice_channels_stay_peer_to_peer_without_an_sfu() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let voice_id = app.create_channel_of_type(&owner, &server_id, "lounge", "voice").await;
+
+    let (_, channels) = app
+        .request(Method::GET, &format!("/api/servers/{server_id}/channels"), Some(&owner.token), None)
+        .await;
+    assert_eq!(channels[0]["voice_mode"], "mesh");
+
+    let (status, body) = app
+        .request(Method::PATCH, &format!("/api/channels/{voice_id}"), Some(&owner.token), Some(json!({"voice_mode": "sfu"})))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "sfu_unavailable");
+}
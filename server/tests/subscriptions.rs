@@ -0,0 +1,101 @@
+mod common;
+
+use axum::http::Method;
+use common::{TestApp, WsClient};
+
+#[tokio::test]
+async fn joining_subscribes_open_sockets_and_notifies_members() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    app.join_server(&bob, &server_id).await;
+    let joined = alice_ws.expect("member_joined").await;
+    assert_eq!(joined["member"]["user_id"], bob.id.as_str());
+
+    // No reconnect needed
+    app.post_message(&alice, &channel_id, "welcome").await;
+    let created = bob_ws.expect("message_created").await;
+    assert_eq!(created["message"]["content"], "welcome");
+}
+
+#[tokio::test]
+async fn creating_a_server_subscribes_the_owner() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+
+    let server_id = app.create_server(&alice, "fresh").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.post_message(&alice, &channel_id, "first").await;
+
+    let created = alice_ws.expect("message_created").await;
+    assert_eq!(created["message"]["content"], "first");
+}
+
+#[tokio::test]
+async fn leaving_unsubscribes_and_notifies_members() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &format!("/api/servers/{server_id}/leave"),
+            Some(&bob.token),
+            None,
+        )
+        .await;
+    assert!(status.is_success());
+
+    let left = alice_ws.expect("member_left").await;
+    assert_eq!(left["user_id"], bob.id.as_str());
+    let own = bob_ws.expect("member_left").await;
+    assert_eq!(own["server_id"], server_id.as_str());
+
+    app.post_message(&alice, &channel_id, "bye bob").await;
+    alice_ws.expect("message_created").await;
+    bob_ws.expect_none("message_created").await;
+}
+
+#[tokio::test]
+async fn kicked_member_stops_receiving_events() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let addr = app.spawn().await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            &format!("/api/servers/{server_id}/members/{}", bob.id),
+            Some(&alice.token),
+            None,
+        )
+        .await;
+    assert!(status.is_success());
+    bob_ws.expect("member_left").await;
+
+    app.post_message(&alice, &channel_id, "private again").await;
+    bob_ws.expect_none("message_created").await;
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, WsClient};
+use serde_json::json;
+
+#[tokio::test]
+async fn replies_carry_a_preview_of_the_parent() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let general = app.create_channel(&alice, &server_id, "general").await;
+    let random = app.create_channel(&alice, &server_id, "random").await;
+    app.join_server(&bob, &server_id).await;
+
+    let parent = app.post_message(&alice, &general, "what's for lunch?").await;
+    let (status, reply) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{general}/messages"),
+            Some(&bob.token),
+            Some(json!({"content": "tacos", "reply_to": parent})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(reply["reply_to"]["message_id"], parent.as_str());
+    assert_eq!(reply["reply_to"]["author"]["username"], "alice");
+    assert_eq!(reply["reply_to"]["content"], "what's for lunch?");
+
+    // The parent must be in the same channel
+    let (status, body) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{random}/messages"),
+            Some(&bob.token),
+            Some(json!({"content": "tacos", "reply_to": parent})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_reply");
+
+    // Deleting the parent keeps the reference but drops the preview
+    let (status, _) = app
+        .request(Method::DELETE, &format!("/api/messages/{parent}"), Some(&alice.token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, history) = app
+        .request(Method::GET, &format!("/api/channels/{general}/messages"), Some(&bob.token), None)
+        .await;
+    // The parent is now a tombstone just before the reply
+    assert!(history[0]["deleted_at"].is_string());
+    let reply = &history.as_array().unwrap()[1];
+    assert_eq!(reply["reply_to"]["message_id"], parent.as_str());
+    assert!(reply["reply_to"]["author"].is_null());
+}
+
+#[tokio::test]
+async fn threads_have_their_own_history_participants_and_events() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+    let root = app.post_message(&alice, &channel_id, "release planning").await;
+
+    let addr = app.spawn().await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    let (status, thread) = app
+        .request(Method::POST, &format!("/api/messages/{root}/thread"), Some(&alice.token), None)
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(thread["name"], "release planning");
+    let thread_id = thread["id"].as_str().unwrap().to_string();
+    let created = bob_ws.expect("thread_created").await;
+    assert_eq!(created["thread"]["id"], thread_id.as_str());
+
+    let (status, body) = app
+        .request(Method::POST, &format!("/api/messages/{root}/thread"), Some(&bob.token), None)
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "thread_exists");
+
+    let (status, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/threads/{thread_id}/messages"),
+            Some(&bob.token),
+            Some(json!({"content": "friday works"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(message["thread_id"], thread_id.as_str());
+    let event = bob_ws.expect("thread_message_created").await;
+    assert_eq!(event["thread"]["message_count"], 1);
+    assert_eq!(event["message"]["content"], "friday works");
+    bob_ws.expect_none("message_created").await;
+
+    // Replies stay out of the channel timeline; the root shows the thread
+    let (_, history) = app
+        .request(Method::GET, &format!("/api/channels/{channel_id}/messages"), Some(&bob.token), None)
+        .await;
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["thread"]["id"], thread_id.as_str());
+    assert_eq!(history[0]["thread"]["message_count"], 1);
+
+    let (_, replies) = app
+        .request(Method::GET, &format!("/api/threads/{thread_id}/messages"), Some(&alice.token), None)
+        .await;
+    assert_eq!(replies.as_array().unwrap().len(), 1);
+
+    let (_, participants) = app
+        .request(Method::GET, &format!("/api/threads/{thread_id}/participants"), Some(&alice.token), None)
+        .await;
+    let names: Vec<_> = participants
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|u| u["username"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["alice", "bob"]);
+}
+
+#[tokio::test]
+async fn websocket_messages_can_target_a_thread() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    let root = app.post_message(&alice, &channel_id, "root").await;
+    let (_, thread) = app
+        .request(
+            Method::POST,
+            &format!("/api/messages/{root}/thread"),
+            Some(&alice.token),
+            Some(json!({"name": "side chat"})),
+        )
+        .await;
+    let thread_id = thread["id"].as_str().unwrap().to_string();
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    alice_ws
+        .send("send_message", json!({"channel_id": channel_id, "thread_id": thread_id, "content": "in thread"}))
+        .await;
+    let event = alice_ws.expect("thread_message_created").await;
+    assert_eq!(event["thread"]["name"], "side chat");
+    assert_eq!(event["message"]["content"], "in thread");
+
+    let (status, _) = app
+        .request(Method::GET, &format!("/api/threads/{thread_id}/messages"), Some(&outsider.token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
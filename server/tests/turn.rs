@@ -0,0 +1,89 @@
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestConfig};
+use serde_json::json;
+use server::routes::turn::{self, TurnConfig};
+
+fn configured() -> TurnConfig {
+    TurnConfig {
+        secret: Some("north".into()),
+        uris: turn::parse_uris("turn:turn.example.com:3478?transport=udp, turn:turn.example.com:3478?transport=tcp,turns:turn.example.com:5349, stun:turn.example.com:3478"),
+        ttl: Duration::from_secs(600),
+    }
+}
+
+#[test]
+fn credentials_follow_the_coturn_rest_scheme() {
+    // Checked against base64(HMAC-SHA1(secret, username)) computed independently
+    assert_eq!(
+        turn::credential("north", "1700000000:5f0c2f0e-0000-4000-8000-000000000001"),
+        "ibNjLy1kR40ltwc4PLeF1VCFFVs="
+    );
+
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let creds = turn::credentials_for(&configured(), "alice-id", now);
+    assert_eq!(creds.username.as_deref(), Some("1700000600:alice-id"));
+    assert_eq!(creds.credential, Some(turn::credential("north", "1700000600:alice-id")));
+    assert_eq!(creds.ttl, Some(600));
+    assert_eq!(creds.uris.len(), 4);
+
+    let uris = turn::parse_uris(" stun:a.example , http://nope.example,,turn:a.example,stun:a.example ");
+    assert_eq!(uris, ["stun:a.example", "turn:a.example"]);
+}
+
+#[tokio::test]
+async fn clients_get_short_lived_credentials_of_their_own() {
+    let app = TestApp::with_config(TestConfig {
+        turn: configured(),
+        ..Default::default()
+    });
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+
+    let (status, creds) = app.request(Method::GET, "/api/turn", Some(&alice.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let username = creds["username"].as_str().unwrap();
+    let (expiry, user_id) = username.split_once(':').unwrap();
+    assert_eq!(user_id, alice.id);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let expiry: u64 = expiry.parse().unwrap();
+    assert!((now + 590..=now + 610).contains(&expiry), "{expiry} vs {now}");
+    assert_eq!(creds["credential"], turn::credential("north", username));
+    assert_eq!(creds["ttl"], 600);
+    assert_eq!(creds["uris"][2], "turns:turn.example.com:5349");
+    assert!(!creds.to_string().contains("north"));
+
+    let (_, other) = app.request(Method::GET, "/api/turn", Some(&bob.token), None).await;
+    assert_ne!(other["credential"], creds["credential"]);
+
+    let (status, _) = app.request(Method::GET, "/api/turn", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn without_a_turn_secret_only_stun_is_offered() {
+    let app = TestApp::with_config(TestConfig {
+        turn: TurnConfig {
+            secret: None,
+            ..configured()
+        },
+        ..Default::default()
+    });
+    let alice = app.register("alice").await;
+    let (status, creds) = app.request(Method::GET, "/api/turn", Some(&alice.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        creds,
+        json!({"uris": ["stun:turn.example.com:3478"], "username": null, "credential": null, "ttl": null})
+    );
+
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let (status, creds) = app.request(Method::GET, "/api/turn", Some(&alice.token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(creds["uris"], json!([]));
+    assert!(creds["credential"].is_null());
+}
@@ -0,0 +1,147 @@
+#![cfg(feature = "embedded-turn")]
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use server::routes::turn::{credentials_for, TurnConfig};
+use server::turn_server::{parse_port_range, EmbeddedTurn, EmbeddedTurnConfig};
+use tokio::net::UdpSocket;
+use turn::client::{Client, ClientConfig};
+use webrtc_util::Conn;
+
+const SECRET: &str = "north";
+
+async fn start(config: EmbeddedTurnConfig) -> EmbeddedTurn {
+    EmbeddedTurn::start(
+        EmbeddedTurnConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            ..config
+        },
+        SECRET.into(),
+    )
+    .await
+    .unwrap()
+}
+
+fn config() -> EmbeddedTurnConfig {
+    EmbeddedTurnConfig {
+        relay_ports: (42100, 42199),
+        max_allocations_per_user: 1,
+        ..EmbeddedTurnConfig::default()
+    }
+}
+
+/// A TURN client logged in with the credentials `GET /api/turn` would give
+/// `user_id`, issued at `issued`.
+async fn client(server: &EmbeddedTurn, user_id: &str, issued: SystemTime) -> Client {
+    let turn = TurnConfig {
+        secret: Some(SECRET.into()),
+        ..TurnConfig::default()
+    };
+    let creds = credentials_for(&turn, user_id, issued);
+    let addr = server.local_addr().to_string();
+    let client = Client::new(ClientConfig {
+        stun_serv_addr: addr.clone(),
+        turn_serv_addr: addr,
+        username: creds.username.unwrap(),
+        password: creds.credential.unwrap(),
+        realm: "subspace".into(),
+        software: String::new(),
+        rto_in_ms: 0,
+        conn: Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()),
+        vnet: None,
+    })
+    .await
+    .unwrap();
+    client.listen().await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn allocations_use_shared_credentials_within_the_quota() {
+    let server = start(config()).await;
+    let metrics = &server.metrics;
+
+    // STUN needs no credentials
+    let alice = client(&server, "alice-id", SystemTime::now()).await;
+    let reflexive = alice.send_binding_request().await.unwrap();
+    assert_eq!(reflexive.ip().to_string(), "127.0.0.1");
+
+    let relay = alice.allocate().await.unwrap();
+    let relayed: SocketAddr = relay.local_addr().unwrap();
+    assert!((42100..=42199).contains(&relayed.port()), "{relayed}");
+
+    // A second relay for alice is over her quota, bob still gets his
+    let alice_again = client(&server, "alice-id", SystemTime::now()).await;
+    assert!(alice_again.allocate().await.is_err());
+    let bob = client(&server, "bob-id", SystemTime::now()).await;
+    let bob_relay = bob.allocate().await.unwrap();
+    assert_eq!(metrics.allocations_active.load(Ordering::Relaxed), 2);
+    assert_eq!(metrics.allocations_rejected.load(Ordering::Relaxed), 1);
+
+    // Expired credentials are refused
+    let expired = client(&server, "carol-id", SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60)).await;
+    assert!(expired.allocate().await.is_err());
+    assert_eq!(metrics.auth_rejected.load(Ordering::Relaxed), 1);
+
+    // Closing a relay gives it back
+    relay.close().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(metrics.allocations_active.load(Ordering::Relaxed), 1);
+    let _relay = alice_again.allocate().await.unwrap();
+    assert_eq!(metrics.allocations_total.load(Ordering::Relaxed), 3);
+
+    bob_relay.close().await.unwrap();
+    for client in [alice, alice_again, bob, expired] {
+        client.close().await.unwrap();
+    }
+    server.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn relayed_traffic_is_counted_and_capped_per_user() {
+    let server = start(EmbeddedTurnConfig {
+        relay_ports: (42200, 42299),
+        user_bandwidth: 4000,
+        ..config()
+    })
+    .await;
+    let alice = client(&server, "alice-id", SystemTime::now()).await;
+    let relay = alice.allocate().await.unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+
+    // A one second bucket lets the first 4000 bytes through
+    for _ in 0..10 {
+        relay.send_to(&[7; 1000], peer_addr).await.unwrap();
+    }
+    let mut received = 0;
+    let mut buf = [0; 1500];
+    while let Ok(Ok((n, _))) = tokio::time::timeout(Duration::from_millis(300), peer.recv_from(&mut buf)).await {
+        received += n;
+    }
+    assert_eq!(received, 4000);
+    let metrics = &server.metrics;
+    assert_eq!(metrics.relayed_bytes.load(Ordering::Relaxed), 4000);
+    assert_eq!(metrics.dropped_packets.load(Ordering::Relaxed), 6);
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("# TYPE subspace_turn_allocations gauge\nsubspace_turn_allocations 1\n"), "{rendered}");
+    assert!(rendered.contains("subspace_turn_relayed_bytes_total 4000\n"));
+    assert!(rendered.contains("subspace_turn_dropped_packets_total 6\n"));
+
+    relay.close().await.unwrap();
+    alice.close().await.unwrap();
+    server.close().await.unwrap();
+}
+
+#[test]
+fn relay_port_ranges_are_validated() {
+    assert_eq!(parse_port_range("49160-49200"), Some((49160, 49200)));
+    assert_eq!(parse_port_range(" 50000 - 50000 "), Some((50000, 50000)));
+    for range in ["49200-49160", "0-10", "49160", "a-b", "1-70000"] {
+        assert_eq!(parse_port_range(range), None, "{range}");
+    }
+}
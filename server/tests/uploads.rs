@@ -0,0 +1,537 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestConfig, WsClient};
+use serde_json::json;
+use server::routes::uploads::UploadLimits;
+
+/// The start of an MP4 file: enough for the type to be detected.
+const CLIP: &[u8] = b"\0\0\0\x10ftypisom0123";
+
+async fn is_stored(app: &TestApp, url: &str) -> bool {
+    app.state.storage.get(url.strip_prefix("/uploads/").unwrap()).await.is_ok()
+}
+
+#[tokio::test]
+async fn uploads_attach_to_channel_messages_once() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+    app.join_server(&bob, &server_id).await;
+
+    let addr = app.spawn().await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    let upload = app.upload(&alice, "notes.txt", "text/plain", b"hello").await;
+    let upload_id = upload["id"].as_str().unwrap();
+    assert_eq!(upload["size_bytes"], 5);
+
+    let (status, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{channel_id}/messages"),
+            Some(&alice.token),
+            Some(json!({"content": null, "attachment_ids": [upload_id]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{message}");
+    let attachment = &message["attachments"][0];
+    assert_eq!(attachment["id"], upload_id);
+    assert_eq!(attachment["file_name"], "notes.txt");
+    let signed = attachment["file_url"].as_str().unwrap();
+    assert!(signed.starts_with(&format!("{}?expires=", upload["url"].as_str().unwrap())));
+
+    let event = bob_ws.expect("message_created").await;
+    assert_eq!(event["message"]["attachments"][0]["id"], upload_id);
+
+    let (_, history) = app
+        .request(Method::GET, &format!("/api/channels/{channel_id}/messages"), Some(&bob.token), None)
+        .await;
+    assert_eq!(history[0]["attachments"][0]["id"], upload_id);
+
+    // An upload can only be attached once, and only by its owner
+    let bob_upload = app.upload(&bob, "b.txt", "text/plain", b"bob").await;
+    for (user, id) in [(&alice, upload_id), (&alice, bob_upload["id"].as_str().unwrap())] {
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/channels/{channel_id}/messages"),
+                Some(&user.token),
+                Some(json!({"content": "again", "attachment_ids": [id]})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_attachment");
+    }
+    // The rejected post left nothing behind
+    let (_, history) = app
+        .request(Method::GET, &format!("/api/channels/{channel_id}/messages"), Some(&bob.token), None)
+        .await;
+    assert_eq!(history.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn uploads_attach_to_dms_and_ws_messages() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    let (_, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&alice.token), Some(json!({"recipient_username": "bob"})))
+        .await;
+    let conversation_id = conversation["id"].as_str().unwrap();
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+
+    let upload = app.upload(&alice, "cat.png", "image/png", b"\x89PNG").await;
+    let (status, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/dms/{conversation_id}/messages"),
+            Some(&alice.token),
+            Some(json!({"content": "look", "attachment_ids": [upload["id"]]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{message}");
+    assert_eq!(message["attachments"][0]["id"], upload["id"]);
+    let event = bob_ws.expect("dm_message_created").await;
+    assert_eq!(event["message"]["attachments"][0]["mime_type"], "image/png");
+
+    let upload = app.upload(&alice, "dog.png", "image/png", b"\x89PNG").await;
+    alice_ws
+        .send(
+            "send_message",
+            json!({"channel_id": channel_id, "content": "", "attachment_ids": [upload["id"]]}),
+        )
+        .await;
+    let event = alice_ws.expect("message_created").await;
+    assert_eq!(event["message"]["attachments"][0]["id"], upload["id"]);
+}
+
+#[tokio::test]
+async fn orphaned_uploads_are_collected() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    let orphan = app.upload(&alice, "orphan.txt", "text/plain", b"x").await;
+    let avatar = app.upload(&alice, "me.png", "image/png", b"\x89PNG").await;
+    let (status, _) = app
+        .request(Method::PATCH, "/api/me", Some(&alice.token), Some(json!({"avatar_url": avatar["url"]})))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let orphan_url = orphan["url"].as_str().unwrap();
+    assert!(is_stored(&app, orphan_url).await);
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    server::routes::uploads::collect_orphaned_uploads(&app.state, Duration::ZERO).await;
+
+    assert!(!is_stored(&app, orphan_url).await);
+    assert!(is_stored(&app, avatar["url"].as_str().unwrap()).await);
+    let (status, body) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{channel_id}/messages"),
+            Some(&alice.token),
+            Some(json!({"content": "late", "attachment_ids": [orphan["id"]]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_attachment");
+}
+
+#[tokio::test]
+async fn channel_files_need_a_signature_or_membership() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    let upload = app.upload(&alice, "clip.mp4", "video/mp4", CLIP).await;
+    let url = upload["url"].as_str().unwrap();
+    let (_, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{channel_id}/messages"),
+            Some(&alice.token),
+            Some(json!({"content": "clip", "attachment_ids": [upload["id"]]})),
+        )
+        .await;
+    let signed = message["attachments"][0]["file_url"].as_str().unwrap();
+
+    let (status, headers, body) = app.get_raw(signed, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, CLIP);
+    assert_eq!(headers["content-type"], "video/mp4");
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert!(headers["content-disposition"].to_str().unwrap().starts_with("inline;"));
+
+    // Seeking
+    let (status, headers, body) = app.get_raw(signed, &[("range", "bytes=8-11")]).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, b"isom");
+    assert_eq!(headers["content-range"], "bytes 8-11/16");
+
+    // Flip the last hex digit of the signature
+    let (rest, last) = signed.split_at(signed.len() - 1);
+    let tampered = format!("{rest}{}", if last == "0" { "1" } else { "0" });
+    assert_ne!(tampered, signed);
+    assert_eq!(app.get_raw(&tampered, &[]).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(app.get_raw(url, &[]).await.0, StatusCode::UNAUTHORIZED);
+
+    let bearer = |user: &common::TestUser| format!("Bearer {}", user.token);
+    let (status, _, _) = app.get_raw(url, &[("authorization", &bearer(&alice))]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = app.get_raw(url, &[("authorization", &bearer(&outsider))]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get_raw("/uploads/unknown.png", &[]).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn dm_pending_and_public_files_follow_their_owner() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let outsider = app.register("outsider").await;
+    let bearer = |user: &common::TestUser| format!("Bearer {}", user.token);
+
+    let (_, conversation) = app
+        .request(Method::POST, "/api/dms", Some(&alice.token), Some(json!({"recipient_username": "bob"})))
+        .await;
+    let report = app.upload(&alice, "report.pdf", "application/pdf", b"%PDF-1.4").await;
+    let url = report["url"].as_str().unwrap();
+
+    // Pending: only the uploader
+    let (status, _, _) = app.get_raw(url, &[("authorization", &bearer(&alice))]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = app.get_raw(url, &[("authorization", &bearer(&bob))]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    app.request(
+        Method::POST,
+        &format!("/api/dms/{}/messages", conversation["id"].as_str().unwrap()),
+        Some(&alice.token),
+        Some(json!({"content": "see", "attachment_ids": [report["id"]]})),
+    )
+    .await;
+    let (status, headers, _) = app.get_raw(url, &[("authorization", &bearer(&bob))]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "application/octet-stream");
+    assert_eq!(
+        headers["content-disposition"],
+        "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+    );
+    let (status, _, _) = app.get_raw(url, &[("authorization", &bearer(&outsider))]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Avatars are public
+    let avatar = app.upload(&alice, "me.png", "image/png", b"\x89PNG").await;
+    app.request(Method::PATCH, "/api/me", Some(&alice.token), Some(json!({"avatar_url": avatar["url"]})))
+        .await;
+    let (status, headers, _) = app.get_raw(avatar["url"].as_str().unwrap(), &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "image/png");
+
+    // Pointing an avatar at an attachment does not make it public
+    app.request(Method::PATCH, "/api/me", Some(&outsider.token), Some(json!({"avatar_url": url})))
+        .await;
+    assert_eq!(app.get_raw(url, &[]).await.0, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn uploads_are_typed_by_content_and_markup_is_only_downloaded() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+
+    // The declared type and extension are ignored
+    let upload = app.upload(&alice, "innocent.html", "text/html", b"\x89PNG\r\n\x1a\n").await;
+    assert_eq!(upload["mime_type"], "image/png");
+    assert!(upload["url"].as_str().unwrap().ends_with(".png"));
+    assert_eq!(upload["file_name"], "innocent.html");
+
+    // Form fields sent before the file are skipped
+    let (status, upload) = app
+        .try_upload_with_fields(&alice, &[("caption", "hello")], "notes.txt", "text/plain", b"hello")
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!((upload["file_name"].as_str(), upload["size_bytes"].as_i64()), (Some("notes.txt"), Some(5)));
+
+    // Markup is kept as text and downloaded rather than rendered
+    let bearer = format!("Bearer {}", alice.token);
+    for (name, data) in [
+        ("page.html", &b"<!DOCTYPE html><p>hi</p>"[..]),
+        ("notes.txt", b"just text <script>alert(1)</script>"),
+        ("logo.svg", b"<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>"),
+        ("logo.png", b"<?xml version=\"1.0\"?><svg></svg>"),
+    ] {
+        let upload = app.upload(&alice, name, "image/png", data).await;
+        assert_eq!(upload["mime_type"], "text/plain", "{name}");
+        assert!(upload["url"].as_str().unwrap().ends_with(".txt"), "{name}");
+        let (status, headers, body) = app.get_raw(upload["url"].as_str().unwrap(), &[("authorization", &bearer)]).await;
+        assert_eq!(status, StatusCode::OK, "{name}");
+        assert_eq!(body, data, "{name}");
+        assert_eq!(headers["content-type"], "text/plain", "{name}");
+        assert!(headers["content-disposition"].to_str().unwrap().starts_with("attachment;"), "{name}");
+    }
+
+    let (status, body) = app.try_upload(&alice, "setup.txt", "text/plain", b"MZ\x90\0\x03\0").await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(body["code"], "unsupported_type");
+    assert_eq!(body["detected"], "application/vnd.microsoft.portable-executable");
+
+    let (status, body) = app.try_upload(&alice, "empty.txt", "text/plain", b"").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "empty_file");
+
+    // Only the accepted uploads reached the disk
+    assert_eq!(app.state.storage.list().await.unwrap().len(), 6);
+}
+
+#[tokio::test]
+async fn size_limits_and_quotas_are_enforced() {
+    let app = TestApp::with_config(TestConfig {
+        upload_limits: UploadLimits {
+            max_image_bytes: 8,
+            user_quota_bytes: 20,
+            server_quota_bytes: 15,
+            ..UploadLimits::default()
+        },
+        ..Default::default()
+    });
+    let alice = app.register("alice").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    let (status, body) = app.try_upload(&alice, "big.png", "image/png", b"\x89PNG\r\n\x1a\n!").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "file_too_large");
+    assert_eq!(body["kind"], "image");
+    assert_eq!(body["limit_bytes"], 8);
+
+    let first = app.upload(&alice, "a.txt", "text/plain", b"0123456789").await;
+    let second = app.upload(&alice, "b.txt", "text/plain", b"0123456789").await;
+    let (status, body) = app.try_upload(&alice, "c.txt", "text/plain", b"x").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "quota_exceeded");
+    assert_eq!(body["used_bytes"], 20);
+
+    let messages_path = format!("/api/channels/{channel_id}/messages");
+    let post = |id: &serde_json::Value| {
+        app.request(
+            Method::POST,
+            &messages_path,
+            Some(&alice.token),
+            Some(json!({"content": "file", "attachment_ids": [id]})),
+        )
+    };
+    let (status, _) = post(&first["id"]).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, body) = post(&second["id"]).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "server_quota_exceeded");
+
+    // Failed uploads leave nothing in storage
+    assert_eq!(app.state.storage.list().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn concurrent_uploads_stay_within_the_quota() {
+    let app = TestApp::with_config(TestConfig {
+        upload_limits: UploadLimits {
+            user_quota_bytes: 20,
+            ..UploadLimits::default()
+        },
+        ..Default::default()
+    });
+    let alice = app.register("alice").await;
+
+    // All of them pass the early check before any is recorded
+    let uploads = (0..5).map(|_| app.try_upload(&alice, "a.txt", "text/plain", b"0123456789"));
+    let statuses: Vec<StatusCode> = futures_util::future::join_all(uploads)
+        .await
+        .into_iter()
+        .map(|(status, _)| status)
+        .collect();
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::CREATED).count(), 2, "{statuses:?}");
+    assert_eq!(app.state.db.get_user_storage_bytes(&alice.id).unwrap(), 20);
+    assert_eq!(app.state.storage.list().await.unwrap().len(), 2);
+}
+
+/// A JPEG whose EXIF says to rotate it a quarter turn and names a camera
+/// owner that must not survive the upload.
+fn rotated_jpeg_with_exif() -> Vec<u8> {
+    use img_parts::{jpeg::Jpeg, Bytes, ImageEXIF};
+
+    let pixels = image::RgbImage::from_fn(200, 100, |x, y| image::Rgb([x as u8, y as u8, 128]));
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(pixels)
+        .write_to(&mut encoded, image::ImageFormat::Jpeg)
+        .unwrap();
+
+    let mut exif = b"MM\0\x2a\0\0\0\x08\0\x02".to_vec();
+    exif.extend_from_slice(b"\x01\x12\0\x03\0\0\0\x01\0\x06\0\0"); // Orientation: rotate 90
+    exif.extend_from_slice(b"\x01\x3b\0\x02\0\0\0\x04ANN\0"); // Artist
+    exif.extend_from_slice(&[0; 4]);
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(encoded.into_inner())).unwrap();
+    jpeg.set_exif(Some(Bytes::from(exif)));
+    jpeg.encoder().bytes().to_vec()
+}
+
+#[tokio::test]
+async fn images_get_dimensions_placeholders_and_thumbnails() {
+    use img_parts::{jpeg::Jpeg, Bytes, ImageEXIF};
+
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let outsider = app.register("outsider").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    let original = rotated_jpeg_with_exif();
+    let upload = app.upload(&alice, "photo.jpg", "image/jpeg", &original).await;
+    // Dimensions are reported upright
+    assert_eq!(upload["width"], 100);
+    assert_eq!(upload["height"], 200);
+    assert!(!upload["blurhash"].as_str().unwrap().is_empty());
+    // Small enough to be viewed from the original
+    assert!(upload["preview_url"].is_null());
+
+    // Identifying metadata is gone but the orientation stays
+    let key = upload["url"].as_str().unwrap().strip_prefix("/uploads/").unwrap();
+    let stored = app.state.storage.get(key).await.unwrap();
+    assert_eq!(upload["size_bytes"], stored.len());
+    let exif = Jpeg::from_bytes(Bytes::from(stored)).unwrap().exif().unwrap();
+    assert!(!exif.windows(3).any(|w| w == b"ANN"));
+    assert_eq!(
+        image::metadata::Orientation::from_exif_chunk(&exif),
+        Some(image::metadata::Orientation::Rotate90)
+    );
+
+    let (_, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{channel_id}/messages"),
+            Some(&alice.token),
+            Some(json!({"content": "look", "attachment_ids": [upload["id"]]})),
+        )
+        .await;
+    let attachment = &message["attachments"][0];
+    assert_eq!(attachment["width"], 100);
+    assert_eq!(attachment["blurhash"], upload["blurhash"]);
+
+    let thumbnail = attachment["thumbnail_url"].as_str().unwrap();
+    let (status, headers, body) = app.get_raw(thumbnail, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "image/webp");
+    let decoded = image::load_from_memory(&body).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (100, 200));
+
+    // Renditions are as private as the original
+    let unsigned = upload["thumbnail_url"].as_str().unwrap();
+    let (status, _, _) = app
+        .get_raw(unsigned, &[("authorization", &format!("Bearer {}", outsider.token))])
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// An MP4 box of type `kind` around `body`.
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// A portrait phone clip: a 1920x1080 track turned a quarter turn, 2.5 s
+/// long, with its sound track listed first.
+fn portrait_mp4() -> Vec<u8> {
+    let mut mvhd = vec![0; 100];
+    mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes()); // timescale
+    mvhd[16..20].copy_from_slice(&2500u32.to_be_bytes()); // duration
+    let track = |handler: &[u8; 4], width: u32, height: u32| {
+        let mut tkhd = vec![0; 84];
+        tkhd[44..48].copy_from_slice(&0x10000u32.to_be_bytes()); // matrix b
+        tkhd[52..56].copy_from_slice(&0xFFFF_0000u32.to_be_bytes()); // matrix c
+        tkhd[72..76].copy_from_slice(&0x4000_0000u32.to_be_bytes()); // matrix w
+        tkhd[76..80].copy_from_slice(&(width << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(height << 16).to_be_bytes());
+        let mut hdlr = vec![0; 24];
+        hdlr[8..12].copy_from_slice(handler);
+        let mdia = mp4_box(b"mdia", &mp4_box(b"hdlr", &hdlr));
+        mp4_box(b"trak", &[mp4_box(b"tkhd", &tkhd), mdia].concat())
+    };
+    let moov = [mp4_box(b"mvhd", &mvhd), track(b"soun", 0, 0), track(b"vide", 1920, 1080)].concat();
+    [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"mdat", &[0; 64]), mp4_box(b"moov", &moov)].concat()
+}
+
+/// A Matroska element, its size written in the 8-byte form.
+fn ebml(id: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut out = id.to_vec();
+    out.extend_from_slice(&(0x0100_0000_0000_0000u64 | body.len() as u64).to_be_bytes());
+    out.extend_from_slice(body);
+    out
+}
+
+/// A 320x240 WebM, 1.5 s long in 1 ms timecodes, whose segment has no size
+/// as when recorded live.
+fn live_webm() -> Vec<u8> {
+    let header = ebml(&[0x1A, 0x45, 0xDF, 0xA3], &ebml(&[0x42, 0x82], b"webm"));
+    let info = ebml(
+        &[0x15, 0x49, 0xA9, 0x66],
+        &[ebml(&[0x2A, 0xD7, 0xB1], &[0x0F, 0x42, 0x40]), ebml(&[0x44, 0x89], &1500f64.to_be_bytes())].concat(),
+    );
+    let video = ebml(&[0xE0], &[ebml(&[0xB0], &[0x01, 0x40]), ebml(&[0xBA], &[0xF0])].concat());
+    let tracks = ebml(&[0x16, 0x54, 0xAE, 0x6B], &ebml(&[0xAE], &[ebml(&[0x83], &[1]), video].concat()));
+    let cluster = ebml(&[0x1F, 0x43, 0xB6, 0x75], &[0; 32]);
+    let mut segment = vec![0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+    segment.extend([info, tracks, cluster].concat());
+    [header, segment].concat()
+}
+
+#[tokio::test]
+async fn videos_get_dimensions_and_duration() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let server_id = app.create_server(&alice, "club").await;
+    let channel_id = app.create_channel(&alice, &server_id, "general").await;
+
+    // Reported upright
+    let mp4 = app.upload(&alice, "portrait.mp4", "video/mp4", &portrait_mp4()).await;
+    assert_eq!(mp4["mime_type"], "video/mp4");
+    assert_eq!((mp4["width"].clone(), mp4["height"].clone()), (json!(1080), json!(1920)));
+    assert_eq!(mp4["duration_secs"], 2.5);
+    assert!(mp4["thumbnail_url"].is_null());
+
+    let webm = app.upload(&alice, "live.webm", "video/webm", &live_webm()).await;
+    assert_eq!(webm["mime_type"], "video/webm");
+    assert_eq!((webm["width"].clone(), webm["height"].clone()), (json!(320), json!(240)));
+    assert_eq!(webm["duration_secs"], 1.5);
+
+    // Headers that cannot be read leave the video without them
+    let truncated = app.upload(&alice, "clip.mp4", "video/mp4", CLIP).await;
+    assert!(truncated["width"].is_null());
+    assert!(truncated["duration_secs"].is_null());
+
+    let (_, message) = app
+        .request(
+            Method::POST,
+            &format!("/api/channels/{channel_id}/messages"),
+            Some(&alice.token),
+            Some(json!({"content": "clips", "attachment_ids": [mp4["id"], webm["id"]]})),
+        )
+        .await;
+    let attachments = message["attachments"].as_array().unwrap();
+    let portrait = attachments.iter().find(|a| a["id"] == mp4["id"]).unwrap();
+    assert_eq!(portrait["width"], 1080);
+    assert_eq!(portrait["duration_secs"], 2.5);
+}
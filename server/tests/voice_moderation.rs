@@ -0,0 +1,200 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestUser, WsClient};
+use serde_json::{json, Value};
+
+struct Fixture {
+    app: TestApp,
+    owner: TestUser,
+    member: TestUser,
+    moderator: TestUser,
+    server_id: String,
+    voice_id: String,
+}
+
+/// A server with a plain member and a moderator holding the mute, deafen
+/// and move permissions.
+async fn fixture() -> Fixture {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let moderator = app.register("moderator").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let voice_id = app.create_channel_of_type(&owner, &server_id, "lounge", "voice").await;
+    for user in [&member, &moderator] {
+        app.join_server(user, &server_id).await;
+    }
+    let (_, role) = app
+        .request(
+            Method::POST,
+            &format!("/api/servers/{server_id}/roles"),
+            Some(&owner.token),
+            Some(json!({"name": "Moderators", "permissions": (1 << 9) | (1 << 10) | (1 << 11)})),
+        )
+        .await;
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            &format!("/api/servers/{server_id}/members/{}/roles/{}", moderator.id, role["id"].as_str().unwrap()),
+            Some(&owner.token),
+            None,
+        )
+        .await;
+    assert!(status.is_success());
+    Fixture {
+        app,
+        owner,
+        member,
+        moderator,
+        server_id,
+        voice_id,
+    }
+}
+
+impl Fixture {
+    async fn moderate(&self, by: &TestUser, target: &TestUser, body: Value) -> (StatusCode, Value) {
+        let path = format!("/api/servers/{}/members/{}/voice", self.server_id, target.id);
+        self.app.request(Method::PATCH, &path, Some(&by.token), Some(body)).await
+    }
+
+    async fn disconnect(&self, by: &TestUser, target: &TestUser) -> (StatusCode, Value) {
+        let path = format!("/api/servers/{}/members/{}/voice", self.server_id, target.id);
+        self.app.request(Method::DELETE, &path, Some(&by.token), None).await
+    }
+
+    async fn audit_actions(&self) -> Vec<String> {
+        let path = format!("/api/servers/{}/audit_log", self.server_id);
+        let (status, entries) = self.app.request(Method::GET, &path, Some(&self.owner.token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["action"].as_str().unwrap().to_string())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn server_mute_and_deafen_are_kept_apart_from_self_flags() {
+    let f = fixture().await;
+    let addr = f.app.spawn().await;
+    let mut watcher = WsClient::connect(addr, &f.owner.token).await;
+    let mut ws = WsClient::connect(addr, &f.member.token).await;
+    ws.send("join_voice", json!({"channel_id": f.voice_id})).await;
+    watcher.expect("voice_state_update").await;
+
+    let (status, body) = f.moderate(&f.member, &f.moderator, json!({"server_muted": true})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "missing_permissions");
+    let (status, body) = f.moderate(&f.moderator, &f.owner, json!({"server_muted": true})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "cannot_moderate");
+
+    let (status, _) = f.moderate(&f.moderator, &f.member, json!({"server_muted": true})).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let state = &watcher.expect("voice_state_update").await["voice_states"][0];
+    assert_eq!((state["muted"].as_bool(), state["server_muted"].as_bool()), (Some(false), Some(true)));
+
+    // Self-mute still works, lifting it does not
+    ws.send("voice_mute_deafen", json!({"muted": true, "deafened": false})).await;
+    assert_eq!(watcher.expect("voice_state_update").await["voice_states"][0]["muted"], true);
+    ws.send("voice_mute_deafen", json!({"muted": false, "deafened": false})).await;
+    assert_eq!(ws.expect("error").await["code"], "server_muted");
+    watcher.expect_none("voice_state_update").await;
+
+    // Server deafen also keeps them muted
+    let (status, _) = f
+        .moderate(&f.moderator, &f.member, json!({"server_muted": false, "server_deafened": true}))
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let state = &watcher.expect("voice_state_update").await["voice_states"][0];
+    assert_eq!((state["server_muted"].as_bool(), state["server_deafened"].as_bool()), (Some(false), Some(true)));
+    ws.send("voice_mute_deafen", json!({"muted": false, "deafened": false})).await;
+    assert_eq!(ws.expect("error").await["code"], "server_muted");
+
+    // Server flags survive leaving and rejoining
+    ws.send("leave_voice", json!({})).await;
+    watcher.expect("voice_state_update").await;
+    ws.send("join_voice", json!({"channel_id": f.voice_id})).await;
+    assert_eq!(watcher.expect("voice_state_update").await["voice_states"][0]["server_deafened"], true);
+
+    let (status, _) = f.moderate(&f.moderator, &f.member, json!({"server_deafened": false})).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    ws.send("voice_mute_deafen", json!({"muted": false, "deafened": false})).await;
+    let state = &watcher.expect("voice_state_update").await["voice_states"][0];
+    assert_eq!((state["muted"].as_bool(), state["server_deafened"].as_bool()), (Some(false), Some(false)));
+
+    // Setting a flag to what it already is does not show up in the log
+    f.moderate(&f.moderator, &f.member, json!({"server_muted": false})).await;
+    assert_eq!(
+        f.audit_actions().await,
+        ["server_undeafen", "server_deafen", "server_unmute", "server_mute"]
+    );
+    let (status, _) = f
+        .app
+        .request(Method::GET, &format!("/api/servers/{}/audit_log", f.server_id), Some(&f.moderator.token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn moderators_move_and_disconnect_members() {
+    let f = fixture().await;
+    let text_id = f.app.create_channel(&f.owner, &f.server_id, "general").await;
+    let stage_id = f.app.create_channel_of_type(&f.owner, &f.server_id, "stage", "voice").await;
+    let addr = f.app.spawn().await;
+    let mut watcher = WsClient::connect(addr, &f.owner.token).await;
+    let mut ws = WsClient::connect(addr, &f.member.token).await;
+
+    let (status, body) = f.moderate(&f.moderator, &f.member, json!({"channel_id": stage_id})).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "not_in_voice");
+
+    ws.send("join_voice", json!({"channel_id": f.voice_id})).await;
+    watcher.expect("voice_state_update").await;
+    ws.send("voice_mute_deafen", json!({"muted": true, "deafened": false})).await;
+    watcher.expect("voice_state_update").await;
+
+    let (status, body) = f.moderate(&f.moderator, &f.member, json!({"channel_id": text_id})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "not_voice_channel");
+    let (status, _) = f.moderate(&f.member, &f.moderator, json!({"channel_id": stage_id})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = f.moderate(&f.moderator, &f.member, json!({"channel_id": stage_id})).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(ws.expect("voice_moved").await["channel_id"], stage_id.as_str());
+    let left = watcher.expect("voice_state_update").await;
+    assert_eq!((left["channel_id"].as_str(), left["voice_states"].clone()), (Some(f.voice_id.as_str()), json!([])));
+    let joined = watcher.expect("voice_state_update").await;
+    assert_eq!(joined["channel_id"], stage_id.as_str());
+    // Self flags come along
+    assert_eq!(joined["voice_states"][0]["muted"], true);
+
+    let (status, _) = f.disconnect(&f.member, &f.moderator).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = f.disconnect(&f.moderator, &f.member).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let moved = ws.expect("voice_moved").await;
+    assert_eq!((moved["channel_id"].clone(), moved["moved_by"].as_str()), (Value::Null, Some(f.moderator.id.as_str())));
+    assert_eq!(watcher.expect("voice_state_update").await["voice_states"], json!([]));
+    assert_eq!(f.app.state.db.get_user_voice_channel(&f.member.id).unwrap(), None);
+    let (status, body) = f.disconnect(&f.moderator, &f.member).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "not_in_voice");
+
+    let path = format!("/api/servers/{}/audit_log?limit=1", f.server_id);
+    let (_, entries) = f.app.request(Method::GET, &path, Some(&f.owner.token), None).await;
+    assert_eq!(entries[0]["action"], "voice_disconnect");
+    assert_eq!(entries[0]["channel_id"], stage_id.as_str());
+    let path = format!("/api/servers/{}/audit_log?before={}", f.server_id, entries[0]["id"].as_str().unwrap());
+    let (_, entries) = f.app.request(Method::GET, &path, Some(&f.owner.token), None).await;
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["action"], "voice_move");
+    assert_eq!(entries[0]["actor_id"], f.moderator.id.as_str());
+    assert_eq!(entries[0]["target_user_id"], f.member.id.as_str());
+    assert_eq!(entries[0]["channel_id"], f.voice_id.as_str());
+    assert_eq!(entries[0]["to_channel_id"], stage_id.as_str());
+}
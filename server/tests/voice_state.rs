@@ -0,0 +1,95 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{TestApp, TestConfig, WsClient};
+use serde_json::json;
+use server::ws::Heartbeat;
+
+#[tokio::test]
+async fn voice_follows_the_session_that_joined() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+    let voice_id = app.create_channel_of_type(&owner, &server_id, "lounge", "voice").await;
+    let addr = app.spawn().await;
+    let mut watcher = WsClient::connect(addr, &owner.token).await;
+
+    let mut desktop = WsClient::connect(addr, &member.token).await;
+    let phone = WsClient::connect(addr, &member.token).await;
+    desktop.send("join_voice", json!({"channel_id": voice_id})).await;
+    let update = watcher.expect("voice_state_update").await;
+    assert_eq!(update["voice_states"][0]["user_id"], member.id);
+
+    // Closing a session that is not in voice leaves the call alone
+    drop(phone);
+    watcher.expect_none("voice_state_update").await;
+    assert_eq!(app.state.db.get_user_voice_channel(&member.id).unwrap(), Some(voice_id.clone()));
+
+    // ...and so does another session hanging up
+    let mut tablet = WsClient::connect(addr, &member.token).await;
+    tablet.send("leave_voice", json!({})).await;
+    watcher.expect_none("voice_state_update").await;
+    assert_eq!(app.state.db.get_user_voice_channel(&member.id).unwrap(), Some(voice_id.clone()));
+    drop(tablet);
+
+    // Joining from another session hands the call over to it
+    let mut phone = WsClient::connect(addr, &member.token).await;
+    phone.send("join_voice", json!({"channel_id": voice_id})).await;
+    watcher.expect("voice_state_update").await;
+    drop(desktop);
+    watcher.expect_none("voice_state_update").await;
+
+    drop(phone);
+    let update = watcher.expect("voice_state_update").await;
+    assert_eq!(update["channel_id"], voice_id);
+    assert_eq!(update["voice_states"], json!([]));
+}
+
+#[tokio::test]
+async fn silent_sessions_time_out_of_voice() {
+    let app = TestApp::with_config(TestConfig {
+        heartbeat: Heartbeat {
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(500),
+        },
+        ..Default::default()
+    });
+    let owner = app.register("owner").await;
+    let member = app.register("member").await;
+    let server_id = app.create_server(&owner, "club").await;
+    app.join_server(&member, &server_id).await;
+    let voice_id = app.create_channel_of_type(&owner, &server_id, "lounge", "voice").await;
+    let addr = app.spawn().await;
+    let mut watcher = WsClient::connect(addr, &owner.token).await;
+
+    // Never reads again, so never answers a ping
+    let mut frozen = WsClient::connect(addr, &member.token).await;
+    frozen.send("join_voice", json!({"channel_id": voice_id})).await;
+    assert_eq!(watcher.expect("voice_state_update").await["voice_states"][0]["user_id"], member.id);
+
+    // The watcher keeps answering pings and stays in
+    let update = watcher.expect("voice_state_update").await;
+    assert_eq!(update["voice_states"], json!([]));
+    assert_eq!(app.state.db.get_user_voice_channel(&member.id).unwrap(), None);
+    watcher.send("join_voice", json!({"channel_id": voice_id})).await;
+    assert_eq!(watcher.expect("voice_state_update").await["voice_states"][0]["user_id"], owner.id);
+    drop(frozen);
+}
+
+#[tokio::test]
+async fn voice_states_from_the_last_run_are_cleared() {
+    let app = TestApp::new();
+    let owner = app.register("owner").await;
+    let server_id = app.create_server(&owner, "club").await;
+    let voice_id = app.create_channel_of_type(&owner, &server_id, "lounge", "voice").await;
+    let mut ws = WsClient::connect(app.spawn().await, &owner.token).await;
+    ws.send("join_voice", json!({"channel_id": voice_id})).await;
+    ws.expect("voice_state_update").await;
+
+    assert_eq!(app.state.db.clear_voice_states().unwrap(), 1);
+    assert!(app.state.db.get_voice_states_for_channel(&voice_id).unwrap().is_empty());
+    assert_eq!(app.state.db.clear_voice_states().unwrap(), 0);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use axum::http::Method;
+use common::{TestApp, WsClient};
+use serde_json::json;
+
+#[tokio::test]
+async fn signaling_reaches_only_the_target() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+    let server_id = app.create_server(&alice, "voice").await;
+    let voice_id = app
+        .create_channel_of_type(&alice, &server_id, "lounge", "voice")
+        .await;
+    app.join_server(&bob, &server_id).await;
+    app.join_server(&carol, &server_id).await;
+
+    let addr = app.spawn().await;
+    let mut alice_ws = WsClient::connect(addr, &alice.token).await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+    let mut carol_ws = WsClient::connect(addr, &carol.token).await;
+
+    alice_ws.send("join_voice", json!({"channel_id": voice_id})).await;
+    alice_ws.expect("voice_state_update").await;
+    bob_ws.send("join_voice", json!({"channel_id": voice_id})).await;
+    bob_ws.expect("voice_state_update").await;
+
+    alice_ws
+        .send(
+            "signal_sdp",
+            json!({"target_user_id": bob.id, "sdp": "v=0", "sdp_type": "offer"}),
+        )
+        .await;
+    let relay = bob_ws.expect("signal_sdp").await;
+    assert_eq!(relay["from_user_id"], alice.id.as_str());
+
+    alice_ws
+        .send(
+            "signal_ice",
+            json!({"target_user_id": bob.id, "candidate": "candidate:1", "sdp_mid": "0", "sdp_mline_index": 0}),
+        )
+        .await;
+    bob_ws.expect("signal_ice").await;
+
+    // Carol is a server member but not in voice: she sees neither, and
+    // signaling aimed at her is dropped
+    carol_ws.expect_none("signal_sdp").await;
+    alice_ws
+        .send(
+            "signal_sdp",
+            json!({"target_user_id": carol.id, "sdp": "v=0", "sdp_type": "offer"}),
+        )
+        .await;
+    carol_ws.expect_none("signal_sdp").await;
+    alice_ws.expect_none("signal_sdp").await;
+}
+
+#[tokio::test]
+async fn dm_events_reach_each_session_of_participants_once() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    let carol = app.register("carol").await;
+
+    // Shared servers used to cause one copy per server
+    for name in ["one", "two"] {
+        let server_id = app.create_server(&alice, name).await;
+        app.join_server(&bob, &server_id).await;
+        app.join_server(&carol, &server_id).await;
+    }
+
+    let (_, conversation) = app
+        .request(
+            Method::POST,
+            "/api/dms",
+            Some(&alice.token),
+            Some(json!({"recipient_username": "bob"})),
+        )
+        .await;
+    let conversation_id = conversation["id"].as_str().unwrap().to_string();
+
+    let addr = app.spawn().await;
+    let mut bob_desktop = WsClient::connect(addr, &bob.token).await;
+    let mut bob_phone = WsClient::connect(addr, &bob.token).await;
+    let mut carol_ws = WsClient::connect(addr, &carol.token).await;
+
+    app.request(
+        Method::POST,
+        &format!("/api/dms/{conversation_id}/messages"),
+        Some(&alice.token),
+        Some(json!({"content": "psst"})),
+    )
+    .await;
+
+    for ws in [&mut bob_desktop, &mut bob_phone] {
+        let event = ws.expect("dm_message_created").await;
+        assert_eq!(event["message"]["content"], "psst");
+        ws.expect_none("dm_message_created").await;
+    }
+    carol_ws.expect_none("dm_message_created").await;
+}
+
+#[tokio::test]
+async fn status_update_is_delivered_once_per_session() {
+    let app = TestApp::new();
+    let alice = app.register("alice").await;
+    let bob = app.register("bob").await;
+    for name in ["one", "two", "three"] {
+        let server_id = app.create_server(&alice, name).await;
+        app.join_server(&bob, &server_id).await;
+    }
+
+    let addr = app.spawn().await;
+    let mut bob_ws = WsClient::connect(addr, &bob.token).await;
+    let _alice_ws = WsClient::connect(addr, &alice.token).await;
+
+    let mut copies = 0;
+    while let Some(env) = bob_ws.recv_within(std::time::Duration::from_millis(500)).await {
+        if env["type"] == "user_status_update" && env["payload"]["user_id"] == alice.id.as_str() {
+            copies += 1;
+        }
+    }
+    assert_eq!(copies, 1);
+}
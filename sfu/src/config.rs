@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+
+/// Settings of the SFU process, read from the environment.
+#[derive(Debug, Clone)]
+pub struct SfuConfig {
+    pub bind: SocketAddr,
+    /// Signs the tokens the server hands out; the server's `SFU_SECRET`
+    pub secret: String,
+    /// Address clients reach the SFU's media ports on when it runs behind
+    /// NAT, advertised instead of the host candidates
+    pub public_ip: Option<String>,
+    /// UDP ports media goes over, inclusive. `None` picks any free port.
+    pub udp_ports: Option<(u16, u16)>,
+}
+
+impl SfuConfig {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3002)),
+            secret: secret.into(),
+            public_ip: None,
+            udp_ports: None,
+        }
+    }
+
+    /// Reads `SFU_SECRET`, which is required, `SFU_BIND_ADDR`,
+    /// `SFU_PUBLIC_IP` and `SFU_UDP_PORTS` (e.g. `50000-50100`).
+    pub fn from_env() -> Result<Self, String> {
+        let secret = std::env::var("SFU_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .ok_or("SFU_SECRET must be set to the server's SFU_SECRET")?;
+        let mut config = Self::new(secret);
+        if let Ok(bind) = std::env::var("SFU_BIND_ADDR") {
+            config.bind = bind.parse().map_err(|_| format!("SFU_BIND_ADDR is not an address: {bind}"))?;
+        }
+        config.public_ip = std::env::var("SFU_PUBLIC_IP").ok().filter(|ip| !ip.is_empty());
+        if let Ok(ports) = std::env::var("SFU_UDP_PORTS") {
+            config.udp_ports = Some(parse_port_range(&ports).ok_or(format!("SFU_UDP_PORTS is not a port range: {ports}"))?);
+        }
+        Ok(config)
+    }
+}
+
+/// Parses `min-max`, both ends inclusive and non-zero.
+pub fn parse_port_range(range: &str) -> Option<(u16, u16)> {
+    let (min, max) = range.split_once('-')?;
+    let (min, max) = (min.trim().parse::<u16>().ok()?, max.trim().parse::<u16>().ok()?);
+    (min > 0 && min <= max).then_some((min, max))
+}
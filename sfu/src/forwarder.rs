@@ -0,0 +1,160 @@
+//! Picks which simulcast layer of a track a subscriber gets and rewrites
+//! packets so that switching layers looks like one continuous stream.
+
+use shared::sfu::SimulcastLayer;
+use webrtc::rtp::header::Header;
+
+/// Index of the layer a publisher sends under `rid`: `l`, `m` and `h` for
+/// simulcast, nothing for a track with a single layer.
+pub fn layer_index(rid: &str) -> Option<usize> {
+    match rid {
+        "" | "l" => Some(0),
+        "m" => Some(1),
+        "h" => Some(2),
+        _ => None,
+    }
+}
+
+fn index_of(layer: SimulcastLayer) -> usize {
+    match layer {
+        SimulcastLayer::Low => 0,
+        SimulcastLayer::Medium => 1,
+        SimulcastLayer::High => 2,
+    }
+}
+
+/// The best of `available` layers no better than `desired`, or the lowest
+/// one if all are better.
+pub fn select_layer(desired: SimulcastLayer, available: &[usize]) -> Option<usize> {
+    let desired = index_of(desired);
+    available
+        .iter()
+        .copied()
+        .filter(|layer| *layer <= desired)
+        .max()
+        .or_else(|| available.iter().copied().min())
+}
+
+/// Whether an RTP payload starts a frame decodable on its own. Codecs this
+/// does not know are assumed to be, so their single layer still plays.
+pub fn is_keyframe(mime_type: &str, payload: &[u8]) -> bool {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "video/vp8" => vp8_keyframe(payload),
+        "video/h264" => h264_keyframe(payload),
+        _ => true,
+    }
+}
+
+fn vp8_keyframe(payload: &[u8]) -> bool {
+    // RFC 7741 payload descriptor, then the VP8 frame header
+    let Some(&first) = payload.first() else { return false };
+    let start_of_partition = first & 0x10 != 0 && first & 0x0f == 0;
+    let mut offset = 1;
+    if first & 0x80 != 0 {
+        let Some(&extensions) = payload.get(1) else { return false };
+        offset += 1;
+        if extensions & 0x80 != 0 {
+            // Picture id, 15 bits long when M is set
+            let long = payload.get(offset).is_some_and(|b| b & 0x80 != 0);
+            offset += if long { 2 } else { 1 };
+        }
+        if extensions & 0x40 != 0 {
+            offset += 1;
+        }
+        if extensions & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+    start_of_partition && payload.get(offset).is_some_and(|b| b & 0x01 == 0)
+}
+
+fn h264_keyframe(payload: &[u8]) -> bool {
+    const IDR: u8 = 5;
+    const SPS: u8 = 7;
+    let Some(&first) = payload.first() else { return false };
+    match first & 0x1f {
+        IDR | SPS => true,
+        // STAP-A: length-prefixed NAL units
+        24 => {
+            let mut offset = 1;
+            while let (Some(&hi), Some(&lo)) = (payload.get(offset), payload.get(offset + 1)) {
+                let size = usize::from(u16::from_be_bytes([hi, lo]));
+                if payload.get(offset + 2).is_some_and(|nal| matches!(nal & 0x1f, IDR | SPS)) {
+                    return true;
+                }
+                offset += 2 + size;
+            }
+            false
+        }
+        // FU-A: the first fragment carries the NAL type
+        28 => payload.get(1).is_some_and(|fu| fu & 0x80 != 0 && matches!(fu & 0x1f, IDR | SPS)),
+        _ => false,
+    }
+}
+
+/// Per-subscriber state of one forwarded track.
+#[derive(Debug, Default)]
+pub struct Forwarder {
+    /// Layer being forwarded, `None` until the first keyframe
+    current: Option<usize>,
+    /// Layer to switch to at its next keyframe
+    target: Option<usize>,
+    seq_offset: u16,
+    ts_offset: u32,
+    last_seq: u16,
+    last_ts: u32,
+}
+
+/// Timestamp step put between the last packet of one layer and the first
+/// of the next: one frame at 30 fps on the 90 kHz video clock.
+const SWITCH_TS_STEP: u32 = 3000;
+
+impl Forwarder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Sets the layer to forward. Returns true while the forwarder waits
+    /// for a keyframe on it, which the caller should ask the publisher for.
+    pub fn set_target(&mut self, layer: usize) -> bool {
+        self.target = Some(layer);
+        self.current != self.target
+    }
+
+    /// Decides whether a packet of `layer` goes to the subscriber and, if
+    /// so, rewrites its sequence number and timestamp. Switches to the
+    /// target layer on its first keyframe; until then the current layer
+    /// keeps flowing.
+    pub fn forward(&mut self, layer: usize, header: &mut Header, keyframe: bool) -> bool {
+        if self.target.is_none() {
+            self.target = Some(layer);
+        }
+        if Some(layer) != self.current {
+            if Some(layer) != self.target || !keyframe {
+                return false;
+            }
+            if self.current.is_some() {
+                self.seq_offset = self.last_seq.wrapping_add(1).wrapping_sub(header.sequence_number);
+                self.ts_offset = self.last_ts.wrapping_add(SWITCH_TS_STEP).wrapping_sub(header.timestamp);
+            } else {
+                self.seq_offset = 0;
+                self.ts_offset = 0;
+                self.last_seq = header.sequence_number.wrapping_sub(1);
+                self.last_ts = header.timestamp;
+            }
+            self.current = Some(layer);
+        }
+        header.sequence_number = header.sequence_number.wrapping_add(self.seq_offset);
+        header.timestamp = header.timestamp.wrapping_add(self.ts_offset);
+        // Late packets must not move the switch point backwards
+        if header.sequence_number.wrapping_sub(self.last_seq) < 0x8000 {
+            self.last_seq = header.sequence_number;
+            self.last_ts = header.timestamp;
+        }
+        true
+    }
+}
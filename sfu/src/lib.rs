@@ -0,0 +1,107 @@
+//! Selective forwarding unit for voice channels in SFU mode: every client
+//! sends its audio and video here once, and the SFU forwards it to the
+//! rest of the channel, picking a simulcast layer per receiver.
+
+pub mod config;
+pub mod forwarder;
+pub mod room;
+pub mod session;
+pub mod speaker;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{routing::get, Router};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::{APIBuilder, API};
+use webrtc::ice::udp_network::{EphemeralUDP, UDPNetwork};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpHeaderExtensionCapability, RTPCodecType};
+use webrtc::sdp::extmap::{AUDIO_LEVEL_URI, SDES_MID_URI, SDES_RTP_STREAM_ID_URI};
+
+use crate::config::SfuConfig;
+use crate::room::Room;
+
+/// Carries the rid of simulcast retransmissions
+const SDES_REPAIRED_RTP_STREAM_ID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id";
+
+pub struct Sfu {
+    pub config: SfuConfig,
+    api: API,
+    rooms: Mutex<HashMap<String, Arc<Room>>>,
+}
+
+impl Sfu {
+    pub fn new(config: SfuConfig) -> Result<Arc<Self>, webrtc::Error> {
+        let mut media = MediaEngine::default();
+        media.register_default_codecs()?;
+        // Simulcast layers are told apart by these
+        for uri in [SDES_MID_URI, SDES_RTP_STREAM_ID_URI, SDES_REPAIRED_RTP_STREAM_ID_URI] {
+            media.register_header_extension(RTCRtpHeaderExtensionCapability { uri: uri.to_string() }, RTPCodecType::Video, None)?;
+        }
+        media.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: AUDIO_LEVEL_URI.to_string(),
+            },
+            RTPCodecType::Audio,
+            None,
+        )?;
+        let registry = register_default_interceptors(Registry::new(), &mut media)?;
+
+        let mut settings = SettingEngine::default();
+        if let Some(ip) = &config.public_ip {
+            settings.set_nat_1to1_ips(vec![ip.clone()], RTCIceCandidateType::Host);
+        }
+        if let Some((min, max)) = config.udp_ports {
+            settings.set_udp_network(UDPNetwork::Ephemeral(EphemeralUDP::new(min, max)?));
+        }
+
+        let api = APIBuilder::new()
+            .with_media_engine(media)
+            .with_interceptor_registry(registry)
+            .with_setting_engine(settings)
+            .build();
+        Ok(Arc::new(Self {
+            config,
+            api,
+            rooms: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    pub async fn new_peer_connection(&self) -> Result<Arc<RTCPeerConnection>, webrtc::Error> {
+        Ok(Arc::new(self.api.new_peer_connection(RTCConfiguration::default()).await?))
+    }
+
+    /// The room for a voice channel, created on first use.
+    pub fn room(&self, id: &str) -> Arc<Room> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_insert_with(|| Room::new(id.to_string()))
+            .clone()
+    }
+
+    /// Drops `room` once its last participant is gone.
+    pub fn release(&self, room: &Arc<Room>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if room.is_empty() && rooms.get(&room.id).is_some_and(|r| Arc::ptr_eq(r, room)) {
+            rooms.remove(&room.id);
+            room.close();
+        }
+    }
+
+    pub fn room_count(&self) -> usize {
+        self.rooms.lock().unwrap().len()
+    }
+}
+
+/// Signaling WebSocket at `/rtc`.
+pub fn app(sfu: Arc<Sfu>) -> Router {
+    Router::new().route("/rtc", get(session::ws_handler)).with_state(sfu)
+}
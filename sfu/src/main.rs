@@ -0,0 +1,21 @@
+use sfu::config::SfuConfig;
+use sfu::Sfu;
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    let config = SfuConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let bind = config.bind;
+    let sfu = Sfu::new(config).expect("Failed to set up WebRTC");
+
+    let listener = tokio::net::TcpListener::bind(bind).await.expect("Failed to bind");
+    tracing::info!("SFU listening on {bind}");
+    axum::serve(listener, sfu::app(sfu)).await.expect("Server error");
+}
@@ -0,0 +1,574 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use shared::sfu::{SfuCandidate, SfuDescription, SfuParticipantLeft, SfuTarget, SimulcastLayer};
+use shared::ws_messages::{WsEnvelope, WsError};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTPCodecType};
+use webrtc::rtp_transceiver::rtp_receiver::RTCRtpReceiver;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::sdp::extmap::AUDIO_LEVEL_URI;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+use webrtc::track::track_remote::TrackRemote;
+
+use crate::forwarder::{is_keyframe, layer_index, select_layer, Forwarder};
+use crate::speaker::SpeakerDetector;
+
+/// Outbound signaling queue of a participant's WebSocket.
+pub type Outbox = mpsc::UnboundedSender<WsEnvelope>;
+
+/// How often speakers and layers are re-evaluated
+const TICK: Duration = Duration::from_millis(250);
+/// Minimum gap between keyframe requests for the same layer
+const KEYFRAME_INTERVAL: Duration = Duration::from_millis(500);
+/// Rooms with up to this many video publishers get everyone's high layer
+const SMALL_ROOM_VIDEO: usize = 3;
+
+pub fn envelope(msg_type: &str, payload: impl Serialize) -> WsEnvelope {
+    WsEnvelope {
+        msg_type: msg_type.to_string(),
+        payload: serde_json::to_value(payload).unwrap(),
+    }
+}
+
+pub fn error(message: &str, code: &str) -> WsEnvelope {
+    envelope(
+        "error",
+        WsError {
+            message: message.to_string(),
+            code: Some(code.to_string()),
+        },
+    )
+}
+
+/// Offer/answer state of the subscriber connection, where the SFU offers.
+/// Changes made while an offer is out are sent as one more offer once it
+/// is answered.
+#[derive(Debug, Default)]
+struct Negotiation {
+    awaiting_answer: bool,
+    pending: bool,
+}
+
+/// One client in a room, with the connection it publishes on and the one
+/// it receives everyone else on.
+pub struct Participant {
+    pub user_id: Uuid,
+    /// Tells a user's connection apart from the one replacing it
+    pub session: Uuid,
+    pub publish: bool,
+    outbox: Outbox,
+    pub publisher: Arc<RTCPeerConnection>,
+    pub subscriber: Arc<RTCPeerConnection>,
+    negotiation: tokio::sync::Mutex<Negotiation>,
+    /// Layers asked for with `set_layer`, by publisher
+    layer_prefs: Mutex<HashMap<Uuid, SimulcastLayer>>,
+}
+
+impl Participant {
+    pub fn new(
+        user_id: Uuid,
+        publish: bool,
+        outbox: Outbox,
+        publisher: Arc<RTCPeerConnection>,
+        subscriber: Arc<RTCPeerConnection>,
+    ) -> Self {
+        for (pc, target) in [(&publisher, SfuTarget::Publisher), (&subscriber, SfuTarget::Subscriber)] {
+            let outbox = outbox.clone();
+            pc.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                if let Some(init) = candidate.and_then(|c| c.to_json().ok()) {
+                    let _ = outbox.send(envelope(
+                        "candidate",
+                        SfuCandidate {
+                            target,
+                            candidate: init.candidate,
+                            sdp_mid: init.sdp_mid,
+                            sdp_mline_index: init.sdp_mline_index,
+                        },
+                    ));
+                }
+                Box::pin(async {})
+            }));
+        }
+        Self {
+            user_id,
+            session: Uuid::new_v4(),
+            publish,
+            outbox,
+            publisher,
+            subscriber,
+            negotiation: tokio::sync::Mutex::new(Negotiation::default()),
+            layer_prefs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn send(&self, message: WsEnvelope) {
+        let _ = self.outbox.send(message);
+    }
+
+    /// Answers an offer on the publisher connection.
+    pub async fn answer_publisher(&self, sdp: String) -> Result<String, webrtc::Error> {
+        self.publisher.set_remote_description(RTCSessionDescription::offer(sdp)?).await?;
+        let answer = self.publisher.create_answer(None).await?;
+        self.publisher.set_local_description(answer.clone()).await?;
+        Ok(answer.sdp)
+    }
+
+    /// Offers the subscriber connection's current tracks, or queues
+    /// another offer if one is still unanswered.
+    pub async fn negotiate(&self) {
+        let mut negotiation = self.negotiation.lock().await;
+        if negotiation.awaiting_answer {
+            negotiation.pending = true;
+            return;
+        }
+        let offer = match self.subscriber.create_offer(None).await {
+            Ok(offer) => offer,
+            Err(e) => {
+                tracing::warn!("Failed to create an offer for {}: {e}", self.user_id);
+                return;
+            }
+        };
+        if let Err(e) = self.subscriber.set_local_description(offer.clone()).await {
+            tracing::warn!("Failed to apply the offer for {}: {e}", self.user_id);
+            return;
+        }
+        negotiation.awaiting_answer = true;
+        self.send(envelope(
+            "offer",
+            SfuDescription {
+                target: SfuTarget::Subscriber,
+                sdp: offer.sdp,
+            },
+        ));
+    }
+
+    pub async fn accept_subscriber_answer(&self, sdp: String) -> Result<(), webrtc::Error> {
+        self.subscriber.set_remote_description(RTCSessionDescription::answer(sdp)?).await?;
+        let again = {
+            let mut negotiation = self.negotiation.lock().await;
+            negotiation.awaiting_answer = false;
+            std::mem::take(&mut negotiation.pending)
+        };
+        if again {
+            self.negotiate().await;
+        }
+        Ok(())
+    }
+
+    pub fn set_layer(&self, publisher: Uuid, layer: Option<SimulcastLayer>) {
+        let mut prefs = self.layer_prefs.lock().unwrap();
+        match layer {
+            Some(layer) => prefs.insert(publisher, layer),
+            None => prefs.remove(&publisher),
+        };
+    }
+
+    fn layer_pref(&self, publisher: Uuid) -> Option<SimulcastLayer> {
+        self.layer_prefs.lock().unwrap().get(&publisher).copied()
+    }
+
+    async fn close(&self) {
+        for pc in [&self.publisher, &self.subscriber] {
+            if let Err(e) = pc.close().await {
+                tracing::debug!("Closing a peer connection of {} failed: {e}", self.user_id);
+            }
+        }
+    }
+}
+
+/// A track of one subscriber's connection fed from a publication.
+struct DownTrack {
+    track: Arc<TrackLocalStaticRTP>,
+    sender: Arc<RTCRtpSender>,
+    forwarder: Mutex<Forwarder>,
+}
+
+/// A track a participant publishes, with its simulcast layers and
+/// everyone it is forwarded to.
+struct Publication {
+    publisher: Uuid,
+    track_id: String,
+    kind: RTPCodecType,
+    codec: RTCRtpCodecCapability,
+    publisher_pc: Arc<RTCPeerConnection>,
+    /// Layer index -> SSRC the publisher sends it with
+    layers: Mutex<HashMap<usize, u32>>,
+    /// By subscriber
+    downtracks: Mutex<HashMap<Uuid, Arc<DownTrack>>>,
+    last_keyframe_request: Mutex<HashMap<usize, Instant>>,
+}
+
+impl Publication {
+    fn available_layers(&self) -> Vec<usize> {
+        let mut layers: Vec<usize> = self.layers.lock().unwrap().keys().copied().collect();
+        layers.sort_unstable();
+        layers
+    }
+
+    fn downtracks(&self) -> Vec<(Uuid, Arc<DownTrack>)> {
+        self.downtracks.lock().unwrap().iter().map(|(id, dt)| (*id, dt.clone())).collect()
+    }
+
+    /// Asks the publisher for a keyframe on `layer`, at most once every
+    /// `KEYFRAME_INTERVAL`.
+    async fn request_keyframe(&self, layer: usize) {
+        let Some(ssrc) = self.layers.lock().unwrap().get(&layer).copied() else { return };
+        {
+            let now = Instant::now();
+            let mut last = self.last_keyframe_request.lock().unwrap();
+            if last.get(&layer).is_some_and(|at| now.duration_since(*at) < KEYFRAME_INTERVAL) {
+                return;
+            }
+            last.insert(layer, now);
+        }
+        let pli = PictureLossIndication {
+            sender_ssrc: 0,
+            media_ssrc: ssrc,
+        };
+        if let Err(e) = self.publisher_pc.write_rtcp(&[Box::new(pli)]).await {
+            tracing::debug!("Failed to request a keyframe from {}: {e}", self.publisher);
+        }
+    }
+}
+
+/// Everyone connected to one voice channel.
+pub struct Room {
+    pub id: String,
+    participants: Mutex<HashMap<Uuid, Arc<Participant>>>,
+    publications: Mutex<HashMap<(Uuid, String), Arc<Publication>>>,
+    speakers: Mutex<SpeakerDetector>,
+    /// Serialises joins, leaves and (un)publishing so every subscriber
+    /// ends up with each publication exactly once
+    changes: tokio::sync::Mutex<()>,
+    closed: AtomicBool,
+}
+
+impl Room {
+    /// Creates a room and starts its speaker and layer ticker, which runs
+    /// until the room is closed.
+    pub fn new(id: String) -> Arc<Self> {
+        let room = Arc::new(Self {
+            id,
+            participants: Mutex::new(HashMap::new()),
+            publications: Mutex::new(HashMap::new()),
+            speakers: Mutex::new(SpeakerDetector::new()),
+            changes: tokio::sync::Mutex::new(()),
+            closed: AtomicBool::new(false),
+        });
+        tokio::spawn(run_ticker(Arc::downgrade(&room)));
+        room
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.participants.lock().unwrap().is_empty()
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    fn participant(&self, user_id: Uuid) -> Option<Arc<Participant>> {
+        self.participants.lock().unwrap().get(&user_id).cloned()
+    }
+
+    fn broadcast(&self, message: &WsEnvelope, except: Option<Uuid>) {
+        for participant in self.participants.lock().unwrap().values() {
+            if Some(participant.user_id) != except {
+                participant.send(message.clone());
+            }
+        }
+    }
+
+    /// Adds `participant`, replacing the user's previous connection, and
+    /// sends it everything already published.
+    pub async fn join(self: &Arc<Self>, participant: Arc<Participant>) {
+        if let Some(previous) = self.participant(participant.user_id) {
+            previous.send(error("Connected from somewhere else", "replaced"));
+            self.leave(participant.user_id, previous.session).await;
+        }
+        let room = Arc::downgrade(self);
+        let user_id = participant.user_id;
+        let publisher_pc = Arc::downgrade(&participant.publisher);
+        participant.publisher.on_track(Box::new(
+            move |track: Option<Arc<TrackRemote>>, receiver: Option<Arc<RTCRtpReceiver>>| {
+                if let (Some(room), Some(publisher_pc), Some(track), Some(receiver)) =
+                    (room.upgrade(), publisher_pc.upgrade(), track, receiver)
+                {
+                    tokio::spawn(async move { room.publish(user_id, publisher_pc, track, receiver).await });
+                }
+                Box::pin(async {})
+            },
+        ));
+
+        let _changes = self.changes.lock().await;
+        self.participants.lock().unwrap().insert(user_id, participant.clone());
+        let publications: Vec<Arc<Publication>> = self.publications.lock().unwrap().values().cloned().collect();
+        let mut attached = false;
+        for publication in publications.iter().filter(|p| p.publisher != user_id) {
+            attached |= self.attach(&participant, publication).await;
+        }
+        if attached {
+            participant.negotiate().await;
+        }
+    }
+
+    /// Removes the participant if `session` is still the user's current
+    /// one, along with what it published.
+    pub async fn leave(&self, user_id: Uuid, session: Uuid) {
+        let _changes = self.changes.lock().await;
+        let participant = {
+            let mut participants = self.participants.lock().unwrap();
+            match participants.get(&user_id) {
+                Some(p) if p.session == session => participants.remove(&user_id).unwrap(),
+                _ => return,
+            }
+        };
+        self.speakers.lock().unwrap().remove(user_id);
+        let (theirs, others): (Vec<_>, Vec<_>) = self
+            .publications
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .partition(|p| p.publisher == user_id);
+        for publication in others {
+            publication.downtracks.lock().unwrap().remove(&user_id);
+        }
+        for publication in theirs {
+            self.detach_all(&publication).await;
+        }
+        participant.close().await;
+        self.broadcast(&envelope("participant_left", SfuParticipantLeft { user_id }), None);
+    }
+
+    /// Forwards a track `publisher_id` started sending, until it ends.
+    /// Each simulcast layer arrives as a track of its own.
+    async fn publish(
+        self: Arc<Self>,
+        publisher_id: Uuid,
+        publisher_pc: Arc<RTCPeerConnection>,
+        track: Arc<TrackRemote>,
+        receiver: Arc<RTCRtpReceiver>,
+    ) {
+        let Some(layer) = layer_index(track.rid()) else {
+            tracing::warn!("Ignoring simulcast layer with unknown rid {:?} from {publisher_id}", track.rid());
+            return;
+        };
+        let track_id = track.id().await;
+        let codec = track.codec().await.capability;
+        let kind = track.kind();
+        let audio_level_id = receiver
+            .get_parameters()
+            .await
+            .header_extensions
+            .iter()
+            .find(|ext| ext.uri == AUDIO_LEVEL_URI)
+            .map(|ext| ext.id as u8);
+
+        let publication = {
+            let _changes = self.changes.lock().await;
+            if self.participant(publisher_id).is_none_or(|p| !Arc::ptr_eq(&p.publisher, &publisher_pc)) {
+                return;
+            }
+            let key = (publisher_id, track_id.clone());
+            let existing = self.publications.lock().unwrap().get(&key).cloned();
+            let publication = match existing {
+                Some(publication) => publication,
+                None => {
+                    let publication = Arc::new(Publication {
+                        publisher: publisher_id,
+                        track_id,
+                        kind,
+                        codec: codec.clone(),
+                        publisher_pc,
+                        layers: Mutex::new(HashMap::new()),
+                        downtracks: Mutex::new(HashMap::new()),
+                        last_keyframe_request: Mutex::new(HashMap::new()),
+                    });
+                    self.publications.lock().unwrap().insert(key, publication.clone());
+                    let subscribers: Vec<Arc<Participant>> = self
+                        .participants
+                        .lock()
+                        .unwrap()
+                        .values()
+                        .filter(|p| p.user_id != publisher_id)
+                        .cloned()
+                        .collect();
+                    for subscriber in subscribers {
+                        if self.attach(&subscriber, &publication).await {
+                            subscriber.negotiate().await;
+                        }
+                    }
+                    publication
+                }
+            };
+            publication.layers.lock().unwrap().insert(layer, track.ssrc());
+            publication
+        };
+        tracing::debug!("{publisher_id} publishes {kind} track {} layer {layer}", publication.track_id);
+
+        while let Ok((mut packet, _)) = track.read_rtp().await {
+            if let Some(level) = audio_level_id
+                .and_then(|id| packet.header.get_extension(id))
+                .and_then(|ext| ext.first().copied())
+            {
+                self.speakers.lock().unwrap().observe(publisher_id, level & 0x7f, Instant::now());
+            }
+            let keyframe = kind != RTPCodecType::Video || is_keyframe(&codec.mime_type, &packet.payload);
+            // The publisher's extension ids mean nothing to subscribers
+            packet.header.extension = false;
+            packet.header.extensions.clear();
+            for (_, downtrack) in publication.downtracks() {
+                let mut out = packet.clone();
+                if !downtrack.forwarder.lock().unwrap().forward(layer, &mut out.header, keyframe) {
+                    continue;
+                }
+                if let Err(e) = downtrack.track.write_rtp(&out).await {
+                    tracing::trace!("Dropped a forwarded packet: {e}");
+                }
+            }
+        }
+
+        let last_layer = {
+            let mut layers = publication.layers.lock().unwrap();
+            layers.remove(&layer);
+            layers.is_empty()
+        };
+        if last_layer {
+            let _changes = self.changes.lock().await;
+            self.detach_all(&publication).await;
+        }
+    }
+
+    /// Adds a track fed from `publication` to the subscriber's connection.
+    /// The caller renegotiates.
+    async fn attach(&self, subscriber: &Arc<Participant>, publication: &Arc<Publication>) -> bool {
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            publication.codec.clone(),
+            publication.track_id.clone(),
+            // Lets clients tell whose media a stream is
+            publication.publisher.to_string(),
+        ));
+        let sender = match subscriber
+            .subscriber
+            .add_track(track.clone() as Arc<dyn TrackLocal + Send + Sync>)
+            .await
+        {
+            Ok(sender) => sender,
+            Err(e) => {
+                tracing::warn!("Failed to send {}'s track to {}: {e}", publication.publisher, subscriber.user_id);
+                return false;
+            }
+        };
+        let downtrack = Arc::new(DownTrack {
+            track,
+            sender: sender.clone(),
+            forwarder: Mutex::new(Forwarder::new()),
+        });
+        publication.downtracks.lock().unwrap().insert(subscriber.user_id, downtrack.clone());
+
+        // Keyframe requests from the subscriber go to the publisher
+        let publication = Arc::downgrade(publication);
+        tokio::spawn(async move {
+            while let Ok((packets, _)) = sender.read_rtcp().await {
+                let wants_keyframe = packets.iter().any(|p| {
+                    p.as_any().downcast_ref::<PictureLossIndication>().is_some()
+                        || p.as_any().downcast_ref::<FullIntraRequest>().is_some()
+                });
+                let Some(publication) = publication.upgrade() else { break };
+                if wants_keyframe {
+                    let layer = downtrack.forwarder.lock().unwrap().current();
+                    if let Some(layer) = layer {
+                        publication.request_keyframe(layer).await;
+                    }
+                }
+            }
+        });
+        true
+    }
+
+    /// Stops forwarding a publication that ended.
+    async fn detach_all(&self, publication: &Arc<Publication>) {
+        let key = (publication.publisher, publication.track_id.clone());
+        {
+            let mut publications = self.publications.lock().unwrap();
+            if !publications.get(&key).is_some_and(|p| Arc::ptr_eq(p, publication)) {
+                return;
+            }
+            publications.remove(&key);
+        }
+        let downtracks: Vec<(Uuid, Arc<DownTrack>)> = publication.downtracks.lock().unwrap().drain().collect();
+        for (subscriber_id, downtrack) in downtracks {
+            let Some(subscriber) = self.participant(subscriber_id) else { continue };
+            if subscriber.subscriber.remove_track(&downtrack.sender).await.is_ok() {
+                subscriber.negotiate().await;
+            }
+        }
+    }
+
+    /// Reports speaker changes and moves subscribers between layers.
+    async fn tick(&self) {
+        let (update, dominant) = {
+            let mut speakers = self.speakers.lock().unwrap();
+            (speakers.tick(Instant::now()), speakers.dominant())
+        };
+        if let Some(update) = update {
+            self.broadcast(&envelope("active_speakers", update), None);
+        }
+
+        let videos: Vec<Arc<Publication>> = self
+            .publications
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|p| p.kind == RTPCodecType::Video)
+            .cloned()
+            .collect();
+        let video_publishers = videos.iter().map(|p| p.publisher).collect::<HashSet<_>>().len();
+        for publication in videos {
+            let available = publication.available_layers();
+            let auto = if video_publishers <= SMALL_ROOM_VIDEO || Some(publication.publisher) == dominant {
+                SimulcastLayer::High
+            } else {
+                SimulcastLayer::Low
+            };
+            let mut keyframes = HashSet::new();
+            for (subscriber_id, downtrack) in publication.downtracks() {
+                let desired = self
+                    .participant(subscriber_id)
+                    .and_then(|s| s.layer_pref(publication.publisher))
+                    .unwrap_or(auto);
+                let Some(target) = select_layer(desired, &available) else { continue };
+                if downtrack.forwarder.lock().unwrap().set_target(target) {
+                    keyframes.insert(target);
+                }
+            }
+            for layer in keyframes {
+                publication.request_keyframe(layer).await;
+            }
+        }
+    }
+}
+
+async fn run_ticker(room: Weak<Room>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let Some(room) = room.upgrade() else { break };
+        if room.closed.load(Ordering::Relaxed) {
+            break;
+        }
+        room.tick().await;
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+};
+use futures_util::{SinkExt, StreamExt};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use shared::sfu::{SfuCandidate, SfuClaims, SfuDescription, SfuSetLayer, SfuTarget};
+use shared::ws_messages::{WsAuth, WsEnvelope};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+
+use crate::room::{envelope, error, Participant};
+use crate::Sfu;
+
+/// How long a new connection has to send its `auth` message
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn validate_token(token: &str, secret: &str) -> Result<SfuClaims, jsonwebtoken::errors::Error> {
+    let data = decode::<SfuClaims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default())?;
+    Ok(data.claims)
+}
+
+pub async fn ws_handler(ws: WebSocketUpgrade, State(sfu): State<Arc<Sfu>>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, sfu))
+}
+
+fn to_text(message: &WsEnvelope) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap().into())
+}
+
+async fn handle_socket(socket: WebSocket, sfu: Arc<Sfu>) {
+    let (mut sender, mut receiver) = socket.split();
+
+    // First message must be auth
+    let claims = match tokio::time::timeout(AUTH_TIMEOUT, receiver.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<WsEnvelope>(&text)
+            .ok()
+            .filter(|env| env.msg_type == "auth")
+            .and_then(|env| serde_json::from_value::<WsAuth>(env.payload).ok())
+            .and_then(|auth| validate_token(&auth.token, &sfu.config.secret).ok())
+            .and_then(|claims| Some((Uuid::parse_str(&claims.sub).ok()?, claims))),
+        _ => return,
+    };
+    let Some((user_id, claims)) = claims else {
+        let _ = sender.send(to_text(&error("Invalid token", "invalid_token"))).await;
+        return;
+    };
+
+    let (outbox, mut outbox_rx) = mpsc::unbounded_channel::<WsEnvelope>();
+    let connections = match (sfu.new_peer_connection().await, sfu.new_peer_connection().await) {
+        (Ok(publisher), Ok(subscriber)) => (publisher, subscriber),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Failed to create peer connections: {e}");
+            let _ = sender.send(to_text(&error("Media is unavailable", "internal"))).await;
+            return;
+        }
+    };
+    let participant = Arc::new(Participant::new(user_id, claims.publish, outbox, connections.0, connections.1));
+
+    if sender.send(to_text(&envelope("auth_success", serde_json::Value::Null))).await.is_err() {
+        return;
+    }
+    tracing::info!("{user_id} joined room {}", claims.room);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outbox_rx.recv().await {
+            if sender.send(to_text(&message)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let room = sfu.room(&claims.room);
+    room.join(participant.clone()).await;
+
+    while let Some(Ok(message)) = receiver.next().await {
+        match message {
+            Message::Text(text) => {
+                if let Ok(env) = serde_json::from_str::<WsEnvelope>(&text) {
+                    handle_client_message(&participant, env).await;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    room.leave(user_id, participant.session).await;
+    sfu.release(&room);
+    writer.abort();
+    tracing::info!("{user_id} left room {}", claims.room);
+}
+
+fn parse<T: DeserializeOwned>(participant: &Participant, env: WsEnvelope) -> Option<T> {
+    let parsed = serde_json::from_value(env.payload).ok();
+    if parsed.is_none() {
+        participant.send(error(&format!("Malformed {} message", env.msg_type), "invalid_payload"));
+    }
+    parsed
+}
+
+async fn handle_client_message(participant: &Arc<Participant>, env: WsEnvelope) {
+    match env.msg_type.as_str() {
+        "offer" => {
+            let Some(offer) = parse::<SfuDescription>(participant, env) else { return };
+            if offer.target != SfuTarget::Publisher {
+                participant.send(error("Only the publisher connection takes offers", "invalid_target"));
+                return;
+            }
+            if !participant.publish {
+                participant.send(error("You are not allowed to speak in this channel", "cannot_publish"));
+                return;
+            }
+            match participant.answer_publisher(offer.sdp).await {
+                Ok(sdp) => participant.send(envelope(
+                    "answer",
+                    SfuDescription {
+                        target: SfuTarget::Publisher,
+                        sdp,
+                    },
+                )),
+                Err(e) => participant.send(error(&format!("Offer rejected: {e}"), "invalid_sdp")),
+            }
+        }
+        "answer" => {
+            let Some(answer) = parse::<SfuDescription>(participant, env) else { return };
+            if answer.target != SfuTarget::Subscriber {
+                participant.send(error("Only the subscriber connection takes answers", "invalid_target"));
+                return;
+            }
+            if let Err(e) = participant.accept_subscriber_answer(answer.sdp).await {
+                participant.send(error(&format!("Answer rejected: {e}"), "invalid_sdp"));
+            }
+        }
+        "candidate" => {
+            let Some(candidate) = parse::<SfuCandidate>(participant, env) else { return };
+            let pc = match candidate.target {
+                SfuTarget::Publisher => &participant.publisher,
+                SfuTarget::Subscriber => &participant.subscriber,
+            };
+            let init = RTCIceCandidateInit {
+                candidate: candidate.candidate,
+                sdp_mid: candidate.sdp_mid,
+                sdp_mline_index: candidate.sdp_mline_index,
+                username_fragment: None,
+            };
+            if let Err(e) = pc.add_ice_candidate(init).await {
+                tracing::debug!("Ignoring ICE candidate from {}: {e}", participant.user_id);
+            }
+        }
+        "set_layer" => {
+            let Some(request) = parse::<SfuSetLayer>(participant, env) else { return };
+            participant.set_layer(request.user_id, request.layer);
+        }
+        other => {
+            tracing::debug!("Unknown SFU message type: {other}");
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Active speaker detection from the audio levels publishers put in their
+//! RTP header extensions (RFC 6464).
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use shared::sfu::{SfuActiveSpeakers, SfuSpeaker};
+use uuid::Uuid;
+
+/// Level of digital silence in dBov
+pub const SILENCE: f32 = -127.0;
+/// Smoothed level above which someone counts as speaking
+const SPEAKING_THRESHOLD: f32 = -50.0;
+/// How much louder than the dominant speaker someone has to be to take over
+const DOMINANT_HYSTERESIS: f32 = 6.0;
+/// Weight of each new packet's level in the moving average
+const SMOOTHING: f32 = 0.2;
+/// Publishers that stop sending audio (muted, or DTX during silence) are
+/// quiet after this long
+const STALE_AFTER: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+struct Level {
+    smoothed: f32,
+    last_packet: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct SpeakerDetector {
+    levels: HashMap<Uuid, Level>,
+    dominant: Option<Uuid>,
+    /// Dominant and speakers last reported, to only report changes
+    reported: Option<(Option<Uuid>, Vec<Uuid>)>,
+}
+
+impl SpeakerDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a packet from `user_id` carrying `level` in -dBov, as the
+    /// extension encodes it (0 is loudest, 127 silence).
+    pub fn observe(&mut self, user_id: Uuid, level: u8, now: Instant) {
+        let dbov = -f32::from(level.min(127));
+        let entry = self.levels.entry(user_id).or_insert(Level {
+            smoothed: SILENCE,
+            last_packet: now,
+        });
+        entry.smoothed += (dbov - entry.smoothed) * SMOOTHING;
+        entry.last_packet = now;
+    }
+
+    pub fn remove(&mut self, user_id: Uuid) {
+        self.levels.remove(&user_id);
+        if self.dominant == Some(user_id) {
+            self.dominant = None;
+        }
+    }
+
+    pub fn dominant(&self) -> Option<Uuid> {
+        self.dominant
+    }
+
+    fn level(&self, user_id: Uuid, now: Instant) -> f32 {
+        match self.levels.get(&user_id) {
+            Some(level) if now.duration_since(level.last_packet) < STALE_AFTER => level.smoothed,
+            _ => SILENCE,
+        }
+    }
+
+    /// Re-evaluates who is speaking. Returns the new state when it differs
+    /// from the last one returned.
+    pub fn tick(&mut self, now: Instant) -> Option<SfuActiveSpeakers> {
+        let mut speakers: Vec<SfuSpeaker> = self
+            .levels
+            .keys()
+            .map(|user_id| SfuSpeaker {
+                user_id: *user_id,
+                level: self.level(*user_id, now),
+            })
+            .filter(|speaker| speaker.level > SPEAKING_THRESHOLD)
+            .collect();
+        speakers.sort_by(|a, b| b.level.total_cmp(&a.level));
+
+        if let Some(loudest) = speakers.first() {
+            let takes_over = match self.dominant {
+                None => true,
+                Some(dominant) if dominant == loudest.user_id => false,
+                Some(dominant) => {
+                    let level = self.level(dominant, now);
+                    level <= SPEAKING_THRESHOLD || loudest.level - level >= DOMINANT_HYSTERESIS
+                }
+            };
+            if takes_over {
+                self.dominant = Some(loudest.user_id);
+            }
+        }
+
+        let state = (self.dominant, speakers.iter().map(|s| s.user_id).collect::<Vec<_>>());
+        if self.reported.as_ref() == Some(&state) {
+            return None;
+        }
+        self.reported = Some(state);
+        Some(SfuActiveSpeakers {
+            dominant: self.dominant,
+            speakers,
+        })
+    }
+}
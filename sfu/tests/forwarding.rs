@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use sfu::forwarder::{is_keyframe, layer_index, select_layer, Forwarder};
+use sfu::speaker::SpeakerDetector;
+use shared::sfu::SimulcastLayer;
+use uuid::Uuid;
+use webrtc::rtp::header::Header;
+
+fn header(sequence_number: u16, timestamp: u32) -> Header {
+    Header {
+        sequence_number,
+        timestamp,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn layers_fall_back_to_what_the_publisher_sends() {
+    assert_eq!(layer_index("l"), Some(0));
+    assert_eq!(layer_index("h"), Some(2));
+    assert_eq!(layer_index(""), Some(0));
+    assert_eq!(layer_index("x"), None);
+
+    assert_eq!(select_layer(SimulcastLayer::High, &[0, 1, 2]), Some(2));
+    assert_eq!(select_layer(SimulcastLayer::Medium, &[0, 1, 2]), Some(1));
+    assert_eq!(select_layer(SimulcastLayer::High, &[0, 1]), Some(1));
+    // The publisher stopped sending low and medium, e.g. on a good link
+    assert_eq!(select_layer(SimulcastLayer::Low, &[2]), Some(2));
+    assert_eq!(select_layer(SimulcastLayer::Low, &[]), None);
+}
+
+#[test]
+fn keyframes_are_recognised() {
+    // VP8 with a picture id, starting a partition
+    assert!(is_keyframe("video/VP8", &[0x90, 0x80, 0x12, 0x00, 0x9d]));
+    assert!(!is_keyframe("video/VP8", &[0x90, 0x80, 0x12, 0x01]));
+    // Not the start of a frame
+    assert!(!is_keyframe("video/VP8", &[0x00, 0x00]));
+    // H.264 IDR, STAP-A carrying SPS, FU-A start of an IDR
+    assert!(is_keyframe("video/H264", &[0x65]));
+    assert!(is_keyframe("video/H264", &[0x78, 0x00, 0x02, 0x67, 0x42]));
+    assert!(is_keyframe("video/H264", &[0x7c, 0x85]));
+    assert!(!is_keyframe("video/H264", &[0x7c, 0x05]));
+    assert!(!is_keyframe("video/H264", &[0x41]));
+}
+
+#[test]
+fn switching_layers_waits_for_a_keyframe_and_keeps_sequence_numbers_continuous() {
+    let mut forwarder = Forwarder::new();
+    assert!(forwarder.set_target(0));
+
+    // Nothing goes out before a keyframe
+    assert!(!forwarder.forward(0, &mut header(100, 9000), false));
+    let mut h = header(101, 12000);
+    assert!(forwarder.forward(0, &mut h, true));
+    assert_eq!((h.sequence_number, h.timestamp), (101, 12000));
+    assert!(!forwarder.forward(2, &mut header(5000, 700_000), true));
+
+    // Asking for the high layer keeps low flowing until high has a keyframe
+    assert!(forwarder.set_target(2));
+    let mut h = header(102, 15000);
+    assert!(forwarder.forward(0, &mut h, false));
+    assert!(!forwarder.forward(2, &mut header(5001, 703_000), false));
+    let mut h = header(5002, 706_000);
+    assert!(forwarder.forward(2, &mut h, true));
+    assert_eq!((h.sequence_number, h.timestamp), (103, 18000));
+    assert_eq!(forwarder.current(), Some(2));
+    assert!(!forwarder.set_target(2));
+
+    let mut h = header(5003, 709_000);
+    assert!(forwarder.forward(2, &mut h, false));
+    assert_eq!((h.sequence_number, h.timestamp), (104, 21000));
+    assert!(!forwarder.forward(0, &mut header(103, 18000), true));
+
+    // Sequence numbers wrap
+    let mut forwarder = Forwarder::new();
+    assert!(forwarder.forward(0, &mut header(u16::MAX, 0), true));
+    forwarder.set_target(1);
+    let mut h = header(7, 90_000);
+    assert!(forwarder.forward(1, &mut h, true));
+    assert_eq!(h.sequence_number, 0);
+}
+
+#[test]
+fn the_dominant_speaker_changes_only_when_someone_is_clearly_louder() {
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut speakers = SpeakerDetector::new();
+    let start = Instant::now();
+    let mut now = start;
+    let mut talk = |speakers: &mut SpeakerDetector, levels: &[(Uuid, u8)], packets: u32| {
+        for _ in 0..packets {
+            now += Duration::from_millis(20);
+            for (user, level) in levels {
+                speakers.observe(*user, *level, now);
+            }
+        }
+        speakers.tick(now)
+    };
+
+    let update = talk(&mut speakers, &[(alice, 30), (bob, 127)], 25).unwrap();
+    assert_eq!(update.dominant, Some(alice));
+    assert_eq!(update.speakers.len(), 1);
+    // Nothing changed, nothing to report
+    assert!(talk(&mut speakers, &[(alice, 30), (bob, 127)], 25).is_none());
+
+    // Bob joins in slightly louder: both speak, alice stays dominant
+    let update = talk(&mut speakers, &[(alice, 30), (bob, 27)], 25).unwrap();
+    assert_eq!(update.dominant, Some(alice));
+    assert_eq!(update.speakers[0].user_id, bob);
+    assert_eq!(update.speakers.len(), 2);
+
+    // Much louder takes over
+    let update = talk(&mut speakers, &[(alice, 30), (bob, 15)], 25).unwrap();
+    assert_eq!(update.dominant, Some(bob));
+
+    // Going quiet keeps bob dominant until alice talks alone
+    let update = talk(&mut speakers, &[(alice, 127), (bob, 127)], 25).unwrap();
+    assert_eq!(update.dominant, Some(bob));
+    assert!(update.speakers.is_empty());
+    let update = talk(&mut speakers, &[(alice, 40)], 25).unwrap();
+    assert_eq!(update.dominant, Some(alice));
+
+    // Publishers that stop sending fall silent
+    now += Duration::from_secs(1);
+    let update = speakers.tick(now).unwrap();
+    assert!(update.speakers.is_empty());
+    speakers.remove(alice);
+    assert_eq!(speakers.tick(now).unwrap().dominant, None);
+}
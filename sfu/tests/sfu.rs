@@ -0,0 +1,352 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::{json, Value};
+use sfu::config::SfuConfig;
+use sfu::Sfu;
+use shared::sfu::SfuClaims;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_OPUS, MIME_TYPE_VP8};
+use webrtc::api::APIBuilder;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp::extension::audio_level_extension::AudioLevelExtension;
+use webrtc::rtp::extension::HeaderExtension;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpHeaderExtensionCapability, RTPCodecType};
+use webrtc::rtp_transceiver::rtp_receiver::RTCRtpReceiver;
+use webrtc::sdp::extmap::AUDIO_LEVEL_URI;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::TrackLocal;
+use webrtc::track::track_remote::TrackRemote;
+
+const SECRET: &str = "north";
+const WAIT: Duration = Duration::from_secs(10);
+
+async fn start() -> (Arc<Sfu>, String) {
+    let sfu = Sfu::new(SfuConfig::new(SECRET)).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/rtc", listener.local_addr().unwrap());
+    let app = sfu::app(sfu.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (sfu, url)
+}
+
+fn token(user_id: Uuid, room: &str, publish: bool, secret: &str) -> String {
+    let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as usize + 600;
+    let claims = SfuClaims {
+        sub: user_id.to_string(),
+        room: room.into(),
+        publish,
+        exp,
+    };
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+}
+
+fn envelope(msg_type: &str, payload: Value) -> Message {
+    Message::Text(json!({"type": msg_type, "payload": payload}).to_string())
+}
+
+async fn peer_connection() -> Arc<RTCPeerConnection> {
+    let mut media = MediaEngine::default();
+    media.register_default_codecs().unwrap();
+    media
+        .register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: AUDIO_LEVEL_URI.into(),
+            },
+            RTPCodecType::Audio,
+            None,
+        )
+        .unwrap();
+    let registry = register_default_interceptors(Registry::new(), &mut media).unwrap();
+    let api = APIBuilder::new().with_media_engine(media).with_interceptor_registry(registry).build();
+    Arc::new(api.new_peer_connection(RTCConfiguration::default()).await.unwrap())
+}
+
+/// A track the SFU forwarded to a peer: `(stream id, kind)`
+type Received = (String, RTPCodecType);
+
+/// A synthetic client: its two peer connections, driven by the SFU's
+/// signaling the way the web client does.
+struct Peer {
+    user_id: Uuid,
+    outbox: mpsc::UnboundedSender<Message>,
+    publisher: Arc<RTCPeerConnection>,
+    /// Every message other than signaling
+    events: mpsc::UnboundedReceiver<Value>,
+    tracks: mpsc::UnboundedReceiver<Received>,
+}
+
+impl Peer {
+    async fn connect(url: &str, room: &str, publish: bool) -> Peer {
+        let user_id = Uuid::new_v4();
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let (mut sink, mut stream) = ws.split();
+        sink.send(envelope("auth", json!({"token": token(user_id, room, publish, SECRET)})))
+            .await
+            .unwrap();
+        let reply: Value = match stream.next().await {
+            Some(Ok(Message::Text(text))) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected auth_success, got {other:?}"),
+        };
+        assert_eq!(reply["type"], "auth_success", "{reply}");
+
+        let (outbox, mut outbox_rx) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(message) = outbox_rx.recv().await {
+                if sink.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let publisher = peer_connection().await;
+        let subscriber = peer_connection().await;
+        for (pc, target) in [(&publisher, "publisher"), (&subscriber, "subscriber")] {
+            let outbox = outbox.clone();
+            pc.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                if let Some(init) = candidate.and_then(|c| c.to_json().ok()) {
+                    let _ = outbox.send(envelope(
+                        "candidate",
+                        json!({
+                            "target": target,
+                            "candidate": init.candidate,
+                            "sdp_mid": init.sdp_mid,
+                            "sdp_mline_index": init.sdp_mline_index,
+                        }),
+                    ));
+                }
+                Box::pin(async {})
+            }));
+        }
+
+        let (track_tx, tracks) = mpsc::unbounded_channel();
+        subscriber.on_track(Box::new(move |track: Option<Arc<TrackRemote>>, _: Option<Arc<RTCRtpReceiver>>| {
+            let track_tx = track_tx.clone();
+            Box::pin(async move {
+                let Some(track) = track else { return };
+                // Only count tracks media actually arrives on
+                if track.read_rtp().await.is_ok() {
+                    let _ = track_tx.send((track.stream_id().await, track.kind()));
+                }
+                tokio::spawn(async move { while track.read_rtp().await.is_ok() {} });
+            })
+        }));
+
+        let (event_tx, events) = mpsc::unbounded_channel();
+        let (signal_publisher, signal_subscriber, signal_outbox) = (publisher.clone(), subscriber.clone(), outbox.clone());
+        tokio::spawn(async move {
+            while let Some(Ok(Message::Text(text))) = stream.next().await {
+                let env: Value = serde_json::from_str(&text).unwrap();
+                let payload = &env["payload"];
+                let pc = match payload["target"].as_str() {
+                    Some("publisher") => &signal_publisher,
+                    _ => &signal_subscriber,
+                };
+                match env["type"].as_str().unwrap() {
+                    "offer" => {
+                        let sdp = payload["sdp"].as_str().unwrap().to_string();
+                        pc.set_remote_description(RTCSessionDescription::offer(sdp).unwrap()).await.unwrap();
+                        let answer = pc.create_answer(None).await.unwrap();
+                        pc.set_local_description(answer.clone()).await.unwrap();
+                        let _ = signal_outbox.send(envelope("answer", json!({"target": "subscriber", "sdp": answer.sdp})));
+                    }
+                    "answer" => {
+                        let sdp = payload["sdp"].as_str().unwrap().to_string();
+                        pc.set_remote_description(RTCSessionDescription::answer(sdp).unwrap()).await.unwrap();
+                    }
+                    "candidate" => {
+                        let init = RTCIceCandidateInit {
+                            candidate: payload["candidate"].as_str().unwrap().to_string(),
+                            sdp_mid: payload["sdp_mid"].as_str().map(String::from),
+                            sdp_mline_index: payload["sdp_mline_index"].as_u64().map(|i| i as u16),
+                            username_fragment: None,
+                        };
+                        let _ = pc.add_ice_candidate(init).await;
+                    }
+                    _ => {
+                        let _ = event_tx.send(env);
+                    }
+                }
+            }
+        });
+
+        Peer {
+            user_id,
+            outbox,
+            publisher,
+            events,
+            tracks,
+        }
+    }
+
+    /// Publishes an audio track at `level` -dBov and, with `video`, a VP8
+    /// track made of keyframes.
+    async fn publish(&self, level: u8, video: bool) {
+        let audio = Arc::new(TrackLocalStaticRTP::new(
+            RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_OPUS.into(),
+                ..Default::default()
+            },
+            "audio".into(),
+            "mic".into(),
+        ));
+        self.publisher.add_track(audio.clone() as Arc<dyn TrackLocal + Send + Sync>).await.unwrap();
+        let camera = if video {
+            let camera = Arc::new(TrackLocalStaticRTP::new(
+                RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_VP8.into(),
+                    ..Default::default()
+                },
+                "video".into(),
+                "camera".into(),
+            ));
+            self.publisher.add_track(camera.clone() as Arc<dyn TrackLocal + Send + Sync>).await.unwrap();
+            Some(camera)
+        } else {
+            None
+        };
+
+        let offer = self.publisher.create_offer(None).await.unwrap();
+        self.publisher.set_local_description(offer.clone()).await.unwrap();
+        self.send("offer", json!({"target": "publisher", "sdp": offer.sdp}));
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(20));
+            for seq in 0u16.. {
+                interval.tick().await;
+                let mut packet = Packet {
+                    payload: vec![0xfc; 40].into(),
+                    ..Default::default()
+                };
+                packet.header.version = 2;
+                packet.header.sequence_number = seq;
+                packet.header.timestamp = u32::from(seq) * 960;
+                let level = HeaderExtension::AudioLevel(AudioLevelExtension { level, voice: level < 127 });
+                if audio.write_rtp_with_extensions(&packet, &[level]).await.is_err() {
+                    break;
+                }
+                if let Some(camera) = &camera {
+                    // Payload descriptor starting a partition, then a keyframe header
+                    packet.payload = vec![0x10, 0x00, 0x9d, 0x01, 0x2a, 0, 0, 0].into();
+                    packet.header.timestamp = u32::from(seq) * 1800;
+                    packet.header.marker = true;
+                    if camera.write_rtp_with_extensions(&packet, &[]).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Hangs up the way the client does when leaving the channel.
+    async fn leave(self) {
+        self.outbox.send(Message::Close(None)).unwrap();
+        self.publisher.close().await.unwrap();
+    }
+
+    fn send(&self, msg_type: &str, payload: Value) {
+        self.outbox.send(envelope(msg_type, payload)).unwrap();
+    }
+
+    /// Waits for `count` forwarded tracks and returns them sorted.
+    async fn tracks(&mut self, count: usize) -> Vec<Received> {
+        let mut tracks = Vec::new();
+        while tracks.len() < count {
+            let track = tokio::time::timeout(WAIT, self.tracks.recv())
+                .await
+                .unwrap_or_else(|_| panic!("only got {tracks:?}"))
+                .unwrap();
+            tracks.push(track);
+        }
+        tracks.sort_by_key(|(stream, kind)| (stream.clone(), *kind as u8));
+        tracks
+    }
+
+    /// Waits for an event of `msg_type` matching `filter`.
+    async fn expect(&mut self, msg_type: &str, filter: impl Fn(&Value) -> bool) -> Value {
+        let wait = async {
+            loop {
+                let event = self.events.recv().await.unwrap();
+                if event["type"] == msg_type && filter(&event["payload"]) {
+                    return event["payload"].clone();
+                }
+            }
+        };
+        tokio::time::timeout(WAIT, wait).await.unwrap_or_else(|_| panic!("no {msg_type}"))
+    }
+}
+
+#[tokio::test]
+async fn publishers_are_forwarded_to_everyone_else_with_active_speakers() {
+    let (sfu, url) = start().await;
+    let alice = Peer::connect(&url, "lounge", true).await;
+    let mut bob = Peer::connect(&url, "lounge", true).await;
+    alice.publish(10, true).await;
+    bob.publish(127, false).await;
+
+    let alice_id = alice.user_id.to_string();
+    let bob_id = bob.user_id.to_string();
+    assert_eq!(
+        bob.tracks(2).await,
+        [(alice_id.clone(), RTPCodecType::Audio), (alice_id.clone(), RTPCodecType::Video)]
+    );
+    let mut alice = alice;
+    assert_eq!(alice.tracks(1).await, [(bob_id.clone(), RTPCodecType::Audio)]);
+
+    // Alice is loud, bob sends silence
+    let speakers = bob.expect("active_speakers", |p| p["dominant"] == alice_id.as_str()).await;
+    assert_eq!(speakers["speakers"].as_array().unwrap().len(), 1);
+    assert_eq!(speakers["speakers"][0]["user_id"], alice_id.as_str());
+    assert!(speakers["speakers"][0]["level"].as_f64().unwrap() > -50.0);
+
+    // Listeners receive everyone but cannot send
+    let mut carol = Peer::connect(&url, "lounge", false).await;
+    carol.send("offer", json!({"target": "publisher", "sdp": "v=0"}));
+    let error = carol.expect("error", |_| true).await;
+    assert_eq!(error["code"], "cannot_publish");
+    let tracks = carol.tracks(3).await;
+    assert_eq!(tracks.iter().filter(|(stream, _)| *stream == alice_id).count(), 2);
+    assert!(tracks.contains(&(bob_id.clone(), RTPCodecType::Audio)));
+
+    // Other rooms see nothing
+    let mut dave = Peer::connect(&url, "elsewhere", true).await;
+    assert!(tokio::time::timeout(Duration::from_millis(500), dave.tracks.recv()).await.is_err());
+    assert_eq!(sfu.room_count(), 2);
+
+    let left = bob.user_id;
+    bob.leave().await;
+    let gone = alice.expect("participant_left", |p| p["user_id"] == left.to_string().as_str()).await;
+    assert_eq!(gone["user_id"], left.to_string().as_str());
+
+    dave.leave().await;
+    tokio::time::timeout(WAIT, async {
+        while sfu.room_count() > 1 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn tokens_must_be_signed_with_the_shared_secret() {
+    let (_sfu, url) = start().await;
+    for token in [token(Uuid::new_v4(), "lounge", true, "wrong"), "garbage".into()] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        ws.send(envelope("auth", json!({"token": token}))).await.unwrap();
+        let Some(Ok(Message::Text(text))) = ws.next().await else { panic!("no reply") };
+        let reply: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["payload"]["code"], "invalid_token");
+    }
+}
@@ -1,2 +1,3 @@
 pub mod models;
+pub mod sfu;
 pub mod ws_messages;
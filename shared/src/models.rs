@@ -50,6 +50,17 @@ pub struct Server {
     pub owner_id: Uuid,
     pub created_at: String,
     pub updated_at: String,
+    /// Links in messages get preview embeds
+    #[serde(default = "default_true")]
+    pub link_previews: bool,
+    /// Days earlier versions of edited messages are kept; 0 keeps them
+    #[serde(default)]
+    pub edit_history_days: i64,
+    /// Unread messages across the server's text channels, for the caller
+    #[serde(default)]
+    pub unread_count: i64,
+    #[serde(default)]
+    pub mention_count: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +69,18 @@ pub struct CreateServerRequest {
     pub icon_url: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateServerRequest {
+    pub name: Option<String>,
+    pub icon_url: Option<String>,
+    pub link_previews: Option<bool>,
+    pub edit_history_days: Option<i64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
 // ────────────────────────────────────────────────────────────────────────────
 // Server Member
 // ────────────────────────────────────────────────────────────────────────────
@@ -71,6 +94,52 @@ pub struct ServerMember {
     pub username: String,
     pub avatar_url: Option<String>,
     pub status: Option<UserStatus>,
+    /// Ids of explicitly assigned roles (@everyone is implied)
+    #[serde(default)]
+    pub roles: Vec<Uuid>,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Roles & Permissions
+// ────────────────────────────────────────────────────────────────────────────
+
+/// A server role. `permissions` is a bitset; the role whose id equals the
+/// server id is @everyone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub name: String,
+    pub permissions: i64,
+    pub position: i32,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRoleRequest {
+    pub name: String,
+    pub permissions: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateRoleRequest {
+    pub name: Option<String>,
+    pub permissions: Option<i64>,
+}
+
+/// Per-role allow/deny bitsets applied on top of server permissions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelPermissionOverride {
+    pub channel_id: Uuid,
+    pub role_id: Uuid,
+    pub allow: i64,
+    pub deny: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetChannelOverrideRequest {
+    pub allow: i64,
+    pub deny: i64,
 }
 
 // ────────────────────────────────────────────────────────────────────────────
@@ -87,6 +156,32 @@ pub struct Channel {
     pub position: i32,
     pub created_at: String,
     pub updated_at: String,
+    /// Messages after the caller's read marker, not counting their own
+    #[serde(default)]
+    pub unread_count: i64,
+    /// Unread mentions of the caller
+    #[serde(default)]
+    pub mention_count: i64,
+    #[serde(default)]
+    pub last_read_message_id: Option<Uuid>,
+    /// `None` for channels outside any category
+    #[serde(default)]
+    pub category_id: Option<Uuid>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// Seconds members wait between messages; 0 = off
+    #[serde(default)]
+    pub slowmode_secs: i64,
+    #[serde(default)]
+    pub nsfw: bool,
+    /// How voice channels carry media: `mesh`, where every member sends to
+    /// every other, or `sfu`, where everyone sends once to the SFU
+    #[serde(default = "default_voice_mode")]
+    pub voice_mode: String,
+}
+
+fn default_voice_mode() -> String {
+    "mesh".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,6 +189,64 @@ pub struct CreateChannelRequest {
     pub name: String,
     #[serde(rename = "type")]
     pub channel_type: String,
+    #[serde(default)]
+    pub category_id: Option<Uuid>,
+}
+
+/// Unset fields are left as they are; an empty `topic` clears it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateChannelRequest {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub slowmode_secs: Option<i64>,
+    pub nsfw: Option<bool>,
+    /// Voice channels only
+    pub voice_mode: Option<String>,
+}
+
+/// A collapsible group of channels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelCategory {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub name: String,
+    pub position: i32,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateCategoryRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateCategoryRequest {
+    pub name: String,
+}
+
+/// New places for some of a server's channels and categories, applied
+/// all at once. Channels not listed keep theirs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorderChannelsRequest {
+    #[serde(default)]
+    pub channels: Vec<ChannelPosition>,
+    #[serde(default)]
+    pub categories: Vec<CategoryPosition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelPosition {
+    pub id: Uuid,
+    /// The category to move into; `None` moves the channel out of any
+    #[serde(default)]
+    pub category_id: Option<Uuid>,
+    pub position: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryPosition {
+    pub id: Uuid,
+    pub position: i32,
 }
 
 // ────────────────────────────────────────────────────────────────────────────
@@ -112,11 +265,132 @@ pub struct Message {
     pub author: Option<UserPublic>,
     pub attachments: Vec<Attachment>,
     pub reactions: Vec<ReactionGroup>,
+    /// The message this one replies to
+    #[serde(default)]
+    pub reply_to: Option<MessageReference>,
+    /// Set when the message is a reply inside a thread
+    #[serde(default)]
+    pub thread_id: Option<Uuid>,
+    /// The thread rooted at this message, if one was started
+    #[serde(default)]
+    pub thread: Option<Thread>,
+    /// Previews of the links in the content. Generated after the message
+    /// is sent and delivered with `message_updated`.
+    #[serde(default)]
+    pub embeds: Vec<Embed>,
+    /// Set once the message is deleted. Unless the viewer can manage
+    /// messages it is then a tombstone without content, files, embeds or
+    /// reactions.
+    #[serde(default)]
+    pub deleted_at: Option<String>,
+    /// Who deleted it; only shown to the author and moderators
+    #[serde(default)]
+    pub deleted_by: Option<Uuid>,
+}
+
+/// An earlier version of an edited channel message or DM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageRevision {
+    /// Counts up with each edit
+    pub revision: i64,
+    pub content: Option<String>,
+    /// When this version was posted or last edited
+    pub created_at: String,
+    /// When an edit replaced it, and who made that edit
+    pub replaced_at: String,
+    pub replaced_by: Option<Uuid>,
+}
+
+/// Preview of a link, from the page's OpenGraph or Twitter card metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embed {
+    /// The link as written in the message
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    pub image_url: Option<String>,
+    /// The page asks for its image to be shown full width
+    pub large_image: bool,
+}
+
+/// Compact preview of a replied-to message. `author` is `None` once the
+/// parent has been deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReference {
+    pub message_id: Uuid,
+    pub author: Option<UserPublic>,
+    /// The start of the parent's content
+    pub content: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateMessageRequest {
     pub content: Option<String>,
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+    /// Ids of the caller's pending uploads to attach
+    #[serde(default)]
+    pub attachment_ids: Vec<Uuid>,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Mention
+// ────────────────────────────────────────────────────────────────────────────
+
+/// An entry in the caller's mentions inbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mention {
+    pub message: Message,
+    pub server_id: Uuid,
+    pub read: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarkMentionsReadRequest {
+    /// Omit to mark every mention as read
+    pub message_ids: Option<Vec<Uuid>>,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Read state
+// ────────────────────────────────────────────────────────────────────────────
+
+/// The caller's read marker in one channel or DM conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadState {
+    pub channel_id: Option<Uuid>,
+    pub conversation_id: Option<Uuid>,
+    pub last_read_message_id: Option<Uuid>,
+    pub unread_count: i64,
+    pub mention_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AckRequest {
+    pub message_id: Uuid,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Thread
+// ────────────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thread {
+    pub id: Uuid,
+    pub channel_id: Uuid,
+    pub root_message_id: Uuid,
+    pub creator_id: Uuid,
+    pub name: String,
+    pub message_count: i64,
+    pub last_message_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateThreadRequest {
+    /// Defaults to the start of the root message
+    pub name: Option<String>,
 }
 
 // ────────────────────────────────────────────────────────────────────────────
@@ -132,6 +406,37 @@ pub struct Attachment {
     pub mime_type: String,
     pub size_bytes: Option<i64>,
     pub created_at: String,
+    /// Images and videos: display size, so clients can lay out the
+    /// message before the media loads
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Placeholder to show while the image loads
+    pub blurhash: Option<String>,
+    /// Small WebP rendition for the message list
+    pub thumbnail_url: Option<String>,
+    /// Larger WebP rendition for the image viewer; absent when the
+    /// original is small enough to show as is
+    pub preview_url: Option<String>,
+    /// Videos only: length in seconds
+    pub duration_secs: Option<f64>,
+}
+
+/// A stored file that is not attached to a message yet. Pending uploads
+/// are deleted if nothing references them in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub id: Uuid,
+    pub url: String,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size_bytes: i64,
+    /// Same as on [`Attachment`]
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub blurhash: Option<String>,
+    pub thumbnail_url: Option<String>,
+    pub preview_url: Option<String>,
+    pub duration_secs: Option<f64>,
 }
 
 // ────────────────────────────────────────────────────────────────────────────
@@ -162,13 +467,46 @@ pub struct ReactionGroup {
 pub struct VoiceState {
     pub user_id: Uuid,
     pub channel_id: Uuid,
+    /// Self-mute and self-deafen, set by the user
     pub muted: bool,
     pub deafened: bool,
+    /// Set by moderators; the user cannot lift them
+    #[serde(default)]
+    pub server_muted: bool,
+    #[serde(default)]
+    pub server_deafened: bool,
     pub joined_at: String,
     pub username: Option<String>,
     pub avatar_url: Option<String>,
 }
 
+/// Moderator voice controls for one member. Unset fields are left as they
+/// are; `channel_id` moves a member who is in voice to another channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateMemberVoiceRequest {
+    pub server_muted: Option<bool>,
+    pub server_deafened: Option<bool>,
+    pub channel_id: Option<Uuid>,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Audit Log
+// ────────────────────────────────────────────────────────────────────────────
+
+/// A moderator action. `channel_id` is the voice channel the target was in;
+/// moves also set `to_channel_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub server_id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub target_user_id: Option<Uuid>,
+    pub channel_id: Option<Uuid>,
+    pub to_channel_id: Option<Uuid>,
+    pub created_at: String,
+}
+
 // ────────────────────────────────────────────────────────────────────────────
 // Auth
 // ────────────────────────────────────────────────────────────────────────────
@@ -207,6 +545,13 @@ pub struct DmConversation {
     pub last_message: Option<DmMessage>,
     pub created_at: String,
     pub updated_at: String,
+    #[serde(default)]
+    pub unread_count: i64,
+    /// Every direct message counts as a mention, so this equals `unread_count`
+    #[serde(default)]
+    pub mention_count: i64,
+    #[serde(default)]
+    pub last_read_message_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -230,4 +575,77 @@ pub struct CreateDmRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateDmMessageRequest {
     pub content: Option<String>,
+    /// Ids of the caller's pending uploads to attach; ignored on edit
+    #[serde(default)]
+    pub attachment_ids: Vec<Uuid>,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Search
+// ────────────────────────────────────────────────────────────────────────────
+
+/// A channel message matching a search. `snippet` is HTML-escaped message
+/// text with the matched terms wrapped in `<mark>` tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageSearchHit {
+    pub message: Message,
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DmMessageSearchHit {
+    pub message: DmMessage,
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub messages: Vec<MessageSearchHit>,
+    pub dm_messages: Vec<DmMessageSearchHit>,
+    pub has_more: bool,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Invites
+// ────────────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub code: String,
+    pub server_id: Uuid,
+    pub channel_id: Option<Uuid>,
+    pub creator_id: Uuid,
+    pub max_uses: Option<i64>,
+    pub uses: i64,
+    pub expires_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateInviteRequest {
+    /// Omitted or 0 for unlimited
+    pub max_uses: Option<i64>,
+    /// Lifetime in seconds; omitted or 0 never expires
+    pub max_age_secs: Option<i64>,
+    /// Channel to open after joining
+    pub channel_id: Option<Uuid>,
+}
+
+/// What anyone holding the code can see before joining.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvitePreview {
+    pub code: String,
+    pub server_id: Uuid,
+    pub server_name: String,
+    pub server_icon_url: Option<String>,
+    pub member_count: i64,
+    pub channel_id: Option<Uuid>,
+    pub channel_name: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteAccepted {
+    pub server: Server,
+    pub channel_id: Option<Uuid>,
 }
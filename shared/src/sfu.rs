@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// ────────────────────────────────────────────────────────────────────────────
+// Tokens
+// ────────────────────────────────────────────────────────────────────────────
+
+/// Claims of the token `GET /api/channels/{id}/sfu` hands out, signed with
+/// the secret the server shares with the SFU.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuClaims {
+    /// User id
+    pub sub: String,
+    /// Voice channel id
+    pub room: String,
+    /// Whether the user may send audio and video
+    pub publish: bool,
+    pub exp: usize,
+}
+
+// ────────────────────────────────────────────────────────────────────────────
+// Signaling
+//
+// Clients talk to the SFU over a WebSocket using the same envelope as the
+// server, starting with an `auth` message carrying the token. Each client
+// has two peer connections: it offers its uplink on the publisher one, and
+// the SFU offers everyone else's tracks on the subscriber one.
+// ────────────────────────────────────────────────────────────────────────────
+
+/// Which of a client's two peer connections a message is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SfuTarget {
+    Publisher,
+    Subscriber,
+}
+
+/// `offer` and `answer`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuDescription {
+    pub target: SfuTarget,
+    pub sdp: String,
+}
+
+/// `candidate`, a trickled ICE candidate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuCandidate {
+    pub target: SfuTarget,
+    pub candidate: String,
+    #[serde(default)]
+    pub sdp_mid: Option<String>,
+    #[serde(default)]
+    pub sdp_mline_index: Option<u16>,
+}
+
+/// Simulcast layers, sent by clients with the rids `l`, `m` and `h`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimulcastLayer {
+    Low,
+    Medium,
+    High,
+}
+
+/// `set_layer`: the best layer the client wants of `user_id`'s video.
+/// `None` goes back to the SFU's choice, which is the high layer for the
+/// dominant speaker and small rooms and the low layer otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuSetLayer {
+    pub user_id: Uuid,
+    #[serde(default)]
+    pub layer: Option<SimulcastLayer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuSpeaker {
+    pub user_id: Uuid,
+    /// Smoothed audio level in dBov, from -127 (silence) to 0
+    pub level: f32,
+}
+
+/// `active_speakers`, sent whenever who is speaking changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuActiveSpeakers {
+    /// The main speaker, kept after they go quiet until someone else talks
+    pub dominant: Option<Uuid>,
+    /// Everyone speaking right now, loudest first
+    pub speakers: Vec<SfuSpeaker>,
+}
+
+/// `participant_left`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SfuParticipantLeft {
+    pub user_id: Uuid,
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::models::{Message, ReactionGroup, UserPublic, VoiceState};
+use crate::models::{Channel, ChannelCategory, Embed, Message, ReactionGroup, ReadState, Thread, UserPublic, VoiceState};
 
 /// All WebSocket messages share this envelope format
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +24,14 @@ pub struct WsAuth {
 pub struct WsSendMessage {
     pub channel_id: Uuid,
     pub content: String,
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+    /// Posts into this thread instead of the channel timeline
+    #[serde(default)]
+    pub thread_id: Option<Uuid>,
+    /// Ids of the sender's pending uploads to attach
+    #[serde(default)]
+    pub attachment_ids: Vec<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +62,17 @@ pub struct WsTyping {
     pub channel_id: Uuid,
 }
 
+/// Advances the read marker; exactly one of `channel_id` and
+/// `conversation_id` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsAck {
+    #[serde(default)]
+    pub channel_id: Option<Uuid>,
+    #[serde(default)]
+    pub conversation_id: Option<Uuid>,
+    pub message_id: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsJoinVoice {
     pub channel_id: Uuid,
@@ -100,12 +119,46 @@ pub struct WsMessageCreated {
     pub message: Message,
 }
 
+/// A deleted message its author brought back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsMessageRestored {
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsThreadCreated {
+    pub thread: Thread,
+}
+
+/// A reply posted inside a thread; `thread` carries the updated counts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsThreadMessageCreated {
+    pub thread: Thread,
+    pub message: Message,
+}
+
+/// Sent only to the users a message mentions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsMentionCreated {
+    pub server_id: Uuid,
+    pub message: Message,
+}
+
+/// Sent to the user's other sessions after they ack
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsReadStateUpdate {
+    pub read_state: ReadState,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsMessageUpdated {
     pub message_id: Uuid,
     pub content: Option<String>,
     pub edited_at: Option<String>,
     pub pinned: Option<bool>,
+    /// Replaces the message's embeds when set
+    #[serde(default)]
+    pub embeds: Option<Vec<Embed>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -132,6 +185,14 @@ pub struct WsVoiceStateUpdate {
     pub voice_states: Vec<VoiceState>,
 }
 
+/// Sent to a user a moderator moved to another voice channel, or
+/// disconnected from voice when `channel_id` is `None`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsVoiceMoved {
+    pub channel_id: Option<Uuid>,
+    pub moved_by: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsSignalSdpRelay {
     pub from_user_id: Uuid,
@@ -149,15 +210,60 @@ pub struct WsSignalIceRelay {
     pub sdp_mline_index: Option<u16>,
 }
 
+/// The per-user read state fields of `channel` are not filled in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsChannelCreated {
+    pub channel: Channel,
+}
+
+/// A renamed, reconfigured or moved channel. The per-user read state
+/// fields of `channel` are not filled in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsChannelUpdated {
+    pub channel: Channel,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsChannelDeleted {
+    pub server_id: Uuid,
+    pub channel_id: Uuid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsCategoryCreated {
+    pub category: ChannelCategory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsCategoryUpdated {
+    pub category: ChannelCategory,
+}
+
+/// The category's channels are now uncategorised, keeping their positions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsCategoryDeleted {
+    pub server_id: Uuid,
+    pub category_id: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsMemberJoined {
     pub server_id: Uuid,
     pub member: crate::models::ServerMember,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsMemberLeft {
+    pub server_id: Uuid,
+    pub user_id: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsError {
     pub message: String,
+    /// Machine-readable reason, e.g. `missing_permissions`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
 }
 
 // ────────────────────────────────────────────────────────────────────────────
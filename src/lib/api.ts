@@ -86,6 +86,45 @@ export async function updateMe(data: Record<string, unknown>) {
     return request("/me", { method: "PATCH", body: JSON.stringify(data) });
 }
 
+// ── Mentions ─────────────────────────────────────────────────────────
+
+/** Newest first; `before` is the message id of the last mention already loaded. */
+export async function listMentions(
+    params: { limit?: number; before?: string; unread?: boolean } = {},
+): Promise<import("./types").Mention[]> {
+    const search = new URLSearchParams();
+    for (const [key, value] of Object.entries(params)) {
+        if (value !== undefined) search.set(key, String(value));
+    }
+    const query = search.toString();
+    return request(`/me/mentions${query ? `?${query}` : ""}`);
+}
+
+/** Marks the given mentions as read, or all of them when `messageIds` is omitted. */
+export async function markMentionsRead(messageIds?: string[]) {
+    return request("/me/mentions/read", {
+        method: "POST",
+        body: JSON.stringify({ message_ids: messageIds }),
+    });
+}
+
+// ── Read states ──────────────────────────────────────────────────────
+
+/** Moves the read marker forward; acking an older message is a no-op. */
+export async function ackChannel(channelId: string, messageId: string): Promise<import("./types").ReadState> {
+    return request(`/channels/${channelId}/ack`, {
+        method: "POST",
+        body: JSON.stringify({ message_id: messageId }),
+    });
+}
+
+export async function ackDm(conversationId: string, messageId: string): Promise<import("./types").ReadState> {
+    return request(`/dms/${conversationId}/ack`, {
+        method: "POST",
+        body: JSON.stringify({ message_id: messageId }),
+    });
+}
+
 // ── Servers ──────────────────────────────────────────────────────────
 
 export async function listServers(): Promise<Server[]> {
@@ -99,17 +138,13 @@ export async function createServer(name: string, icon_url?: string): Promise<Ser
     });
 }
 
-export async function updateServer(serverId: string, data: { name?: string; icon_url?: string }): Promise<Server> {
+export async function updateServer(serverId: string, data: { name?: string; icon_url?: string; link_previews?: boolean; edit_history_days?: number }): Promise<Server> {
     return request(`/servers/${serverId}`, {
         method: "PATCH",
         body: JSON.stringify(data),
     });
 }
 
-export async function joinServer(serverId: string) {
-    return request(`/servers/${serverId}/join`, { method: "POST" });
-}
-
 export async function leaveServer(serverId: string) {
     return request(`/servers/${serverId}/leave`, { method: "POST" });
 }
@@ -118,16 +153,135 @@ export async function getServerMembers(serverId: string): Promise<ServerMember[]
     return request(`/servers/${serverId}/members`);
 }
 
+export async function kickMember(serverId: string, userId: string) {
+    return request(`/servers/${serverId}/members/${userId}`, { method: "DELETE" });
+}
+
+/** Server mute, server deafen or move a member to another voice channel. */
+export async function updateMemberVoice(
+    serverId: string,
+    userId: string,
+    changes: { server_muted?: boolean; server_deafened?: boolean; channel_id?: string },
+) {
+    return request(`/servers/${serverId}/members/${userId}/voice`, {
+        method: "PATCH",
+        body: JSON.stringify(changes),
+    });
+}
+
+export async function disconnectMemberVoice(serverId: string, userId: string) {
+    return request(`/servers/${serverId}/members/${userId}/voice`, { method: "DELETE" });
+}
+
+/** Newest first; `before` is the id of the oldest entry already loaded. Needs manage server. */
+export async function getAuditLog(
+    serverId: string,
+    params: { limit?: number; before?: string } = {},
+): Promise<import("./types").AuditLogEntry[]> {
+    return request(`/servers/${serverId}/audit_log${pageQuery(params)}`);
+}
+
+// ── Invites ──────────────────────────────────────────────────────────
+
+export async function createInvite(
+    serverId: string,
+    options: { max_uses?: number; max_age_secs?: number; channel_id?: string } = {},
+): Promise<import("./types").Invite> {
+    return request(`/servers/${serverId}/invites`, {
+        method: "POST",
+        body: JSON.stringify(options),
+    });
+}
+
+export async function listInvites(serverId: string): Promise<import("./types").Invite[]> {
+    return request(`/servers/${serverId}/invites`);
+}
+
+export async function revokeInvite(code: string) {
+    return request(`/invites/${code}`, { method: "DELETE" });
+}
+
+export async function getInvite(code: string): Promise<import("./types").InvitePreview> {
+    return request(`/invites/${code}`);
+}
+
+export async function acceptInvite(code: string): Promise<import("./types").InviteAccepted> {
+    return request(`/invites/${code}/accept`, { method: "POST" });
+}
+
+// ── Roles ────────────────────────────────────────────────────────────
+
+export async function listRoles(serverId: string): Promise<import("./types").Role[]> {
+    return request(`/servers/${serverId}/roles`);
+}
+
+export async function createRole(serverId: string, name: string, permissions: number): Promise<import("./types").Role> {
+    return request(`/servers/${serverId}/roles`, {
+        method: "POST",
+        body: JSON.stringify({ name, permissions }),
+    });
+}
+
+export async function updateRole(roleId: string, data: { name?: string; permissions?: number }): Promise<import("./types").Role> {
+    return request(`/roles/${roleId}`, {
+        method: "PATCH",
+        body: JSON.stringify(data),
+    });
+}
+
+export async function deleteRole(roleId: string) {
+    return request(`/roles/${roleId}`, { method: "DELETE" });
+}
+
+export async function assignRole(serverId: string, userId: string, roleId: string) {
+    return request(`/servers/${serverId}/members/${userId}/roles/${roleId}`, { method: "PUT" });
+}
+
+export async function unassignRole(serverId: string, userId: string, roleId: string) {
+    return request(`/servers/${serverId}/members/${userId}/roles/${roleId}`, { method: "DELETE" });
+}
+
+export async function getChannelPermissions(channelId: string): Promise<import("./types").ChannelPermissionOverride[]> {
+    return request(`/channels/${channelId}/permissions`);
+}
+
+export async function setChannelPermissions(channelId: string, roleId: string, allow: number, deny: number) {
+    return request(`/channels/${channelId}/permissions/${roleId}`, {
+        method: "PUT",
+        body: JSON.stringify({ allow, deny }),
+    });
+}
+
+export async function clearChannelPermissions(channelId: string, roleId: string) {
+    return request(`/channels/${channelId}/permissions/${roleId}`, { method: "DELETE" });
+}
+
 // ── Channels ─────────────────────────────────────────────────────────
 
 export async function listChannels(serverId: string): Promise<Channel[]> {
     return request(`/servers/${serverId}/channels`);
 }
 
-export async function createChannel(serverId: string, name: string, type: string): Promise<Channel> {
+export async function createChannel(
+    serverId: string,
+    name: string,
+    type: string,
+    categoryId: string | null = null,
+): Promise<Channel> {
     return request(`/servers/${serverId}/channels`, {
         method: "POST",
-        body: JSON.stringify({ name, type }),
+        body: JSON.stringify({ name, type, category_id: categoryId }),
+    });
+}
+
+/** Unset fields are left as they are; an empty topic clears it. */
+export async function updateChannel(
+    channelId: string,
+    data: { name?: string; topic?: string; slowmode_secs?: number; nsfw?: boolean; voice_mode?: "mesh" | "sfu" },
+): Promise<Channel> {
+    return request(`/channels/${channelId}`, {
+        method: "PATCH",
+        body: JSON.stringify(data),
     });
 }
 
@@ -135,18 +289,73 @@ export async function deleteChannel(channelId: string) {
     return request(`/channels/${channelId}`, { method: "DELETE" });
 }
 
+/** Moves channels and categories in one step; either all of them move or none do. */
+export async function reorderChannels(
+    serverId: string,
+    channels: { id: string; category_id: string | null; position: number }[],
+    categories: { id: string; position: number }[] = [],
+) {
+    return request(`/servers/${serverId}/channels`, {
+        method: "PATCH",
+        body: JSON.stringify({ channels, categories }),
+    });
+}
+
+export async function listCategories(serverId: string): Promise<import("./types").ChannelCategory[]> {
+    return request(`/servers/${serverId}/categories`);
+}
+
+export async function createCategory(serverId: string, name: string): Promise<import("./types").ChannelCategory> {
+    return request(`/servers/${serverId}/categories`, {
+        method: "POST",
+        body: JSON.stringify({ name }),
+    });
+}
+
+export async function renameCategory(categoryId: string, name: string): Promise<import("./types").ChannelCategory> {
+    return request(`/categories/${categoryId}`, {
+        method: "PATCH",
+        body: JSON.stringify({ name }),
+    });
+}
+
+export async function deleteCategory(categoryId: string) {
+    return request(`/categories/${categoryId}`, { method: "DELETE" });
+}
+
 // ── Messages ─────────────────────────────────────────────────────────
 
-export async function getMessages(channelId: string, limit = 50, before?: string): Promise<Message[]> {
-    const params = new URLSearchParams({ limit: String(limit) });
-    if (before) params.set("before", before);
-    return request(`/channels/${channelId}/messages?${params}`);
+/** History paging; `before`, `after` and `around` are message ids and only one may be set. */
+export interface MessagePageParams {
+    limit?: number;
+    before?: string;
+    after?: string;
+    around?: string;
+}
+
+function pageQuery(params: MessagePageParams): string {
+    const search = new URLSearchParams();
+    for (const [key, value] of Object.entries(params)) {
+        if (value !== undefined) search.set(key, String(value));
+    }
+    const query = search.toString();
+    return query ? `?${query}` : "";
+}
+
+/** Returns one page of history, oldest first. */
+export async function getMessages(channelId: string, params: MessagePageParams = {}): Promise<Message[]> {
+    return request(`/channels/${channelId}/messages${pageQuery(params)}`);
 }
 
-export async function createMessage(channelId: string, content: string): Promise<Message> {
+export async function createMessage(
+    channelId: string,
+    content: string,
+    replyTo?: string,
+    attachmentIds: string[] = [],
+): Promise<Message> {
     return request(`/channels/${channelId}/messages`, {
         method: "POST",
-        body: JSON.stringify({ content }),
+        body: JSON.stringify({ content, reply_to: replyTo, attachment_ids: attachmentIds }),
     });
 }
 
@@ -161,10 +370,18 @@ export async function editMessage(messageId: string, content: string) {
     });
 }
 
+export async function getMessageHistory(messageId: string): Promise<import("./types").MessageRevision[]> {
+    return request(`/messages/${messageId}/history`);
+}
+
 export async function deleteMessage(messageId: string) {
     return request(`/messages/${messageId}`, { method: "DELETE" });
 }
 
+export async function restoreMessage(messageId: string): Promise<Message> {
+    return request(`/messages/${messageId}/restore`, { method: "POST" });
+}
+
 export async function pinMessage(messageId: string) {
     return request(`/messages/${messageId}/pin`, { method: "POST" });
 }
@@ -187,9 +404,64 @@ export async function removeReaction(messageId: string, emoji: string) {
     });
 }
 
+// ── Threads ──────────────────────────────────────────────────────────
+
+export async function createThread(messageId: string, name?: string): Promise<import("./types").Thread> {
+    return request(`/messages/${messageId}/thread`, {
+        method: "POST",
+        body: JSON.stringify({ name }),
+    });
+}
+
+export async function listThreads(channelId: string): Promise<import("./types").Thread[]> {
+    return request(`/channels/${channelId}/threads`);
+}
+
+export async function getThread(threadId: string): Promise<import("./types").Thread> {
+    return request(`/threads/${threadId}`);
+}
+
+export async function getThreadMessages(threadId: string, params: MessagePageParams = {}): Promise<Message[]> {
+    return request(`/threads/${threadId}/messages${pageQuery(params)}`);
+}
+
+export async function createThreadMessage(threadId: string, content: string, replyTo?: string): Promise<Message> {
+    return request(`/threads/${threadId}/messages`, {
+        method: "POST",
+        body: JSON.stringify({ content, reply_to: replyTo }),
+    });
+}
+
+export async function getThreadParticipants(threadId: string): Promise<import("./types").UserPublic[]> {
+    return request(`/threads/${threadId}/participants`);
+}
+
+// ── Search ───────────────────────────────────────────────────────────
+
+export interface SearchParams {
+    server_id?: string;
+    channel_id?: string;
+    conversation_id?: string;
+    author_id?: string;
+    since?: string;
+    until?: string;
+    limit?: number;
+    offset?: number;
+}
+
+/** Full-text search. `query` may include the `has:attachment` filter. */
+export async function searchMessages(query: string, params: SearchParams = {}): Promise<import("./types").SearchResults> {
+    const search = new URLSearchParams({ q: query });
+    for (const [key, value] of Object.entries(params)) {
+        if (value !== undefined) search.set(key, String(value));
+    }
+    return request(`/search?${search}`);
+}
+
 // ── Upload ───────────────────────────────────────────────────────────
 
-export async function uploadFile(file: File): Promise<{ url: string; file_name: string; mime_type: string; size_bytes: number }> {
+/** Stores a file as a pending upload; attach it by passing its `id` when posting. */
+export async function uploadFile(file: File): Promise<import("./types").Upload> {
     const formData = new FormData();
     formData.append("file", file);
     const token = localStorage.getItem("token");
@@ -198,16 +470,33 @@ export async function uploadFile(file: File): Promise<{ url: string; file_name:
         headers: token ? { Authorization: `Bearer ${token}` } : {},
         body: formData,
     });
-    if (!res.ok) throw new Error(`Upload failed: ${res.status}`);
+    if (!res.ok) {
+        // Rejections carry `{ error, code }` explaining why
+        const body = await res.json().catch(() => null);
+        throw new Error(body?.error ?? `Upload failed: ${res.status}`);
+    }
     return res.json();
 }
 
 // ── TURN ─────────────────────────────────────────────────────────────
 
-export async function getTurnCredentials(): Promise<{ uris: string[], username: string; credential: string }> {
+/** Short-lived TURN credentials; `username` and `credential` are null when the server has no TURN server set up. */
+export async function getTurnCredentials(): Promise<{
+    uris: string[];
+    username: string | null;
+    credential: string | null;
+    ttl: number | null;
+}> {
     return request("/turn");
 }
 
+// ── SFU ──────────────────────────────────────────────────────────────
+
+/** Where to connect for a voice channel in SFU mode, and the token to authenticate with. Requires having joined the channel. */
+export async function getSfuSession(channelId: string): Promise<{ url: string; token: string }> {
+    return request(`/channels/${channelId}/sfu`);
+}
+
 // ── Version ──────────────────────────────────────────────────────────
 
 export async function getServerVersion(): Promise<{ version: string }> {
@@ -227,14 +516,18 @@ export async function createDmConversation(recipientUsername: string): Promise<i
     });
 }
 
-export async function getDmMessages(conversationId: string): Promise<import("./types").DmMessage[]> {
-    return request(`/dms/${conversationId}/messages`);
+export async function getDmMessages(conversationId: string, params: MessagePageParams = {}): Promise<import("./types").DmMessage[]> {
+    return request(`/dms/${conversationId}/messages${pageQuery(params)}`);
 }
 
-export async function createDmMessage(conversationId: string, content: string): Promise<import("./types").DmMessage> {
+export async function createDmMessage(
+    conversationId: string,
+    content: string,
+    attachmentIds: string[] = [],
+): Promise<import("./types").DmMessage> {
     return request(`/dms/${conversationId}/messages`, {
         method: "POST",
-        body: JSON.stringify({ content }),
+        body: JSON.stringify({ content, attachment_ids: attachmentIds }),
     });
 }
 
@@ -245,6 +538,10 @@ export async function editDmMessage(messageId: string, content: string) {
     });
 }
 
+export async function getDmMessageHistory(messageId: string): Promise<import("./types").MessageRevision[]> {
+    return request(`/dm_messages/${messageId}/history`);
+}
+
 export async function deleteDmMessage(messageId: string) {
     return request(`/dm_messages/${messageId}`, { method: "DELETE" });
 }
@@ -1,5 +1,5 @@
 import { writable, derived, get } from "svelte/store";
-import type { User, UserPublic, Server, Channel, Message, ServerMember, VoiceState } from "./types";
+import type { User, UserPublic, Server, Channel, ChannelCategory, Message, ServerMember, VoiceState } from "./types";
 import { DEFAULT_THEME } from "./config";
 
 // ── Auth ─────────────────────────────────────────────────────────────
@@ -29,8 +29,33 @@ export const currentChannel = derived(
     ([$channels, $id]) => $channels.find((c) => c.id === $id) ?? null
 );
 
-export const textChannels = derived(channels, ($c) => $c.filter((c) => c.type === "text"));
-export const voiceChannels = derived(channels, ($c) => $c.filter((c) => c.type === "voice"));
+const byPosition = (a: { position: number; created_at: string }, b: { position: number; created_at: string }) =>
+    a.position - b.position || a.created_at.localeCompare(b.created_at);
+
+/** Channels outside any category */
+export const textChannels = derived(channels, ($c) =>
+    $c.filter((c) => c.type === "text" && !c.category_id).sort(byPosition),
+);
+export const voiceChannels = derived(channels, ($c) =>
+    $c.filter((c) => c.type === "voice" && !c.category_id).sort(byPosition),
+);
+
+export const categories = writable<ChannelCategory[]>([]);
+/** Categories in order, each with its channels in order */
+export const categorizedChannels = derived([categories, channels], ([$cats, $chs]) =>
+    [...$cats].sort(byPosition).map((category) => ({
+        category,
+        channels: $chs.filter((c) => c.category_id === category.id).sort(byPosition),
+    })),
+);
+
+/** Ids of the categories the user has folded away */
+export const collapsedCategories = writable<string[]>(
+    JSON.parse(localStorage.getItem("collapsedCategories") ?? "[]"),
+);
+collapsedCategories.subscribe((ids) => {
+    localStorage.setItem("collapsedCategories", JSON.stringify(ids));
+});
 
 // ── Messages ─────────────────────────────────────────────────────────
 export const messages = writable<Message[]>([]);
@@ -86,6 +111,10 @@ export const currentDmConversation = derived(
 export const dmMessages = writable<import("./types").DmMessage[]>([]);
 export const isDmMode = writable(false);
 
+// ── Mentions ─────────────────────────────────────────────────────────
+/** Mentions received over the socket since the inbox was last opened */
+export const unreadMentionCount = writable(0);
+
 // ── User Status ──────────────────────────────────────────────────────
 export const userStatuses = writable<Record<string, import("./types").UserStatus>>({});
 
@@ -127,6 +156,7 @@ export function logout() {
     currentUser.set(null);
     servers.set([]);
     channels.set([]);
+    categories.set([]);
     messages.set([]);
     pinnedMessages.set([]);
     members.set([]);
@@ -35,6 +35,14 @@ export interface Server {
     owner_id: string;
     created_at: string;
     updated_at: string;
+    /** Joining requires an invite code */
+    /** Links in messages get preview embeds */
+    link_previews: boolean;
+    /** Days earlier versions of edited messages are kept; 0 keeps them forever */
+    edit_history_days: number;
+    /** Unread messages across the server's text channels */
+    unread_count: number;
+    mention_count: number;
 }
 
 export interface Channel {
@@ -43,9 +51,27 @@ export interface Channel {
     name: string;
     type: "text" | "voice";
     position: number;
-    topic?: string;
+    /** null for channels outside any category */
+    category_id: string | null;
+    topic: string | null;
+    /** Seconds members wait between messages; 0 = off */
+    slowmode_secs: number;
+    nsfw: boolean;
+    /** Voice channels: "mesh" sends media peer to peer, "sfu" through the server's SFU */
+    voice_mode: "mesh" | "sfu";
     created_at: string;
     updated_at: string;
+    unread_count: number;
+    mention_count: number;
+    last_read_message_id: string | null;
+}
+
+export interface ChannelCategory {
+    id: string;
+    server_id: string;
+    name: string;
+    position: number;
+    created_at: string;
 }
 
 export interface Message {
@@ -59,6 +85,62 @@ export interface Message {
     author: UserPublic | null;
     attachments: Attachment[];
     reactions: ReactionGroup[];
+    reply_to: MessageReference | null;
+    /** Set for replies inside a thread */
+    thread_id: string | null;
+    /** The thread rooted at this message */
+    thread: Thread | null;
+    /** Link previews, filled in by a later `message_updated` */
+    embeds: Embed[];
+    /** Set once deleted; only moderators still get the content */
+    deleted_at: string | null;
+    /** Who deleted it, shown to the author and moderators */
+    deleted_by: string | null;
+}
+
+/** Preview of a link from the page's OpenGraph or Twitter card tags */
+export interface Embed {
+    url: string;
+    title: string | null;
+    description: string | null;
+    site_name: string | null;
+    image_url: string | null;
+    large_image: boolean;
+}
+
+/** An earlier version of an edited message, oldest first */
+export interface MessageRevision {
+    revision: number;
+    content: string | null;
+    /** When this version was posted or last edited */
+    created_at: string;
+    /** When the next edit replaced it */
+    replaced_at: string;
+    replaced_by: string | null;
+}
+
+/** Preview of a replied-to message; `author` is null once it was deleted */
+export interface MessageReference {
+    message_id: string;
+    author: UserPublic | null;
+    content: string | null;
+}
+
+export interface Mention {
+    message: Message;
+    server_id: string;
+    read: boolean;
+}
+
+export interface Thread {
+    id: string;
+    channel_id: string;
+    root_message_id: string;
+    creator_id: string;
+    name: string;
+    message_count: number;
+    last_message_at: string | null;
+    created_at: string;
 }
 
 export interface Attachment {
@@ -69,6 +151,31 @@ export interface Attachment {
     mime_type: string;
     size_bytes: number | null;
     created_at: string;
+    /** Images and videos; lets the message be laid out before the media loads */
+    width?: number | null;
+    height?: number | null;
+    blurhash?: string | null;
+    /** Small WebP rendition for the message list */
+    thumbnail_url?: string | null;
+    /** Larger WebP rendition for viewing; absent when the original is small */
+    preview_url?: string | null;
+    /** Videos only, in seconds */
+    duration_secs?: number | null;
+}
+
+/** A stored file waiting to be attached via `attachment_ids` */
+export interface Upload {
+    id: string;
+    url: string;
+    file_name: string;
+    mime_type: string;
+    size_bytes: number;
+    width: number | null;
+    height: number | null;
+    blurhash: string | null;
+    thumbnail_url: string | null;
+    preview_url: string | null;
+    duration_secs: number | null;
 }
 
 export interface ReactionGroup {
@@ -82,6 +189,9 @@ export interface VoiceState {
     channel_id: string;
     muted: boolean;
     deafened: boolean;
+    /** Set by moderators; the user cannot lift them */
+    server_muted: boolean;
+    server_deafened: boolean;
     joined_at: string;
     username: string | null;
     avatar_url: string | null;
@@ -95,6 +205,8 @@ export interface ServerMember {
     username: string;
     avatar_url: string | null;
     status: UserStatus | null;
+    /** Explicitly assigned role ids; @everyone is implied */
+    roles: string[];
 }
 
 export interface AuthResponse {
@@ -134,6 +246,19 @@ export interface DmConversation {
     last_message: DmMessage | null;
     created_at: string;
     updated_at: string;
+    unread_count: number;
+    /** Every unread DM counts as a mention */
+    mention_count: number;
+    last_read_message_id: string | null;
+}
+
+/** The caller's read marker in one channel or DM conversation */
+export interface ReadState {
+    channel_id: string | null;
+    conversation_id: string | null;
+    last_read_message_id: string | null;
+    unread_count: number;
+    mention_count: number;
 }
 
 export interface DmMessage {
@@ -147,3 +272,98 @@ export interface DmMessage {
     attachments: Attachment[];
     reactions: ReactionGroup[];
 }
+
+// ── Search ───────────────────────────────────────────────────────────
+
+export interface MessageSearchHit {
+    message: Message;
+    snippet: string;
+}
+
+export interface DmMessageSearchHit {
+    message: DmMessage;
+    snippet: string;
+}
+
+export interface SearchResults {
+    messages: MessageSearchHit[];
+    dm_messages: DmMessageSearchHit[];
+    has_more: boolean;
+}
+
+// ── Roles & Permissions ──────────────────────────────────────────────
+
+export const Permission = {
+    MANAGE_CHANNELS: 1 << 0,
+    MANAGE_MESSAGES: 1 << 1,
+    PIN_MESSAGES: 1 << 2,
+    KICK_MEMBERS: 1 << 3,
+    MANAGE_ROLES: 1 << 4,
+    CONNECT_VOICE: 1 << 5,
+    SPEAK: 1 << 6,
+    MANAGE_SERVER: 1 << 7,
+    CREATE_INVITE: 1 << 8,
+    MUTE_MEMBERS: 1 << 9,
+    DEAFEN_MEMBERS: 1 << 10,
+    MOVE_MEMBERS: 1 << 11,
+} as const;
+
+export interface Role {
+    id: string;
+    server_id: string;
+    name: string;
+    permissions: number;
+    position: number;
+    created_at: string;
+}
+
+export interface ChannelPermissionOverride {
+    channel_id: string;
+    role_id: string;
+    allow: number;
+    deny: number;
+}
+
+// ── Audit Log ────────────────────────────────────────────────────────
+
+export interface AuditLogEntry {
+    id: string;
+    server_id: string;
+    actor_id: string | null;
+    /** `server_mute`, `server_unmute`, `server_deafen`, `server_undeafen`, `voice_move` or `voice_disconnect` */
+    action: string;
+    target_user_id: string | null;
+    channel_id: string | null;
+    /** Moves only */
+    to_channel_id: string | null;
+    created_at: string;
+}
+
+// ── Invites ──────────────────────────────────────────────────────────
+
+export interface Invite {
+    code: string;
+    server_id: string;
+    channel_id: string | null;
+    creator_id: string;
+    max_uses: number | null;
+    uses: number;
+    expires_at: string | null;
+    created_at: string;
+}
+
+export interface InvitePreview {
+    code: string;
+    server_id: string;
+    server_name: string;
+    server_icon_url: string | null;
+    member_count: number;
+    channel_id: string | null;
+    channel_name: string | null;
+    expires_at: string | null;
+}
+
+export interface InviteAccepted {
+    server: Server;
+    channel_id: string | null;
+}
@@ -1,10 +1,10 @@
 import { get } from "svelte/store";
-import { currentUser, voiceStates, voiceChannelId } from "./stores";
+import { channels, currentUser, voiceStates, voiceChannelId, isMuted, isDeafened } from "./stores";
 import { wsSignalSdp, wsSignalIce } from "./ws";
 import type { SignalSdpPayload, SignalIcePayload } from "./types";
 import { writable } from "svelte/store";
 
-import { getServerUrl, getTurnCredentials } from "./api";
+import { getServerUrl, getSfuSession, getTurnCredentials } from "./api";
 
 async function getIceServers(): Promise<RTCConfiguration> {
     const serverUrl = getServerUrl();
@@ -20,6 +20,13 @@ async function getIceServers(): Promise<RTCConfiguration> {
         const creds = await getTurnCredentials();
         let turnUrls = creds.uris;
 
+        if (!creds.username || !creds.credential) {
+            // No TURN server configured, only STUN
+            return {
+                iceServers: [{ urls: turnUrls.length > 0 ? turnUrls : `stun:${hostname}:3478` }],
+            };
+        }
+
         if (!turnUrls || turnUrls.length === 0) {
             // Just assume the TURN server is running on the same host as the subspace instance
             turnUrls = [`turn:${hostname}:3478`];
@@ -58,6 +65,10 @@ let localStream: MediaStream | null = null;
 let localVideoTrack: MediaStreamTrack | null = null;
 let localScreenTrack: MediaStreamTrack | null = null;
 let peerConnections: Record<string, RTCPeerConnection> = {};
+/** Set while in a voice channel whose media goes through the SFU instead of peer to peer */
+let sfu: { ws: WebSocket; publisher: RTCPeerConnection; subscriber: RTCPeerConnection } | null = null;
+/** Who the SFU says is speaking; replaces local analysis of remote audio in SFU mode */
+let sfuSpeakers = new Set<string>();
 
 export const remoteStreams = writable<Record<string, MediaStream[]>>({});
 export const localVideoStream = writable<MediaStream | null>(null);
@@ -363,7 +374,8 @@ function startSpeakingCheckLoop() {
         }
 
         // Check remotes
-        for (const [userId, { analyser }] of Object.entries(remoteNodes)) {
+        if (sfu) sfuSpeakers.forEach((userId) => speaking.add(userId));
+        else for (const [userId, { analyser }] of Object.entries(remoteNodes)) {
             const data = new Uint8Array(analyser.frequencyBinCount);
             analyser.getByteFrequencyData(data);
             const avg = data.reduce((a, b) => a + b, 0) / data.length;
@@ -401,6 +413,12 @@ export async function joinVoice(channelId: string) {
         startLocalSpeakingDetection(localStream);
         startSpeakingCheckLoop();
 
+        // SFU channels send everything through one connection to the SFU
+        if (get(channels).find((c) => c.id === channelId)?.voice_mode === "sfu") {
+            await joinSfu(channelId);
+            return;
+        }
+
         // 3. Listen for signaling events
         window.addEventListener("webrtc_signal", handleSignal as unknown as EventListener);
 
@@ -438,6 +456,14 @@ export function leaveVoice() {
     // 2. Close all peer connections
     Object.values(peerConnections).forEach((pc) => pc.close());
     peerConnections = {};
+    if (sfu) {
+        const { ws, publisher, subscriber } = sfu;
+        sfu = null;
+        ws.close();
+        publisher.close();
+        subscriber.close();
+        sfuSpeakers = new Set();
+    }
 
     // 3. Cleanup remote nodes
     Object.keys(remoteNodes).forEach(cleanupRemoteUser);
@@ -453,19 +479,57 @@ export function leaveVoice() {
     window.removeEventListener("webrtc_signal", handleSignal as unknown as EventListener);
 }
 
+// Server mute and deafen are set by moderators and hold on top of the
+// user's own; a server deafen also mutes.
+let serverMuted = false;
+let serverDeafened = false;
+
+voiceStates.subscribe((all) => {
+    const channelId = get(voiceChannelId);
+    const myId = get(currentUser)?.id;
+    const mine = channelId ? all[channelId]?.find((s) => s.user_id === myId) : undefined;
+    const muted = mine?.server_muted ?? false;
+    const deafened = mine?.server_deafened ?? false;
+    if (muted === serverMuted && deafened === serverDeafened) return;
+    serverMuted = muted;
+    serverDeafened = deafened;
+    toggleMute(get(isMuted));
+    toggleDeafen(get(isDeafened));
+});
+
 export function toggleMute(muted: boolean) {
     if (localStream) {
-        localStream.getAudioTracks().forEach((t) => (t.enabled = !muted));
+        const silenced = muted || serverMuted || serverDeafened;
+        localStream.getAudioTracks().forEach((t) => (t.enabled = !silenced));
     }
 }
 
 export function toggleDeafen(deafened: boolean) {
     // Mute/unmute all remote audio via GainNodes
     Object.values(remoteNodes).forEach(({ gain }) => {
-        gain.gain.value = deafened ? 0 : 1;
+        gain.gain.value = deafened || serverDeafened ? 0 : 1;
     });
 }
 
+/** A moderator moved us to another voice channel, or disconnected us when `channel_id` is null. */
+async function handleVoiceMoved(event: CustomEvent<{ channel_id: string | null }>) {
+    if (!get(voiceChannelId)) return;
+    const channelId = event.detail.channel_id;
+    leaveVoice();
+    voiceChannelId.set(channelId);
+    if (channelId) {
+        await joinVoice(channelId);
+        toggleMute(get(isMuted));
+    } else {
+        isMuted.set(false);
+        isDeafened.set(false);
+    }
+}
+
+if (typeof window !== "undefined") {
+    window.addEventListener("voice_moved", handleVoiceMoved as unknown as EventListener);
+}
+
 export async function toggleVideo(enable: boolean) {
     if (enable) {
         if (localVideoTrack) return; // already enabled
@@ -474,6 +538,8 @@ export async function toggleVideo(enable: boolean) {
             localVideoTrack = stream.getVideoTracks()[0];
             localVideoStream.set(stream);
 
+            if (sfu) publishToSfu(localVideoTrack);
+
             // Add track to all peer connections
             Object.values(peerConnections).forEach(pc => {
                 pc.addTrack(localVideoTrack!, stream); // stream doesn't matter much here but required
@@ -491,6 +557,7 @@ export async function toggleVideo(enable: boolean) {
     } else {
         if (localVideoTrack) {
             localVideoTrack.stop();
+            if (sfu) unpublishFromSfu(localVideoTrack);
             // Remove from PCs
             Object.values(peerConnections).forEach(pc => {
                 const sender = pc.getSenders().find(s => s.track === localVideoTrack);
@@ -510,6 +577,7 @@ export async function toggleScreenShare(enable: boolean) {
             localScreenTrack = stream.getVideoTracks()[0];
             localScreenStream.set(stream);
 
+            if (sfu) publishToSfu(localScreenTrack);
             Object.values(peerConnections).forEach(pc => {
                 pc.addTrack(localScreenTrack!, stream);
             });
@@ -523,6 +591,7 @@ export async function toggleScreenShare(enable: boolean) {
     } else {
         if (localScreenTrack) {
             localScreenTrack.stop();
+            if (sfu) unpublishFromSfu(localScreenTrack);
             Object.values(peerConnections).forEach(pc => {
                 const sender = pc.getSenders().find(s => s.track === localScreenTrack);
                 if (sender) pc.removeTrack(sender);
@@ -688,6 +757,135 @@ async function handleSignal(event: CustomEvent) {
     }
 }
 
+// ── SFU ──────────────────────────────────────────────────────────────────────
+
+/** Video goes up in three sizes; the SFU picks one per viewer */
+const SIMULCAST_ENCODINGS: RTCRtpEncodingParameters[] = [
+    { rid: "l", scaleResolutionDownBy: 4, maxBitrate: 150_000 },
+    { rid: "m", scaleResolutionDownBy: 2, maxBitrate: 500_000 },
+    { rid: "h", maxBitrate: 1_500_000 },
+];
+
+async function getSfuSessionOnceJoined(channelId: string) {
+    // The join_voice message may still be on its way to the server
+    for (let attempt = 0; ; attempt++) {
+        try {
+            return await getSfuSession(channelId);
+        } catch (e) {
+            if (attempt >= 10 || !String(e).includes("not_in_voice")) throw e;
+            await new Promise((resolve) => setTimeout(resolve, 200));
+        }
+    }
+}
+
+async function joinSfu(channelId: string) {
+    const session = await getSfuSessionOnceJoined(channelId);
+    const iceServers = await getIceServers();
+    const ws = new WebSocket(session.url);
+    // We offer our own media on the publisher connection; the SFU offers everyone else's on the subscriber one
+    const publisher = new RTCPeerConnection(iceServers);
+    const subscriber = new RTCPeerConnection(iceServers);
+    const current = { ws, publisher, subscriber };
+    sfu = current;
+
+    const send = (type: string, payload: unknown) => {
+        if (ws.readyState === WebSocket.OPEN) ws.send(JSON.stringify({ type, payload }));
+    };
+
+    for (const [pc, target] of [[publisher, "publisher"], [subscriber, "subscriber"]] as const) {
+        pc.onicecandidate = (event) => {
+            if (event.candidate) {
+                send("candidate", {
+                    target,
+                    candidate: event.candidate.candidate,
+                    sdp_mid: event.candidate.sdpMid,
+                    sdp_mline_index: event.candidate.sdpMLineIndex,
+                });
+            }
+        };
+        pc.oniceconnectionstatechange = () => {
+            console.log(`[SFU] ICE Connection State (${target}):`, pc.iceConnectionState);
+            if (pc.iceConnectionState === "failed") webrtcError.set("Could not reach the voice server.");
+        };
+    }
+
+    // Each user's tracks arrive in a stream named after them
+    subscriber.ontrack = (event) => {
+        const [stream] = event.streams;
+        if (stream) handleRemoteStream(stream.id, stream);
+    };
+
+    publisher.onnegotiationneeded = async () => {
+        try {
+            const offer = await publisher.createOffer();
+            await publisher.setLocalDescription(offer);
+            send("offer", { target: "publisher", sdp: offer.sdp });
+        } catch (e) {
+            console.error("[SFU] Negotiation error:", e);
+        }
+    };
+
+    ws.onopen = () => send("auth", { token: session.token });
+    ws.onclose = () => {
+        if (sfu === current) webrtcError.set("Lost the connection to the voice server.");
+    };
+    ws.onmessage = async (event) => {
+        const env = JSON.parse(event.data);
+        const payload = env.payload;
+        try {
+            switch (env.type) {
+                case "auth_success":
+                    localStream?.getAudioTracks().forEach((track) => publisher.addTrack(track, localStream!));
+                    if (localVideoTrack) publishToSfu(localVideoTrack);
+                    if (localScreenTrack) publishToSfu(localScreenTrack);
+                    break;
+                case "offer": {
+                    await subscriber.setRemoteDescription({ type: "offer", sdp: payload.sdp });
+                    const answer = await subscriber.createAnswer();
+                    await subscriber.setLocalDescription(answer);
+                    send("answer", { target: "subscriber", sdp: answer.sdp });
+                    break;
+                }
+                case "answer":
+                    await publisher.setRemoteDescription({ type: "answer", sdp: payload.sdp });
+                    break;
+                case "candidate":
+                    await (payload.target === "publisher" ? publisher : subscriber).addIceCandidate({
+                        candidate: payload.candidate,
+                        sdpMid: payload.sdp_mid,
+                        sdpMLineIndex: payload.sdp_mline_index,
+                    });
+                    break;
+                case "active_speakers":
+                    sfuSpeakers = new Set(payload.speakers.map((s: { user_id: string }) => s.user_id));
+                    break;
+                case "participant_left":
+                    cleanupRemoteUser(payload.user_id);
+                    break;
+                case "error":
+                    console.error("[SFU]", payload.message);
+                    webrtcError.set(payload.message);
+                    break;
+            }
+        } catch (e) {
+            console.error(`[SFU] Failed to handle ${env.type}:`, e);
+        }
+    };
+}
+
+function publishToSfu(track: MediaStreamTrack) {
+    sfu?.publisher.addTransceiver(track, {
+        direction: "sendonly",
+        streams: [new MediaStream([track])],
+        sendEncodings: SIMULCAST_ENCODINGS,
+    });
+}
+
+function unpublishFromSfu(track: MediaStreamTrack) {
+    const sender = sfu?.publisher.getSenders().find((s) => s.track === track);
+    if (sender) sfu!.publisher.removeTrack(sender);
+}
+
 // ── Diagnostics Collection ──────────────────────────────────────────────────
 
 function generateDetailedStatus(
@@ -1,6 +1,6 @@
 import { get } from "svelte/store";
-import { authToken, currentUser, currentChannelId, currentServerId, messages, pinnedMessages, voiceStates, voiceChannelId, addTypingUser, members, dmMessages, currentDmConversationId, dmConversations, updateUserStatus } from "./stores";
-import type { WsEnvelope, Message, VoiceState, DmMessage, UserStatus } from "./types";
+import { authToken, currentUser, currentChannelId, currentServerId, servers, messages, pinnedMessages, voiceStates, voiceChannelId, addTypingUser, members, dmMessages, currentDmConversationId, dmConversations, updateUserStatus, unreadMentionCount, channels, categories } from "./stores";
+import type { WsEnvelope, Message, VoiceState, DmMessage, UserStatus, Thread, ReadState, Channel, ChannelCategory } from "./types";
 import { getServerUrl } from "./api";
 
 function getWsUrl(): string {
@@ -58,8 +58,17 @@ export function send(envelope: WsEnvelope) {
 
 // ── Convenience senders ──────────────────────────────────────────────
 
-export function wsSendMessage(channelId: string, content: string) {
-    send({ type: "send_message", payload: { channel_id: channelId, content } });
+export function wsSendMessage(
+    channelId: string,
+    content: string,
+    options: { reply_to?: string; thread_id?: string; attachment_ids?: string[] } = {},
+) {
+    send({ type: "send_message", payload: { channel_id: channelId, content, ...options } });
+}
+
+/** Acks over the socket so the user's other sessions get `read_state_update`. */
+export function wsAck(target: { channel_id: string } | { conversation_id: string }, messageId: string) {
+    send({ type: "ack", payload: { ...target, message_id: messageId } });
 }
 
 export function wsSendTyping(channelId: string) {
@@ -107,13 +116,18 @@ export function wsSignalIce(
 
 function handleMessage(env: WsEnvelope) {
     switch (env.type) {
-        case "auth_success":
+        case "auth_success": {
             console.log("WebSocket authenticated");
+            // Voice belongs to the connection that joined, so a reconnect
+            // has to join again
+            const voiceChannel = get(voiceChannelId);
+            if (voiceChannel) wsJoinVoice(voiceChannel);
             // Request notification permission
             if (typeof Notification !== "undefined" && Notification.permission === "default") {
                 Notification.requestPermission();
             }
             break;
+        }
 
         case "message_created": {
             const msg: Message = env.payload.message;
@@ -135,15 +149,98 @@ function handleMessage(env: WsEnvelope) {
             break;
         }
 
+        case "mention_created": {
+            unreadMentionCount.update((n) => n + 1);
+            break;
+        }
+
+        case "read_state_update": {
+            const state: ReadState = env.payload.read_state;
+            if (state.channel_id) {
+                const previous = get(channels).find((c) => c.id === state.channel_id);
+                channels.update((chs) =>
+                    chs.map((c) =>
+                        c.id === state.channel_id
+                            ? {
+                                ...c,
+                                unread_count: state.unread_count,
+                                mention_count: state.mention_count,
+                                last_read_message_id: state.last_read_message_id,
+                            }
+                            : c,
+                    ),
+                );
+                if (previous) {
+                    servers.update((list) =>
+                        list.map((s) =>
+                            s.id === previous.server_id
+                                ? {
+                                    ...s,
+                                    unread_count: Math.max(0, s.unread_count - previous.unread_count + state.unread_count),
+                                    mention_count: Math.max(0, s.mention_count - previous.mention_count + state.mention_count),
+                                }
+                                : s,
+                        ),
+                    );
+                }
+            } else if (state.conversation_id) {
+                dmConversations.update((convs) =>
+                    convs.map((c) =>
+                        c.id === state.conversation_id
+                            ? {
+                                ...c,
+                                unread_count: state.unread_count,
+                                mention_count: state.mention_count,
+                                last_read_message_id: state.last_read_message_id,
+                            }
+                            : c,
+                    ),
+                );
+            }
+            break;
+        }
+
+        case "thread_created":
+        case "thread_message_created": {
+            // Keep the root message's thread summary current; replies
+            // themselves are loaded by the thread view.
+            const thread: Thread = env.payload.thread;
+            messages.update((msgs) =>
+                msgs.map((m) => (m.id === thread.root_message_id ? { ...m, thread } : m)),
+            );
+            break;
+        }
+
         case "message_deleted": {
+            // The message stays in the timeline as a tombstone
             const { message_id } = env.payload;
-            messages.update((msgs) => msgs.filter((m) => m.id !== message_id));
+            messages.update((msgs) =>
+                msgs.map((m) =>
+                    m.id === message_id
+                        ? {
+                            ...m,
+                            content: null,
+                            attachments: [],
+                            reactions: [],
+                            embeds: [],
+                            pinned: false,
+                            deleted_at: m.deleted_at ?? new Date().toISOString(),
+                        }
+                        : m,
+                ),
+            );
             pinnedMessages.update((pins) => pins.filter((p) => p.id !== message_id));
             break;
         }
 
+        case "message_restored": {
+            const msg: Message = env.payload.message;
+            messages.update((msgs) => msgs.map((m) => (m.id === msg.id ? msg : m)));
+            break;
+        }
+
         case "message_updated": {
-            const { message_id, content, edited_at, pinned } = env.payload;
+            const { message_id, content, edited_at, pinned, embeds } = env.payload;
 
             messages.update((msgs) =>
                 msgs.map((m) =>
@@ -153,6 +250,7 @@ function handleMessage(env: WsEnvelope) {
                             content: content ?? m.content,
                             edited_at: edited_at ?? m.edited_at,
                             pinned: pinned ?? m.pinned,
+                            embeds: embeds ?? m.embeds,
                         }
                         : m,
                 ),
@@ -203,11 +301,75 @@ function handleMessage(env: WsEnvelope) {
             break;
         }
 
+        case "channel_created":
+        case "channel_updated": {
+            // Read state is per user and not part of the event
+            const channel: Channel = env.payload.channel;
+            if (channel.server_id !== get(currentServerId)) break;
+            channels.update((list) => {
+                const existing = list.find((c) => c.id === channel.id);
+                if (!existing) return [...list, channel];
+                const { unread_count, mention_count, last_read_message_id } = existing;
+                return list.map((c) =>
+                    c.id === channel.id ? { ...channel, unread_count, mention_count, last_read_message_id } : c,
+                );
+            });
+            break;
+        }
+
+        case "channel_deleted": {
+            const { channel_id } = env.payload;
+            channels.update((list) => list.filter((c) => c.id !== channel_id));
+            if (get(currentChannelId) === channel_id) {
+                currentChannelId.set(null);
+                messages.set([]);
+                pinnedMessages.set([]);
+            }
+            break;
+        }
+
+        case "category_created":
+        case "category_updated": {
+            const category: ChannelCategory = env.payload.category;
+            if (category.server_id !== get(currentServerId)) break;
+            categories.update((list) =>
+                list.some((c) => c.id === category.id)
+                    ? list.map((c) => (c.id === category.id ? category : c))
+                    : [...list, category],
+            );
+            break;
+        }
+
+        case "category_deleted": {
+            // Its channels stay, outside any category
+            const { category_id } = env.payload;
+            categories.update((list) => list.filter((c) => c.id !== category_id));
+            channels.update((list) =>
+                list.map((c) => (c.category_id === category_id ? { ...c, category_id: null } : c)),
+            );
+            break;
+        }
+
         case "member_joined": {
             const { server_id, member } = env.payload;
             const current = get(currentServerId);
             if (current === server_id) {
-                members.update((m) => [...m, member]);
+                members.update((m) => (m.some((x) => x.user_id === member.user_id) ? m : [...m, member]));
+            }
+            break;
+        }
+
+        case "member_left": {
+            const { server_id, user_id } = env.payload;
+            if (user_id === get(currentUser)?.id) {
+                // Left or kicked, possibly from another session
+                servers.update((list) => list.filter((s) => s.id !== server_id));
+                if (get(currentServerId) === server_id) {
+                    currentServerId.set(null);
+                    currentChannelId.set(null);
+                }
+            } else if (get(currentServerId) === server_id) {
+                members.update((m) => m.filter((x) => x.user_id !== user_id));
             }
             break;
         }
@@ -220,6 +382,13 @@ function handleMessage(env: WsEnvelope) {
             }
             break;
 
+        case "voice_moved":
+            // A moderator moved or disconnected us; handled by webrtc.ts
+            if (typeof window !== "undefined") {
+                window.dispatchEvent(new CustomEvent("voice_moved", { detail: env.payload }));
+            }
+            break;
+
         case "dm_message_created": {
             const msg: DmMessage = env.payload.message;
             const currentConv = get(currentDmConversationId);